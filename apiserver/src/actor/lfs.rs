@@ -0,0 +1,119 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// This file defines the types of the Git LFS batch API:
+// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md
+
+/// The content type of the requests and responses of the batch API.
+pub const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// The only transfer adapter supported: objects are uploaded and downloaded with a single
+/// request each.
+const BASIC_TRANSFER: &str = "basic";
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum Operation {
+    #[serde(rename = "download")] Download,
+    #[serde(rename = "upload")] Upload,
+}
+
+// The transfer adapters the client supports aren't looked at: all clients support the basic one.
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operation: Operation,
+    pub objects: Vec<RequestObject>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RequestObject {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    transfer: &'static str,
+    objects: Vec<ResponseObject>,
+}
+
+impl BatchResponse {
+    pub fn new(objects: Vec<ResponseObject>) -> Self {
+        Self {
+            transfer: BASIC_TRANSFER,
+            objects,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ResponseObject {
+    oid: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actions: Option<Actions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ObjectError>,
+}
+
+impl ResponseObject {
+    /// An object that the client has nothing to do with, e.g. an object to upload that the
+    /// server already has.
+    pub fn done(object: RequestObject) -> Self {
+        Self {
+            oid: object.oid,
+            size: object.size,
+            actions: None,
+            error: None,
+        }
+    }
+
+    pub fn download(object: RequestObject, href: String) -> Self {
+        Self {
+            actions: Some(Actions {
+                download: Some(Action { href }),
+                upload: None,
+            }),
+            ..Self::done(object)
+        }
+    }
+
+    pub fn upload(object: RequestObject, href: String) -> Self {
+        Self {
+            actions: Some(Actions {
+                download: None,
+                upload: Some(Action { href }),
+            }),
+            ..Self::done(object)
+        }
+    }
+
+    /// `code` is an HTTP status code, as the batch API uses them for the errors of objects too.
+    pub fn error(object: RequestObject, code: u16, message: String) -> Self {
+        Self {
+            error: Some(ObjectError { code, message }),
+            ..Self::done(object)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Actions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download: Option<Action>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload: Option<Action>,
+}
+
+#[derive(Serialize)]
+struct Action {
+    href: String,
+}
+
+#[derive(Serialize)]
+struct ObjectError {
+    code: u16,
+    message: String,
+}
@@ -4,6 +4,7 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+mod lfs;
 mod model;
 mod query;
 mod repo;
@@ -22,6 +23,7 @@ use metaconfig::repoconfig::RepoConfigs;
 
 use errors::ErrorKind;
 
+pub use self::lfs::BatchRequest;
 pub use self::query::{MononokeQuery, MononokeRepoQuery};
 pub use self::repo::MononokeRepoActor;
 pub use self::response::MononokeRepoResponse;
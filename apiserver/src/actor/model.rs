@@ -14,6 +14,7 @@ use failure::Error;
 
 use blobrepo::HgBlobChangeset;
 use mercurial_types::{Changeset as HgChangeset, Entry as HgEntry, Type};
+use revset::BisectStep as RevsetBisectStep;
 
 #[derive(Serialize)]
 pub enum FileType {
@@ -87,3 +88,18 @@ impl TryFrom<HgBlobChangeset> for Changeset {
         })
     }
 }
+
+#[derive(Serialize)]
+pub struct BisectStep {
+    next: Option<String>,
+    remaining: usize,
+}
+
+impl From<RevsetBisectStep> for BisectStep {
+    fn from(step: RevsetBisectStep) -> BisectStep {
+        BisectStep {
+            next: step.next.map(|node| node.to_string()),
+            remaining: step.remaining,
+        }
+    }
+}
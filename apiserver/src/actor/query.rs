@@ -4,14 +4,17 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::fmt;
+
 use actix::Message;
 use actix::dev::Request;
 use failure::Error;
-use futures_ext::BoxFuture;
+use bytes::Bytes;
+use futures_ext::{BoxFuture, BoxStream};
 
 use super::{MononokeRepoActor, MononokeRepoResponse};
+use super::lfs::BatchRequest;
 
-#[derive(Debug)]
 pub enum MononokeRepoQuery {
     GetRawFile {
         path: String,
@@ -34,6 +37,103 @@ pub enum MononokeRepoQuery {
         proposed_ancestor: String,
         proposed_descendent: String,
     },
+    MergeBase {
+        first: String,
+        second: String,
+    },
+    Revset {
+        revset: String,
+        limit: usize,
+    },
+    Bisect {
+        goods: Vec<String>,
+        bad: String,
+    },
+    LfsBatch {
+        request: BatchRequest,
+        // The URL of the repo, that the URLs of the objects are relative to.
+        repo_url: String,
+    },
+    DownloadLfsObject {
+        oid: String,
+    },
+    UploadLfsObject {
+        oid: String,
+        size: u64,
+        content: BoxStream<Bytes, Error>,
+    },
+}
+
+// The content of an upload is a stream, so only the other fields of the queries are printed.
+impl fmt::Debug for MononokeRepoQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MononokeRepoQuery::*;
+
+        match *self {
+            GetRawFile {
+                ref path,
+                ref changeset,
+            } => f.debug_struct("GetRawFile")
+                .field("path", path)
+                .field("changeset", changeset)
+                .finish(),
+            ListDirectory {
+                ref path,
+                ref changeset,
+            } => f.debug_struct("ListDirectory")
+                .field("path", path)
+                .field("changeset", changeset)
+                .finish(),
+            GetBlobContent { ref hash } => {
+                f.debug_struct("GetBlobContent").field("hash", hash).finish()
+            }
+            GetTree { ref hash } => f.debug_struct("GetTree").field("hash", hash).finish(),
+            GetChangeset { ref hash } => {
+                f.debug_struct("GetChangeset").field("hash", hash).finish()
+            }
+            IsAncestor {
+                ref proposed_ancestor,
+                ref proposed_descendent,
+            } => f.debug_struct("IsAncestor")
+                .field("proposed_ancestor", proposed_ancestor)
+                .field("proposed_descendent", proposed_descendent)
+                .finish(),
+            MergeBase {
+                ref first,
+                ref second,
+            } => f.debug_struct("MergeBase")
+                .field("first", first)
+                .field("second", second)
+                .finish(),
+            Revset {
+                ref revset,
+                ref limit,
+            } => f.debug_struct("Revset")
+                .field("revset", revset)
+                .field("limit", limit)
+                .finish(),
+            Bisect { ref goods, ref bad } => f.debug_struct("Bisect")
+                .field("goods", goods)
+                .field("bad", bad)
+                .finish(),
+            LfsBatch {
+                ref request,
+                ref repo_url,
+            } => f.debug_struct("LfsBatch")
+                .field("request", request)
+                .field("repo_url", repo_url)
+                .finish(),
+            DownloadLfsObject { ref oid } => {
+                f.debug_struct("DownloadLfsObject").field("oid", oid).finish()
+            }
+            UploadLfsObject {
+                ref oid, ref size, ..
+            } => f.debug_struct("UploadLfsObject")
+                .field("oid", oid)
+                .field("size", size)
+                .finish(),
+        }
+    }
 }
 
 impl Message for MononokeRepoQuery {
@@ -5,34 +5,46 @@
 // GNU General Public License version 2 or any later version.
 
 use std::convert::TryInto;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix::{Actor, Context, Handler};
+use bytes::Bytes;
 use failure::{err_msg, Error, Result};
-use futures::{Future, IntoFuture};
+use futures::{future, stream, Future, IntoFuture, Stream};
 use futures::sync::oneshot;
-use futures_ext::BoxFuture;
+use futures_ext::{BoxFuture, BoxStream, StreamExt};
 use slog::Logger;
 use tokio::runtime::TaskExecutor;
+use tokio::timer::Interval;
 
 use api;
 use blobrepo::BlobRepo;
+use blobstore::Keyring;
 use futures_ext::FutureExt;
-use mercurial_types::RepositoryId;
+use mercurial_types::{HgChangesetId, HgNodeHash, RepositoryId};
+use mercurial_types::hash::Sha256;
 use mercurial_types::manifest::Content;
 use metaconfig::repoconfig::RepoConfig;
 use metaconfig::repoconfig::RepoType::{BlobManifold, BlobRocks};
-use mononoke_types::FileContents;
-use reachabilityindex::{GenerationNumberBFS, ReachabilityIndex};
+use reachabilityindex::{ReachabilityIndex, SkiplistIndex};
+use revset::BisectStep;
 
 use errors::ErrorKind;
 use from_string as FS;
 
 use super::{MononokeRepoQuery, MononokeRepoResponse};
+use super::lfs::{BatchRequest, BatchResponse, Operation, ResponseObject};
 use super::model::Entry;
 
+// How often the skiplist index is loaded again, to pick up the commits that the servers indexed
+// after pushes since it was last loaded.
+const SKIPLIST_INDEX_RELOAD_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 pub struct MononokeRepoActor {
     repo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
     logger: Logger,
     executor: TaskExecutor,
 }
@@ -40,16 +52,46 @@ pub struct MononokeRepoActor {
 impl MononokeRepoActor {
     pub fn new(logger: Logger, config: RepoConfig, executor: TaskExecutor) -> Result<Self> {
         let repoid = RepositoryId::new(config.repoid);
+        let keyring = match config.encryption_keyfile {
+            Some(ref keyfile) => {
+                Some(Keyring::open(keyfile)?.allow_plaintext(config.encryption_allow_plaintext))
+            }
+            None => None,
+        };
         let repo = match config.repotype {
-            BlobRocks(ref path) => BlobRepo::new_rocksdb(logger.clone(), &path, repoid),
+            BlobRocks(ref path) => BlobRepo::new_rocksdb(
+                logger.clone(),
+                &path,
+                repoid,
+                keyring,
+                config.disk_cache.as_ref(),
+                config.compression.as_ref(),
+            ),
             BlobManifold { ref args, .. } => BlobRepo::new_manifold(logger.clone(), args, repoid),
             _ => Err(err_msg("Unsupported repo type.")),
         };
 
-        repo.map(|repo| Self {
-            repo: Arc::new(repo),
-            logger: logger,
-            executor: executor,
+        repo.map(|repo| {
+            let repo = Arc::new(repo);
+            let skiplist_index = Arc::new(SkiplistIndex::new());
+            // Queries don't wait for the index to be loaded, they index what they need until
+            // then. The apiserver doesn't see pushes, so it loads the index again periodically
+            // instead of extending it.
+            if let Some(key) = config.skiplist_index_blobstore_key {
+                executor.spawn(load_skiplist_index(
+                    repo.clone(),
+                    skiplist_index.clone(),
+                    key,
+                    logger.clone(),
+                ));
+            }
+
+            Self {
+                repo,
+                skiplist_index,
+                logger: logger,
+                executor: executor,
+            }
         })
     }
 
@@ -85,23 +127,9 @@ impl MononokeRepoActor {
         proposed_ancestor: String,
         proposed_descendent: String,
     ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
-        let genbfs = GenerationNumberBFS::new();
-        let src_hash_maybe = FS::get_nodehash(&proposed_descendent);
-        let dst_hash_maybe = FS::get_nodehash(&proposed_ancestor);
-        let src_hash_future = src_hash_maybe.into_future().or_else({
-            cloned!(self.repo);
-            move |_| {
-                FS::string_to_bookmark_changeset_id(proposed_descendent, repo)
-                    .map(|node_cs| *node_cs.as_nodehash())
-            }
-        });
-        let dst_hash_future = dst_hash_maybe.into_future().or_else({
-            cloned!(self.repo);
-            move |_| {
-                FS::string_to_bookmark_changeset_id(proposed_ancestor, repo)
-                    .map(|node_cs| *node_cs.as_nodehash())
-            }
-        });
+        let skiplist_index = self.skiplist_index.clone();
+        let src_hash_future = resolve_node(self.repo.clone(), proposed_descendent);
+        let dst_hash_future = resolve_node(self.repo.clone(), proposed_ancestor);
 
         let (tx, rx) = oneshot::channel::<Result<bool>>();
 
@@ -110,7 +138,7 @@ impl MononokeRepoActor {
                 .and_then(|src| dst_hash_future.map(move |dst| (src, dst)))
                 .and_then({
                     cloned!(self.repo);
-                    move |(src, dst)| genbfs.query_reachability(repo, src, dst)
+                    move |(src, dst)| skiplist_index.query_reachability(repo, src, dst)
                 })
                 .then(|r| tx.send(r).map_err(|_| ())),
         );
@@ -121,17 +149,112 @@ impl MononokeRepoActor {
             .boxify())
     }
 
+    fn merge_base(
+        &self,
+        first: String,
+        second: String,
+    ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let skiplist_index = self.skiplist_index.clone();
+        let first_hash_future = resolve_node(self.repo.clone(), first);
+        let second_hash_future = resolve_node(self.repo.clone(), second);
+
+        let (tx, rx) = oneshot::channel::<Result<Vec<HgNodeHash>>>();
+
+        self.executor.spawn(
+            first_hash_future
+                .join(second_hash_future)
+                .and_then({
+                    cloned!(self.repo);
+                    move |(first, second)| {
+                        skiplist_index.lowest_common_ancestors(repo, vec![first, second])
+                    }
+                })
+                .then(|r| tx.send(r).map_err(|_| ())),
+        );
+
+        Ok(rx.flatten()
+            .map(|nodes| MononokeRepoResponse::MergeBase {
+                nodes: nodes.into_iter().map(|node| node.to_string()).collect(),
+            })
+            .from_err()
+            .boxify())
+    }
+
+    /// The first `limit` changesets of the revset, from the highest generation.
+    fn revset(
+        &self,
+        revset: String,
+        limit: usize,
+    ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let changesets = api::get_changesets_by_revset(self.repo.clone(), &revset)?;
+
+        let (tx, rx) = oneshot::channel::<Result<Vec<HgChangesetId>>>();
+
+        self.executor.spawn(
+            changesets
+                .take(limit as u64)
+                .collect()
+                .then(|r| tx.send(r).map_err(|_| ())),
+        );
+
+        Ok(rx.flatten()
+            .map(|changesets| MononokeRepoResponse::Revset {
+                changesets: changesets.into_iter().map(|cs| cs.to_string()).collect(),
+            })
+            .from_err()
+            .boxify())
+    }
+
+    /// The next changeset to test when bisecting between `goods` and `bad`. The client keeps the
+    /// state of the bisection and sends all the changesets found good so far and the last one
+    /// found bad each time.
+    fn bisect(
+        &self,
+        goods: Vec<String>,
+        bad: String,
+    ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let good_hashes_future = future::join_all(
+            goods
+                .into_iter()
+                .map(|good| resolve_node(self.repo.clone(), good))
+                .collect::<Vec<_>>(),
+        );
+        let bad_hash_future = resolve_node(self.repo.clone(), bad);
+
+        let (tx, rx) = oneshot::channel::<Result<BisectStep>>();
+
+        self.executor.spawn(
+            good_hashes_future
+                .join(bad_hash_future)
+                .and_then({
+                    cloned!(self.repo);
+                    move |(goods, bad)| {
+                        let goods = goods.into_iter().map(HgChangesetId::new).collect();
+                        let bad = HgChangesetId::new(bad);
+                        api::get_bisect_step(repo, goods, bad)
+                    }
+                })
+                .then(|r| tx.send(r).map_err(|_| ())),
+        );
+
+        Ok(rx.flatten()
+            .map(|step| MononokeRepoResponse::Bisect { step: step.into() })
+            .from_err()
+            .boxify())
+    }
+
     fn get_blob_content(&self, hash: String) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
         let blobhash = FS::get_nodehash(&hash)?;
 
+        // The contents are streamed, so that large chunked files aren't buffered. Like for LFS
+        // objects, wait for the first chunk so that missing blobs get an error status.
         Ok(self.repo
-            .get_file_content(&blobhash)
-            .and_then(move |content| match content {
-                FileContents::Bytes(content) => {
-                    Ok(MononokeRepoResponse::GetBlobContent { content })
-                }
+            .get_file_content_stream(&blobhash)
+            .into_future()
+            .map_err(|(err, _rest)| err)
+            .map(|(first, rest)| MononokeRepoResponse::GetBlobContent {
+                content: stream::iter_ok(first).chain(rest).boxify(),
             })
-            .from_err()
             .boxify())
     }
 
@@ -190,6 +313,79 @@ impl MononokeRepoActor {
             .from_err()
             .boxify())
     }
+
+    /// Tells the client where to download or upload each of the objects of the request. Invalid
+    /// and missing objects are reported for each object, as the batch API expects.
+    fn lfs_batch(
+        &self,
+        request: BatchRequest,
+        repo_url: String,
+    ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let BatchRequest { operation, objects } = request;
+        let objects = objects.into_iter().map(move |object| {
+            let oid = match Sha256::from_str(&object.oid) {
+                Ok(oid) => oid,
+                Err(err) => {
+                    let message = format!("invalid oid: {}", err);
+                    return future::ok(ResponseObject::error(object, 422, message)).left_future();
+                }
+            };
+
+            let repo_url = repo_url.clone();
+            self.repo
+                .get_lfs_content_id(&oid)
+                .map(move |content_id| match (operation, content_id) {
+                    (Operation::Download, Some(_)) => {
+                        let href = format!("{}/lfs/download/{}", repo_url, oid);
+                        ResponseObject::download(object, href)
+                    }
+                    (Operation::Download, None) => {
+                        ResponseObject::error(object, 404, "object does not exist".into())
+                    }
+                    (Operation::Upload, Some(_)) => ResponseObject::done(object),
+                    (Operation::Upload, None) => {
+                        let href = format!("{}/lfs/upload/{}/{}", repo_url, oid, object.size);
+                        ResponseObject::upload(object, href)
+                    }
+                })
+                .right_future()
+        });
+
+        Ok(future::join_all(objects)
+            .map(|objects| MononokeRepoResponse::LfsBatch {
+                response: BatchResponse::new(objects),
+            })
+            .boxify())
+    }
+
+    fn download_lfs_object(&self, oid: String) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let oid = FS::get_sha256(&oid)?;
+
+        // Wait for the first chunk, so that missing objects are reported with an error status
+        // rather than as a truncated response.
+        Ok(self.repo
+            .get_lfs_object_stream(&oid)
+            .into_future()
+            .map_err(|(err, _rest)| err)
+            .map(|(first, rest)| MononokeRepoResponse::DownloadLfsObject {
+                content: stream::iter_ok(first).chain(rest).boxify(),
+            })
+            .boxify())
+    }
+
+    fn upload_lfs_object(
+        &self,
+        oid: String,
+        size: u64,
+        content: BoxStream<Bytes, Error>,
+    ) -> Result<BoxFuture<MononokeRepoResponse, Error>> {
+        let oid = FS::get_sha256(&oid)?;
+
+        Ok(self.repo
+            .upload_lfs_object_stream(oid, size, content)
+            .map(|_content_id| MononokeRepoResponse::UploadLfsObject)
+            .boxify())
+    }
 }
 
 impl Actor for MononokeRepoActor {
@@ -212,6 +408,51 @@ impl Handler<MononokeRepoQuery> for MononokeRepoActor {
                 proposed_ancestor,
                 proposed_descendent,
             } => self.is_ancestor(proposed_ancestor, proposed_descendent),
+            MergeBase { first, second } => self.merge_base(first, second),
+            Revset { revset, limit } => self.revset(revset, limit),
+            Bisect { goods, bad } => self.bisect(goods, bad),
+            LfsBatch { request, repo_url } => self.lfs_batch(request, repo_url),
+            DownloadLfsObject { oid } => self.download_lfs_object(oid),
+            UploadLfsObject { oid, size, content } => self.upload_lfs_object(oid, size, content),
         }
     }
 }
+
+/// Resolve a revision given as a hash or a bookmark name.
+fn resolve_node(
+    repo: Arc<BlobRepo>,
+    revision: String,
+) -> impl Future<Item = HgNodeHash, Error = Error> + Send + 'static {
+    FS::get_nodehash(&revision)
+        .into_future()
+        .or_else(move |_| {
+            FS::string_to_bookmark_changeset_id(revision, repo)
+                .map(|node_cs| *node_cs.as_nodehash())
+        })
+}
+
+fn load_skiplist_index(
+    repo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
+    key: String,
+    logger: Logger,
+) -> impl Future<Item = (), Error = ()> + Send + 'static {
+    // The first tick is immediate, for the initial load.
+    Interval::new(Instant::now(), SKIPLIST_INDEX_RELOAD_INTERVAL)
+        .map_err(|_| ())
+        .for_each(move |_| {
+            cloned!(key, logger);
+            skiplist_index
+                .load_from_blobstore(&repo, key.clone())
+                .then(move |loaded| {
+                    match loaded {
+                        Ok(Some(count)) => {
+                            info!(logger, "loaded skiplist index with {} nodes", count)
+                        }
+                        Ok(None) => warn!(logger, "skiplist index blob {} not found", key),
+                        Err(err) => error!(logger, "failed to load skiplist index: {}", err),
+                    }
+                    Ok(())
+                })
+        })
+}
@@ -8,16 +8,23 @@ use std::result::Result;
 
 use actix_web;
 use actix_web::{Body, HttpRequest, HttpResponse, Json, Responder};
+use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
 use bytes::Bytes;
+use failure::Error;
+use futures::Stream;
+use futures_ext::BoxStream;
 
-use super::model::{Changeset, Entry};
+use errors::ErrorKind;
+
+use super::lfs::{BatchResponse, LFS_CONTENT_TYPE};
+use super::model::{BisectStep, Changeset, Entry};
 
 pub enum MononokeRepoResponse {
     GetRawFile {
         content: Bytes,
     },
     GetBlobContent {
-        content: Bytes,
+        content: BoxStream<Bytes, Error>,
     },
     ListDirectory {
         files: Box<Iterator<Item = Entry> + Send>,
@@ -31,6 +38,22 @@ pub enum MononokeRepoResponse {
     IsAncestor {
         answer: bool,
     },
+    MergeBase {
+        nodes: Vec<String>,
+    },
+    Revset {
+        changesets: Vec<String>,
+    },
+    Bisect {
+        step: BisectStep,
+    },
+    LfsBatch {
+        response: BatchResponse,
+    },
+    DownloadLfsObject {
+        content: BoxStream<Bytes, Error>,
+    },
+    UploadLfsObject,
 }
 
 fn binary_response(content: Bytes) -> HttpResponse {
@@ -47,7 +70,7 @@ impl Responder for MononokeRepoResponse {
         use self::MononokeRepoResponse::*;
 
         match self {
-            GetRawFile { content } | GetBlobContent { content } => Ok(binary_response(content)),
+            GetRawFile { content } => Ok(binary_response(content)),
             ListDirectory { files } | GetTree { files } => {
                 Json(files.collect::<Vec<_>>()).respond_to(req)
             }
@@ -59,6 +82,20 @@ impl Responder for MononokeRepoResponse {
                     "false".into()
                 }
             })),
+            MergeBase { nodes } => Json(nodes).respond_to(req),
+            Revset { changesets } => Json(changesets).respond_to(req),
+            Bisect { step } => Json(step).respond_to(req),
+            LfsBatch { response } => {
+                let mut response = Json(response).respond_to(req)?;
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static(LFS_CONTENT_TYPE));
+                Ok(response)
+            }
+            GetBlobContent { content } | DownloadLfsObject { content } => Ok(HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .streaming(content.map_err(ErrorKind::from))),
+            UploadLfsObject => Ok(HttpResponse::Ok().finish()),
         }
     }
 }
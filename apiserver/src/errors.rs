@@ -14,7 +14,9 @@ use failure::{Context, Error, Fail};
 
 use api::errors::ErrorKind as ApiError;
 use blobrepo::ErrorKind as BlobRepoError;
+use blobstore::censored_reason;
 use reachabilityindex::errors::ErrorKind as ReachabilityIndexError;
+use revset::ErrorKind as RevsetError;
 
 #[derive(Serialize, Debug)]
 struct ErrorResponse {
@@ -25,6 +27,7 @@ struct ErrorResponse {
 #[derive(Debug)]
 pub enum ErrorKind {
     NotFound(String, Option<Error>),
+    Censored(String, Option<Error>),
     InvalidInput(String, Option<Error>),
     InternalError(Error),
 }
@@ -35,6 +38,7 @@ impl ErrorKind {
 
         match self {
             NotFound(..) => StatusCode::NOT_FOUND,
+            Censored(..) => StatusCode::GONE,
             InvalidInput(..) => StatusCode::BAD_REQUEST,
             InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -57,7 +61,9 @@ impl Fail for ErrorKind {
         use errors::ErrorKind::*;
 
         match self {
-            NotFound(_, cause) | InvalidInput(_, cause) => cause.as_ref().map(|e| e.as_fail()),
+            NotFound(_, cause) | Censored(_, cause) | InvalidInput(_, cause) => {
+                cause.as_ref().map(|e| e.as_fail())
+            }
             InternalError(err) => Some(err.as_fail()),
         }
     }
@@ -69,6 +75,7 @@ impl fmt::Display for ErrorKind {
 
         match self {
             NotFound(_0, _) => write!(f, "{} is not found", _0),
+            Censored(_0, _) => write!(f, "content is censored: {}", _0),
             InvalidInput(_0, _) => write!(f, "{} is invalid", _0),
             InternalError(_0) => write!(f, "internal server error: {}", _0),
         }
@@ -95,10 +102,18 @@ impl ResponseError for ErrorKind {
 
 impl From<Error> for ErrorKind {
     fn from(e: Error) -> ErrorKind {
+        // Redacted blobs can be reached from many places, and the error might have been given
+        // some context on the way, so look for it in all the causes.
+        let reason = censored_reason(&e).map(|reason| reason.to_string());
+        if let Some(reason) = reason {
+            return ErrorKind::Censored(reason, Some(e));
+        }
+
         e.downcast::<ErrorKind>()
             .or_else(|err| err.downcast::<BlobRepoError>().map(|e| e.into()))
             .or_else(|err| err.downcast::<ApiError>().map(|e| e.into()))
             .or_else(|err| err.downcast::<ReachabilityIndexError>().map(|e| e.into()))
+            .or_else(|err| err.downcast::<RevsetError>().map(|e| e.into()))
             .unwrap_or_else(|e| ErrorKind::InternalError(e))
     }
 }
@@ -149,9 +164,27 @@ impl From<ReachabilityIndexError> for ErrorKind {
             CheckExistenceFailed(s, t) => {
                 ErrorKind::NotFound(s.clone(), Some(CheckExistenceFailed(s, t).into()))
             }
-            e @ GenerationFetchFailed(_) | e @ ParentsFetchFailed(_) => {
-                ErrorKind::InternalError(e.into())
-            }
+            e @ TooManyNodes(..) => ErrorKind::InvalidInput(e.to_string(), Some(e.into())),
+            e @ GenerationFetchFailed(_)
+            | e @ ParentsFetchFailed(_)
+            | e @ InvalidSerializedIndex(_) => ErrorKind::InternalError(e.into()),
+        }
+    }
+}
+
+impl From<RevsetError> for ErrorKind {
+    fn from(e: RevsetError) -> ErrorKind {
+        use self::RevsetError::*;
+
+        match e {
+            e @ InvalidRevset(_) => ErrorKind::InvalidInput(e.to_string(), Some(e.into())),
+            UnknownRevision(s) => ErrorKind::NotFound(s.clone(), Some(UnknownRevision(s).into())),
+            e @ InvalidBisect(..) => ErrorKind::InvalidInput(e.to_string(), Some(e.into())),
+            e @ TooManyNodes(_) => ErrorKind::InvalidInput(e.to_string(), Some(e.into())),
+            e @ RepoError(_)
+            | e @ GenerationFetchFailed
+            | e @ ParentsFetchFailed
+            | e @ ChildrenFetchFailed => ErrorKind::InternalError(e.into()),
         }
     }
 }
@@ -18,6 +18,7 @@ use api;
 use blobrepo::BlobRepo;
 use bookmarks::Bookmark;
 use mercurial_types::{HgChangesetId, HgNodeHash};
+use mercurial_types::hash::Sha256;
 use mononoke_types::MPath;
 
 use errors::ErrorKind;
@@ -41,6 +42,10 @@ pub fn get_nodehash(hash: &str) -> Result<HgNodeHash> {
         .map_err(|e| ErrorKind::InvalidInput(hash.to_string(), Some(e)).into())
 }
 
+pub fn get_sha256(oid: &str) -> Result<Sha256> {
+    Sha256::from_str(oid).map_err(|e| ErrorKind::InvalidInput(oid.to_string(), Some(e)).into())
+}
+
 // interpret a string as a bookmark and find the corresponding changeset id.
 // this method doesn't consider that the string could be a node hash, so any caller
 // should do that check themselves, and if it fails, then attempt to use this method.
@@ -10,6 +10,7 @@
 extern crate actix;
 extern crate actix_web;
 extern crate blobrepo;
+extern crate blobstore;
 extern crate bookmarks;
 extern crate bytes;
 extern crate chrono;
@@ -24,6 +25,7 @@ extern crate metaconfig;
 extern crate mononoke_api as api;
 extern crate mononoke_types;
 extern crate reachabilityindex;
+extern crate revset;
 extern crate scuba_ext;
 extern crate secure_utils;
 extern crate serde;
@@ -49,13 +51,15 @@ mod middleware;
 use std::path::Path;
 use std::str::FromStr;
 
-use actix::{Actor, Addr};
-use actix_web::{http, server, App, HttpRequest, HttpResponse, State};
+use actix::{Actor, Addr, Arbiter};
+use actix_web::{http, server, App, HttpMessage, HttpRequest, HttpResponse, Json, State};
 use blobrepo::BlobRepo;
 use bookmarks::Bookmark;
 use clap::Arg;
-use failure::{err_msg, Result};
-use futures::Future;
+use failure::{err_msg, Error, Result};
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc;
+use futures_ext::StreamExt;
 use slog::{Drain, Level, Logger};
 use slog_glog_fmt::{kv_categorizer, kv_defaults, GlogFormat};
 use slog_logview::LogViewDrain;
@@ -66,11 +70,18 @@ use mercurial_types::nodehash::HgChangesetId;
 use metaconfig::RepoConfigs;
 use scuba_ext::ScubaSampleBuilder;
 
-use actor::{unwrap_request, MononokeActor, MononokeQuery, MononokeRepoQuery, MononokeRepoResponse};
+use actor::{unwrap_request, BatchRequest, MononokeActor, MononokeQuery, MononokeRepoQuery,
+            MononokeRepoResponse};
 use errors::ErrorKind;
 
 mod config {
     pub const SCUBA_TABLE: &str = "mononoke_apiserver";
+    /// Number of chunks of an LFS upload that are buffered while they are stored.
+    pub const LFS_UPLOAD_BUFFER_SIZE: usize = 16;
+    /// Number of changesets a revset query returns when no limit is given.
+    pub const REVSET_DEFAULT_LIMIT: usize = 1000;
+    /// Maximum number of changesets a revset query can return.
+    pub const REVSET_MAX_LIMIT: usize = 10000;
 }
 
 #[derive(Deserialize)]
@@ -87,12 +98,50 @@ struct IsAncestorQueryInfo {
     proposed_descendent: String,
 }
 
+#[derive(Deserialize)]
+struct MergeBaseQueryInfo {
+    repo: String,
+    first: String,
+    second: String,
+}
+
+#[derive(Deserialize)]
+struct BisectQueryInfo {
+    repo: String,
+    goods: String,
+    bad: String,
+}
+
+#[derive(Deserialize)]
+struct RevsetQueryInfo {
+    expr: String,
+    limit: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct HashQueryInfo {
     repo: String,
     hash: String,
 }
 
+#[derive(Deserialize)]
+struct RepoQueryInfo {
+    repo: String,
+}
+
+#[derive(Deserialize)]
+struct LfsObjectQueryInfo {
+    repo: String,
+    oid: String,
+}
+
+#[derive(Deserialize)]
+struct LfsUploadQueryInfo {
+    repo: String,
+    oid: String,
+    size: u64,
+}
+
 // The argument of this function is because the trait `actix_web::FromRequest` is implemented
 // for tuple (A, B, ...) (up to 9 elements) [1]. These arguments must implement
 // `actix_web::FromRequest` as well so actix-web will try to extract them from `actix::HttpRequest`
@@ -122,6 +171,53 @@ fn is_ancestor(
     }))
 }
 
+fn merge_base(
+    (state, info): (State<HttpServerState>, actix_web::Path<MergeBaseQueryInfo>),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::MergeBase {
+            first: info.first.clone(),
+            second: info.second.clone(),
+        },
+    }))
+}
+
+// The good changesets are comma-separated, e.g. `/repo/bisect/<good1>,<good2>/<bad>`.
+fn bisect(
+    (state, info): (State<HttpServerState>, actix_web::Path<BisectQueryInfo>),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::Bisect {
+            goods: info.goods.split(',').map(|good| good.to_string()).collect(),
+            bad: info.bad.clone(),
+        },
+    }))
+}
+
+// The revset is given in the query string, e.g. `/repo/revset?expr=::master%20-%20::stable`, as
+// its syntax uses characters that can't be in a path segment.
+fn revset(
+    (state, info, query): (
+        State<HttpServerState>,
+        actix_web::Path<RepoQueryInfo>,
+        actix_web::Query<RevsetQueryInfo>,
+    ),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    let limit = query
+        .limit
+        .unwrap_or(config::REVSET_DEFAULT_LIMIT)
+        .min(config::REVSET_MAX_LIMIT);
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::Revset {
+            revset: query.expr.clone(),
+            limit,
+        },
+    }))
+}
+
 fn list_directory(
     (state, info): (State<HttpServerState>, actix_web::Path<QueryInfo>),
 ) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
@@ -167,6 +263,71 @@ fn get_changeset(
     }))
 }
 
+fn lfs_batch(
+    (state, info, request, req): (
+        State<HttpServerState>,
+        actix_web::Path<RepoQueryInfo>,
+        Json<BatchRequest>,
+        HttpRequest<HttpServerState>,
+    ),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    let repo_url = {
+        let conn = req.connection_info();
+        format!("{}://{}/{}", conn.scheme(), conn.host(), info.repo)
+    };
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::LfsBatch {
+            request: request.into_inner(),
+            repo_url,
+        },
+    }))
+}
+
+fn download_lfs_object(
+    (state, info): (State<HttpServerState>, actix_web::Path<LfsObjectQueryInfo>),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::DownloadLfsObject {
+            oid: info.oid.clone(),
+        },
+    }))
+}
+
+fn upload_lfs_object(
+    (state, info, req): (
+        State<HttpServerState>,
+        actix_web::Path<LfsUploadQueryInfo>,
+        HttpRequest<HttpServerState>,
+    ),
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    // The payload of the request can't be sent to the repo actor, so it is forwarded through a
+    // channel as it arrives. Errors are sent as items, as the channel can't carry them.
+    let (sender, receiver) = mpsc::channel(config::LFS_UPLOAD_BUFFER_SIZE);
+    Arbiter::spawn(
+        req.payload()
+            .then(|chunk| Ok::<_, ()>(chunk.map_err(Error::from)))
+            .forward(sender.sink_map_err(|_| ()))
+            .map(|_| ()),
+    );
+    let content = receiver
+        .then(|chunk| match chunk {
+            Ok(chunk) => chunk,
+            Err(()) => Err(err_msg("LFS upload channel failed")),
+        })
+        .boxify();
+
+    unwrap_request(state.mononoke.send(MononokeQuery {
+        repo: info.repo.clone(),
+        kind: MononokeRepoQuery::UploadLfsObject {
+            oid: info.oid.clone(),
+            size: info.size,
+            content,
+        },
+    }))
+}
+
 fn setup_logger(debug: bool) -> Logger {
     let level = if debug { Level::Debug } else { Level::Info };
 
@@ -195,6 +356,9 @@ fn create_config<P: AsRef<Path>>(
         logger.new(o!["repo" => "Config repo"]),
         path.as_ref(),
         RepositoryId::new(0),
+        None,
+        None,
+        None,
     )?;
 
     let changeset: HgChangesetId = bookmark
@@ -388,6 +552,15 @@ fn main() -> Result<()> {
                         "/is_ancestor/{proposed_ancestor}/{proposed_descendent}",
                         |r| r.method(http::Method::GET).with_async(is_ancestor),
                     )
+                    .resource("/merge_base/{first}/{second}", |r| {
+                        r.method(http::Method::GET).with_async(merge_base)
+                    })
+                    .resource("/bisect/{goods}/{bad}", |r| {
+                        r.method(http::Method::GET).with_async(bisect)
+                    })
+                    .resource("/revset", |r| {
+                        r.method(http::Method::GET).with_async(revset)
+                    })
                     .resource("/list/{changeset}/{path:.*}", |r| {
                         r.method(http::Method::GET).with_async(list_directory)
                     })
@@ -400,6 +573,15 @@ fn main() -> Result<()> {
                     .resource("/changeset/{hash}", |r| {
                         r.method(http::Method::GET).with_async(get_changeset)
                     })
+                    .resource("/objects/batch", |r| {
+                        r.method(http::Method::POST).with_async(lfs_batch)
+                    })
+                    .resource("/lfs/download/{oid}", |r| {
+                        r.method(http::Method::GET).with_async(download_lfs_object)
+                    })
+                    .resource("/lfs/upload/{oid}/{size}", |r| {
+                        r.method(http::Method::PUT).with_async(upload_lfs_object)
+                    })
             })
     });
 
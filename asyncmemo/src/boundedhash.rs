@@ -6,7 +6,7 @@
 
 use std::hash::Hash;
 
-use linked_hash_map::LinkedHashMap;
+use linked_hash_map::{self, LinkedHashMap};
 use weight::Weight;
 
 #[derive(Debug, Clone)]
@@ -155,6 +155,12 @@ where
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         self.hash.get_mut(key)
     }
+
+    /// Iterate over the entries, least recently inserted first.
+    #[inline]
+    pub fn iter(&self) -> linked_hash_map::Iter<K, V> {
+        self.hash.iter()
+    }
 }
 
 #[cfg(test)]
@@ -26,6 +26,9 @@
 //! or rate limiting, so if process is prone to failure then it can "succeed" but return a
 //! sentinel value representing the failure which the application can handle with its own logic.
 //!
+//! The complete results can be saved to a snapshot and loaded back into a new cache, e.g. across
+//! restarts, if the `Filler` knows how to serialize them.
+//!
 //! TODO: add interface to allow multiple implementations of the underlying cache, to allow
 //!   eviction and other policies to be controlled.
 //!
@@ -34,6 +37,7 @@
 
 #[cfg(test)]
 extern crate async_unit;
+extern crate byteorder;
 extern crate bytes;
 extern crate futures;
 extern crate futures_ext;
@@ -51,11 +55,15 @@ extern crate tokio_timer;
 
 use std::collections::hash_map::DefaultHasher;
 use std::fmt::{self, Debug};
+use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
 use std::sync::Arc;
 use std::thread;
 use std::usize;
 
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use futures::{Async, Future, Poll};
 use futures::future::{IntoFuture, Shared, SharedError, SharedItem};
 use parking_lot::Mutex;
@@ -84,6 +92,9 @@ define_stats! {
 
 const SHARD_NUM: usize = 1000;
 
+/// Start of the snapshots written by `Asyncmemo::save_snapshot`, with a format version.
+const SNAPSHOT_MAGIC: &[u8] = b"\xffAMS\x01";
+
 /// Asynchronous memoizing cache for async processes
 ///
 /// The cache requires an instance of an implementation of the `Filler` trait
@@ -124,6 +135,26 @@ pub trait Filler: Sized {
     type Value: IntoFuture + 'static;
 
     fn fill(&self, cache: &Asyncmemo<Self>, key: &Self::Key) -> Self::Value;
+
+    /// Serialize a complete entry of the cache, so that it can be saved with
+    /// `Asyncmemo::save_snapshot`. Entries this returns `None` for are left out of snapshots;
+    /// by default they all are.
+    fn serialize_entry(
+        &self,
+        _key: &Self::Key,
+        _value: &<Self::Value as IntoFuture>::Item,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Deserialize an entry serialized by `serialize_entry`. Entries this returns `None` for are
+    /// skipped by `Asyncmemo::load_snapshot`, e.g. if they were saved by an older version.
+    fn deserialize_entry(
+        &self,
+        _data: &[u8],
+    ) -> Option<(Self::Key, <Self::Value as IntoFuture>::Item)> {
+        None
+    }
 }
 
 type FillerSlot<F> = Slot<
@@ -538,6 +569,28 @@ where
         }
     }
 
+    /// Return the cached result for a key, if there is one, without starting a fetch. This is
+    /// meant for callers that fetch the missing keys in bulk and `insert` them afterwards.
+    pub fn get_cached<K: Into<F::Key>>(&self, key: K) -> Option<<F::Value as IntoFuture>::Item>
+    where
+        <F::Value as IntoFuture>::Item: Clone,
+    {
+        let key = key.into();
+        let mut locked = self.inner.hash_vec[self.get_shard(&key)].lock();
+        match locked.get_mut(&key) {
+            Some(&mut Slot::Complete(ref value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Insert a result that was fetched without going through the filler. A key that is already
+    /// cached, or being fetched, is left alone. Returns true if the result was inserted.
+    pub fn insert<K: Into<F::Key>>(&self, key: K, value: <F::Value as IntoFuture>::Item) -> bool {
+        let key = key.into();
+        let mut locked = self.inner.hash_vec[self.get_shard(&key)].lock();
+        locked.get_mut(&key).is_none() && locked.insert(key, Slot::Complete(value)).is_ok()
+    }
+
     /// Invalidate a specific key
     pub fn invalidate<K: Into<F::Key>>(&self, key: K) {
         let key = key.into();
@@ -596,6 +649,91 @@ where
         is_empty
     }
 
+    /// Write the complete entries of the cache that the filler can serialize to `writer`.
+    /// Returns the number of entries written.
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+
+        let mut count = 0;
+        for hash in &self.inner.hash_vec {
+            // Only serialize under the lock, so that slow writes don't block the cache.
+            let entries: Vec<_> = {
+                let hash = hash.lock();
+                hash.iter()
+                    .filter_map(|(key, slot)| match slot {
+                        &Slot::Complete(ref value) => {
+                            self.inner.filler.serialize_entry(key, value)
+                        }
+                        &Slot::Waiting(..) => None,
+                    })
+                    .collect()
+            };
+
+            for entry in entries {
+                write_len(&mut writer, entry.len())?;
+                writer.write_all(&entry)?;
+                count += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Load the entries of a snapshot written by `save_snapshot` into the cache. The limits of
+    /// the cache apply as usual, and entries already in the cache are kept. Returns the number of
+    /// entries loaded.
+    pub fn load_snapshot<R: Read>(&self, mut reader: R) -> io::Result<usize> {
+        let mut magic = vec![0; SNAPSHOT_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an asyncmemo snapshot",
+            ));
+        }
+
+        let mut count = 0;
+        while let Some(len) = read_len(&mut reader)? {
+            let mut data = vec![0; len];
+            reader.read_exact(&mut data)?;
+
+            if let Some((key, value)) = self.inner.filler.deserialize_entry(&data) {
+                let mut hash = self.inner.hash_vec[self.get_shard(&key)].lock();
+                if hash.get_mut(&key).is_none() && hash.insert(key, Slot::Complete(value)).is_ok()
+                {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Like `save_snapshot`, but to a file. The file is replaced atomically, so that a crash
+    /// while saving leaves the previous snapshot intact.
+    pub fn save_snapshot_file<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let count = {
+            let file = File::create(&tmp_path)?;
+            let count = self.save_snapshot(BufWriter::new(&file))?;
+            file.sync_all()?;
+            count
+        };
+        fs::rename(&tmp_path, path)?;
+        Ok(count)
+    }
+
+    /// Like `load_snapshot`, but from a file. Nothing is loaded if the file doesn't exist.
+    pub fn load_snapshot_file<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        match File::open(path) {
+            Ok(file) => self.load_snapshot(BufReader::new(file)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
     fn get_shard<K: Hash>(&self, key: &K) -> usize {
         let mut hasher = DefaultHasher::new();
         key.hash(&mut hasher);
@@ -615,3 +753,30 @@ where
         }
     }
 }
+
+// Snapshot entries are prefixed with their length, as a big-endian u32.
+fn write_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    if len > u32::max_value() as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "snapshot entry too large",
+        ));
+    }
+    writer.write_u32::<BigEndian>(len as u32)
+}
+
+// Returns None at the end of the snapshot.
+fn read_len<R: Read>(reader: &mut R) -> io::Result<Option<usize>> {
+    let mut buf = [0; 4];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Some(BigEndian::read_u32(&buf) as usize))
+}
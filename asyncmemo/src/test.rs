@@ -909,3 +909,106 @@ fn polling_hash_trimming() {
     t1.join().unwrap();
     t2.join().unwrap();
 }
+
+// Like Upperer, but its entries can be saved to snapshots
+struct SnapshotUpperer<'a>(&'a AtomicUsize);
+
+impl<'a> Filler for SnapshotUpperer<'a> {
+    type Key = String;
+    type Value = Result<String, ()>;
+
+    fn fill(&self, _cache: &Asyncmemo<Self>, key: &Self::Key) -> Self::Value {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(key.to_uppercase())
+    }
+
+    fn serialize_entry(&self, key: &Self::Key, value: &String) -> Option<Vec<u8>> {
+        if key == "skipped" {
+            return None;
+        }
+        Some(format!("{}\0{}", key, value).into_bytes())
+    }
+
+    fn deserialize_entry(&self, data: &[u8]) -> Option<(Self::Key, String)> {
+        let data = String::from_utf8(data.to_vec()).ok()?;
+        let mut parts = data.splitn(2, '\0');
+        let key = parts.next()?.to_string();
+        let value = parts.next()?.to_string();
+        Some((key, value))
+    }
+}
+
+#[test]
+fn snapshot() {
+    let count = AtomicUsize::new(0);
+    let c = Asyncmemo::new_unbounded("test", SnapshotUpperer(&count), 10);
+    for key in &["hello", "world", "skipped"] {
+        c.get(*key).wait().unwrap();
+    }
+    assert_eq!(count.load(Ordering::Relaxed), 3);
+
+    let mut snapshot = Vec::new();
+    assert_eq!(c.save_snapshot(&mut snapshot).unwrap(), 2);
+
+    let loaded = Asyncmemo::new_unbounded("test", SnapshotUpperer(&count), 3);
+    assert_eq!(loaded.load_snapshot(snapshot.as_slice()).unwrap(), 2);
+    assert_eq!(loaded.len(), 2);
+    assert!(loaded.key_present_in_cache("hello"));
+    assert!(loaded.key_present_in_cache("world"));
+    assert!(!loaded.key_present_in_cache("skipped"));
+
+    assert_eq!(loaded.get("hello").wait().unwrap(), "HELLO");
+    assert_eq!(count.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn snapshot_limits() {
+    let count = AtomicUsize::new(0);
+    let c = Asyncmemo::new_unbounded("test", SnapshotUpperer(&count), 1);
+    for key in &["a", "b", "c"] {
+        c.get(*key).wait().unwrap();
+    }
+    let mut snapshot = Vec::new();
+    assert_eq!(c.save_snapshot(&mut snapshot).unwrap(), 3);
+
+    // The entries are loaded in the order they were used, so the oldest ones are evicted.
+    let loaded = Asyncmemo::with_limits_and_shards("test", SnapshotUpperer(&count), 2, 1000, 1);
+    loaded.load_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert!(!loaded.key_present_in_cache("a"));
+    assert!(loaded.key_present_in_cache("b"));
+    assert!(loaded.key_present_in_cache("c"));
+}
+
+#[test]
+fn snapshot_invalid() {
+    let count = AtomicUsize::new(0);
+    let c = Asyncmemo::new_unbounded("test", SnapshotUpperer(&count), 1);
+    assert!(c.load_snapshot(&b"not a snapshot"[..]).is_err());
+
+    // Entries that the filler can't serialize aren't saved.
+    let c = Asyncmemo::new_unbounded("test", Upperer(&count), 1);
+    c.get("foo").wait().unwrap();
+    let mut snapshot = Vec::new();
+    assert_eq!(c.save_snapshot(&mut snapshot).unwrap(), 0);
+}
+
+#[test]
+fn get_cached_and_insert() {
+    let count = AtomicUsize::new(0);
+    let c = Asyncmemo::new_unbounded("test", Upperer(&count), 1);
+
+    assert_eq!(c.get_cached("foo"), None);
+    assert_eq!(count.load(Ordering::Relaxed), 0);
+
+    assert!(c.insert("foo", "inserted".to_string()));
+    assert_eq!(c.get_cached("foo"), Some("inserted".to_string()));
+    assert_eq!(c.get("foo").wait().unwrap(), "inserted");
+    assert_eq!(count.load(Ordering::Relaxed), 0);
+
+    // Cached results aren't replaced.
+    assert_eq!(c.get("bar").wait().unwrap(), "BAR");
+    assert!(!c.insert("bar", "inserted".to_string()));
+    assert_eq!(c.get_cached("bar"), Some("BAR".to_string()));
+    assert_eq!(count.load(Ordering::Relaxed), 1);
+}
@@ -83,6 +83,16 @@ where
     }
 }
 
+impl<A> Weight for Vec<A>
+where
+    A: Weight,
+{
+    #[inline]
+    fn get_weight(&self) -> usize {
+        mem::size_of::<Self>() + self.iter().map(Weight::get_weight).sum::<usize>()
+    }
+}
+
 impl<A> Weight for Option<A>
 where
     A: Weight,
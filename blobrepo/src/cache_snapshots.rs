@@ -0,0 +1,106 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// The in-memory caches of a repo can be saved to files in a snapshot directory and loaded back
+// when the repo is opened again, so that a restarted server doesn't start with cold caches.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::{Result, ResultExt};
+use slog::Logger;
+
+use blobstore::{Blobstore, CountedBlobstore, MemoizedBlobstore};
+use changesets::CachingChangests;
+use filenodes::CachingFilenodes;
+
+/// A cache whose contents can be saved to a snapshot file and loaded back.
+pub trait SnapshotCache: Send + Sync {
+    fn save_snapshot(&self, path: &Path) -> Result<usize>;
+    fn load_snapshot(&self, path: &Path) -> Result<usize>;
+}
+
+impl<T: Blobstore + Clone> SnapshotCache for CountedBlobstore<MemoizedBlobstore<T>> {
+    fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        self.as_inner().save_snapshot(path)
+    }
+
+    fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        self.as_inner().load_snapshot(path)
+    }
+}
+
+impl SnapshotCache for CachingChangests {
+    fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        CachingChangests::save_snapshot(self, path)
+    }
+
+    fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        CachingChangests::load_snapshot(self, path)
+    }
+}
+
+impl SnapshotCache for CachingFilenodes {
+    fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        CachingFilenodes::save_snapshot(self, path)
+    }
+
+    fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        CachingFilenodes::load_snapshot(self, path)
+    }
+}
+
+/// The caches of a repo, each with the name of its snapshot file in `dir`.
+pub struct CacheSnapshots {
+    logger: Logger,
+    dir: PathBuf,
+    caches: Vec<(&'static str, Arc<SnapshotCache>)>,
+}
+
+impl CacheSnapshots {
+    pub fn new(
+        logger: Logger,
+        dir: PathBuf,
+        caches: Vec<(&'static str, Arc<SnapshotCache>)>,
+    ) -> Self {
+        Self {
+            logger,
+            dir,
+            caches,
+        }
+    }
+
+    /// Load the snapshots that exist. A snapshot that can't be loaded only leaves its cache
+    /// cold, so failures are logged rather than returned.
+    pub fn load(&self) {
+        for &(name, ref cache) in &self.caches {
+            match cache.load_snapshot(&self.dir.join(name)) {
+                Ok(count) => info!(
+                    self.logger,
+                    "loaded {} entries into the {} cache", count, name
+                ),
+                Err(err) => warn!(
+                    self.logger,
+                    "failed to load the snapshot of the {} cache: {}", name, err
+                ),
+            }
+        }
+    }
+
+    /// Save all the caches, replacing the previous snapshots.
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|_| format!("failed to create {}", self.dir.display()))?;
+        for &(name, ref cache) in &self.caches {
+            let count = cache
+                .save_snapshot(&self.dir.join(name))
+                .with_context(|_| format!("failed to save the snapshot of the {} cache", name))?;
+            info!(self.logger, "saved {} entries of the {} cache", count, name);
+        }
+        Ok(())
+    }
+}
@@ -11,7 +11,8 @@ use bincode;
 
 use mercurial_types::{HgBlob, HgBlobHash, HgChangesetId, HgFileNodeId, HgNodeHash, HgParents,
                       MPath, RepoPath, Type};
-use mononoke_types::{ChangesetId, ContentId};
+use mercurial_types::hash::Sha256;
+use mononoke_types::{ChangesetId, ContentChunkId, ContentId};
 
 use HgBlobChangeset;
 
@@ -23,6 +24,9 @@ pub enum StateOpenError {
     Changesets,
     Filenodes,
     BonsaiHgMapping,
+    ObsMarkers,
+    BlobstoreSyncQueue,
+    RedactedBlobs,
 }
 
 impl fmt::Display for StateOpenError {
@@ -36,6 +40,9 @@ impl fmt::Display for StateOpenError {
             Changesets => write!(f, "changesets"),
             Filenodes => write!(f, "filenodes"),
             BonsaiHgMapping => write!(f, "bonsai_hg_mapping"),
+            ObsMarkers => write!(f, "obsmarkers"),
+            BlobstoreSyncQueue => write!(f, "blobstore_sync_queue"),
+            RedactedBlobs => write!(f, "redacted_blobs"),
         }
     }
 }
@@ -60,6 +67,17 @@ pub enum ErrorKind {
     #[fail(display = "Error while deserializing file contents retrieved from key '{}'", _0)]
     FileContentsDeserializeFailed(String),
     #[fail(display = "Content blob missing for id: {}", _0)] ContentBlobMissing(ContentId),
+    #[fail(display = "Content chunk blob missing for id: {}", _0)]
+    ContentChunkMissing(ContentChunkId),
+    #[fail(display = "File {} has {} bytes, more than the limit of {}", _0, _1, _2)]
+    FileTooLarge(HgNodeHash, u64, u64),
+    #[fail(display = "Content chunk {} has {} bytes, expected {}", _0, _1, _2)]
+    ContentChunkSizeMismatch(ContentChunkId, u64, u64),
+    #[fail(display = "LFS object missing for oid: {}", _0)] LfsObjectMissing(Sha256),
+    #[fail(display = "LFS object has sha256 {}, expected {}", _0, _1)]
+    LfsObjectHashMismatch(Sha256, Sha256),
+    #[fail(display = "Error while deserializing LFS alias retrieved from key '{}'", _0)]
+    LfsAliasDeserializeFailed(String),
     #[fail(display = "Uploaded blob is incomplete {:?}", _0)] BadUploadBlob(HgBlob),
     #[fail(display = "HgParents are not in blob store {:?}", _0)] ParentsUnknown(HgParents),
     #[fail(display = "Serialization of node failed {} ({})", _0, _1)]
@@ -6,16 +6,20 @@
 
 //! Plain files, symlinks
 
+use bytes::{Bytes, BytesMut};
 use failure::{Error, FutureFailureErrorExt};
 use futures::future::{self, Future};
-use futures_ext::{BoxFuture, FutureExt};
+use futures::stream::{self, Stream};
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use mercurial::file;
 use mercurial_types::{FileType, HgBlob, HgFileEnvelope, HgFileNodeId, HgManifestId, HgNodeHash,
                       HgParents, MPath, MPathElement};
+use mercurial_types::hash::Sha256;
 use mercurial_types::manifest::{Content, Entry, Manifest, Type};
 use mercurial_types::nodehash::HgEntryId;
-use mononoke_types::{BlobstoreValue, ContentId, FileContents, MononokeId};
+use mononoke_types::{BlobstoreValue, ContentChunk, ContentChunkPointer, ContentId, FileContents,
+                     MononokeId};
 
 use blobstore::Blobstore;
 
@@ -25,6 +29,9 @@ use manifest::{fetch_manifest_envelope, fetch_raw_manifest_bytes, BlobManifest};
 
 use repo::RepoBlobstore;
 
+/// Number of the chunks of chunked contents that are fetched at once.
+const CHUNK_FETCH_BUFFER_SIZE: usize = 4;
+
 #[derive(Clone)]
 pub struct HgBlobEntry {
     blobstore: RepoBlobstore,
@@ -146,9 +153,92 @@ pub fn fetch_file_envelope_opt(
         .from_err()
 }
 
+/// Fetches file contents into memory. Chunked contents are put back together, so the result is
+/// always `FileContents::Bytes`.
 pub fn fetch_file_contents(
     blobstore: &RepoBlobstore,
     content_id: ContentId,
+) -> impl Future<Item = FileContents, Error = Error> {
+    fetch_stored_file_contents(blobstore, content_id).and_then({
+        let blobstore = blobstore.clone();
+        move |contents| match contents {
+            FileContents::Bytes(_) => future::ok(contents).left_future(),
+            FileContents::Chunked(chunked) => {
+                let size = chunked.size() as usize;
+                fetch_chunks(blobstore, chunked.into_chunks())
+                    .fold(BytesMut::with_capacity(size), |mut bytes, chunk| {
+                        bytes.extend_from_slice(&chunk);
+                        Ok::<_, Error>(bytes)
+                    })
+                    .map(|bytes| FileContents::Bytes(bytes.freeze()))
+                    .right_future()
+            }
+        }
+    })
+}
+
+/// Streams file contents, one chunk at a time for chunked contents, so that large contents don't
+/// have to be in memory all at once.
+pub fn fetch_file_contents_stream(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+) -> BoxStream<Bytes, Error> {
+    fetch_stored_file_contents(blobstore, content_id)
+        .map({
+            let blobstore = blobstore.clone();
+            move |contents| match contents {
+                FileContents::Bytes(bytes) => stream::once(Ok(bytes)).boxify(),
+                FileContents::Chunked(chunked) => fetch_chunks(blobstore, chunked.into_chunks()),
+            }
+        })
+        .flatten_stream()
+        .boxify()
+}
+
+fn fetch_chunks(
+    blobstore: RepoBlobstore,
+    chunks: Vec<ContentChunkPointer>,
+) -> BoxStream<Bytes, Error> {
+    stream::iter_ok(chunks)
+        .map(move |chunk| fetch_content_chunk(&blobstore, chunk))
+        .buffered(CHUNK_FETCH_BUFFER_SIZE)
+        .boxify()
+}
+
+fn fetch_content_chunk(
+    blobstore: &RepoBlobstore,
+    chunk: ContentChunkPointer,
+) -> impl Future<Item = Bytes, Error = Error> {
+    let chunk_id = chunk.chunk_id();
+    let blobstore_key = chunk_id.blobstore_key();
+    blobstore
+        .get(blobstore_key.clone())
+        .context("While fetching content chunk blob")
+        .map_err(Error::from)
+        .and_then(move |bytes| {
+            let blobstore_bytes = match bytes {
+                Some(bytes) => bytes,
+                None => bail_err!(ErrorKind::ContentChunkMissing(chunk_id)),
+            };
+            let bytes = ContentChunk::from_blob(blobstore_bytes.into())?.into_bytes();
+            if bytes.len() as u64 != chunk.size() {
+                bail_err!(ErrorKind::ContentChunkSizeMismatch(
+                    chunk_id,
+                    bytes.len() as u64,
+                    chunk.size()
+                ));
+            }
+            Ok(bytes)
+        })
+        .with_context(|_| ErrorKind::FileContentsDeserializeFailed(blobstore_key))
+        .from_err()
+}
+
+/// Fetches file contents as they are stored: only the list of the chunks is fetched for chunked
+/// contents.
+pub fn fetch_stored_file_contents(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
 ) -> impl Future<Item = FileContents, Error = Error> {
     let blobstore_key = content_id.blobstore_key();
     blobstore
@@ -167,6 +257,29 @@ pub fn fetch_file_contents(
         .from_err()
 }
 
+/// The key of the blob that maps the SHA-256 of file contents, which is how LFS refers to them,
+/// to their id.
+pub fn lfs_alias_key(oid: &Sha256) -> String {
+    format!("alias.sha256.{}", oid)
+}
+
+pub fn fetch_lfs_alias(
+    blobstore: &RepoBlobstore,
+    oid: &Sha256,
+) -> impl Future<Item = Option<ContentId>, Error = Error> {
+    let blobstore_key = lfs_alias_key(oid);
+    blobstore
+        .get(blobstore_key.clone())
+        .context("While fetching LFS alias blob")
+        .map_err(Error::from)
+        .and_then(|bytes| match bytes {
+            Some(bytes) => Ok(Some(ContentId::from_bytes(bytes.as_bytes())?)),
+            None => Ok(None),
+        })
+        .with_context(|_| ErrorKind::LfsAliasDeserializeFailed(blobstore_key))
+        .from_err()
+}
+
 impl HgBlobEntry {
     pub fn new(blobstore: RepoBlobstore, name: MPathElement, nodeid: HgNodeHash, ty: Type) -> Self {
         Self {
@@ -32,6 +32,7 @@ extern crate futures_stats;
 
 extern crate ascii;
 extern crate blobstore;
+extern crate blobstore_sync_queue;
 extern crate bonsai_hg_mapping;
 extern crate bookmarks;
 extern crate changesets;
@@ -46,6 +47,9 @@ extern crate manifoldblob;
 extern crate mercurial;
 extern crate mercurial_types;
 extern crate mononoke_types;
+extern crate multiplexedblob;
+extern crate obsmarkers;
+extern crate redacted_blobs;
 extern crate rocksblob;
 extern crate rocksdb;
 extern crate scuba_ext;
@@ -59,6 +63,7 @@ extern crate fixtures;
 extern crate mercurial_types_mocks;
 
 mod bonsai_generation;
+mod cache_snapshots;
 mod changeset;
 mod errors;
 mod file;
@@ -71,10 +76,11 @@ mod utils;
 pub use errors::*;
 
 pub use changeset::{HgBlobChangeset, HgChangesetContent};
-pub use file::HgBlobEntry;
+pub use file::{lfs_alias_key, HgBlobEntry};
 pub use manifest::BlobManifest;
-pub use repo::{save_bonsai_changeset, BlobRepo, ChangesetMetadata, ContentBlobInfo,
-               ContentBlobMeta, CreateChangeset, ManifoldArgs, UploadHgFileContents,
+pub use repo::{save_bonsai_changeset, BlobRepo, ChangesetMetadata, ComponentBlobstoreArgs,
+               ComponentBlobstoreType, CompressionArgs, ContentBlobInfo, ContentBlobMeta,
+               CreateChangeset, DiskCacheArgs, ManifoldArgs, RepoBlobstore, UploadHgFileContents,
                UploadHgFileEntry, UploadHgNodeHash, UploadHgTreeEntry};
 pub use repo_commit::ChangesetHandle;
 // TODO: This is exported for testing - is this the right place for it?
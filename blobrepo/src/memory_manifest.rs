@@ -808,6 +808,7 @@ impl MemoryManifestEntry {
                         p1: p1.clone(),
                         p2: p2.clone(),
                         path: path,
+                        flags: 0,
                     };
                     let (_, upload_future) = try_boxfuture!(upload_entry.upload(&repo));
                     upload_future
@@ -4,10 +4,10 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::mem;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::usize;
 
@@ -28,35 +28,46 @@ use uuid::Uuid;
 
 use super::changeset::HgChangesetContent;
 use super::utils::{IncompleteFilenodeInfo, IncompleteFilenodes};
-use blobstore::{new_memcache_blobstore, Blobstore, EagerMemblob, MemWritesBlobstore,
-                MemoizedBlobstore, PrefixBlobstore};
+use blobstore::{new_disk_cache_blobstore, new_memcache_blobstore, Blobstore, BlobstoreId,
+                CompressedBlobstore, EagerMemblob, EncryptedBlobstore, Keyring,
+                MemWritesBlobstore, MemoizedBlobstore, PrefixBlobstore, RedactedBlobstore,
+                RedactedKeys, DEFAULT_COMPRESSION_LEVEL, DEFAULT_COMPRESSION_THRESHOLD};
+use blobstore_sync_queue::SqliteBlobstoreSyncQueue;
 use bonsai_generation::{create_bonsai_changeset_object, save_bonsai_changeset_object};
-use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, CachingBonsaiHgMapping,
-                        MysqlBonsaiHgMapping, SqliteBonsaiHgMapping};
-use bookmarks::{self, Bookmark, BookmarkPrefix, Bookmarks};
-use changesets::{CachingChangests, ChangesetEntry, ChangesetInsert, Changesets, MysqlChangesets,
-                 SqliteChangesets};
+use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, BonsaiOrHgChangesetId,
+                        CachingBonsaiHgMapping, MemWritesBonsaiHgMapping, MysqlBonsaiHgMapping,
+                        SqliteBonsaiHgMapping};
+use bookmarks::{self, Bookmark, BookmarkPrefix, Bookmarks, MemWritesBookmarks};
+use changesets::{CachingChangests, ChangesetEntry, ChangesetInsert, Changesets,
+                 MemWritesChangesets, MysqlChangesets, SqliteChangesets};
 use dbbookmarks::{MysqlDbBookmarks, SqliteDbBookmarks};
 use delayblob::DelayBlob;
 use dieselfilenodes::{MysqlFilenodes, SqliteFilenodes, DEFAULT_INSERT_CHUNK_SIZE};
 use fileblob::Fileblob;
-use filenodes::{CachingFilenodes, FilenodeInfo, Filenodes};
+use filenodes::{CachingFilenodes, FilenodeInfo, Filenodes, MemWritesFilenodes};
 use manifoldblob::ManifoldBlob;
 use mercurial::file::File;
 use mercurial_types::{Changeset, Entry, HgBlob, HgBlobNode, HgChangesetId, HgFileEnvelopeMut,
-                      HgFileNodeId, HgManifestEnvelopeMut, HgManifestId, HgNodeHash, HgParents,
-                      Manifest, RepoPath, RepositoryId, Type};
+                      HgFileNodeId, HgManifestEnvelopeMut, HgManifestId, HgNodeHash, HgObsMarker,
+                      HgParents, Manifest, RepoPath, RepositoryId, Type};
+use mercurial_types::hash::{Sha256, Sha256Context};
 use mercurial_types::manifest::Content;
-use mononoke_types::{Blob, BlobstoreValue, BonsaiChangeset, ChangesetId, ContentId, DateTime,
-                     FileChange, FileContents, FileType, Generation, MPath, MPathElement,
-                     MononokeId};
+use mononoke_types::{Blob, BlobstoreBytes, BlobstoreValue, BonsaiChangeset, ChangesetId,
+                     ChunkedFileContentsBuilder, ContentId, DateTime, FileChange, FileContents,
+                     FileType, Generation, MPath, MPathElement, MononokeId, DEFAULT_CHUNK_SIZE};
+use multiplexedblob::MultiplexedBlobstore;
+use obsmarkers::{MemWritesObsMarkers, MysqlObsMarkers, ObsMarkers, SqliteObsMarkers};
+use redacted_blobs::{MysqlRedactedBlobs, RedactedBlobs, SqliteRedactedBlobs};
 use rocksblob::Rocksblob;
 use rocksdb;
 
 use BlobManifest;
 use HgBlobChangeset;
+use cache_snapshots::{CacheSnapshots, SnapshotCache};
 use errors::*;
-use file::{fetch_file_content_and_renames_from_blobstore, fetch_raw_filenode_bytes, HgBlobEntry};
+use file::{fetch_file_content_and_renames_from_blobstore, fetch_file_contents,
+           fetch_file_contents_stream, fetch_file_envelope, fetch_lfs_alias,
+           fetch_raw_filenode_bytes, fetch_stored_file_contents, lfs_alias_key, HgBlobEntry};
 use memory_manifest::MemoryRootManifest;
 use repo_commit::*;
 
@@ -64,12 +75,16 @@ define_stats! {
     prefix = "mononoke.blobrepo";
     get_bonsai_changeset: timeseries(RATE, SUM),
     get_file_content: timeseries(RATE, SUM),
+    get_file_content_stream: timeseries(RATE, SUM),
+    get_file_content_bounded: timeseries(RATE, SUM),
+    get_file_flags: timeseries(RATE, SUM),
     get_raw_hg_content: timeseries(RATE, SUM),
     get_parents: timeseries(RATE, SUM),
     get_file_copy: timeseries(RATE, SUM),
     get_changesets: timeseries(RATE, SUM),
     get_heads: timeseries(RATE, SUM),
     changeset_exists: timeseries(RATE, SUM),
+    many_changesets_exist: timeseries(RATE, SUM),
     get_changeset_parents: timeseries(RATE, SUM),
     get_changeset_by_changesetid: timeseries(RATE, SUM),
     get_hg_file_copy_from_blobstore: timeseries(RATE, SUM),
@@ -78,11 +93,25 @@ define_stats! {
     get_bookmark: timeseries(RATE, SUM),
     get_bookmarks: timeseries(RATE, SUM),
     get_bonsai_from_hg: timeseries(RATE, SUM),
+    get_hg_from_bonsai: timeseries(RATE, SUM),
+    get_all_changesets: timeseries(RATE, SUM),
+    get_changeset_children: timeseries(RATE, SUM),
+    backfill_changeset_children: timeseries(RATE, SUM),
+    get_file_content_id: timeseries(RATE, SUM),
+    get_stored_file_contents: timeseries(RATE, SUM),
+    get_lfs_content_id: timeseries(RATE, SUM),
+    get_lfs_object_stream: timeseries(RATE, SUM),
     update_bookmark_transaction: timeseries(RATE, SUM),
+    add_obsmarkers: timeseries(RATE, SUM),
+    get_all_obsmarkers: timeseries(RATE, SUM),
+    get_relevant_obsmarkers: timeseries(RATE, SUM),
     get_linknode: timeseries(RATE, SUM),
     get_all_filenodes: timeseries(RATE, SUM),
+    get_all_filenodes_many: timeseries(RATE, SUM),
     get_generation_number: timeseries(RATE, SUM),
     upload_blob: timeseries(RATE, SUM),
+    upload_file_contents_stream: timeseries(RATE, SUM),
+    upload_lfs_object_stream: timeseries(RATE, SUM),
     upload_hg_file_entry: timeseries(RATE, SUM),
     upload_hg_tree_entry: timeseries(RATE, SUM),
     create_changeset: timeseries(RATE, SUM),
@@ -122,19 +151,96 @@ pub struct ManifoldArgs {
     /// It limits the number of simultaneous requests that can be sent from a single io thread
     /// If not set then default value is used.
     pub max_concurrent_requests_per_io_thread: usize,
+    /// Directory with snapshots of the in-memory caches. If set, the caches are loaded from it
+    /// when the repo is opened, and saved to it by `BlobRepo::save_cache_snapshots`.
+    pub cache_snapshot_dir: Option<PathBuf>,
+}
+
+/// Arguments for setting up a cache on local disk in front of the blobstore of a local repo,
+/// which is useful when the blobstore itself is slow, e.g. on a network filesystem.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiskCacheArgs {
+    /// Directory of the cache. The cached blobs stay there across restarts.
+    pub path: PathBuf,
+    /// How many bytes of blobs the cache keeps.
+    pub max_size: u64,
+}
+
+/// Arguments for compressing the blobs of a local repo. Blobs are stored as they are without
+/// them, so repos whose blobs were written compressed must always be opened with them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompressionArgs {
+    /// Blobs smaller than this many bytes are stored uncompressed.
+    pub threshold: usize,
+    /// The zstd compression level.
+    pub level: i32,
+}
+
+impl Default for CompressionArgs {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            level: DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+/// Kinds of local blobstores that can be part of a multiplexed blobstore.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ComponentBlobstoreType {
+    /// Every blob is a file in a directory.
+    Files,
+    /// The blobs are stored in a RocksDb database. Only one process can open it, so the blobstore
+    /// healer can't heal multiplexed blobstores with such components while the server runs.
+    Rocks,
+}
+
+/// Arguments for setting up one of the blobstores of a multiplexed blobstore.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComponentBlobstoreArgs {
+    /// Id of the blobstore. It is recorded in the sync queue for the writes the blobstore missed,
+    /// so it must not change once the blobstore is in use.
+    pub blobstore_id: BlobstoreId,
+    /// Kind of the blobstore.
+    pub blobstore_type: ComponentBlobstoreType,
+    /// Directory with the data of the blobstore.
+    pub path: PathBuf,
+}
+
+impl ComponentBlobstoreArgs {
+    /// Open the blobstore, creating it if it doesn't exist.
+    pub fn open(&self) -> Result<Arc<Blobstore>> {
+        let blobstore: Arc<Blobstore> = match self.blobstore_type {
+            ComponentBlobstoreType::Files => Arc::new(Fileblob::create(&self.path)?),
+            ComponentBlobstoreType::Rocks => {
+                let options = rocksdb::Options::new().create_if_missing(true);
+                Arc::new(Rocksblob::open_with_options(&self.path, options)?)
+            }
+        };
+        Ok(blobstore)
+    }
 }
 
 pub struct BlobRepo {
     logger: Logger,
     blobstore: RepoBlobstore,
+    // The same blobs, without the redaction. See `unredacted`.
+    unredacted_blobstore: RepoBlobstore,
     bookmarks: Arc<Bookmarks>,
     filenodes: Arc<Filenodes>,
     changesets: Arc<Changesets>,
     bonsai_hg_mapping: Arc<BonsaiHgMapping>,
+    obsmarkers: Arc<ObsMarkers>,
+    redacted_blobs: Arc<RedactedBlobs>,
     repoid: RepositoryId,
+    // The caches to save to the snapshot directory, if the repo has one.
+    cache_snapshots: Option<Arc<CacheSnapshots>>,
 }
 
 impl BlobRepo {
+    /// The blobs listed in `redacted_blobs` can't be read from the repo. The list is loaded once,
+    /// when the first blob is read, so changes to it are only seen by repos created after them;
+    /// servers have to be restarted to pick them up.
     pub fn new(
         logger: Logger,
         bookmarks: Arc<Bookmarks>,
@@ -142,34 +248,72 @@ impl BlobRepo {
         filenodes: Arc<Filenodes>,
         changesets: Arc<Changesets>,
         bonsai_hg_mapping: Arc<BonsaiHgMapping>,
+        obsmarkers: Arc<ObsMarkers>,
+        redacted_blobs: Arc<RedactedBlobs>,
         repoid: RepositoryId,
     ) -> Self {
+        let redacted = redact(blobstore.clone(), &redacted_blobs, repoid);
+
         BlobRepo {
             logger,
             bookmarks,
-            blobstore: PrefixBlobstore::new(blobstore, repoid.prefix()),
+            blobstore: PrefixBlobstore::new(redacted, repoid.prefix()),
+            unredacted_blobstore: PrefixBlobstore::new(blobstore, repoid.prefix()),
             filenodes,
             changesets,
             bonsai_hg_mapping,
+            obsmarkers,
+            redacted_blobs,
             repoid,
+            cache_snapshots: None,
         }
     }
 
     /// Most local use cases should use new_rocksdb instead. This is only meant for test
     /// fixtures.
-    pub fn new_files(logger: Logger, path: &Path, repoid: RepositoryId) -> Result<Self> {
+    pub fn new_files(
+        logger: Logger,
+        path: &Path,
+        repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<Self> {
         let blobstore = Fileblob::create(path.join("blobs"))
             .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(
+            logger,
+            path,
+            Arc::new(blobstore),
+            repoid,
+            keyring,
+            disk_cache,
+            compression,
+        )
     }
 
-    pub fn new_rocksdb(logger: Logger, path: &Path, repoid: RepositoryId) -> Result<Self> {
+    pub fn new_rocksdb(
+        logger: Logger,
+        path: &Path,
+        repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<Self> {
         let options = rocksdb::Options::new().create_if_missing(true);
         let blobstore = Rocksblob::open_with_options(path.join("blobs"), options)
             .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(
+            logger,
+            path,
+            Arc::new(blobstore),
+            repoid,
+            keyring,
+            disk_cache,
+            compression,
+        )
     }
 
     pub fn new_rocksdb_delayed<F>(
@@ -197,15 +341,60 @@ impl BlobRepo {
             assert_present_roundtrips,
         );
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(logger, path, Arc::new(blobstore), repoid, None, None, None)
+    }
+
+    /// Create a BlobRepo whose blobs are stored in all of the `blobstores`. A write succeeds once
+    /// `write_quorum` of them stored the blob; the writes the other blobstores missed are recorded
+    /// in a sync queue in `path`, where the rest of the local state is stored too.
+    pub fn new_multiplexed(
+        logger: Logger,
+        path: &Path,
+        blobstores: &[ComponentBlobstoreArgs],
+        write_quorum: usize,
+        repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<Self> {
+        let components = blobstores
+            .iter()
+            .map(|args| Ok((args.blobstore_id, args.open()?)))
+            .collect::<Result<Vec<_>>>()
+            .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+
+        let queue = SqliteBlobstoreSyncQueue::open_or_create(
+            path.join("blobstore_sync_queue").to_string_lossy(),
+        ).context(ErrorKind::StateOpen(StateOpenError::BlobstoreSyncQueue))?;
+
+        let blobstore =
+            MultiplexedBlobstore::new(repoid, components, write_quorum, Arc::new(queue))
+                .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+
+        Self::new_local(
+            logger,
+            path,
+            Arc::new(blobstore),
+            repoid,
+            keyring,
+            disk_cache,
+            compression,
+        )
     }
 
-    /// Create a new BlobRepo with purely local state.
-    fn new_local(
+    /// Create a new BlobRepo with purely local state, storing blobs in `blobstore`. Tools that
+    /// need direct access to the blobstore of a local repo open it themselves and use this.
+    /// If a `keyring` is given, the blobs are encrypted with it. If `disk_cache` is given, the
+    /// blobs read from `blobstore` are cached on local disk. If `compression` is given, the large
+    /// blobs are compressed before they are encrypted.
+    pub fn new_local(
         logger: Logger,
         path: &Path,
         blobstore: Arc<Blobstore>,
         repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
     ) -> Result<Self> {
         let bookmarks = SqliteDbBookmarks::open_or_create(path.join("books").to_string_lossy())
             .context(ErrorKind::StateOpen(StateOpenError::Bookmarks))?;
@@ -219,6 +408,34 @@ impl BlobRepo {
         let bonsai_hg_mapping =
             SqliteBonsaiHgMapping::open_or_create(path.join("bonsai_hg_mapping").to_string_lossy())
                 .context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?;
+        let obsmarkers = SqliteObsMarkers::open_or_create(path.join("obsmarkers").to_string_lossy())
+            .context(ErrorKind::StateOpen(StateOpenError::ObsMarkers))?;
+        let redacted_blobs =
+            SqliteRedactedBlobs::open_or_create(path.join("redacted_blobs").to_string_lossy())
+                .context(ErrorKind::StateOpen(StateOpenError::RedactedBlobs))?;
+
+        // The cache stores the blobs as they are in the blobstore, so that encrypted blobs stay
+        // encrypted on the local disk too.
+        let blobstore: Arc<Blobstore> = match disk_cache {
+            Some(args) => {
+                let blobstore = new_disk_cache_blobstore(blobstore, &args.path, args.max_size)
+                    .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+                Arc::new(blobstore)
+            }
+            None => blobstore,
+        };
+        let blobstore: Arc<Blobstore> = match keyring {
+            Some(keyring) => {
+                let blobstore = EncryptedBlobstore::new(blobstore, keyring)
+                    .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+                Arc::new(blobstore)
+            }
+            None => blobstore,
+        };
+        let blobstore: Arc<Blobstore> = match compression {
+            Some(args) => Arc::new(CompressedBlobstore::new(blobstore, args.threshold, args.level)),
+            None => blobstore,
+        };
 
         Ok(Self::new(
             logger,
@@ -227,6 +444,8 @@ impl BlobRepo {
             Arc::new(filenodes),
             Arc::new(changesets),
             Arc::new(bonsai_hg_mapping),
+            Arc::new(obsmarkers),
+            Arc::new(redacted_blobs),
             repoid,
         ))
     }
@@ -247,6 +466,10 @@ impl BlobRepo {
                 .context(ErrorKind::StateOpen(StateOpenError::Changesets))?),
             Arc::new(SqliteBonsaiHgMapping::in_memory()
                 .context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?),
+            Arc::new(SqliteObsMarkers::in_memory()
+                .context(ErrorKind::StateOpen(StateOpenError::ObsMarkers))?),
+            Arc::new(SqliteRedactedBlobs::in_memory()
+                .context(ErrorKind::StateOpen(StateOpenError::RedactedBlobs))?),
             RepositoryId::new(0),
         ))
     }
@@ -268,20 +491,27 @@ impl BlobRepo {
             args.max_concurrent_requests_per_io_thread,
         );
         let blobstore = new_memcache_blobstore(blobstore, "manifold", args.bucket.as_ref())?;
-        let blobstore = MemoizedBlobstore::new(blobstore, usize::MAX, args.blobstore_cache_size);
+        let blobstore = Arc::new(MemoizedBlobstore::new(
+            blobstore,
+            usize::MAX,
+            args.blobstore_cache_size,
+        ));
 
         let filenodes = MysqlFilenodes::open(&args.db_address, DEFAULT_INSERT_CHUNK_SIZE)
             .context(ErrorKind::StateOpen(StateOpenError::Filenodes))?;
-        let filenodes = CachingFilenodes::new(
+        let filenodes = Arc::new(CachingFilenodes::new(
             Arc::new(filenodes),
             args.filenodes_cache_size,
             "dieselfilenodes",
             &args.db_address,
-        );
+        ));
 
         let changesets = MysqlChangesets::open(&args.db_address)
             .context(ErrorKind::StateOpen(StateOpenError::Changesets))?;
-        let changesets = CachingChangests::new(Arc::new(changesets), args.changesets_cache_size);
+        let changesets = Arc::new(CachingChangests::new(
+            Arc::new(changesets),
+            args.changesets_cache_size,
+        ));
 
         let bonsai_hg_mapping = MysqlBonsaiHgMapping::open(&args.db_address)
             .context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?;
@@ -290,49 +520,99 @@ impl BlobRepo {
             args.bonsai_hg_mapping_cache_size,
         );
 
-        Ok(Self::new(
-            logger,
-            Arc::new(bookmarks),
-            Arc::new(blobstore),
-            Arc::new(filenodes),
-            Arc::new(changesets),
-            Arc::new(bonsai_hg_mapping),
-            repoid,
-        ))
+        let obsmarkers = MysqlObsMarkers::open(&args.db_address)
+            .context(ErrorKind::StateOpen(StateOpenError::ObsMarkers))?;
+
+        let redacted_blobs = MysqlRedactedBlobs::open(&args.db_address)
+            .context(ErrorKind::StateOpen(StateOpenError::RedactedBlobs))?;
+
+        let cache_snapshots = args.cache_snapshot_dir.as_ref().map(|dir| {
+            let cache_snapshots = CacheSnapshots::new(
+                logger.clone(),
+                dir.clone(),
+                vec![
+                    ("blobstore", blobstore.clone() as Arc<SnapshotCache>),
+                    ("filenodes", filenodes.clone()),
+                    ("changesets", changesets.clone()),
+                ],
+            );
+            cache_snapshots.load();
+            Arc::new(cache_snapshots)
+        });
+
+        Ok(BlobRepo {
+            cache_snapshots,
+            ..Self::new(
+                logger,
+                Arc::new(bookmarks),
+                blobstore,
+                filenodes,
+                changesets,
+                Arc::new(bonsai_hg_mapping),
+                Arc::new(obsmarkers),
+                Arc::new(redacted_blobs),
+                repoid,
+            )
+        })
+    }
+
+    /// Whether the repo has a directory to save the snapshots of its caches to.
+    pub fn has_cache_snapshots(&self) -> bool {
+        self.cache_snapshots.is_some()
     }
 
-    /// Convert this BlobRepo instance into one that only does writes in memory.
+    /// Save the in-memory caches to the snapshot directory of the repo, so that they are loaded
+    /// when it's opened again. Does nothing if the repo has no snapshot directory.
+    pub fn save_cache_snapshots(&self) -> Result<()> {
+        match self.cache_snapshots {
+            Some(ref cache_snapshots) => cache_snapshots.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Convert this BlobRepo instance into one that only does writes in memory. Reads go to the
+    /// underlying stores for anything that wasn't written through the new instance.
     ///
     /// ------------
     /// IMPORTANT!!!
     /// ------------
-    /// Currently this applies to the blobstore *ONLY*. A future improvement would be to also
-    /// do database writes in-memory.
+    /// This applies to the blobstore, bookmarks, changesets, filenodes, bonsai-hg mapping and
+    /// obsmarkers *ONLY*. Redaction list changes are still written to the database.
     #[allow(non_snake_case)]
     pub fn in_memory_writes_READ_DOC_COMMENT(self) -> BlobRepo {
         let BlobRepo {
             logger,
             bookmarks,
-            blobstore,
+            blobstore: _,
+            unredacted_blobstore,
             filenodes,
             changesets,
             bonsai_hg_mapping,
+            obsmarkers,
+            redacted_blobs,
             repoid,
+            cache_snapshots,
         } = self;
 
-        // Drop the PrefixBlobstore (it will be wrapped up in one again by BlobRepo::new)
-        let blobstore = blobstore.into_inner();
-        let blobstore = Arc::new(MemWritesBlobstore::new(blobstore));
+        // Drop the PrefixBlobstore and put it back on top of the MemWritesBlobstore, and redact
+        // the blobs above the MemWritesBlobstore so that both views see the writes.
+        let blobstore = unredacted_blobstore.into_inner();
+        let blobstore: Arc<Blobstore> = Arc::new(MemWritesBlobstore::new(blobstore));
+        let redacted = redact(blobstore.clone(), &redacted_blobs, repoid);
 
-        BlobRepo::new(
+        BlobRepo {
             logger,
-            bookmarks,
-            blobstore,
-            filenodes,
-            changesets,
-            bonsai_hg_mapping,
+            bookmarks: Arc::new(MemWritesBookmarks::new(bookmarks)),
+            blobstore: PrefixBlobstore::new(redacted, repoid.prefix()),
+            unredacted_blobstore: PrefixBlobstore::new(blobstore, repoid.prefix()),
+            filenodes: Arc::new(MemWritesFilenodes::new(filenodes)),
+            changesets: Arc::new(MemWritesChangesets::new(changesets)),
+            bonsai_hg_mapping: Arc::new(MemWritesBonsaiHgMapping::new(bonsai_hg_mapping)),
+            obsmarkers: Arc::new(MemWritesObsMarkers::new(obsmarkers)),
+            redacted_blobs,
             repoid,
-        )
+            cache_snapshots,
+        }
     }
 
     fn fetch<K>(&self, key: &K) -> impl Future<Item = K::Value, Error = Error> + Send
@@ -384,6 +664,65 @@ impl BlobRepo {
             .boxify()
     }
 
+    /// Like `get_file_content`, but the contents of chunked files are returned one chunk at a
+    /// time instead of all at once.
+    pub fn get_file_content_stream(&self, key: &HgNodeHash) -> BoxStream<Bytes, Error> {
+        STATS::get_file_content_stream.add_value(1);
+        let blobstore = self.blobstore.clone();
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(move |envelope| fetch_file_contents_stream(&blobstore, *envelope.content_id()))
+            .flatten_stream()
+            .boxify()
+    }
+
+    /// Like `get_file_content`, but fails with `FileTooLarge` instead of fetching contents of
+    /// more than `max_size` bytes. This is for the callers that need whole contents in memory
+    /// and can't use `get_file_content_stream`.
+    pub fn get_file_content_bounded(
+        &self,
+        key: &HgNodeHash,
+        max_size: u64,
+    ) -> BoxFuture<FileContents, Error> {
+        STATS::get_file_content_bounded.add_value(1);
+        let key = *key;
+        let blobstore = self.blobstore.clone();
+        fetch_file_envelope(&self.blobstore, key)
+            .and_then(move |envelope| {
+                let size = envelope.content_size();
+                if size > max_size {
+                    let err = ErrorKind::FileTooLarge(key, size, max_size).into();
+                    return future::err(err).left_future();
+                }
+                fetch_file_contents(&blobstore, *envelope.content_id()).right_future()
+            })
+            .boxify()
+    }
+
+    /// The revlog flags the file node was pushed with.
+    pub fn get_file_flags(&self, key: &HgNodeHash) -> BoxFuture<u16, Error> {
+        STATS::get_file_flags.add_value(1);
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(|envelope| envelope.flags())
+            .boxify()
+    }
+
+    /// The id of the contents of the file node, without the Mercurial copy metadata.
+    pub fn get_file_content_id(&self, key: &HgNodeHash) -> BoxFuture<ContentId, Error> {
+        STATS::get_file_content_id.add_value(1);
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(|envelope| *envelope.content_id())
+            .boxify()
+    }
+
+    /// File contents as they are stored: for chunked contents, only the list of the chunks.
+    pub fn get_stored_file_contents(
+        &self,
+        content_id: ContentId,
+    ) -> BoxFuture<FileContents, Error> {
+        STATS::get_stored_file_contents.add_value(1);
+        fetch_stored_file_contents(&self.blobstore, content_id).boxify()
+    }
+
     // TODO: (rain1) T30456231 It should be possible in principle to make the return type a wrapper
     // around a Chain, but it isn't because of API deficiencies in bytes::Buf. See D8412210.
 
@@ -486,6 +825,44 @@ impl BlobRepo {
             .boxify()
     }
 
+    /// Batched version of `changeset_exists`, with the results in the order of `changesetids`.
+    pub fn many_changesets_exist(
+        &self,
+        changesetids: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<bool>, Error> {
+        STATS::many_changesets_exist.add_value(1);
+        let repo = self.clone();
+        let repoid = self.repoid.clone();
+        let hg_ids = changesetids
+            .iter()
+            .map(|id| BonsaiOrHgChangesetId::Hg(*id))
+            .collect();
+
+        self.bonsai_hg_mapping
+            .get_many(repoid, hg_ids)
+            .and_then(move |entries| {
+                let bonsai_ids = entries.iter().map(|entry| entry.bcs_id).collect();
+                let bonsai_from_hg: HashMap<_, _> = entries
+                    .into_iter()
+                    .map(|entry| (entry.hg_cs_id, entry.bcs_id))
+                    .collect();
+                repo.changesets
+                    .get_many(repoid, bonsai_ids)
+                    .map(move |found| {
+                        let found: HashSet<_> =
+                            found.into_iter().map(|entry| entry.cs_id).collect();
+                        changesetids
+                            .iter()
+                            .map(|id| match bonsai_from_hg.get(id) {
+                                Some(bonsai) => found.contains(bonsai),
+                                None => false,
+                            })
+                            .collect()
+                    })
+            })
+            .boxify()
+    }
+
     // TODO(stash): make it accept ChangesetId
     pub fn get_changeset_parents(
         &self,
@@ -510,6 +887,45 @@ impl BlobRepo {
             .boxify()
     }
 
+    /// The changesets that have this one as a parent. Changesets added before children were
+    /// recorded are missing until `backfill_changeset_children` is run for them.
+    pub fn get_changeset_children(
+        &self,
+        changesetid: &HgChangesetId,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+        STATS::get_changeset_children.add_value(1);
+        let changesetid = *changesetid;
+        let repo = self.clone();
+        let repoid = self.repoid.clone();
+
+        self.get_bonsai_from_hg(&changesetid)
+            .and_then(move |maybebonsai| {
+                maybebonsai.ok_or(ErrorKind::BonsaiMappingNotFound(changesetid).into())
+            })
+            .and_then({
+                cloned!(repo);
+                move |bonsai| repo.changesets.get_children(repoid, bonsai)
+            })
+            .and_then(move |bonsai_children| {
+                future::join_all(
+                    bonsai_children.into_iter().map(move |bonsai_child| {
+                        repo.get_hg_from_bonsai_changeset(bonsai_child)
+                    }),
+                )
+            })
+            .boxify()
+    }
+
+    /// Record the bonsai changesets as children of their parents, for the changesets added
+    /// before children were recorded. Returns the number of changesets found.
+    pub fn backfill_changeset_children(
+        &self,
+        bonsai_cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<usize, Error> {
+        STATS::backfill_changeset_children.add_value(1);
+        self.changesets.backfill_children(self.repoid, bonsai_cs_ids)
+    }
+
     fn get_bonsai_cs_entry_or_fail(
         &self,
         changesetid: HgChangesetId,
@@ -576,6 +992,26 @@ impl BlobRepo {
         self.bookmarks.create_transaction(&self.repoid)
     }
 
+    pub fn add_obsmarkers(&self, markers: Vec<HgObsMarker>) -> BoxFuture<(), Error> {
+        STATS::add_obsmarkers.add_value(1);
+        self.obsmarkers.add(self.repoid, markers)
+    }
+
+    pub fn get_all_obsmarkers(&self) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        STATS::get_all_obsmarkers.add_value(1);
+        self.obsmarkers.get_all(self.repoid)
+    }
+
+    /// Get the obsolescence markers that have any of `changesets` as a predecessor, successor
+    /// or parent.
+    pub fn get_relevant_obsmarkers(
+        &self,
+        changesets: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        STATS::get_relevant_obsmarkers.add_value(1);
+        self.obsmarkers.get_relevant(self.repoid, changesets)
+    }
+
     pub fn get_linknode(
         &self,
         path: RepoPath,
@@ -600,6 +1036,15 @@ impl BlobRepo {
         self.filenodes.get_all_filenodes(&path, &self.repoid)
     }
 
+    /// Batched version of `get_all_filenodes`. Every path is in the result.
+    pub fn get_all_filenodes_many(
+        &self,
+        paths: Vec<RepoPath>,
+    ) -> BoxFuture<HashMap<RepoPath, Vec<FilenodeInfo>>, Error> {
+        STATS::get_all_filenodes_many.add_value(1);
+        self.filenodes.get_all_filenodes_many(paths, &self.repoid)
+    }
+
     pub fn get_bonsai_from_hg(
         &self,
         hg_cs_id: &HgChangesetId,
@@ -609,6 +1054,22 @@ impl BlobRepo {
             .get_bonsai_from_hg(self.repoid, *hg_cs_id)
     }
 
+    /// Look up the hg changeset of a bonsai changeset, without generating it if it's missing.
+    pub fn get_hg_from_bonsai(
+        &self,
+        bonsai_cs_id: ChangesetId,
+    ) -> BoxFuture<Option<HgChangesetId>, Error> {
+        STATS::get_hg_from_bonsai.add_value(1);
+        self.bonsai_hg_mapping
+            .get_hg_from_bonsai(self.repoid, bonsai_cs_id)
+    }
+
+    /// Ids of all the bonsai changesets of the repo, for tools that need to walk all of it.
+    pub fn get_all_changesets(&self) -> BoxFuture<Vec<ChangesetId>, Error> {
+        STATS::get_all_changesets.add_value(1);
+        self.changesets.get_all_ids(self.repoid)
+    }
+
     pub fn get_bonsai_changeset(
         &self,
         bonsai_cs_id: ChangesetId,
@@ -675,6 +1136,139 @@ impl BlobRepo {
             })
     }
 
+    /// Stores file contents that arrive as a stream of `size` bytes in total, without having all
+    /// of them in memory: the contents are split into chunks, which are uploaded as soon as they
+    /// are complete. Returns the id the contents would have if they were stored in a single blob.
+    pub fn upload_file_contents_stream<S>(
+        &self,
+        size: u64,
+        contents: S,
+    ) -> BoxFuture<ContentId, Error>
+    where
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+    {
+        STATS::upload_file_contents_stream.add_value(1);
+        let builder = ChunkedFileContentsBuilder::new(size, DEFAULT_CHUNK_SIZE);
+        contents
+            .fold(builder, {
+                let repo = self.clone();
+                move |mut builder, bytes| {
+                    let uploads: Vec<_> = builder
+                        .write(bytes)
+                        .into_iter()
+                        .map(|blob| repo.upload_blob(blob))
+                        .collect();
+                    future::join_all(uploads).map(move |_| builder)
+                }
+            })
+            .and_then(|builder| builder.finish())
+            .and_then({
+                let repo = self.clone();
+                move |(last, contents)| {
+                    let last_upload = match last {
+                        Some(blob) => repo.upload_blob(blob).map(|_| ()).left_future(),
+                        None => future::ok(()).right_future(),
+                    };
+                    // The contents are uploaded last, so that they never refer to missing chunks.
+                    let blob = contents.into_blob();
+                    last_upload.and_then(move |()| repo.upload_blob(blob))
+                }
+            })
+            .boxify()
+    }
+
+    /// The id of the file contents with SHA-256 `oid`, if they were stored as an LFS object.
+    pub fn get_lfs_content_id(&self, oid: &Sha256) -> BoxFuture<Option<ContentId>, Error> {
+        STATS::get_lfs_content_id.add_value(1);
+        fetch_lfs_alias(&self.blobstore, oid).boxify()
+    }
+
+    /// Streams the contents of an LFS object, one chunk at a time.
+    pub fn get_lfs_object_stream(&self, oid: &Sha256) -> BoxStream<Bytes, Error> {
+        STATS::get_lfs_object_stream.add_value(1);
+        let oid = *oid;
+        let blobstore = self.blobstore.clone();
+        fetch_lfs_alias(&self.blobstore, &oid)
+            .and_then(move |content_id| content_id.ok_or(ErrorKind::LfsObjectMissing(oid).into()))
+            .map(move |content_id| fetch_file_contents_stream(&blobstore, content_id))
+            .flatten_stream()
+            .boxify()
+    }
+
+    /// Stores an LFS object as file contents, so that it is chunked like any other large file,
+    /// and makes it available under its SHA-256. Fails if the contents don't have SHA-256 `oid`
+    /// or aren't `size` bytes long.
+    pub fn upload_lfs_object_stream<S>(
+        &self,
+        oid: Sha256,
+        size: u64,
+        contents: S,
+    ) -> BoxFuture<ContentId, Error>
+    where
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+    {
+        STATS::upload_lfs_object_stream.add_value(1);
+        let context = Arc::new(Mutex::new(Sha256Context::new()));
+        let contents = contents.map({
+            let context = context.clone();
+            move |bytes| {
+                context.lock().expect("lock poisoned").update(&bytes);
+                bytes
+            }
+        });
+
+        let blobstore = self.blobstore.clone();
+        self.upload_file_contents_stream(size, contents)
+            .and_then(move |content_id| {
+                let computed = context.lock().expect("lock poisoned").clone().finish();
+                if computed != oid {
+                    bail_err!(ErrorKind::LfsObjectHashMismatch(computed, oid));
+                }
+                Ok(content_id)
+            })
+            .and_then(move |content_id| {
+                // The alias is written last, so that it never refers to missing contents.
+                let alias = BlobstoreBytes::from_bytes(content_id.as_ref());
+                blobstore
+                    .put(lfs_alias_key(&oid), alias)
+                    .map(move |()| content_id)
+            })
+            .boxify()
+    }
+
+    /// Uploads file contents that are in memory, chunked if they are too large for a single blob.
+    /// Returns the id of the contents right away, along with the upload.
+    fn upload_file_contents(&self, contents: FileContents) -> (ContentId, BoxFuture<(), Error>) {
+        let size = contents.size();
+        if size <= DEFAULT_CHUNK_SIZE {
+            let blob = contents.into_blob();
+            let id = *blob.id();
+            return (id, self.upload_blob(blob).map(|_| ()).boxify());
+        }
+
+        // The chunks are slices of the contents, so they don't take any additional memory.
+        let mut builder = ChunkedFileContentsBuilder::new(size as u64, DEFAULT_CHUNK_SIZE);
+        let mut uploads: Vec<_> = builder
+            .write(contents.into_bytes())
+            .into_iter()
+            .map(|blob| self.upload_blob(blob).map(|_| ()).boxify())
+            .collect();
+        let (last, contents) = builder
+            .finish()
+            .expect("the size of the contents was given upfront");
+        uploads.extend(last.map(|blob| self.upload_blob(blob).map(|_| ()).boxify()));
+
+        let blob = contents.into_blob();
+        let id = *blob.id();
+        let upload = future::join_all(uploads)
+            .and_then({
+                let repo = self.clone();
+                move |_| repo.upload_blob(blob).map(|_| ())
+            })
+            .boxify();
+        (id, upload)
+    }
+
     // This is used by tests
     pub fn get_blobstore(&self) -> RepoBlobstore {
         self.blobstore.clone()
@@ -684,6 +1278,19 @@ impl BlobRepo {
         self.logger.clone()
     }
 
+    pub fn get_redacted_blobs(&self) -> Arc<RedactedBlobs> {
+        self.redacted_blobs.clone()
+    }
+
+    /// A copy of this repo that can read the redacted blobs too, for the tools that must see
+    /// every blob of the repo, like garbage collection and scrubbing. Never serve clients from it.
+    pub fn unredacted(&self) -> BlobRepo {
+        BlobRepo {
+            blobstore: self.unredacted_blobstore.clone(),
+            ..self.clone()
+        }
+    }
+
     pub fn get_repoid(&self) -> RepositoryId {
         self.repoid
     }
@@ -737,6 +1344,7 @@ impl BlobRepo {
                             p1: p1.clone().map(|h| h.into_nodehash()),
                             p2: p2.clone().map(|h| h.into_nodehash()),
                             path: path.clone(),
+                            flags: 0,
                         };
                         let upload_fut = match upload_entry.upload(&repo) {
                             Ok((_, upload_fut)) => upload_fut.map(move |(entry, _)| {
@@ -1178,32 +1786,30 @@ impl UploadHgFileContents {
                 // Upload the contents separately (they'll be used for bonsai changesets as well).
                 let contents = f.file_contents();
                 let size = contents.size() as u64;
-                let contents_blob = contents.into_blob();
+                let (content_id, upload_fut) = repo.upload_file_contents(contents);
                 let cbinfo = ContentBlobInfo {
                     path: path.clone(),
                     meta: ContentBlobMeta {
-                        id: *contents_blob.id(),
+                        id: content_id,
                         copy_from,
                     },
                 };
 
-                let upload_fut = repo.upload_blob(contents_blob)
-                    .map(|_content_id| ())
-                    .timed({
-                        let logger = repo.logger.clone();
-                        move |stats, result| {
-                            if result.is_ok() {
-                                UploadHgFileEntry::log_stats(
-                                    logger,
-                                    path,
-                                    node_id,
-                                    "content_uploaded",
-                                    stats,
-                                );
-                            }
-                            Ok(())
+                let upload_fut = upload_fut.timed({
+                    let logger = repo.logger.clone();
+                    move |stats, result| {
+                        if result.is_ok() {
+                            UploadHgFileEntry::log_stats(
+                                logger,
+                                path,
+                                node_id,
+                                "content_uploaded",
+                                stats,
+                            );
                         }
-                    });
+                        Ok(())
+                    }
+                });
                 let compute_fut = future::ok((node_id, metadata, size));
 
                 (cbinfo, Either::B(upload_fut), Either::B(compute_fut))
@@ -1219,7 +1825,7 @@ impl UploadHgFileContents {
     ) -> impl Future<Item = (HgNodeHash, Bytes, u64), Error = Error> {
         // Computing the file node hash requires fetching the blob and gluing it together with the
         // metadata.
-        repo.fetch(&cbmeta.id).map(move |file_contents| {
+        fetch_file_contents(&repo.blobstore, cbmeta.id).map(move |file_contents| {
             let size = file_contents.size() as u64;
             let mut metadata = Vec::new();
             File::generate_metadata(cbmeta.copy_from.as_ref(), &file_contents, &mut metadata)
@@ -1255,6 +1861,9 @@ pub struct UploadHgFileEntry {
     pub p1: Option<HgNodeHash>,
     pub p2: Option<HgNodeHash>,
     pub path: MPath,
+    /// Revlog flags, as sent with the file node in changegroup 3. The file nodes generated from
+    /// bonsai changesets have none.
+    pub flags: u16,
 }
 
 impl UploadHgFileEntry {
@@ -1270,6 +1879,7 @@ impl UploadHgFileEntry {
             p1,
             p2,
             path,
+            flags,
         } = self;
 
         let (cbinfo, content_upload, compute_fut) = contents.execute(repo, p1, p2, path.clone());
@@ -1304,6 +1914,7 @@ impl UploadHgFileEntry {
                     content_id,
                     content_size,
                     metadata,
+                    flags,
                 };
                 let envelope_blob = file_envelope.freeze().into_blob();
 
@@ -1633,16 +2244,42 @@ impl CreateChangeset {
     }
 }
 
+/// Put a layer over `blobstore` that refuses to return the blobs of the redaction list of the
+/// repo.
+fn redact(
+    blobstore: Arc<Blobstore>,
+    redacted_blobs: &Arc<RedactedBlobs>,
+    repoid: RepositoryId,
+) -> Arc<Blobstore> {
+    // The redaction list has the keys relative to the repo, but the blobstore below the
+    // PrefixBlobstore sees them with the prefix.
+    let prefix = repoid.prefix();
+    let redacted = redacted_blobs
+        .get_all(repoid)
+        .map(move |entries| {
+            entries
+                .into_iter()
+                .map(|entry| (format!("{}{}", prefix, entry.blobstore_key), entry.reason))
+                .collect::<RedactedKeys>()
+        })
+        .boxify();
+    Arc::new(RedactedBlobstore::new(blobstore, redacted))
+}
+
 impl Clone for BlobRepo {
     fn clone(&self) -> Self {
         Self {
             logger: self.logger.clone(),
             bookmarks: self.bookmarks.clone(),
             blobstore: self.blobstore.clone(),
+            unredacted_blobstore: self.unredacted_blobstore.clone(),
             filenodes: self.filenodes.clone(),
             changesets: self.changesets.clone(),
             bonsai_hg_mapping: self.bonsai_hg_mapping.clone(),
+            obsmarkers: self.obsmarkers.clone(),
+            redacted_blobs: self.redacted_blobs.clone(),
             repoid: self.repoid.clone(),
+            cache_snapshots: self.cache_snapshots.clone(),
         }
     }
 }
@@ -24,21 +24,28 @@ extern crate mercurial;
 extern crate mercurial_types;
 extern crate mercurial_types_mocks;
 extern crate mononoke_types;
+extern crate redacted_blobs;
 
+use blobstore::{censored_reason, Blobstore};
+use bytes::Bytes;
 use failure::Error;
 use fixtures::{many_files_dirs, merge_uneven};
-use futures::Future;
+use futures::{stream, Future, Stream};
 use futures_ext::{BoxFuture, FutureExt};
 use quickcheck::{quickcheck, Arbitrary, Gen, TestResult, Testable};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::marker::PhantomData;
 
-use blobrepo::{compute_changed_files, BlobRepo, ErrorKind};
+use blobrepo::{compute_changed_files, BlobRepo, ErrorKind, UploadHgFileContents,
+               UploadHgFileEntry, UploadHgNodeHash};
 use mercurial_types::{manifest, Changeset, Entry, FileType, HgChangesetId, HgEntryId,
                       HgManifestId, HgParents, MPath, MPathElement, RepoPath};
-use mononoke_types::{BonsaiChangeset, ChangesetId, ContentId, DateTime, FileChange, FileContents,
-                     MononokeId};
+use mercurial_types::hash::{Sha256, Sha256Context};
+use mercurial_types::lfs::REVIDX_EXTSTORED;
+use mononoke_types::{BlobstoreBytes, BonsaiChangeset, ChangesetId, ContentId, DateTime,
+                     FileChange, FileContents, MononokeId, DEFAULT_CHUNK_SIZE};
 use mononoke_types::bonsai_changeset::BonsaiChangesetMut;
+use redacted_blobs::RedactedBlob;
 
 #[macro_use]
 mod utils;
@@ -124,6 +131,151 @@ test_both_repotypes!(
     upload_blob_one_parent_eager
 );
 
+fn upload_blob_with_flags(repo: BlobRepo) {
+    let path = MPath::new("lfs/pointer").expect("valid path");
+    let upload = UploadHgFileEntry {
+        upload_node_id: UploadHgNodeHash::Generate,
+        contents: UploadHgFileContents::RawBytes(Bytes::from(&b"pointer"[..])),
+        file_type: FileType::Regular,
+        p1: None,
+        p2: None,
+        path,
+        flags: REVIDX_EXTSTORED,
+    };
+    let (_, future) = upload.upload(&repo).unwrap();
+    let (entry, _) = run_future(future).unwrap();
+
+    // The revlog flags are kept with the file node.
+    let hash = entry.get_hash().into_nodehash();
+    assert_eq!(run_future(repo.get_file_flags(&hash)).unwrap(), REVIDX_EXTSTORED);
+}
+
+test_both_repotypes!(
+    upload_blob_with_flags,
+    upload_blob_with_flags_lazy,
+    upload_blob_with_flags_eager
+);
+
+fn large_file_contents() -> Vec<u8> {
+    (0..2 * DEFAULT_CHUNK_SIZE + 1)
+        .map(|i| (i % 251) as u8)
+        .collect()
+}
+
+fn upload_chunked_blob(repo: BlobRepo) {
+    let fake_path = RepoPath::file("fake/file").expect("Can't generate fake RepoPath");
+    let data = large_file_contents();
+
+    let (hash, future) = upload_file_no_parents(&repo, data.clone(), &fake_path);
+    let (entry, _) = run_future(future).unwrap();
+
+    // The contents are stored in chunks, under the id they would have as a single blob...
+    let content_id = run_future(repo.get_file_content_id(&hash)).unwrap();
+    assert_eq!(content_id, FileContents::new_bytes(data.clone()).content_id());
+    match run_future(repo.unittest_fetch(&content_id)).unwrap() {
+        FileContents::Chunked(chunked) => assert_eq!(chunked.chunks().len(), 3),
+        contents => panic!("expected chunked contents, got {:?}", contents),
+    };
+
+    // ...and are put back together when they are fetched...
+    let content = run_future(entry.get_content()).unwrap();
+    match content {
+        manifest::Content::File(FileContents::Bytes(f)) => assert!(f.as_ref() == &data[..]),
+        _ => panic!(),
+    };
+    let bytes = run_future(repo.get_file_content(&hash)).unwrap();
+    assert!(bytes.into_bytes().as_ref() == &data[..]);
+
+    // ...unless they are streamed.
+    let chunks = run_future(repo.get_file_content_stream(&hash).collect()).unwrap();
+    assert_eq!(chunks.len(), 3);
+    let streamed: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.iter().cloned()).collect();
+    assert!(streamed == data);
+
+    // Bounded fetches refuse the contents larger than the bound.
+    let size = data.len() as u64;
+    let bytes = run_future(repo.get_file_content_bounded(&hash, size)).unwrap();
+    assert!(bytes.into_bytes().as_ref() == &data[..]);
+    match run_future(repo.get_file_content_bounded(&hash, size - 1))
+        .expect_err("contents larger than the bound were fetched")
+        .downcast::<ErrorKind>()
+    {
+        Ok(ErrorKind::FileTooLarge(node, actual, max)) => {
+            assert_eq!((node, actual, max), (hash, size, size - 1))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+test_both_repotypes!(
+    upload_chunked_blob,
+    upload_chunked_blob_lazy,
+    upload_chunked_blob_eager
+);
+
+fn upload_file_contents_stream(repo: BlobRepo) {
+    let data = large_file_contents();
+    let parts: Vec<_> = data.chunks(1_000_000)
+        .map(|part| Ok::<_, Error>(Bytes::from(part)))
+        .collect();
+
+    let content_id = run_future(
+        repo.upload_file_contents_stream(data.len() as u64, stream::iter_result(parts)),
+    ).unwrap();
+    assert_eq!(content_id, FileContents::new_bytes(data.clone()).content_id());
+    match run_future(repo.unittest_fetch(&content_id)).unwrap() {
+        FileContents::Chunked(chunked) => {
+            assert_eq!(chunked.size(), data.len() as u64);
+            assert_eq!(chunked.chunks().len(), 3);
+        }
+        contents => panic!("expected chunked contents, got {:?}", contents),
+    };
+
+    // The contents must have the size given upfront.
+    let parts = stream::once::<_, Error>(Ok(Bytes::from(&b"too short"[..])));
+    assert!(run_future(repo.upload_file_contents_stream(10, parts)).is_err());
+}
+
+test_both_repotypes!(
+    upload_file_contents_stream,
+    upload_file_contents_stream_lazy,
+    upload_file_contents_stream_eager
+);
+
+fn upload_lfs_object_stream(repo: BlobRepo) {
+    let data = Bytes::from(&b"lfs object contents"[..]);
+    let mut context = Sha256Context::new();
+    context.update(&data);
+    let oid = context.finish();
+
+    assert_eq!(run_future(repo.get_lfs_content_id(&oid)).unwrap(), None);
+    let parts = stream::once::<_, Error>(Ok(data.clone()));
+    let content_id = run_future(repo.upload_lfs_object_stream(oid, data.len() as u64, parts))
+        .unwrap();
+    assert_eq!(content_id, FileContents::Bytes(data.clone()).content_id());
+    assert_eq!(
+        run_future(repo.get_lfs_content_id(&oid)).unwrap(),
+        Some(content_id)
+    );
+
+    let fetched = run_future(repo.get_lfs_object_stream(&oid).concat2()).unwrap();
+    assert_eq!(fetched, data);
+
+    // The contents must hash to the oid they are uploaded as.
+    let other_oid = Sha256::from_bytes(&[1; 32]).unwrap();
+    let parts = stream::once::<_, Error>(Ok(data.clone()));
+    assert!(
+        run_future(repo.upload_lfs_object_stream(other_oid, data.len() as u64, parts)).is_err()
+    );
+    assert_eq!(run_future(repo.get_lfs_content_id(&other_oid)).unwrap(), None);
+}
+
+test_both_repotypes!(
+    upload_lfs_object_stream,
+    upload_lfs_object_stream_lazy,
+    upload_lfs_object_stream_eager
+);
+
 fn create_one_changeset(repo: BlobRepo) {
     let fake_file_path = RepoPath::file("dir/file").expect("Can't generate fake RepoPath");
     let fake_dir_path = RepoPath::dir("dir").expect("Can't generate fake RepoPath");
@@ -752,3 +904,29 @@ fn test_get_manifest_from_bonsai() {
         }
     });
 }
+
+#[test]
+fn test_unredacted_reads_redacted_blobs() {
+    async_unit::tokio_unit_test(|| {
+        let repo = get_empty_eager_repo();
+        let value = BlobstoreBytes::from_bytes(Bytes::from_static(b"hunter2"));
+
+        // The list is loaded on the first read, so redact the blob before that.
+        run_future(repo.get_redacted_blobs().add(RedactedBlob::new(
+            repo.get_repoid(),
+            "secret".to_string(),
+            "leaked password".to_string(),
+            DateTime::now(),
+        ))).unwrap();
+        run_future(repo.get_blobstore().put("secret".to_string(), value.clone())).unwrap();
+
+        let err = run_future(repo.get_blobstore().get("secret".to_string()))
+            .expect_err("redacted blobs must not be readable");
+        assert_eq!(censored_reason(&err), Some("leaked password"));
+
+        assert_eq!(
+            run_future(repo.unredacted().get_blobstore().get("secret".to_string())).unwrap(),
+            Some(value)
+        );
+    });
+}
@@ -165,6 +165,7 @@ fn upload_hg_file_entry(
         p1,
         p2,
         path: path.into_mpath().expect("expected a path to be present"),
+        flags: 0,
     };
 
     let (_, upload_fut) = upload.upload(repo).unwrap();
@@ -16,18 +16,19 @@ extern crate futures_ext;
 extern crate blobstore;
 extern crate mononoke_types;
 
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir_all, metadata, read_dir, remove_file, File};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use failure::{Error, Result};
-use futures::Async;
+use futures::{stream, Async};
 use futures::future::{poll_fn, Future};
-use url::percent_encoding::{percent_encode, DEFAULT_ENCODE_SET};
+use url::percent_encoding::{percent_decode, percent_encode, DEFAULT_ENCODE_SET};
 
-use futures_ext::{BoxFuture, FutureExt};
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
-use blobstore::Blobstore;
+use blobstore::{enumerate_matches, Blobstore, BlobstoreDeletable, BlobstoreEnumerable};
 use mononoke_types::BlobstoreBytes;
 
 const PREFIX: &str = "blob";
@@ -60,6 +61,22 @@ impl Fileblob {
         let key = percent_encode(key.as_bytes(), DEFAULT_ENCODE_SET);
         self.base.join(format!("{}-{}", PREFIX, key))
     }
+
+    /// Returns all the keys in the blobstore, in no particular order.
+    fn keys(&self) -> Result<Vec<String>> {
+        let file_prefix = format!("{}-", PREFIX);
+        let mut keys = Vec::new();
+        for entry in read_dir(&self.base)? {
+            let name = entry?.file_name();
+            let name = match name.to_str() {
+                Some(name) if name.starts_with(&file_prefix) => name,
+                _ => continue,
+            };
+            let key = percent_decode(name[file_prefix.len()..].as_bytes()).decode_utf8()?;
+            keys.push(key.into_owned());
+        }
+        Ok(keys)
+    }
 }
 
 impl Blobstore for Fileblob {
@@ -90,3 +107,47 @@ impl Blobstore for Fileblob {
         }).boxify()
     }
 }
+
+impl BlobstoreEnumerable for Fileblob {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        let this = self.clone();
+
+        poll_fn::<_, Error, _>(move || {
+            let mut keys: Vec<_> = this.keys()?
+                .into_iter()
+                .filter(|key| enumerate_matches(key, &prefix, after.as_ref().map(String::as_str)))
+                .collect();
+            keys.sort();
+            Ok(Async::Ready(stream::iter_ok::<_, Error>(keys)))
+        }).flatten_stream()
+            .boxify()
+    }
+}
+
+impl BlobstoreDeletable for Fileblob {
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        let p = self.path(&key);
+
+        poll_fn::<_, Error, _>(move || {
+            match remove_file(&p) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+                Ok(()) => (),
+            }
+            Ok(Async::Ready(()))
+        }).boxify()
+    }
+
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        let p = self.path(&key);
+
+        poll_fn::<_, Error, _>(move || {
+            let ret = match metadata(&p) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+                Ok(metadata) => Some(metadata.modified()?),
+            };
+            Ok(Async::Ready(ret))
+        }).boxify()
+    }
+}
@@ -0,0 +1,476 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! A blobstore that stores every blob in several blobstores, so that losing one of them doesn't
+//! lose any data.
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+
+#[cfg(test)]
+extern crate async_unit;
+#[cfg(test)]
+extern crate bytes;
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate stats;
+
+extern crate blobstore;
+extern crate blobstore_sync_queue;
+extern crate mercurial_types;
+extern crate mononoke_types;
+
+use std::fmt;
+use std::sync::Arc;
+
+use failure::Error;
+use futures::{future, Future, Stream};
+use futures::future::Loop;
+use futures::stream::FuturesUnordered;
+use futures_ext::{BoxFuture, FutureExt};
+use stats::Timeseries;
+
+use blobstore::{Blobstore, BlobstoreId};
+use blobstore_sync_queue::{BlobstoreSyncQueue, BlobstoreSyncQueueEntry};
+use mercurial_types::RepositoryId;
+use mononoke_types::{BlobstoreBytes, DateTime};
+
+define_stats! {
+    prefix = "mononoke.multiplexedblob";
+    puts_partial: timeseries(RATE, SUM),
+    puts_missed_blobstores: timeseries(RATE, SUM),
+    puts_failed: timeseries(RATE, SUM),
+    gets_failed: timeseries(RATE, SUM),
+}
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "write quorum {} is not between 1 and the number of blobstores ({})", _0, _1)]
+    InvalidWriteQuorum(usize, usize),
+    #[fail(display = "put of {} was stored by {} blobstores, below the write quorum of {}: {}",
+           _0, _1, _2, _3)]
+    PutQuorumNotReached(String, usize, usize, String),
+    #[fail(display = "{} was not found and {} blobstores failed: {}", _0, _1, _2)]
+    SomeBlobstoresFailed(String, usize, String),
+}
+
+/// Stores every blob in all of its blobstores.
+///
+/// A put succeeds once `write_quorum` blobstores stored the blob. Every other blobstore, whether
+/// it failed or it didn't answer yet, is recorded in the sync queue, and the puts that are still
+/// in flight are dropped: the healer copies the blob to those blobstores later. That way a slow
+/// or hung blobstore doesn't hold up the writes.
+///
+/// A get asks all the blobstores and returns the first blob that is found. A blob is only
+/// reported as missing if all the blobstores answered that they don't have it.
+#[derive(Clone)]
+pub struct MultiplexedBlobstore {
+    repo_id: RepositoryId,
+    blobstores: Arc<Vec<(BlobstoreId, Arc<Blobstore>)>>,
+    write_quorum: usize,
+    queue: Arc<BlobstoreSyncQueue>,
+}
+
+impl MultiplexedBlobstore {
+    pub fn new(
+        repo_id: RepositoryId,
+        blobstores: Vec<(BlobstoreId, Arc<Blobstore>)>,
+        write_quorum: usize,
+        queue: Arc<BlobstoreSyncQueue>,
+    ) -> Result<Self, Error> {
+        if write_quorum == 0 || write_quorum > blobstores.len() {
+            bail_err!(ErrorKind::InvalidWriteQuorum(
+                write_quorum,
+                blobstores.len()
+            ));
+        }
+
+        Ok(Self {
+            repo_id,
+            blobstores: Arc::new(blobstores),
+            write_quorum,
+            queue,
+        })
+    }
+
+    /// Runs `op` on all the blobstores, and returns the first result for which `found` is
+    /// true. If there is none, returns `not_found` unless some of the blobstores failed.
+    fn find_first<T, F, P>(
+        &self,
+        key: String,
+        mut op: F,
+        found: P,
+        not_found: T,
+    ) -> BoxFuture<T, Error>
+    where
+        T: Send + 'static,
+        F: FnMut(&Arc<Blobstore>) -> BoxFuture<T, Error>,
+        P: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        let found = Arc::new(found);
+        let requests: FuturesUnordered<_> = self.blobstores
+            .iter()
+            .map(|&(_, ref blobstore)| op(blobstore).then(|res| Ok::<_, Error>(res)))
+            .collect();
+
+        future::loop_fn(
+            (requests, Vec::new()),
+            move |(requests, mut errors): (_, Vec<Error>)| {
+                let found = found.clone();
+                requests
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .map(move |(res, requests)| match res {
+                        Some(Ok(value)) => {
+                            if found(&value) {
+                                Loop::Break((Some(value), errors))
+                            } else {
+                                Loop::Continue((requests, errors))
+                            }
+                        }
+                        Some(Err(err)) => {
+                            errors.push(err);
+                            Loop::Continue((requests, errors))
+                        }
+                        None => Loop::Break((None, errors)),
+                    })
+            },
+        ).and_then(move |(value, errors)| match value {
+            Some(value) => Ok(value),
+            None if errors.is_empty() => Ok(not_found),
+            None => {
+                STATS::gets_failed.add_value(1);
+                Err(ErrorKind::SomeBlobstoresFailed(key, errors.len(), join_errors(&errors)).into())
+            }
+        })
+            .boxify()
+    }
+}
+
+fn join_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|err| format!("{}", err))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl Blobstore for MultiplexedBlobstore {
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let get_key = key.clone();
+        self.find_first(
+            key,
+            move |blobstore| blobstore.get(get_key.clone()),
+            |value| value.is_some(),
+            None,
+        )
+    }
+
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let puts: FuturesUnordered<_> = self.blobstores
+            .iter()
+            .map(|&(blobstore_id, ref blobstore)| {
+                blobstore
+                    .put(key.clone(), value.clone())
+                    .then(move |res| Ok::<_, Error>((blobstore_id, res)))
+            })
+            .collect();
+
+        let write_quorum = self.write_quorum;
+        let blobstores_count = self.blobstores.len();
+
+        let quorum = future::loop_fn(
+            (puts, Vec::new(), Vec::new()),
+            move |(puts, mut stored, mut errors): (_, Vec<BlobstoreId>, Vec<Error>)| {
+                puts.into_future()
+                    .map_err(|(err, _)| err)
+                    .map(move |(res, puts)| {
+                        match res {
+                            Some((blobstore_id, Ok(()))) => stored.push(blobstore_id),
+                            Some((_, Err(err))) => errors.push(err),
+                            None => return Loop::Break((puts, stored, errors)),
+                        }
+                        let quorum_reached = stored.len() >= write_quorum;
+                        let quorum_unreachable = blobstores_count - errors.len() < write_quorum;
+                        if quorum_reached || quorum_unreachable {
+                            Loop::Break((puts, stored, errors))
+                        } else {
+                            Loop::Continue((puts, stored, errors))
+                        }
+                    })
+            },
+        );
+
+        let this = self.clone();
+        quorum
+            .and_then(move |(_puts, stored, errors)| {
+                if stored.len() < write_quorum {
+                    STATS::puts_failed.add_value(1);
+                    return future::err(
+                        ErrorKind::PutQuorumNotReached(
+                            key,
+                            stored.len(),
+                            write_quorum,
+                            join_errors(&errors),
+                        ).into(),
+                    ).left_future();
+                }
+
+                let timestamp = DateTime::now();
+                let missed: Vec<_> = this.blobstores
+                    .iter()
+                    .filter(|&&(blobstore_id, _)| !stored.contains(&blobstore_id))
+                    .map(|&(blobstore_id, _)| {
+                        BlobstoreSyncQueueEntry::new(
+                            this.repo_id,
+                            key.clone(),
+                            blobstore_id,
+                            timestamp,
+                        )
+                    })
+                    .collect();
+                let record_missed = if missed.is_empty() {
+                    future::ok(()).left_future()
+                } else {
+                    STATS::puts_partial.add_value(1);
+                    STATS::puts_missed_blobstores.add_value(missed.len() as i64);
+                    this.queue.add_many(missed).right_future()
+                };
+
+                // The puts that didn't finish before the quorum was reached are dropped along
+                // with `puts`, their blobstores are repaired from the queue by the healer.
+                record_missed.right_future()
+            })
+            .boxify()
+    }
+
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        let is_present_key = key.clone();
+        self.find_first(
+            key,
+            move |blobstore| blobstore.is_present(is_present_key.clone()),
+            |present| *present,
+            false,
+        )
+    }
+}
+
+impl fmt::Debug for MultiplexedBlobstore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiplexedBlobstore")
+            .field("repo_id", &self.repo_id)
+            .field("blobstores", &self.blobstores)
+            .field("write_quorum", &self.write_quorum)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use blobstore::EagerMemblob;
+    use blobstore_sync_queue::SqliteBlobstoreSyncQueue;
+
+    /// A blobstore that fails all the operations.
+    #[derive(Debug)]
+    struct FailingBlobstore;
+
+    impl Blobstore for FailingBlobstore {
+        fn get(&self, _key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            future::err(failure::err_msg("get failed")).boxify()
+        }
+
+        fn put(&self, _key: String, _value: BlobstoreBytes) -> BoxFuture<(), Error> {
+            future::err(failure::err_msg("put failed")).boxify()
+        }
+    }
+
+    /// A blobstore whose operations never complete.
+    #[derive(Debug)]
+    struct HungBlobstore;
+
+    impl Blobstore for HungBlobstore {
+        fn get(&self, _key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            future::empty().boxify()
+        }
+
+        fn put(&self, _key: String, _value: BlobstoreBytes) -> BoxFuture<(), Error> {
+            future::empty().boxify()
+        }
+    }
+
+    fn repo_id() -> RepositoryId {
+        RepositoryId::new(0)
+    }
+
+    fn multiplexed(
+        blobstores: Vec<Arc<Blobstore>>,
+        write_quorum: usize,
+    ) -> (MultiplexedBlobstore, Arc<BlobstoreSyncQueue>) {
+        let queue: Arc<BlobstoreSyncQueue> = Arc::new(
+            SqliteBlobstoreSyncQueue::in_memory().expect("failed to create the sync queue"),
+        );
+        let blobstores = blobstores
+            .into_iter()
+            .enumerate()
+            .map(|(id, blobstore)| (BlobstoreId::new(id as u64), blobstore))
+            .collect();
+        let multiplexed =
+            MultiplexedBlobstore::new(repo_id(), blobstores, write_quorum, queue.clone())
+                .expect("invalid multiplexed blobstore");
+        (multiplexed, queue)
+    }
+
+    fn queued_blobstores(queue: &Arc<BlobstoreSyncQueue>, key: &str) -> Vec<BlobstoreId> {
+        queue
+            .get(repo_id(), key.to_string())
+            .wait()
+            .expect("sync queue get failed")
+            .into_iter()
+            .map(|entry| entry.blobstore_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_invalid_quorum() {
+        let queue: Arc<BlobstoreSyncQueue> =
+            Arc::new(SqliteBlobstoreSyncQueue::in_memory().unwrap());
+        let blobstore: Arc<Blobstore> = Arc::new(EagerMemblob::new());
+        let blobstores = vec![(BlobstoreId::new(0), blobstore)];
+
+        assert!(
+            MultiplexedBlobstore::new(repo_id(), blobstores.clone(), 0, queue.clone()).is_err()
+        );
+        assert!(MultiplexedBlobstore::new(repo_id(), blobstores, 2, queue).is_err());
+    }
+
+    #[test]
+    fn test_put_all_succeed() {
+        async_unit::tokio_unit_test(|| {
+            let first = EagerMemblob::new();
+            let second = EagerMemblob::new();
+            let (multiplexed, queue) =
+                multiplexed(vec![Arc::new(first.clone()), Arc::new(second.clone())], 2);
+
+            multiplexed
+                .put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+                .wait()
+                .expect("put failed");
+
+            for blobstore in &[first, second] {
+                assert_eq!(
+                    blobstore
+                        .get("key".to_string())
+                        .wait()
+                        .unwrap()
+                        .map(|value| value.into_bytes()),
+                    Some(Bytes::from("value"))
+                );
+            }
+            assert_eq!(queued_blobstores(&queue, "key"), vec![]);
+        });
+    }
+
+    #[test]
+    fn test_put_quorum() {
+        async_unit::tokio_unit_test(|| {
+            let working = EagerMemblob::new();
+            let (multiplexed, queue) =
+                multiplexed(vec![Arc::new(FailingBlobstore), Arc::new(working.clone())], 1);
+
+            multiplexed
+                .put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+                .wait()
+                .expect("put failed");
+
+            assert!(working.is_present("key".to_string()).wait().unwrap());
+            assert_eq!(queued_blobstores(&queue, "key"), vec![BlobstoreId::new(0)]);
+        });
+    }
+
+    #[test]
+    fn test_put_with_hung_blobstore() {
+        let working = EagerMemblob::new();
+        let (multiplexed, queue) =
+            multiplexed(vec![Arc::new(HungBlobstore), Arc::new(working.clone())], 1);
+
+        // This would never return if the put waited for all the blobstores.
+        multiplexed
+            .put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+            .wait()
+            .expect("put failed");
+
+        assert!(working.is_present("key".to_string()).wait().unwrap());
+        assert_eq!(queued_blobstores(&queue, "key"), vec![BlobstoreId::new(0)]);
+    }
+
+    #[test]
+    fn test_put_quorum_not_reached() {
+        async_unit::tokio_unit_test(|| {
+            let (multiplexed, queue) = multiplexed(
+                vec![Arc::new(FailingBlobstore), Arc::new(EagerMemblob::new())],
+                2,
+            );
+
+            assert!(
+                multiplexed
+                    .put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+                    .wait()
+                    .is_err()
+            );
+            assert_eq!(queued_blobstores(&queue, "key"), vec![]);
+        });
+    }
+
+    #[test]
+    fn test_get() {
+        async_unit::tokio_unit_test(|| {
+            let empty = EagerMemblob::new();
+            let full = EagerMemblob::new();
+            full.put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+                .wait()
+                .unwrap();
+            let (multiplexed, _) = multiplexed(vec![Arc::new(empty), Arc::new(full)], 1);
+
+            assert_eq!(
+                multiplexed
+                    .get("key".to_string())
+                    .wait()
+                    .expect("get failed")
+                    .map(|value| value.into_bytes()),
+                Some(Bytes::from("value"))
+            );
+            assert!(multiplexed.is_present("key".to_string()).wait().unwrap());
+            assert_eq!(multiplexed.get("missing".to_string()).wait().unwrap(), None);
+            assert!(!multiplexed.is_present("missing".to_string()).wait().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_get_with_failures() {
+        async_unit::tokio_unit_test(|| {
+            let full = EagerMemblob::new();
+            full.put("key".to_string(), BlobstoreBytes::from_bytes("value"))
+                .wait()
+                .unwrap();
+            let (multiplexed, _) =
+                multiplexed(vec![Arc::new(FailingBlobstore), Arc::new(full)], 1);
+
+            // A blobstore that has the blob is enough
+            assert!(multiplexed.get("key".to_string()).wait().unwrap().is_some());
+            // But a blob can't be reported missing if a blobstore failed
+            assert!(multiplexed.get("missing".to_string()).wait().is_err());
+        });
+    }
+}
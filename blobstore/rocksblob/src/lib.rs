@@ -15,19 +15,24 @@ extern crate rocksdb;
 extern crate blobstore;
 extern crate mononoke_types;
 
+use std::collections::VecDeque;
 use std::path::Path;
+use std::time::SystemTime;
 
 use failure::Error;
-use futures::{Async, Future, Poll};
-use futures_ext::{BoxFuture, FutureExt};
+use futures::{future, Async, Future, Poll, Stream};
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use rocksdb::{Db, ReadOptions, WriteOptions};
 
-use blobstore::Blobstore;
+use blobstore::{Blobstore, BlobstoreDeletable, BlobstoreEnumerable};
 use mononoke_types::BlobstoreBytes;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Number of keys read from the database at once while enumerating.
+const ENUMERATE_BATCH_SIZE: usize = 1000;
+
 #[derive(Clone, Debug)]
 pub struct Rocksblob {
     db: Db,
@@ -59,6 +64,71 @@ pub struct GetBlob(Db, String);
 #[must_use = "futures do nothing unless polled"]
 pub struct PutBlob(Db, String, BlobstoreBytes);
 
+#[must_use = "futures do nothing unless polled"]
+pub struct DeleteBlob(Db, String);
+
+/// Stream of the keys of the database, read in batches. Every batch starts a new iterator at the
+/// last key that was read, so that no database iterator is held between polls.
+#[must_use = "streams do nothing unless polled"]
+pub struct EnumerateKeys {
+    db: Db,
+    prefix: String,
+    cursor: Option<String>,
+    batch: VecDeque<String>,
+    done: bool,
+}
+
+impl EnumerateKeys {
+    fn read_batch(&mut self) -> Result<()> {
+        let rdopts = ReadOptions::new();
+        let mut iter = self.db.iter(&rdopts);
+
+        match self.cursor {
+            Some(ref cursor) if cursor.as_str() >= self.prefix.as_str() => {
+                iter.seek(cursor.as_bytes());
+                if iter.valid() && iter.key() == cursor.as_bytes() {
+                    iter.next();
+                }
+            }
+            _ => iter.seek(self.prefix.as_bytes()),
+        }
+
+        while self.batch.len() < ENUMERATE_BATCH_SIZE {
+            if !iter.valid() || !iter.key().starts_with(self.prefix.as_bytes()) {
+                self.done = true;
+                break;
+            }
+            self.batch.push_back(String::from_utf8(iter.key().to_vec())?);
+            iter.next();
+        }
+        self.cursor = self.batch.back().cloned().or(self.cursor.take());
+        Ok(())
+    }
+}
+
+impl Stream for EnumerateKeys {
+    type Item = String;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.batch.is_empty() && !self.done {
+            self.read_batch()?;
+        }
+        Ok(Async::Ready(self.batch.pop_front()))
+    }
+}
+
+impl Future for DeleteBlob {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let wropts = WriteOptions::new().set_sync(false);
+        self.0.delete(&self.1, &wropts).map_err(Error::from)?;
+        Ok(Async::Ready(()))
+    }
+}
+
 impl Future for GetBlob {
     type Item = Option<BlobstoreBytes>;
     type Error = Error;
@@ -96,3 +166,28 @@ impl Blobstore for Rocksblob where {
         PutBlob(db, key, value).boxify()
     }
 }
+
+impl BlobstoreEnumerable for Rocksblob {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        EnumerateKeys {
+            db: self.db.clone(),
+            prefix,
+            cursor: after,
+            batch: VecDeque::new(),
+            done: false,
+        }.boxify()
+    }
+}
+
+impl BlobstoreDeletable for Rocksblob {
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        let db = self.db.clone();
+
+        DeleteBlob(db, key).boxify()
+    }
+
+    /// RocksDB doesn't record when keys were written.
+    fn last_modified(&self, _key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        future::ok(None).boxify()
+    }
+}
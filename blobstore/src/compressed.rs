@@ -0,0 +1,274 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::time::SystemTime;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use failure::Error;
+use futures::Future;
+use stats::{Histogram, Timeseries};
+use zstd;
+
+use futures_ext::{BoxFuture, BoxStream, FutureExt};
+
+use mononoke_types::BlobstoreBytes;
+
+use {Blobstore, BlobstoreDeletable, BlobstoreEnumerable, CacheBlobstoreExt, ErrorKind};
+
+define_stats! {
+    prefix = "mononoke.blobstore.compressed";
+    puts_compressed: timeseries(RATE, SUM),
+    puts_uncompressed: timeseries(RATE, SUM),
+    put_raw_bytes: timeseries(RATE, SUM),
+    put_stored_bytes: timeseries(RATE, SUM),
+    // Size of the compressed blobs, in percent of their uncompressed size.
+    compression_ratio: histogram(1, 0, 100, AVG, COUNT; P 50; P 95; P 99),
+    gets_compressed: timeseries(RATE, SUM),
+    gets_legacy: timeseries(RATE, SUM),
+}
+
+/// Values smaller than this aren't worth compressing by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Starts every value written by `CompressedBlobstore`. The first byte is not valid UTF-8, so
+/// that legacy values (written without a header) are very unlikely to start with it.
+const MAGIC: &[u8] = b"\xffMCB";
+const KIND_STORED: u8 = 0;
+const KIND_ZSTD: u8 = 1;
+const HEADER_LEN: usize = 5;
+
+/// A layer over an existing blobstore that zstd-compresses the values larger than a threshold.
+///
+/// Compressed values are written with a small header. Values that aren't compressed are written
+/// as they are, so that values written before the layer was added remain readable. The only
+/// exception are uncompressed values that happen to start like the header: they get a header
+/// saying that they're stored uncompressed.
+#[derive(Clone, Debug)]
+pub struct CompressedBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+    threshold: usize,
+    level: i32,
+}
+
+impl<T: Blobstore + Clone> CompressedBlobstore<T> {
+    pub fn new(blobstore: T, threshold: usize, level: i32) -> Self {
+        Self {
+            blobstore,
+            threshold,
+            level,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.blobstore
+    }
+
+    fn encode(&self, value: BlobstoreBytes) -> BlobstoreBytes {
+        let raw = value.into_bytes();
+        STATS::put_raw_bytes.add_value(raw.len() as i64);
+
+        let encoded = if raw.len() >= self.threshold {
+            match zstd::stream::encode_all(raw.as_ref(), self.level) {
+                // Only keep the compressed value if it's actually smaller.
+                Ok(ref compressed) if compressed.len() + HEADER_LEN < raw.len() => {
+                    STATS::puts_compressed.add_value(1);
+                    STATS::compression_ratio
+                        .add_value((compressed.len() * 100 / raw.len()) as i64);
+                    with_header(KIND_ZSTD, compressed)
+                }
+                _ => uncompressed(raw),
+            }
+        } else {
+            uncompressed(raw)
+        };
+
+        STATS::put_stored_bytes.add_value(encoded.len() as i64);
+        BlobstoreBytes::from_bytes(encoded)
+    }
+}
+
+fn with_header(kind: u8, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + data.len());
+    buf.put_slice(MAGIC);
+    buf.put_u8(kind);
+    buf.put_slice(data);
+    buf.freeze()
+}
+
+fn uncompressed(raw: Bytes) -> Bytes {
+    STATS::puts_uncompressed.add_value(1);
+    if raw.starts_with(MAGIC) {
+        with_header(KIND_STORED, &raw)
+    } else {
+        raw
+    }
+}
+
+fn decode(key: &str, value: BlobstoreBytes) -> Result<BlobstoreBytes, Error> {
+    let stored = value.into_bytes();
+    if stored.len() < HEADER_LEN || !stored.starts_with(MAGIC) {
+        STATS::gets_legacy.add_value(1);
+        return Ok(BlobstoreBytes::from_bytes(stored));
+    }
+
+    let data = stored.slice_from(HEADER_LEN);
+    match stored[MAGIC.len()] {
+        KIND_STORED => Ok(BlobstoreBytes::from_bytes(data)),
+        KIND_ZSTD => {
+            STATS::gets_compressed.add_value(1);
+            let raw = zstd::stream::decode_all(data.as_ref()).map_err(|err| {
+                ErrorKind::InvalidCompressedBlob(key.to_string(), format!("{}", err))
+            })?;
+            Ok(BlobstoreBytes::from_bytes(raw))
+        }
+        kind => Err(ErrorKind::InvalidCompressedBlob(
+            key.to_string(),
+            format!("unknown kind {}", kind),
+        ).into()),
+    }
+}
+
+fn decode_opt(
+    key: String,
+    get: BoxFuture<Option<BlobstoreBytes>, Error>,
+) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+    get.and_then(move |value| match value {
+        Some(value) => decode(&key, value).map(Some),
+        None => Ok(None),
+    }).boxify()
+}
+
+impl<T: CacheBlobstoreExt + Clone> CacheBlobstoreExt for CompressedBlobstore<T> {
+    #[inline]
+    fn get_no_cache_fill(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        decode_opt(key.clone(), self.blobstore.get_no_cache_fill(key))
+    }
+
+    #[inline]
+    fn get_cache_only(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        decode_opt(key.clone(), self.blobstore.get_cache_only(key))
+    }
+}
+
+impl<T: Blobstore + Clone> Blobstore for CompressedBlobstore<T> {
+    #[inline]
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        decode_opt(key.clone(), self.blobstore.get(key))
+    }
+
+    #[inline]
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        self.blobstore.put(key, self.encode(value))
+    }
+
+    #[inline]
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(key)
+    }
+}
+
+impl<T: BlobstoreEnumerable + Clone> BlobstoreEnumerable for CompressedBlobstore<T> {
+    #[inline]
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        self.blobstore.enumerate(prefix, after)
+    }
+}
+
+impl<T: BlobstoreDeletable + Clone> BlobstoreDeletable for CompressedBlobstore<T> {
+    #[inline]
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        self.blobstore.delete(key)
+    }
+
+    #[inline]
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        self.blobstore.last_modified(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use memblob::EagerMemblob;
+
+    fn get_raw(blobstore: &EagerMemblob, key: &str) -> Bytes {
+        blobstore
+            .get(key.to_string())
+            .wait()
+            .expect("get should succeed")
+            .expect("value should be present")
+            .into_bytes()
+    }
+
+    fn roundtrip(compressed: &CompressedBlobstore<EagerMemblob>, key: &str, value: Bytes) {
+        compressed
+            .put(key.to_string(), BlobstoreBytes::from_bytes(value.clone()))
+            .wait()
+            .expect("put should succeed");
+        assert_eq!(
+            compressed
+                .get(key.to_string())
+                .wait()
+                .expect("get should succeed")
+                .expect("value should be present")
+                .into_bytes(),
+            value
+        );
+    }
+
+    #[test]
+    fn test_compressed() {
+        let base = EagerMemblob::new();
+        let compressed = CompressedBlobstore::new(base.clone(), 16, DEFAULT_COMPRESSION_LEVEL);
+
+        let large = Bytes::from(vec![b'a'; 1000]);
+        roundtrip(&compressed, "large", large.clone());
+        let stored = get_raw(&base, "large");
+        assert!(stored.starts_with(MAGIC));
+        assert!(stored.len() < large.len());
+
+        // Small values are stored as they are.
+        let small = Bytes::from("small");
+        roundtrip(&compressed, "small", small.clone());
+        assert_eq!(get_raw(&base, "small"), small);
+    }
+
+    #[test]
+    fn test_legacy() {
+        let base = EagerMemblob::new();
+        let compressed = CompressedBlobstore::new(base.clone(), 16, DEFAULT_COMPRESSION_LEVEL);
+
+        let legacy = Bytes::from(vec![b'b'; 1000]);
+        base.put(
+            "legacy".to_string(),
+            BlobstoreBytes::from_bytes(legacy.clone()),
+        ).wait()
+            .expect("put should succeed");
+        assert_eq!(
+            compressed
+                .get("legacy".to_string())
+                .wait()
+                .expect("get should succeed")
+                .expect("value should be present")
+                .into_bytes(),
+            legacy
+        );
+    }
+
+    #[test]
+    fn test_looks_like_header() {
+        let base = EagerMemblob::new();
+        let compressed = CompressedBlobstore::new(base.clone(), 1000, DEFAULT_COMPRESSION_LEVEL);
+
+        let mut value = MAGIC.to_vec();
+        value.push(KIND_ZSTD);
+        value.extend_from_slice(b"not zstd");
+        roundtrip(&compressed, "tricky", Bytes::from(value));
+    }
+}
@@ -0,0 +1,307 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use failure::Result;
+use futures::future;
+use futures_cpupool::CpuPool;
+use futures_ext::{BoxFuture, FutureExt};
+use rand::{self, Rng};
+use mononoke_types::BlobstoreBytes;
+
+use Blobstore;
+use counted_blobstore::CountedBlobstore;
+use in_process_lease::InProcessLease;
+use locking_cache::{CacheBlobstore, CacheOps};
+
+/// Directory of the cache where entries are written before being moved into place.
+const TMP_DIR: &str = "tmp";
+
+/// Temporary files older than this are left by writes interrupted by a crash. Other processes
+/// may share the directory, so newer ones may still be being written.
+const STALE_TMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The entries of the cache in the order they were used, with their sizes.
+#[derive(Default)]
+struct LruIndex {
+    entries: HashMap<String, (u64, u64)>,
+    // From the position of an entry in the use order to its name, least recently used first.
+    order: BTreeMap<u64, String>,
+    next_position: u64,
+    total_size: u64,
+}
+
+impl LruIndex {
+    fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        self.remove(&name);
+        let position = self.next_position;
+        self.next_position += 1;
+        self.order.insert(position, name.clone());
+        self.entries.insert(name, (position, size));
+        self.total_size += size;
+    }
+
+    /// Mark an entry as the most recently used one.
+    fn touch(&mut self, name: &str) {
+        if let Some(&(_, size)) = self.entries.get(name) {
+            self.insert(name.to_string(), size);
+        }
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some((position, size)) = self.entries.remove(name) {
+            self.order.remove(&position);
+            self.total_size -= size;
+        }
+    }
+
+    fn pop_least_recently_used(&mut self) -> Option<String> {
+        let name = self.order.values().next().cloned();
+        if let Some(ref name) = name {
+            self.remove(name);
+        }
+        name
+    }
+}
+
+/// CacheOps that keep the blobs in files in a local directory, e.g. on an SSD, so that the cache
+/// stays warm across restarts. The least recently used blobs are removed once the files take
+/// more than `max_size` bytes. Entries are written to a temporary file that is renamed into
+/// place, so a crash can't leave a partial blob in the cache.
+///
+/// The use order is only kept in memory; when the cache is opened again, the blobs are assumed
+/// to have been used in the order they were written.
+///
+/// The files are read and written on a thread pool, so that the disk IO doesn't block the event
+/// loop.
+#[derive(Clone)]
+pub struct DiskCacheOps {
+    dir: PathBuf,
+    max_size: u64,
+    index: Arc<Mutex<LruIndex>>,
+    // Makes the names of the temporary files of this instance unique, even if several processes
+    // share the directory.
+    tmp_prefix: String,
+    tmp_files: Arc<AtomicUsize>,
+    pool: CpuPool,
+}
+
+impl DiskCacheOps {
+    pub fn open<P: AsRef<Path>>(dir: P, max_size: u64) -> Result<Self> {
+        Self::open_with_pool(dir, max_size, CpuPool::new_num_cpus())
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(dir: P, max_size: u64, pool: CpuPool) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+
+        let tmp_dir = dir.join(TMP_DIR);
+        fs::create_dir_all(&tmp_dir)?;
+        Self::remove_stale_tmp_files(&tmp_dir)?;
+
+        let mut files = Vec::new();
+        for shard in fs::read_dir(&dir)? {
+            let shard = shard?;
+            if shard.file_name() == TMP_DIR || !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(shard.path())? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                match entry.file_name().into_string() {
+                    Ok(name) if metadata.is_file() => {
+                        files.push((metadata.modified()?, name, metadata.len()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        files.sort();
+
+        let mut index = LruIndex::default();
+        for (_, name, size) in files {
+            index.insert(name, size);
+        }
+
+        let cache = Self {
+            dir,
+            max_size,
+            index: Arc::new(Mutex::new(index)),
+            tmp_prefix: format!("{}-{:016x}", process::id(), rand::thread_rng().gen::<u64>()),
+            tmp_files: Arc::new(AtomicUsize::new(0)),
+            pool,
+        };
+        {
+            // The cache may have been opened with a larger size before.
+            let mut index = cache.index.lock().expect("lock poisoned");
+            cache.evict(&mut index);
+        }
+        Ok(cache)
+    }
+
+    fn remove_stale_tmp_files(tmp_dir: &Path) -> io::Result<()> {
+        let now = SystemTime::now();
+        for entry in fs::read_dir(tmp_dir)? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            // Files from the future, e.g. after a clock change, are left alone until they're old.
+            let stale = now.duration_since(modified)
+                .map(|age| age > STALE_TMP_FILE_AGE)
+                .unwrap_or(false);
+            if stale {
+                // Another process may have removed it first.
+                match fs::remove_file(entry.path()) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                    res => res?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The files are named after a hash of the key, so that any key makes a valid file name, and
+    /// spread over 256 directories to keep the directories small.
+    fn file_name(key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(key);
+        hasher.result_str()
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(&name[..2]).join(name)
+    }
+
+    fn evict(&self, index: &mut LruIndex) {
+        while index.total_size > self.max_size {
+            match index.pop_least_recently_used() {
+                Some(name) => {
+                    // A file that can't be removed is forgotten anyway, and only wastes space
+                    // until the cache is opened again.
+                    let _ = fs::remove_file(self.path(&name));
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn read(&self, name: &str) -> io::Result<Option<BlobstoreBytes>> {
+        let mut value = Vec::new();
+        match File::open(self.path(name)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+            Ok(mut file) => {
+                file.read_to_end(&mut value)?;
+                Ok(Some(BlobstoreBytes::from_bytes(value)))
+            }
+        }
+    }
+
+    fn write(&self, name: &str, value: &BlobstoreBytes) -> io::Result<()> {
+        let tmp_path = self.dir.join(TMP_DIR).join(format!(
+            "{}-{}",
+            self.tmp_prefix,
+            self.tmp_files.fetch_add(1, Ordering::Relaxed)
+        ));
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(value.as_bytes().as_ref())?;
+            file.sync_all()?;
+        }
+
+        let path = self.path(name);
+        if let Some(shard) = path.parent() {
+            fs::create_dir_all(shard)?;
+        }
+        fs::rename(&tmp_path, &path).map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            err
+        })
+    }
+}
+
+pub fn new_disk_cache_blobstore<T, P>(
+    blobstore: T,
+    dir: P,
+    max_size: u64,
+) -> Result<CountedBlobstore<CacheBlobstore<DiskCacheOps, InProcessLease, T>>>
+where
+    T: Blobstore + Clone,
+    P: AsRef<Path>,
+{
+    let cache_ops = DiskCacheOps::open(dir, max_size)?;
+    Ok(CountedBlobstore::new(
+        "disk_cache",
+        CacheBlobstore::new(cache_ops, InProcessLease::new(), blobstore),
+    ))
+}
+
+impl CacheOps for DiskCacheOps {
+    fn get(&self, key: &str) -> BoxFuture<Option<BlobstoreBytes>, ()> {
+        let this = self.clone();
+        let name = Self::file_name(key);
+
+        self.pool
+            .spawn_fn(move || {
+                this.read(&name).map_err(|_| ()).map(|value| {
+                    let mut index = this.index.lock().expect("lock poisoned");
+                    match value {
+                        Some(_) => index.touch(&name),
+                        // Removed by another process sharing the directory.
+                        None => index.remove(&name),
+                    }
+                    value
+                })
+            })
+            .boxify()
+    }
+
+    fn put(&self, key: &str, value: BlobstoreBytes) -> BoxFuture<(), ()> {
+        let this = self.clone();
+        let name = Self::file_name(key);
+
+        let size = value.len() as u64;
+        if size > self.max_size {
+            return future::ok(()).boxify();
+        }
+        self.pool
+            .spawn_fn(move || {
+                this.write(&name, &value).map_err(|_| ()).map(|()| {
+                    let mut index = this.index.lock().expect("lock poisoned");
+                    index.insert(name, size);
+                    this.evict(&mut index);
+                })
+            })
+            .boxify()
+    }
+
+    fn check_present(&self, key: &str) -> BoxFuture<bool, ()> {
+        let index = self.index.lock().expect("lock poisoned");
+        future::ok(index.contains(&Self::file_name(key))).boxify()
+    }
+}
+
+impl fmt::Debug for DiskCacheOps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DiskCacheOps")
+            .field("dir", &self.dir)
+            .field("max_size", &self.max_size)
+            .finish()
+    }
+}
@@ -0,0 +1,458 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, BytesMut};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use failure::{Error, Result, ResultExt};
+use futures::{future, Future};
+use rand::{OsRng, Rng};
+use stats::Timeseries;
+
+use futures_ext::{BoxFuture, BoxStream, FutureExt};
+
+use mononoke_types::BlobstoreBytes;
+
+use {Blobstore, BlobstoreDeletable, BlobstoreEnumerable, ErrorKind};
+
+define_stats! {
+    prefix = "mononoke.blobstore.encrypted";
+    puts: timeseries(RATE, SUM),
+    gets_encrypted: timeseries(RATE, SUM),
+    gets_plaintext: timeseries(RATE, SUM),
+    reencrypted: timeseries(RATE, SUM),
+}
+
+/// Starts every value written by `EncryptedBlobstore`.
+const MAGIC: &[u8] = b"\xffMEB";
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+// Magic, version, key id and nonce.
+const HEADER_LEN: usize = 4 + 1 + 4 + NONCE_LEN;
+
+/// The keys a repo's blobs are encrypted with, identified by a number. New values are always
+/// encrypted with the key that has the highest id, the other keys are only used to read values
+/// written before the last rotation.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, [u8; KEY_LEN]>,
+    allow_plaintext: bool,
+}
+
+impl Keyring {
+    pub fn new<I: IntoIterator<Item = (u32, [u8; KEY_LEN])>>(keys: I) -> Result<Self> {
+        let keys: BTreeMap<_, _> = keys.into_iter().collect();
+        if keys.is_empty() {
+            bail_err!(ErrorKind::InvalidKeyring("no keys".into()));
+        }
+        Ok(Keyring {
+            keys,
+            allow_plaintext: false,
+        })
+    }
+
+    /// Whether `EncryptedBlobstore` returns the values written before encryption was enabled as
+    /// they are. By default reading them fails, as anyone with write access to the underlying
+    /// blobstore could otherwise substitute unauthenticated values. This is only meant for
+    /// migrating an existing repo, until `EncryptedBlobstore::reencrypt` has converted its blobs.
+    pub fn allow_plaintext(self, allow_plaintext: bool) -> Self {
+        Keyring {
+            allow_plaintext,
+            ..self
+        }
+    }
+
+    /// Load a keyfile. Every line that isn't empty or a `#` comment holds a key: its id, then
+    /// the 32 bytes of the key in hex. Keys are rotated by appending a key with a higher id.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|_| format!("while reading keyfile {}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (id, key) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(id), Some(key), None) => (id, key),
+                _ => bail_err!(ErrorKind::InvalidKeyring(format!("bad line '{}'", line))),
+            };
+            let id = id.parse::<u32>()
+                .map_err(|_| ErrorKind::InvalidKeyring(format!("bad key id '{}'", id)))?;
+            keys.push((id, parse_key(key)?));
+        }
+        Self::new(keys)
+    }
+
+    /// The id of the key new values are encrypted with.
+    pub fn current_key_id(&self) -> u32 {
+        *self.keys.keys().next_back().expect("keyring is never empty")
+    }
+
+    fn key(&self, id: u32) -> Option<&[u8; KEY_LEN]> {
+        self.keys.get(&id)
+    }
+}
+
+// Don't print the keys themselves.
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("allow_plaintext", &self.allow_plaintext)
+            .finish()
+    }
+}
+
+fn parse_key(hex: &str) -> Result<[u8; KEY_LEN]> {
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        bail_err!(ErrorKind::InvalidKeyring(format!(
+            "keys must be {} hex digits",
+            KEY_LEN * 2
+        )));
+    }
+    let mut key = [0; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| ErrorKind::InvalidKeyring("keys must be in hex".into()))?;
+    }
+    Ok(key)
+}
+
+/// A layer over an existing blobstore that encrypts the values with AES-256-GCM.
+///
+/// Encrypted values start with a header holding the id of the key they were encrypted with and
+/// a random nonce. The header and the blobstore key are authenticated along with the value, so
+/// values can't be tampered with or moved to another key. Values without a header were written
+/// before the layer was added: reading them fails unless the keyring allows plaintext (see
+/// `Keyring::allow_plaintext`), and `reencrypt` converts them.
+///
+/// Encryption defeats compression, so this layer must be below `CompressedBlobstore`.
+#[derive(Clone, Debug)]
+pub struct EncryptedBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+    keyring: Arc<Keyring>,
+    rng: Arc<Mutex<OsRng>>,
+}
+
+impl<T: Blobstore + Clone> EncryptedBlobstore<T> {
+    /// Fails if the nonces can't be generated, i.e. if the OS random number generator isn't
+    /// available.
+    pub fn new(blobstore: T, keyring: Keyring) -> Result<Self> {
+        Ok(Self {
+            blobstore,
+            keyring: Arc::new(keyring),
+            rng: Arc::new(Mutex::new(OsRng::new()?)),
+        })
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.blobstore
+    }
+
+    /// Rewrite the value of `key` encrypted with the current key, if it isn't already. Returns
+    /// whether the value was rewritten. This relies on the underlying blobstore overwriting
+    /// values, which the local blobstores do.
+    pub fn reencrypt(&self, key: String) -> BoxFuture<bool, Error> {
+        let this = self.clone();
+        let keyring = self.keyring.clone();
+        self.blobstore
+            .get(key.clone())
+            .and_then(move |value| -> Result<Option<(String, BlobstoreBytes)>> {
+                let value = value.ok_or_else(|| ErrorKind::NotFound(key.clone()))?;
+                // Converting the values written before encryption was enabled is the point.
+                let (raw, key_id) = decode(&keyring, &key, value, true)?;
+                if key_id == Some(keyring.current_key_id()) {
+                    return Ok(None);
+                }
+                Ok(Some((key, raw)))
+            })
+            .and_then(move |rewrite| match rewrite {
+                Some((key, raw)) => match encode(&this.keyring, &this.rng, &key, raw) {
+                    Ok(encoded) => this.blobstore
+                        .put(key, encoded)
+                        .map(|()| {
+                            STATS::reencrypted.add_value(1);
+                            true
+                        })
+                        .boxify(),
+                    Err(err) => future::err(err).boxify(),
+                },
+                None => future::ok(false).boxify(),
+            })
+            .boxify()
+    }
+}
+
+fn aad(header: &[u8], key: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + key.len());
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(key.as_bytes());
+    aad
+}
+
+fn encode(
+    keyring: &Keyring,
+    rng: &Mutex<OsRng>,
+    key: &str,
+    value: BlobstoreBytes,
+) -> Result<BlobstoreBytes> {
+    let raw = value.into_bytes();
+    let key_id = keyring.current_key_id();
+    let mut nonce = [0; NONCE_LEN];
+    rng.lock().expect("lock poisoned").fill_bytes(&mut nonce);
+
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + raw.len() + TAG_LEN);
+    buf.put_slice(MAGIC);
+    buf.put_u8(VERSION);
+    buf.put_u32_be(key_id);
+    buf.put_slice(&nonce);
+
+    let mut cipher = AesGcm::new(
+        KeySize::KeySize256,
+        keyring.key(key_id).expect("current key is in the keyring"),
+        &nonce,
+        &aad(&buf, key),
+    );
+    let mut ciphertext = vec![0; raw.len()];
+    let mut tag = [0; TAG_LEN];
+    cipher.encrypt(&raw, &mut ciphertext, &mut tag);
+    buf.put_slice(&ciphertext);
+    buf.put_slice(&tag);
+
+    STATS::puts.add_value(1);
+    Ok(BlobstoreBytes::from_bytes(buf.freeze()))
+}
+
+/// Returns the decrypted value and the id of the key it was encrypted with, or None if it
+/// wasn't encrypted. Values that aren't encrypted are only accepted if `allow_plaintext` is set.
+fn decode(
+    keyring: &Keyring,
+    key: &str,
+    value: BlobstoreBytes,
+    allow_plaintext: bool,
+) -> Result<(BlobstoreBytes, Option<u32>)> {
+    let invalid = |reason: &str| ErrorKind::InvalidEncryptedBlob(key.to_string(), reason.into());
+    let stored = value.into_bytes();
+    if !stored.starts_with(MAGIC) {
+        if !allow_plaintext {
+            bail_err!(invalid("not encrypted"));
+        }
+        STATS::gets_plaintext.add_value(1);
+        return Ok((BlobstoreBytes::from_bytes(stored), None));
+    }
+
+    if stored.len() < HEADER_LEN + TAG_LEN {
+        bail_err!(invalid("truncated"));
+    }
+    if stored[MAGIC.len()] != VERSION {
+        bail_err!(invalid(&format!("unknown version {}", stored[MAGIC.len()])));
+    }
+    let key_id = BigEndian::read_u32(&stored[MAGIC.len() + 1..MAGIC.len() + 5]);
+    let encryption_key = keyring
+        .key(key_id)
+        .ok_or_else(|| ErrorKind::UnknownEncryptionKey(key.to_string(), key_id))?;
+
+    let header = &stored[..HEADER_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+    let (ciphertext, tag) = stored[HEADER_LEN..].split_at(stored.len() - HEADER_LEN - TAG_LEN);
+    let mut cipher = AesGcm::new(KeySize::KeySize256, encryption_key, nonce, &aad(header, key));
+    let mut raw = vec![0; ciphertext.len()];
+    if !cipher.decrypt(ciphertext, &mut raw, tag) {
+        bail_err!(invalid("authentication failed"));
+    }
+
+    STATS::gets_encrypted.add_value(1);
+    Ok((BlobstoreBytes::from_bytes(raw), Some(key_id)))
+}
+
+impl<T: Blobstore + Clone> Blobstore for EncryptedBlobstore<T> {
+    #[inline]
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let keyring = self.keyring.clone();
+        self.blobstore
+            .get(key.clone())
+            .and_then(move |value| match value {
+                Some(value) => decode(&keyring, &key, value, keyring.allow_plaintext)
+                    .map(|(raw, _)| Some(raw)),
+                None => Ok(None),
+            })
+            .boxify()
+    }
+
+    #[inline]
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        match encode(&self.keyring, &self.rng, &key, value) {
+            Ok(encoded) => self.blobstore.put(key, encoded),
+            Err(err) => future::err(err).boxify(),
+        }
+    }
+
+    #[inline]
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(key)
+    }
+}
+
+impl<T: BlobstoreEnumerable + Clone> BlobstoreEnumerable for EncryptedBlobstore<T> {
+    #[inline]
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        self.blobstore.enumerate(prefix, after)
+    }
+}
+
+impl<T: BlobstoreDeletable + Clone> BlobstoreDeletable for EncryptedBlobstore<T> {
+    #[inline]
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        self.blobstore.delete(key)
+    }
+
+    #[inline]
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        self.blobstore.last_modified(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use memblob::EagerMemblob;
+
+    const KEY1: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEYFILE: &str = "
+        # test keys
+        1 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f
+    ";
+    const ROTATED_KEYFILE: &str = "
+        1 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f
+        2 202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f
+    ";
+
+    fn get(blobstore: &Blobstore, key: &str) -> Result<Bytes> {
+        Ok(blobstore
+            .get(key.to_string())
+            .wait()?
+            .expect("value should be present")
+            .into_bytes())
+    }
+
+    fn put(blobstore: &Blobstore, key: &str, value: &'static [u8]) {
+        blobstore
+            .put(key.to_string(), BlobstoreBytes::from_bytes(value))
+            .wait()
+            .expect("put should succeed");
+    }
+
+    #[test]
+    fn test_keyring_parse() {
+        let keyring = Keyring::parse(ROTATED_KEYFILE).expect("keyfile should parse");
+        assert_eq!(keyring.current_key_id(), 2);
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("1 0011").is_err());
+        assert!(Keyring::parse(&format!("x {}", KEY1)).is_err());
+        assert!(Keyring::parse(&format!("1 {} extra", KEY1)).is_err());
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let base = EagerMemblob::new();
+        let encrypted =
+            EncryptedBlobstore::new(base.clone(), Keyring::parse(KEYFILE).unwrap()).unwrap();
+
+        put(&encrypted, "key", b"secret value");
+        assert_eq!(get(&encrypted, "key").unwrap(), Bytes::from(&b"secret value"[..]));
+        let stored = get(&base, "key").unwrap();
+        assert!(stored.starts_with(MAGIC));
+        assert!(!stored.windows(6).any(|window| window == b"secret"));
+
+        // Every value gets its own nonce.
+        put(&encrypted, "same", b"secret value");
+        assert_ne!(get(&base, "same").unwrap(), stored);
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let base = EagerMemblob::new();
+        put(&base, "legacy", b"legacy value");
+
+        // Values written before the layer was added are only readable during a migration.
+        let strict =
+            EncryptedBlobstore::new(base.clone(), Keyring::parse(KEYFILE).unwrap()).unwrap();
+        assert!(get(&strict, "legacy").is_err());
+
+        let keyring = Keyring::parse(KEYFILE).unwrap().allow_plaintext(true);
+        let migrating = EncryptedBlobstore::new(base.clone(), keyring).unwrap();
+        assert_eq!(get(&migrating, "legacy").unwrap(), Bytes::from(&b"legacy value"[..]));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let base = EagerMemblob::new();
+        let encrypted =
+            EncryptedBlobstore::new(base.clone(), Keyring::parse(KEYFILE).unwrap()).unwrap();
+        put(&encrypted, "key", b"secret value");
+        let stored = get(&base, "key").unwrap();
+
+        // A value moved to another key doesn't authenticate.
+        base.put("other".to_string(), BlobstoreBytes::from_bytes(stored.clone()))
+            .wait()
+            .unwrap();
+        assert!(get(&encrypted, "other").is_err());
+
+        let mut tampered = stored.to_vec();
+        tampered[HEADER_LEN] ^= 1;
+        base.put("key".to_string(), BlobstoreBytes::from_bytes(tampered))
+            .wait()
+            .unwrap();
+        assert!(get(&encrypted, "key").is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let base = EagerMemblob::new();
+        let old = EncryptedBlobstore::new(base.clone(), Keyring::parse(KEYFILE).unwrap()).unwrap();
+        put(&old, "old", b"old value");
+        put(&base, "legacy", b"legacy value");
+
+        let keyring = Keyring::parse(ROTATED_KEYFILE).unwrap();
+        let rotated = EncryptedBlobstore::new(base.clone(), keyring).unwrap();
+        assert_eq!(get(&rotated, "old").unwrap(), Bytes::from(&b"old value"[..]));
+        put(&rotated, "new", b"new value");
+        // The old keyring doesn't have the key the new value was encrypted with.
+        assert!(get(&old, "new").is_err());
+
+        assert!(rotated.reencrypt("old".to_string()).wait().unwrap());
+        // Re-encrypting converts the values written before encryption was enabled, even though
+        // the keyring doesn't allow reading them.
+        assert!(rotated.reencrypt("legacy".to_string()).wait().unwrap());
+        assert!(!rotated.reencrypt("new".to_string()).wait().unwrap());
+        assert!(get(&base, "legacy").unwrap().starts_with(MAGIC));
+        assert!(get(&old, "old").is_err());
+        assert_eq!(get(&rotated, "old").unwrap(), Bytes::from(&b"old value"[..]));
+        assert_eq!(get(&rotated, "legacy").unwrap(), Bytes::from(&b"legacy value"[..]));
+    }
+}
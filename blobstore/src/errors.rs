@@ -7,4 +7,12 @@
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "Blob {} not found in blobstore", _0)] NotFound(String),
+    #[fail(display = "Blob {} is not a valid compressed blob: {}", _0, _1)]
+    InvalidCompressedBlob(String, String),
+    #[fail(display = "Blob {} is censored: {}", _0, _1)] Censored(String, String),
+    #[fail(display = "Blob {} is not a valid encrypted blob: {}", _0, _1)]
+    InvalidEncryptedBlob(String, String),
+    #[fail(display = "Blob {} is encrypted with unknown key {}", _0, _1)]
+    UnknownEncryptionKey(String, u32),
+    #[fail(display = "Invalid encryption keyring: {}", _0)] InvalidKeyring(String),
 }
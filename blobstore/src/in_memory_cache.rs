@@ -5,8 +5,10 @@
 // GNU General Public License version 2 or any later version.
 
 use std::fmt;
+use std::path::Path;
 
-use failure::Error;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use failure::{Error, Result};
 use futures::{Future, IntoFuture};
 use futures_ext::{BoxFuture, FutureExt};
 
@@ -29,6 +31,18 @@ impl<T: Blobstore + Clone> MemoizedBlobstore<T> {
         let cache = Asyncmemo::with_limits("blobstore", filler, entries_limit, bytes_limit);
         CountedBlobstore::new("in_memory", MemoizedBlobstore { cache, blobstore })
     }
+
+    /// Save the cached blobs to a file, so that they can be loaded with `load_snapshot` after a
+    /// restart. Returns the number of blobs saved.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.save_snapshot_file(path)?)
+    }
+
+    /// Load the blobs saved by `save_snapshot` into the cache, as far as they fit. Returns the
+    /// number of blobs loaded.
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.load_snapshot_file(path)?)
+    }
 }
 
 impl<T: Blobstore + Clone> Blobstore for MemoizedBlobstore<T> {
@@ -97,4 +111,29 @@ impl<T: Blobstore + Clone> Filler for BlobstoreCacheFiller<T> {
             })
             .boxify()
     }
+
+    // An entry is the length of the key as a big-endian integer, the key and the blob.
+    fn serialize_entry(&self, key: &Self::Key, value: &BlobstoreBytes) -> Option<Vec<u8>> {
+        if key.len() > u32::max_value() as usize {
+            return None;
+        }
+        let mut data = Vec::with_capacity(4 + key.len() + value.len());
+        data.write_u32::<BigEndian>(key.len() as u32).ok()?;
+        data.extend_from_slice(key.as_bytes());
+        data.extend_from_slice(value.as_bytes());
+        Some(data)
+    }
+
+    fn deserialize_entry(&self, data: &[u8]) -> Option<(Self::Key, BlobstoreBytes)> {
+        if data.len() < 4 {
+            return None;
+        }
+        let key_len = BigEndian::read_u32(&data[..4]) as usize;
+        if data.len() - 4 < key_len {
+            return None;
+        }
+        let key = String::from_utf8(data[4..4 + key_len].to_vec()).ok()?;
+        let value = BlobstoreBytes::from_bytes(&data[4 + key_len..]);
+        Some((key, value))
+    }
 }
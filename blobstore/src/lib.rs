@@ -7,13 +7,18 @@
 #![deny(warnings)]
 
 extern crate asyncmemo;
+extern crate byteorder;
 extern crate bytes;
+extern crate crypto;
 #[macro_use]
 extern crate failure_ext as failure;
 extern crate futures;
+extern crate futures_cpupool;
 extern crate inlinable_string;
+extern crate rand;
 extern crate tokio;
 extern crate tokio_timer;
+extern crate zstd;
 
 extern crate cachelib;
 extern crate fbwhoami;
@@ -29,21 +34,32 @@ extern crate stats;
 
 use std::fmt;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use failure::Error;
 use futures::{future, Future};
-use futures_ext::{BoxFuture, FutureExt};
+use futures_ext::{BoxFuture, BoxStream, FutureExt};
 
 use mononoke_types::BlobstoreBytes;
 
 mod cachelib_cache;
 pub use cachelib_cache::{new_cachelib_blobstore, new_cachelib_blobstore_no_lease};
 
+mod compressed;
+pub use compressed::{CompressedBlobstore, DEFAULT_COMPRESSION_LEVEL,
+                     DEFAULT_COMPRESSION_THRESHOLD};
+
 mod counted_blobstore;
 pub use counted_blobstore::CountedBlobstore;
 
+mod disk_cache;
+pub use disk_cache::{new_disk_cache_blobstore, DiskCacheOps};
+
 mod dummy_lease;
 
+mod encrypted;
+pub use encrypted::{EncryptedBlobstore, Keyring};
+
 mod in_memory_cache;
 pub use in_memory_cache::MemoizedBlobstore;
 
@@ -64,9 +80,35 @@ pub use mem_writes::MemWritesBlobstore;
 mod prefix;
 pub use prefix::PrefixBlobstore;
 
+mod redacted;
+pub use redacted::{censored_reason, RedactedBlobstore, RedactedKeys};
+
 mod errors;
 pub use errors::ErrorKind;
 
+/// Identifies one of the blobstores that a multiplexed blobstore writes to. The ids are assigned
+/// in the repo config and must stay stable, as they are recorded in the blobstore sync queue.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BlobstoreId(u64);
+
+impl BlobstoreId {
+    #[inline]
+    pub fn new(id: u64) -> Self {
+        BlobstoreId(id)
+    }
+
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for BlobstoreId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// The blobstore interface, shared across all blobstores.
 /// A blobstore must provide the following guarantees:
 /// 1. `get` and `put` are atomic with respect to each other; a put will either put the entire
@@ -149,3 +191,49 @@ impl Blobstore for Box<Blobstore> {
         self.as_ref().assert_present(key)
     }
 }
+
+/// A blobstore that can list the keys it contains. Not every backend can do this efficiently,
+/// so this is separate from `Blobstore`; it is meant for maintenance tools (scrubbing, garbage
+/// collection, migrations) rather than for serving.
+pub trait BlobstoreEnumerable: Blobstore {
+    /// Stream the keys starting with `prefix`, in lexicographic order. If `after` is given, only
+    /// the keys sorting after it are returned, so an interrupted enumeration can be resumed by
+    /// passing the last key it yielded.
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error>;
+}
+
+impl Blobstore for Arc<BlobstoreEnumerable> {
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        self.as_ref().get(key)
+    }
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        self.as_ref().put(key, value)
+    }
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        self.as_ref().is_present(key)
+    }
+    fn assert_present(&self, key: String) -> BoxFuture<(), Error> {
+        self.as_ref().assert_present(key)
+    }
+}
+
+impl BlobstoreEnumerable for Arc<BlobstoreEnumerable> {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        self.as_ref().enumerate(prefix, after)
+    }
+}
+
+/// A blobstore that blobs can be removed from. Removing a blob breaks the guarantees of
+/// `Blobstore` for anyone who still refers to it, so this is only meant for garbage collection.
+pub trait BlobstoreDeletable: BlobstoreEnumerable {
+    /// Remove the value associated with `key`. Removing a missing key is not an error.
+    fn delete(&self, key: String) -> BoxFuture<(), Error>;
+    /// When the value associated with `key` was last written, or None if the blobstore doesn't
+    /// keep track of it.
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error>;
+}
+
+/// Returns whether `key` should be yielded by `BlobstoreEnumerable::enumerate`.
+pub fn enumerate_matches(key: &str, prefix: &str, after: Option<&str>) -> bool {
+    key.starts_with(prefix) && after.map_or(true, |after| key > after)
+}
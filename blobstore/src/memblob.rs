@@ -7,14 +7,16 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use failure::Error;
+use futures::{stream, Future};
 use futures::future::{lazy, IntoFuture};
-use futures_ext::{BoxFuture, FutureExt};
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use mononoke_types::BlobstoreBytes;
 
-use Blobstore;
+use {enumerate_matches, Blobstore, BlobstoreDeletable, BlobstoreEnumerable};
 
 /// In-memory "blob store"
 ///
@@ -83,6 +85,71 @@ impl Blobstore for LazyMemblob {
     }
 }
 
+fn sorted_keys(
+    hash: &HashMap<String, BlobstoreBytes>,
+    prefix: &str,
+    after: Option<&str>,
+) -> Vec<String> {
+    let mut keys: Vec<_> = hash.keys()
+        .filter(|key| enumerate_matches(key, prefix, after))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys
+}
+
+impl BlobstoreEnumerable for EagerMemblob {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        let inner = self.hash.lock().expect("lock poison");
+
+        let keys = sorted_keys(&inner, &prefix, after.as_ref().map(String::as_str));
+        stream::iter_ok(keys).boxify()
+    }
+}
+
+impl BlobstoreEnumerable for LazyMemblob {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        let hash = self.hash.clone();
+
+        lazy(move || {
+            let inner = hash.lock().expect("lock poison");
+            let keys = sorted_keys(&inner, &prefix, after.as_ref().map(String::as_str));
+            Ok::<_, Error>(stream::iter_ok(keys))
+        }).flatten_stream()
+            .boxify()
+    }
+}
+
+impl BlobstoreDeletable for EagerMemblob {
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        let mut inner = self.hash.lock().expect("lock poison");
+
+        inner.remove(&key);
+        Ok(()).into_future().boxify()
+    }
+
+    fn last_modified(&self, _key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        Ok(None).into_future().boxify()
+    }
+}
+
+impl BlobstoreDeletable for LazyMemblob {
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        let hash = self.hash.clone();
+
+        lazy(move || {
+            let mut inner = hash.lock().expect("lock poison");
+
+            inner.remove(&key);
+            Ok(()).into_future()
+        }).boxify()
+    }
+
+    fn last_modified(&self, _key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        Ok(None).into_future().boxify()
+    }
+}
+
 impl fmt::Debug for EagerMemblob {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("EagerMemblob")
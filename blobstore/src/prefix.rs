@@ -4,14 +4,17 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::time::SystemTime;
+
 use failure::Error;
 use inlinable_string::InlinableString;
 
-use futures_ext::BoxFuture;
+use futures::Stream;
+use futures_ext::{BoxFuture, BoxStream, StreamExt};
 
 use mononoke_types::BlobstoreBytes;
 
-use {Blobstore, CacheBlobstoreExt};
+use {Blobstore, BlobstoreDeletable, BlobstoreEnumerable, CacheBlobstoreExt};
 
 /// A layer over an existing blobstore that prepends a fixed string to each get and put.
 #[derive(Clone, Debug)]
@@ -67,6 +70,28 @@ impl<T: Blobstore + Clone> Blobstore for PrefixBlobstore<T> {
     }
 }
 
+impl<T: BlobstoreEnumerable + Clone> BlobstoreEnumerable for PrefixBlobstore<T> {
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        let prefix_len = self.prefix.len();
+        self.blobstore
+            .enumerate(self.prepend(prefix), after.map(|after| self.prepend(after)))
+            .map(move |key| key[prefix_len..].to_string())
+            .boxify()
+    }
+}
+
+impl<T: BlobstoreDeletable + Clone> BlobstoreDeletable for PrefixBlobstore<T> {
+    #[inline]
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        self.blobstore.delete(self.prepend(key))
+    }
+
+    #[inline]
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        self.blobstore.last_modified(self.prepend(key))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -124,4 +149,30 @@ mod test {
                 .expect("is_present should succeed")
         );
     }
+
+    #[test]
+    fn test_prefix_enumerate() {
+        let base = EagerMemblob::new();
+        let prefixed = PrefixBlobstore::new(base.clone(), "prefix123-");
+
+        for key in &["prefix123-a1", "prefix123-a2", "prefix123-b1", "other-a1"] {
+            base.put(key.to_string(), BlobstoreBytes::from_bytes("test"))
+                .wait()
+                .expect("put should succeed");
+        }
+
+        let keys = prefixed
+            .enumerate("a".to_string(), None)
+            .collect()
+            .wait()
+            .expect("enumerate should succeed");
+        assert_eq!(keys, vec!["a1".to_string(), "a2".to_string()]);
+
+        let keys = prefixed
+            .enumerate("".to_string(), Some("a1".to_string()))
+            .collect()
+            .wait()
+            .expect("enumerate should succeed");
+        assert_eq!(keys, vec!["a2".to_string(), "b1".to_string()]);
+    }
 }
@@ -0,0 +1,205 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use failure::{Compat, Error};
+use futures::Future;
+use futures::future::Shared;
+use stats::Timeseries;
+
+use futures_ext::{BoxFuture, BoxStream, FutureExt};
+
+use mononoke_types::BlobstoreBytes;
+
+use {Blobstore, BlobstoreDeletable, BlobstoreEnumerable, CacheBlobstoreExt, ErrorKind};
+
+define_stats! {
+    prefix = "mononoke.blobstore.redacted";
+    gets_redacted: timeseries(RATE, SUM),
+}
+
+/// Maps the keys of the redacted blobs to the reason they were redacted for.
+pub type RedactedKeys = HashMap<String, String>;
+
+/// A layer over an existing blobstore that refuses to return the blobs of a redaction list.
+/// Getting one of them fails with `ErrorKind::Censored`; everything else, including checking
+/// that a redacted blob is present, is passed through.
+///
+/// The list is loaded once, on the first get, so changes to it are only seen by blobstores
+/// created after them.
+#[derive(Clone)]
+pub struct RedactedBlobstore<T: Blobstore + Clone> {
+    blobstore: T,
+    // The Compat<Error> here is because the error type for Shared (a cloneable wrapper called
+    // SharedError) doesn't implement Fail, and only implements Error if the wrapped type
+    // implements Error.
+    redacted: Shared<BoxFuture<Arc<RedactedKeys>, Compat<Error>>>,
+}
+
+impl<T: Blobstore + Clone> RedactedBlobstore<T> {
+    pub fn new(blobstore: T, redacted: BoxFuture<RedactedKeys, Error>) -> Self {
+        Self {
+            blobstore,
+            redacted: redacted.map(Arc::new).map_err(Error::compat).boxify().shared(),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.blobstore
+    }
+
+    fn check(
+        &self,
+        key: String,
+        get: BoxFuture<Option<BlobstoreBytes>, Error>,
+    ) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        self.redacted
+            .clone()
+            .from_err()
+            .and_then(move |redacted| match redacted.get(&key) {
+                Some(reason) => {
+                    STATS::gets_redacted.add_value(1);
+                    Err(ErrorKind::Censored(key, reason.clone()).into())
+                }
+                None => Ok(()),
+            })
+            .and_then(move |()| get)
+            .boxify()
+    }
+}
+
+impl<T: Blobstore + Clone> fmt::Debug for RedactedBlobstore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedactedBlobstore")
+            .field("blobstore", &self.blobstore)
+            .finish()
+    }
+}
+
+impl<T: CacheBlobstoreExt + Clone> CacheBlobstoreExt for RedactedBlobstore<T> {
+    #[inline]
+    fn get_no_cache_fill(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let get = self.blobstore.get_no_cache_fill(key.clone());
+        self.check(key, get)
+    }
+
+    #[inline]
+    fn get_cache_only(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let get = self.blobstore.get_cache_only(key.clone());
+        self.check(key, get)
+    }
+}
+
+impl<T: Blobstore + Clone> Blobstore for RedactedBlobstore<T> {
+    #[inline]
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let get = self.blobstore.get(key.clone());
+        self.check(key, get)
+    }
+
+    #[inline]
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        self.blobstore.put(key, value)
+    }
+
+    #[inline]
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        self.blobstore.is_present(key)
+    }
+}
+
+impl<T: BlobstoreEnumerable + Clone> BlobstoreEnumerable for RedactedBlobstore<T> {
+    #[inline]
+    fn enumerate(&self, prefix: String, after: Option<String>) -> BoxStream<String, Error> {
+        self.blobstore.enumerate(prefix, after)
+    }
+}
+
+impl<T: BlobstoreDeletable + Clone> BlobstoreDeletable for RedactedBlobstore<T> {
+    #[inline]
+    fn delete(&self, key: String) -> BoxFuture<(), Error> {
+        self.blobstore.delete(key)
+    }
+
+    #[inline]
+    fn last_modified(&self, key: String) -> BoxFuture<Option<SystemTime>, Error> {
+        self.blobstore.last_modified(key)
+    }
+}
+
+/// Returns the reason the blob was redacted for if `err`, or one of its causes, is
+/// `ErrorKind::Censored`.
+pub fn censored_reason(err: &Error) -> Option<&str> {
+    err.iter_chain()
+        .filter_map(|cause| match cause.downcast_ref::<ErrorKind>() {
+            Some(&ErrorKind::Censored(_, ref reason)) => Some(reason.as_str()),
+            _ => None,
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+    use futures::future;
+
+    use memblob::EagerMemblob;
+
+    fn redacted_blobstore() -> RedactedBlobstore<EagerMemblob> {
+        let mut redacted = RedactedKeys::new();
+        redacted.insert("secret".to_string(), "leaked password".to_string());
+        RedactedBlobstore::new(EagerMemblob::new(), future::ok(redacted).boxify())
+    }
+
+    #[test]
+    fn test_get_redacted() {
+        let blobstore = redacted_blobstore();
+        let value = BlobstoreBytes::from_bytes(Bytes::from_static(b"hunter2"));
+        blobstore
+            .put("secret".to_string(), value.clone())
+            .and_then(|()| blobstore.put("public".to_string(), value.clone()))
+            .wait()
+            .expect("put should succeed");
+
+        let err = blobstore
+            .get("secret".to_string())
+            .wait()
+            .expect_err("get of a redacted blob should fail");
+        assert_eq!(censored_reason(&err), Some("leaked password"));
+
+        assert!(
+            blobstore
+                .is_present("secret".to_string())
+                .wait()
+                .expect("is_present should succeed")
+        );
+        assert_eq!(
+            blobstore
+                .get("public".to_string())
+                .wait()
+                .expect("get should succeed"),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn test_censored_reason_with_context() {
+        let err: Error = ErrorKind::Censored("secret".to_string(), "leaked token".to_string())
+            .into();
+        let err: Error = err.context("while fetching").into();
+        assert_eq!(censored_reason(&err), Some("leaked token"));
+
+        let err: Error = ErrorKind::NotFound("secret".to_string()).into();
+        assert_eq!(censored_reason(&err), None);
+    }
+}
@@ -21,10 +21,11 @@ extern crate mononoke_types;
 extern crate rocksblob;
 
 use bytes::Bytes;
-use futures::Future;
+use futures::{Future, Stream};
 use tempdir::TempDir;
 
-use blobstore::{Blobstore, EagerMemblob};
+use blobstore::{Blobstore, BlobstoreDeletable, BlobstoreEnumerable, CacheOps, DiskCacheOps,
+                EagerMemblob, MemoizedBlobstore};
 use fileblob::Fileblob;
 use mononoke_types::BlobstoreBytes;
 use rocksblob::Rocksblob;
@@ -67,6 +68,51 @@ where
     assert_eq!(out.into_bytes(), Bytes::from_static(b"bar"));
 }
 
+fn enumerate<B>(blobstore: B)
+where
+    B: BlobstoreEnumerable,
+{
+    let keys = vec!["bar1", "foo2", "foo1", "foo3"];
+    for key in &keys {
+        blobstore
+            .put(key.to_string(), BlobstoreBytes::from_bytes(&b"value"[..]))
+            .wait()
+            .expect("put failed");
+    }
+
+    let enumerate = |prefix: &str, after: Option<&str>| {
+        blobstore
+            .enumerate(prefix.to_string(), after.map(|after| after.to_string()))
+            .collect()
+            .wait()
+            .expect("enumerate failed")
+    };
+
+    assert_eq!(enumerate("", None), vec!["bar1", "foo1", "foo2", "foo3"]);
+    assert_eq!(enumerate("foo", None), vec!["foo1", "foo2", "foo3"]);
+    assert_eq!(enumerate("foo", Some("foo1")), vec!["foo2", "foo3"]);
+    assert_eq!(enumerate("foo", Some("bar1")), vec!["foo1", "foo2", "foo3"]);
+    assert_eq!(enumerate("", Some("foo3")), Vec::<String>::new());
+    assert_eq!(enumerate("baz", None), Vec::<String>::new());
+}
+
+fn delete<B>(blobstore: B)
+where
+    B: BlobstoreDeletable,
+{
+    let foo = "foo".to_string();
+    blobstore
+        .put(foo.clone(), BlobstoreBytes::from_bytes(&b"bar"[..]))
+        .wait()
+        .expect("put failed");
+
+    blobstore.delete(foo.clone()).wait().expect("delete failed");
+    assert!(!blobstore.is_present(foo.clone()).wait().expect("is_present failed"));
+
+    // Deleting a missing key is fine.
+    blobstore.delete(foo).wait().expect("delete failed");
+}
+
 macro_rules! blobstore_test_impl {
     ($mod_name: ident => {
         state: $state: expr,
@@ -93,6 +139,18 @@ macro_rules! blobstore_test_impl {
                 let state = $state;
                 boxable($new_cb(&state));
             }
+
+            #[test]
+            fn test_enumerate() {
+                let state = $state;
+                enumerate($new_cb(&state));
+            }
+
+            #[test]
+            fn test_delete() {
+                let state = $state;
+                delete($new_cb(&state));
+            }
         }
     }
 }
@@ -121,3 +179,117 @@ blobstore_test_impl! {
         persistent: true,
     }
 }
+
+fn cache_get(cache: &DiskCacheOps, key: &str) -> Option<Bytes> {
+    cache
+        .get(key)
+        .wait()
+        .expect("get failed")
+        .map(BlobstoreBytes::into_bytes)
+}
+
+fn cache_put(cache: &DiskCacheOps, key: &str, value: &'static [u8]) {
+    cache
+        .put(key, BlobstoreBytes::from_bytes(value))
+        .wait()
+        .expect("put failed");
+}
+
+#[test]
+fn test_disk_cache_simple() {
+    let dir = TempDir::new("disk_cache_test").unwrap();
+    let cache = DiskCacheOps::open(&dir, 100).unwrap();
+
+    assert_eq!(cache_get(&cache, "foo"), None);
+    assert!(!cache.check_present("foo").wait().unwrap());
+
+    cache_put(&cache, "foo", b"bar");
+    assert_eq!(cache_get(&cache, "foo"), Some(Bytes::from_static(b"bar")));
+    assert!(cache.check_present("foo").wait().unwrap());
+
+    // Too large to ever fit.
+    cache_put(&cache, "large", &[0; 101]);
+    assert_eq!(cache_get(&cache, "large"), None);
+}
+
+#[test]
+fn test_disk_cache_evicts_least_recently_used() {
+    let dir = TempDir::new("disk_cache_test").unwrap();
+    let cache = DiskCacheOps::open(&dir, 10).unwrap();
+
+    cache_put(&cache, "a", b"aaaa");
+    cache_put(&cache, "b", b"bbbb");
+    assert!(cache_get(&cache, "a").is_some());
+    cache_put(&cache, "c", b"cccc");
+
+    assert!(cache_get(&cache, "a").is_some());
+    assert_eq!(cache_get(&cache, "b"), None);
+    assert!(cache_get(&cache, "c").is_some());
+}
+
+#[test]
+fn test_disk_cache_persistent() {
+    let dir = TempDir::new("disk_cache_test").unwrap();
+    {
+        let cache = DiskCacheOps::open(&dir, 100).unwrap();
+        cache_put(&cache, "foo", b"bar");
+        cache_put(&cache, "baz", b"qux");
+    }
+
+    let cache = DiskCacheOps::open(&dir, 100).unwrap();
+    assert!(cache.check_present("foo").wait().unwrap());
+    assert_eq!(cache_get(&cache, "foo"), Some(Bytes::from_static(b"bar")));
+
+    // Reopening with a smaller size evicts the entries that don't fit anymore.
+    let cache = DiskCacheOps::open(&dir, 3).unwrap();
+    assert_eq!(
+        vec![cache_get(&cache, "foo"), cache_get(&cache, "baz")]
+            .into_iter()
+            .filter(Option::is_some)
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_disk_cache_shared_tmp_dir() {
+    let dir = TempDir::new("disk_cache_test").unwrap();
+    let first = DiskCacheOps::open(&dir, 100).unwrap();
+
+    // A write of another process sharing the directory, still in progress.
+    let in_progress = dir.path().join("tmp").join("in-progress");
+    std::fs::write(&in_progress, b"partial").unwrap();
+
+    let second = DiskCacheOps::open(&dir, 100).unwrap();
+    assert!(in_progress.exists());
+
+    cache_put(&first, "foo", b"bar");
+    cache_put(&second, "baz", b"qux");
+    assert_eq!(cache_get(&second, "foo"), Some(Bytes::from_static(b"bar")));
+    assert_eq!(cache_get(&first, "baz"), Some(Bytes::from_static(b"qux")));
+}
+
+#[test]
+fn test_memoized_snapshot() {
+    let dir = TempDir::new("memoized_snapshot_test").unwrap();
+    let snapshot = dir.path().join("snapshot");
+
+    let blobstore = EagerMemblob::new();
+    blobstore
+        .put("foo".to_string(), BlobstoreBytes::from_bytes(&b"bar"[..]))
+        .wait()
+        .expect("put failed");
+    let cache = MemoizedBlobstore::new(blobstore, 100, 10000);
+    cache.get("foo".to_string()).wait().expect("get failed");
+    assert_eq!(cache.as_inner().save_snapshot(&snapshot).unwrap(), 1);
+
+    // The blob is served from the loaded cache, as it isn't in the new blobstore.
+    let cache = MemoizedBlobstore::new(EagerMemblob::new(), 100, 10000);
+    assert_eq!(cache.as_inner().load_snapshot(&snapshot).unwrap(), 1);
+    let out = cache
+        .get("foo".to_string())
+        .wait()
+        .expect("get failed")
+        .expect("missing");
+    assert_eq!(out.into_bytes(), Bytes::from_static(b"bar"));
+}
@@ -0,0 +1,10 @@
+CREATE TABLE blobstore_sync_queue (
+  id BIGINT NOT NULL AUTO_INCREMENT,
+  repo_id INT UNSIGNED NOT NULL,
+  blobstore_key VARCHAR(255) NOT NULL,
+  blobstore_id BIGINT NOT NULL,
+  add_timestamp BIGINT NOT NULL,
+  PRIMARY KEY (id),
+  KEY repo_key (repo_id, blobstore_key),
+  KEY repo_timestamp (repo_id, add_timestamp)
+);
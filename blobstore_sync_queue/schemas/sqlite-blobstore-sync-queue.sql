@@ -0,0 +1,9 @@
+CREATE TABLE blobstore_sync_queue (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  repo_id INTEGER NOT NULL,
+  blobstore_key VARCHAR(255) NOT NULL,
+  blobstore_id BIGINT NOT NULL,
+  add_timestamp BIGINT NOT NULL
+);
+CREATE INDEX blobstore_sync_queue_key ON blobstore_sync_queue (repo_id, blobstore_key);
+CREATE INDEX blobstore_sync_queue_timestamp ON blobstore_sync_queue (repo_id, add_timestamp);
@@ -0,0 +1,329 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Queue of the writes that a multiplexed blobstore didn't do to some of its blobstores.
+//!
+//! A multiplexed blobstore considers a put successful once a quorum of its blobstores stored the
+//! blob. Every blobstore that failed, or didn't answer in time, gets an entry in this queue so
+//! that the blob can later be copied to it from one of the blobstores that have it.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+extern crate db_conn;
+#[macro_use]
+extern crate diesel;
+extern crate failure_ext as failure;
+
+extern crate blobstore;
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+extern crate mononoke_types;
+#[macro_use]
+extern crate stats;
+
+use std::result;
+use std::sync::{Arc, MutexGuard};
+
+use db_conn::{MysqlConnInner, SqliteConnInner};
+use diesel::{delete, insert_into, MysqlConnection, SqliteConnection};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+
+use blobstore::BlobstoreId;
+use futures_ext::{asynchronize, BoxFuture};
+use mercurial_types::RepositoryId;
+use mononoke_types::DateTime;
+use stats::Timeseries;
+
+mod models;
+mod schema;
+
+pub use failure::{Error, Result};
+use models::{BlobstoreSyncQueueRow, NewBlobstoreSyncQueueRow};
+use schema::blobstore_sync_queue;
+
+/// Maximum number of values used in a single `IN` clause. SQLite doesn't allow more than 999
+/// variables in a query.
+const MAX_IN_CLAUSE_SIZE: usize = 500;
+
+define_stats! {
+    prefix = "mononoke.blobstore_sync_queue";
+    adds: timeseries(RATE, SUM),
+    iters: timeseries(RATE, SUM),
+    gets: timeseries(RATE, SUM),
+    dels: timeseries(RATE, SUM),
+}
+
+/// A blob that is missing from one of the blobstores of a multiplexed blobstore.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct BlobstoreSyncQueueEntry {
+    pub repo_id: RepositoryId,
+    pub blobstore_key: String,
+    /// The blobstore that the blob is missing from.
+    pub blobstore_id: BlobstoreId,
+    /// When the entry was added.
+    pub timestamp: DateTime,
+    /// Id of the entry in the queue. Only set for the entries that were read from the queue.
+    pub id: Option<i64>,
+}
+
+impl BlobstoreSyncQueueEntry {
+    pub fn new(
+        repo_id: RepositoryId,
+        blobstore_key: String,
+        blobstore_id: BlobstoreId,
+        timestamp: DateTime,
+    ) -> Self {
+        Self {
+            repo_id,
+            blobstore_key,
+            blobstore_id,
+            timestamp,
+            id: None,
+        }
+    }
+}
+
+pub trait BlobstoreSyncQueue: Send + Sync {
+    /// Add the entries to the queue, in a single transaction.
+    fn add_many(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error>;
+
+    fn add(&self, entry: BlobstoreSyncQueueEntry) -> BoxFuture<(), Error> {
+        self.add_many(vec![entry])
+    }
+
+    /// Returns at most `limit` of the oldest entries of the repo that were added before
+    /// `older_than`. If `after_id` is set, only the entries with a greater id are returned, so
+    /// that the queue can be read page by page even if the entries read aren't removed.
+    fn iter(
+        &self,
+        repo_id: RepositoryId,
+        older_than: DateTime,
+        after_id: Option<i64>,
+        limit: usize,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error>;
+
+    /// Returns all the entries of a key, for all the blobstores.
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        blobstore_key: String,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error>;
+
+    /// Remove the entries from the queue. Entries that weren't read from the queue, i.e. that
+    /// don't have an id, are ignored.
+    fn del(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error>;
+}
+
+impl BlobstoreSyncQueue for Arc<BlobstoreSyncQueue> {
+    fn add_many(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error> {
+        (**self).add_many(entries)
+    }
+
+    fn iter(
+        &self,
+        repo_id: RepositoryId,
+        older_than: DateTime,
+        after_id: Option<i64>,
+        limit: usize,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+        (**self).iter(repo_id, older_than, after_id, limit)
+    }
+
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        blobstore_key: String,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+        (**self).get(repo_id, blobstore_key)
+    }
+
+    fn del(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error> {
+        (**self).del(entries)
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteBlobstoreSyncQueue {
+    inner: SqliteConnInner,
+}
+
+impl SqliteBlobstoreSyncQueue {
+    fn from(inner: SqliteConnInner) -> Self {
+        Self { inner }
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/sqlite-blobstore-sync-queue.sql")
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::in_memory(
+            Self::get_up_query(),
+        )?))
+    }
+
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::open_or_create(
+            path,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_master_conn()
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlBlobstoreSyncQueue {
+    inner: MysqlConnInner,
+}
+
+impl MysqlBlobstoreSyncQueue {
+    fn from(inner: MysqlConnInner) -> Self {
+        Self { inner }
+    }
+
+    pub fn open(db_address: &str) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::open(db_address)?))
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/mysql-blobstore-sync-queue.sql")
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::create_test_db(
+            prefix,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_master_conn()
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_blobstore_sync_queue {
+    ($struct:ty) => {
+        impl BlobstoreSyncQueue for $struct {
+            fn add_many(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error> {
+                STATS::adds.add_value(entries.len() as i64);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    if entries.is_empty() {
+                        return Ok(());
+                    }
+
+                    let rows: Vec<_> = entries
+                        .into_iter()
+                        .map(|entry| NewBlobstoreSyncQueueRow {
+                            repo_id: entry.repo_id,
+                            blobstore_key: entry.blobstore_key,
+                            blobstore_id: entry.blobstore_id.id() as i64,
+                            add_timestamp: entry.timestamp.timestamp_secs(),
+                        })
+                        .collect();
+
+                    let connection = db.get_master_conn()?;
+                    insert_into(blobstore_sync_queue::table)
+                        .values(&rows)
+                        .execute(&*connection)?;
+                    Ok(())
+                })
+            }
+
+            fn iter(
+                &self,
+                repo_id: RepositoryId,
+                older_than: DateTime,
+                after_id: Option<i64>,
+                limit: usize,
+            ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+                STATS::iters.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    let rows = blobstore_sync_queue::table
+                        .filter(blobstore_sync_queue::repo_id.eq(repo_id))
+                        .filter(blobstore_sync_queue::add_timestamp.lt(older_than.timestamp_secs()))
+                        .filter(blobstore_sync_queue::id.gt(after_id.unwrap_or(0)))
+                        .order(blobstore_sync_queue::id.asc())
+                        .limit(limit as i64)
+                        .load::<BlobstoreSyncQueueRow>(&*connection)?;
+                    rows.into_iter().map(row_to_entry).collect()
+                })
+            }
+
+            fn get(
+                &self,
+                repo_id: RepositoryId,
+                blobstore_key: String,
+            ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+                STATS::gets.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let rows = blobstore_sync_queue::table
+                        .filter(blobstore_sync_queue::repo_id.eq(repo_id))
+                        .filter(blobstore_sync_queue::blobstore_key.eq(blobstore_key))
+                        .order(blobstore_sync_queue::id.asc())
+                        .load::<BlobstoreSyncQueueRow>(&*connection)?;
+                    rows.into_iter().map(row_to_entry).collect()
+                })
+            }
+
+            fn del(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error> {
+                let ids: Vec<_> = entries.into_iter().filter_map(|entry| entry.id).collect();
+                STATS::dels.add_value(ids.len() as i64);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    for chunk in ids.chunks(MAX_IN_CLAUSE_SIZE) {
+                        delete(
+                            blobstore_sync_queue::table
+                                .filter(blobstore_sync_queue::id.eq_any(chunk)),
+                        ).execute(&*connection)?;
+                    }
+                    Ok(())
+                })
+            }
+        }
+    };
+}
+
+impl_blobstore_sync_queue!(MysqlBlobstoreSyncQueue);
+impl_blobstore_sync_queue!(SqliteBlobstoreSyncQueue);
+
+fn row_to_entry(row: BlobstoreSyncQueueRow) -> Result<BlobstoreSyncQueueEntry> {
+    Ok(BlobstoreSyncQueueEntry {
+        repo_id: row.repo_id,
+        blobstore_key: row.blobstore_key,
+        blobstore_id: BlobstoreId::new(row.blobstore_id as u64),
+        timestamp: DateTime::from_timestamp(row.add_timestamp, 0)?,
+        id: Some(row.id),
+    })
+}
@@ -0,0 +1,29 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use mercurial_types::RepositoryId;
+
+use schema::blobstore_sync_queue;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable)]
+pub(crate) struct BlobstoreSyncQueueRow {
+    pub id: i64,
+    pub repo_id: RepositoryId,
+    pub blobstore_key: String,
+    pub blobstore_id: i64,
+    pub add_timestamp: i64,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Insertable)]
+#[table_name = "blobstore_sync_queue"]
+pub(crate) struct NewBlobstoreSyncQueueRow {
+    pub repo_id: RepositoryId,
+    pub blobstore_key: String,
+    pub blobstore_id: i64,
+    pub add_timestamp: i64,
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the blobstore sync queue.
+
+#![deny(warnings)]
+
+extern crate async_unit;
+extern crate futures;
+
+extern crate blobstore;
+extern crate blobstore_sync_queue;
+extern crate mercurial_types_mocks;
+extern crate mononoke_types;
+
+use std::sync::Arc;
+
+use futures::Future;
+
+use blobstore::BlobstoreId;
+use blobstore_sync_queue::{BlobstoreSyncQueue, BlobstoreSyncQueueEntry, MysqlBlobstoreSyncQueue,
+                           SqliteBlobstoreSyncQueue};
+use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
+use mononoke_types::DateTime;
+
+fn entry(key: &str, blobstore_id: u64, timestamp: i64) -> BlobstoreSyncQueueEntry {
+    BlobstoreSyncQueueEntry::new(
+        REPO_ZERO,
+        key.to_string(),
+        BlobstoreId::new(blobstore_id),
+        DateTime::from_timestamp(timestamp, 0).expect("invalid timestamp"),
+    )
+}
+
+fn without_ids(entries: Vec<BlobstoreSyncQueueEntry>) -> Vec<BlobstoreSyncQueueEntry> {
+    entries
+        .into_iter()
+        .map(|entry| BlobstoreSyncQueueEntry { id: None, ..entry })
+        .collect()
+}
+
+fn add_and_iter<Q: BlobstoreSyncQueue>(queue: Q) {
+    queue
+        .add_many(vec![entry("key1", 1, 100), entry("key1", 2, 100)])
+        .wait()
+        .expect("Adding entries failed");
+    queue
+        .add(entry("key2", 1, 200))
+        .wait()
+        .expect("Adding an entry failed");
+
+    let older_than = |timestamp| DateTime::from_timestamp(timestamp, 0).unwrap();
+
+    let entries = queue
+        .iter(REPO_ZERO, older_than(300), None, 10)
+        .wait()
+        .expect("iter failed");
+    assert!(entries.iter().all(|entry| entry.id.is_some()));
+    assert_eq!(
+        without_ids(entries),
+        vec![
+            entry("key1", 1, 100),
+            entry("key1", 2, 100),
+            entry("key2", 1, 200),
+        ]
+    );
+
+    let entries = queue
+        .iter(REPO_ZERO, older_than(200), None, 10)
+        .wait()
+        .expect("iter failed");
+    assert_eq!(
+        without_ids(entries),
+        vec![entry("key1", 1, 100), entry("key1", 2, 100)]
+    );
+
+    let entries = queue
+        .iter(REPO_ZERO, older_than(300), None, 1)
+        .wait()
+        .expect("iter failed");
+    assert_eq!(without_ids(entries.clone()), vec![entry("key1", 1, 100)]);
+
+    // The next page starts after the last entry of the previous one
+    let entries = queue
+        .iter(REPO_ZERO, older_than(300), entries[0].id, 10)
+        .wait()
+        .expect("iter failed");
+    assert_eq!(
+        without_ids(entries),
+        vec![entry("key1", 2, 100), entry("key2", 1, 200)]
+    );
+
+    let entries = queue
+        .iter(REPO_ONE, older_than(300), None, 10)
+        .wait()
+        .expect("iter failed");
+    assert_eq!(entries, vec![]);
+}
+
+fn get_and_del<Q: BlobstoreSyncQueue>(queue: Q) {
+    queue
+        .add_many(vec![
+            entry("key1", 1, 100),
+            entry("key1", 2, 100),
+            entry("key2", 1, 200),
+        ])
+        .wait()
+        .expect("Adding entries failed");
+
+    let key1_entries = queue
+        .get(REPO_ZERO, "key1".to_string())
+        .wait()
+        .expect("get failed");
+    assert_eq!(
+        without_ids(key1_entries.clone()),
+        vec![entry("key1", 1, 100), entry("key1", 2, 100)]
+    );
+
+    queue.del(key1_entries).wait().expect("del failed");
+    let key1_entries = queue
+        .get(REPO_ZERO, "key1".to_string())
+        .wait()
+        .expect("get failed");
+    assert_eq!(key1_entries, vec![]);
+
+    // Entries that weren't read from the queue are ignored
+    queue
+        .del(vec![entry("key2", 1, 200)])
+        .wait()
+        .expect("del failed");
+    let key2_entries = queue
+        .get(REPO_ZERO, "key2".to_string())
+        .wait()
+        .expect("get failed");
+    assert_eq!(without_ids(key2_entries), vec![entry("key2", 1, 200)]);
+}
+
+macro_rules! blobstore_sync_queue_test_impl {
+    ($mod_name:ident => { new: $new_cb:expr, }) => {
+        mod $mod_name {
+            use super::*;
+
+            #[test]
+            fn test_add_and_iter() {
+                async_unit::tokio_unit_test(|| {
+                    add_and_iter($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_get_and_del() {
+                async_unit::tokio_unit_test(|| {
+                    get_and_del($new_cb());
+                });
+            }
+        }
+    };
+}
+
+blobstore_sync_queue_test_impl! {
+    sqlite_test => {
+        new: new_sqlite,
+    }
+}
+
+blobstore_sync_queue_test_impl! {
+    sqlite_arced_test => {
+        new: new_sqlite_arced,
+    }
+}
+
+blobstore_sync_queue_test_impl! {
+    mysql_test => {
+        new: new_mysql,
+    }
+}
+
+blobstore_sync_queue_test_impl! {
+    mysql_arced_test => {
+        new: new_mysql_arced,
+    }
+}
+
+fn new_sqlite() -> SqliteBlobstoreSyncQueue {
+    SqliteBlobstoreSyncQueue::in_memory().expect("Creating an in-memory SQLite database failed")
+}
+
+fn new_sqlite_arced() -> Arc<BlobstoreSyncQueue> {
+    Arc::new(new_sqlite())
+}
+
+fn new_mysql() -> MysqlBlobstoreSyncQueue {
+    MysqlBlobstoreSyncQueue::create_test_db("blobstore_sync_queue_test")
+        .expect("Failed to create test database")
+}
+
+fn new_mysql_arced() -> Arc<BlobstoreSyncQueue> {
+    Arc::new(new_mysql())
+}
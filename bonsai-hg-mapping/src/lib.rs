@@ -28,6 +28,7 @@ extern crate mononoke_types;
 #[macro_use]
 extern crate stats;
 
+use std::collections::HashSet;
 use std::result;
 use std::sync::{Arc, MutexGuard};
 
@@ -38,24 +39,32 @@ use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::result::{DatabaseErrorKind, Error as DieselError};
 
-use futures::Future;
+use futures::{future, Future};
 use futures_ext::{asynchronize, BoxFuture, FutureExt};
 use mercurial_types::{HgChangesetId, RepositoryId};
 use mononoke_types::ChangesetId;
 use stats::Timeseries;
 
 mod errors;
+mod mem_writes;
 mod models;
 mod schema;
 
 pub use errors::*;
+pub use mem_writes::MemWritesBonsaiHgMapping;
 use models::BonsaiHgMappingRow;
 use schema::bonsai_hg_mapping;
 
+// SQLite doesn't allow more than 999 parameters in a query, so the entries are fetched in chunks
+// by `get_many`.
+const GET_MANY_CHUNK_SIZE: usize = 500;
+
 define_stats! {
     prefix = "mononoke.bonsai-hg-mapping";
     gets: timeseries(RATE, SUM),
     gets_master: timeseries(RATE, SUM),
+    get_many: timeseries(RATE, SUM),
+    get_many_master: timeseries(RATE, SUM),
     adds: timeseries(RATE, SUM),
 }
 
@@ -93,6 +102,14 @@ pub trait BonsaiHgMapping: Send + Sync {
         cs_id: BonsaiOrHgChangesetId,
     ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error>;
 
+    /// Retrieve the entries of all the changesets that have one, in no particular order, with
+    /// fewer roundtrips to the database than calling `get` for each of them.
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<BonsaiOrHgChangesetId>,
+    ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error>;
+
     fn get_hg_from_bonsai(
         &self,
         repo_id: RepositoryId,
@@ -126,6 +143,14 @@ impl BonsaiHgMapping for Arc<BonsaiHgMapping> {
     ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error> {
         (**self).get(repo_id, cs_id)
     }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<BonsaiOrHgChangesetId>,
+    ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+        (**self).get_many(repo_id, cs_ids)
+    }
 }
 
 pub struct CachingBonsaiHgMapping {
@@ -164,6 +189,40 @@ impl BonsaiHgMapping for CachingBonsaiHgMapping {
             })
             .boxify()
     }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<BonsaiOrHgChangesetId>,
+    ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+        let mut entries = HashSet::new();
+        let mut missing = Vec::new();
+        for cs_id in cs_ids {
+            match self.cache.get_cached((repo_id, cs_id)) {
+                Some(entry) => {
+                    entries.insert(entry);
+                }
+                None => missing.push(cs_id),
+            }
+        }
+        if missing.is_empty() {
+            return future::ok(entries.into_iter().collect()).boxify();
+        }
+
+        let cache = self.cache.clone();
+        self.mapping
+            .get_many(repo_id, missing)
+            .map(move |fetched| {
+                for entry in fetched {
+                    // Like the filler would, cache the entry under both of its changeset ids.
+                    cache.insert((repo_id, entry.bcs_id.into()), entry.clone());
+                    cache.insert((repo_id, entry.hg_cs_id.into()), entry.clone());
+                    entries.insert(entry);
+                }
+                entries.into_iter().collect()
+            })
+            .boxify()
+    }
 }
 
 pub struct BonsaiHgMappingFiller {
@@ -307,6 +366,42 @@ macro_rules! impl_bonsai_hg_mapping {
                 })
             }
 
+            fn get_many(
+                &self,
+                repo_id: RepositoryId,
+                cs_ids: Vec<BonsaiOrHgChangesetId>,
+            ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+                STATS::get_many.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let mut entries = HashSet::new();
+                    {
+                        let connection = db.get_conn()?;
+                        for chunk in cs_ids.chunks(GET_MANY_CHUNK_SIZE) {
+                            entries.extend(Self::actual_get_many(&connection, repo_id, chunk)?);
+                        }
+                    }
+
+                    let found: HashSet<BonsaiOrHgChangesetId> = entries
+                        .iter()
+                        .flat_map(|entry| vec![entry.bcs_id.into(), entry.hg_cs_id.into()])
+                        .collect();
+                    let missing: Vec<_> = cs_ids
+                        .into_iter()
+                        .filter(|cs_id| !found.contains(cs_id))
+                        .collect();
+                    if !missing.is_empty() {
+                        STATS::get_many_master.add_value(1);
+                        let connection = db.get_master_conn()?;
+                        for chunk in missing.chunks(GET_MANY_CHUNK_SIZE) {
+                            entries.extend(Self::actual_get_many(&connection, repo_id, chunk)?);
+                        }
+                    }
+                    Ok(entries.into_iter().collect())
+                })
+            }
+
             fn add(&self, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
                 STATS::adds.add_value(1);
                 let db = self.clone();
@@ -388,6 +483,54 @@ macro_rules! impl_bonsai_hg_mapping {
                         }
                     })
             }
+
+            fn actual_get_many(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_ids: &[BonsaiOrHgChangesetId],
+            ) -> Result<Vec<BonsaiHgMappingEntry>> {
+                let mut bcs_ids = Vec::new();
+                let mut hg_cs_ids = Vec::new();
+                for cs_id in cs_ids {
+                    match *cs_id {
+                        BonsaiOrHgChangesetId::Bonsai(id) => bcs_ids.push(id),
+                        BonsaiOrHgChangesetId::Hg(id) => hg_cs_ids.push(id),
+                    }
+                }
+
+                let mut rows = Vec::new();
+                if !bcs_ids.is_empty() {
+                    rows.extend(
+                        bonsai_hg_mapping::table
+                            .filter(bonsai_hg_mapping::repo_id.eq(repo_id))
+                            .filter(bonsai_hg_mapping::bcs_id.eq_any(bcs_ids))
+                            .load::<BonsaiHgMappingRow>(connection)?,
+                    );
+                }
+                if !hg_cs_ids.is_empty() {
+                    rows.extend(
+                        bonsai_hg_mapping::table
+                            .filter(bonsai_hg_mapping::repo_id.eq(repo_id))
+                            .filter(bonsai_hg_mapping::hg_cs_id.eq_any(hg_cs_ids))
+                            .load::<BonsaiHgMappingRow>(connection)?,
+                    );
+                }
+
+                Ok(rows.into_iter()
+                    .map(|row| {
+                        let BonsaiHgMappingRow {
+                            repo_id,
+                            hg_cs_id,
+                            bcs_id,
+                        } = row;
+                        BonsaiHgMappingEntry {
+                            repo_id,
+                            hg_cs_id,
+                            bcs_id,
+                        }
+                    })
+                    .collect())
+            }
         }
     };
 }
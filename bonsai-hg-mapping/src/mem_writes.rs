@@ -0,0 +1,102 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+use mercurial_types::RepositoryId;
+
+use {BonsaiHgMapping, BonsaiHgMappingEntry, BonsaiOrHgChangesetId};
+use errors::*;
+
+/// A bonsai-hg mapping that reads from the underlying mapping but keeps the entries it adds in
+/// memory.
+#[derive(Clone)]
+pub struct MemWritesBonsaiHgMapping {
+    inner: Arc<BonsaiHgMapping>,
+    // Every entry is stored under both of its changeset ids.
+    mem: Arc<Mutex<HashMap<(RepositoryId, BonsaiOrHgChangesetId), BonsaiHgMappingEntry>>>,
+}
+
+impl MemWritesBonsaiHgMapping {
+    pub fn new(inner: Arc<BonsaiHgMapping>) -> Self {
+        Self {
+            inner,
+            mem: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl BonsaiHgMapping for MemWritesBonsaiHgMapping {
+    fn add(&self, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
+        let mem = self.mem.clone();
+        self.get(entry.repo_id, entry.bcs_id.into())
+            .join(self.get(entry.repo_id, entry.hg_cs_id.into()))
+            .and_then(move |(entry_by_bcs, entry_by_hgcs)| {
+                match entry_by_bcs.or(entry_by_hgcs) {
+                    Some(ref stored_entry) if stored_entry == &entry => Ok(false),
+                    Some(stored_entry) => {
+                        Err(ErrorKind::ConflictingEntries(stored_entry, entry).into())
+                    }
+                    None => {
+                        let mut mem = mem.lock().expect("lock poisoned");
+                        mem.insert((entry.repo_id, entry.bcs_id.into()), entry.clone());
+                        mem.insert((entry.repo_id, entry.hg_cs_id.into()), entry);
+                        Ok(true)
+                    }
+                }
+            })
+            .boxify()
+    }
+
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: BonsaiOrHgChangesetId,
+    ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error> {
+        let entry = {
+            let mem = self.mem.lock().expect("lock poisoned");
+            mem.get(&(repo_id, cs_id)).cloned()
+        };
+        match entry {
+            Some(entry) => future::ok(Some(entry)).boxify(),
+            None => self.inner.get(repo_id, cs_id),
+        }
+    }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<BonsaiOrHgChangesetId>,
+    ) -> BoxFuture<Vec<BonsaiHgMappingEntry>, Error> {
+        let mut entries = HashSet::new();
+        let mut missing = Vec::new();
+        {
+            let mem = self.mem.lock().expect("lock poisoned");
+            for cs_id in cs_ids {
+                match mem.get(&(repo_id, cs_id)) {
+                    Some(entry) => {
+                        entries.insert(entry.clone());
+                    }
+                    None => missing.push(cs_id),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return future::ok(entries.into_iter().collect()).boxify();
+        }
+
+        self.inner
+            .get_many(repo_id, missing)
+            .map(move |fetched| {
+                entries.extend(fetched);
+                entries.into_iter().collect()
+            })
+            .boxify()
+    }
+}
@@ -22,7 +22,8 @@ use std::sync::Arc;
 
 use futures::Future;
 
-use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, ErrorKind, MysqlBonsaiHgMapping,
+use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, CachingBonsaiHgMapping,
+                        ErrorKind, MemWritesBonsaiHgMapping, MysqlBonsaiHgMapping,
                         SqliteBonsaiHgMapping};
 use mercurial_types_mocks::nodehash as hg;
 use mercurial_types_mocks::repo::REPO_ZERO;
@@ -102,6 +103,84 @@ fn missing<M: BonsaiHgMapping>(mapping: M) {
     assert_eq!(result, None);
 }
 
+fn get_many<M: BonsaiHgMapping>(mapping: M) {
+    let first = BonsaiHgMappingEntry {
+        repo_id: REPO_ZERO,
+        hg_cs_id: hg::ONES_CSID,
+        bcs_id: bonsai::ONES_CSID,
+    };
+    let second = BonsaiHgMappingEntry {
+        repo_id: REPO_ZERO,
+        hg_cs_id: hg::TWOS_CSID,
+        bcs_id: bonsai::TWOS_CSID,
+    };
+    for entry in vec![first.clone(), second.clone()] {
+        mapping.add(entry).wait().expect("Adding entry failed");
+    }
+
+    // Twice, as the second time the entries may come from a cache.
+    for _ in 0..2 {
+        let mut result = mapping
+            .get_many(
+                REPO_ZERO,
+                vec![
+                    hg::ONES_CSID.into(),
+                    bonsai::ONES_CSID.into(),
+                    bonsai::TWOS_CSID.into(),
+                    hg::THREES_CSID.into(),
+                ],
+            )
+            .wait()
+            .expect("Get many failed");
+        result.sort_by_key(|entry| entry.bcs_id);
+        assert_eq!(result, vec![first.clone(), second.clone()]);
+    }
+}
+
+fn mem_writes_overlay<M: BonsaiHgMapping + 'static>(mapping: M) {
+    let inner: Arc<BonsaiHgMapping> = Arc::new(mapping);
+    let entry = BonsaiHgMappingEntry {
+        repo_id: REPO_ZERO,
+        hg_cs_id: hg::ONES_CSID,
+        bcs_id: bonsai::ONES_CSID,
+    };
+    inner
+        .add(entry.clone())
+        .wait()
+        .expect("Adding entry to inner failed");
+
+    let overlay = MemWritesBonsaiHgMapping::new(inner.clone());
+    let new_entry = BonsaiHgMappingEntry {
+        repo_id: REPO_ZERO,
+        hg_cs_id: hg::TWOS_CSID,
+        bcs_id: bonsai::TWOS_CSID,
+    };
+    assert_eq!(
+        true,
+        overlay
+            .add(new_entry.clone())
+            .wait()
+            .expect("Adding entry to overlay failed")
+    );
+    assert_eq!(
+        false,
+        overlay
+            .add(entry.clone())
+            .wait()
+            .expect("Adding entry already in inner failed")
+    );
+    let result = overlay
+        .get_bonsai_from_hg(REPO_ZERO, hg::TWOS_CSID)
+        .wait()
+        .expect("Get from overlay failed");
+    assert_eq!(result, Some(bonsai::TWOS_CSID));
+    let result = inner
+        .get(REPO_ZERO, bonsai::TWOS_CSID.into())
+        .wait()
+        .expect("Get from inner failed");
+    assert_eq!(result, None, "the overlay must not write to the inner mapping");
+}
+
 macro_rules! bonsai_hg_mapping_test_impl {
     ($mod_name:ident =>  { new: $new_cb:expr, }) => {
         mod $mod_name {
@@ -120,6 +199,20 @@ macro_rules! bonsai_hg_mapping_test_impl {
                     missing($new_cb());
                 });
             }
+
+            #[test]
+            fn test_get_many() {
+                async_unit::tokio_unit_test(|| {
+                    get_many($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_mem_writes_overlay() {
+                async_unit::tokio_unit_test(|| {
+                    mem_writes_overlay($new_cb());
+                });
+            }
         }
     };
 }
@@ -136,6 +229,18 @@ bonsai_hg_mapping_test_impl! {
     }
 }
 
+bonsai_hg_mapping_test_impl! {
+    caching_test => {
+        new: new_caching,
+    }
+}
+
+bonsai_hg_mapping_test_impl! {
+    mem_writes_test => {
+        new: new_mem_writes,
+    }
+}
+
 bonsai_hg_mapping_test_impl! {
     mysql_test => {
         new: new_mysql,
@@ -158,6 +263,14 @@ fn new_sqlite_arced() -> Arc<BonsaiHgMapping> {
     Arc::new(new_sqlite())
 }
 
+fn new_caching() -> CachingBonsaiHgMapping {
+    CachingBonsaiHgMapping::new(new_sqlite_arced(), 1_000_000)
+}
+
+fn new_mem_writes() -> MemWritesBonsaiHgMapping {
+    MemWritesBonsaiHgMapping::new(new_sqlite_arced())
+}
+
 fn new_mysql() -> MysqlBonsaiHgMapping {
     MysqlBonsaiHgMapping::create_test_db("bonsai_hg_mapping_test")
         .expect("Failed to create test database")
@@ -20,7 +20,9 @@ extern crate mercurial_types;
 extern crate mercurial_types_mocks;
 extern crate tokio;
 
-use bookmarks::{Bookmark, BookmarkPrefix};
+use std::sync::Arc;
+
+use bookmarks::{Bookmark, BookmarkPrefix, MemWritesBookmarks};
 use dbbookmarks::{MysqlDbBookmarks, SqliteDbBookmarks};
 use mercurial_types_mocks::nodehash::{ONES_CSID, TWOS_CSID};
 use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
@@ -354,6 +356,10 @@ bookmarks_test_impl!(sqlite_tests => {
      new: create_sqlite,
  });
 
+bookmarks_test_impl!(mem_writes_tests => {
+     new: create_mem_writes,
+ });
+
 bookmarks_test_impl!(mysql_tests => {
      new: create_mysql,
  });
@@ -362,6 +368,10 @@ fn create_sqlite() -> SqliteDbBookmarks {
     SqliteDbBookmarks::in_memory().unwrap()
 }
 
+fn create_mem_writes() -> MemWritesBookmarks {
+    MemWritesBookmarks::new(Arc::new(create_sqlite()))
+}
+
 fn create_mysql() -> MysqlDbBookmarks {
     MysqlDbBookmarks::create_test_db("mononokefilenodestest").unwrap()
 }
@@ -9,9 +9,12 @@
 extern crate ascii;
 #[macro_use]
 extern crate failure_ext as failure;
+extern crate futures;
 extern crate futures_ext;
 extern crate mercurial_types;
 
+mod mem_writes;
+
 use std::fmt;
 
 use ascii::AsciiString;
@@ -19,6 +22,8 @@ use failure::{Error, Result};
 use futures_ext::{BoxFuture, BoxStream};
 use mercurial_types::{HgChangesetId, RepositoryId};
 
+pub use mem_writes::MemWritesBookmarks;
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Bookmark {
     bookmark: AsciiString,
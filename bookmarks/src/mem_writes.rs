@@ -0,0 +1,202 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use failure::{Error, Result};
+use futures::{future, stream, Future, Stream};
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
+use mercurial_types::{HgChangesetId, RepositoryId};
+
+use {Bookmark, BookmarkPrefix, Bookmarks, Transaction};
+
+// None for a bookmark deleted in memory.
+type MemBookmarks = HashMap<(RepositoryId, Bookmark), Option<HgChangesetId>>;
+
+/// A bookmarks store that reads from the underlying store but keeps the bookmark moves committed
+/// through it in memory.
+#[derive(Clone)]
+pub struct MemWritesBookmarks {
+    inner: Arc<Bookmarks>,
+    mem: Arc<Mutex<MemBookmarks>>,
+}
+
+impl MemWritesBookmarks {
+    pub fn new(inner: Arc<Bookmarks>) -> Self {
+        Self {
+            inner,
+            mem: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Bookmarks for MemWritesBookmarks {
+    fn get(
+        &self,
+        name: &Bookmark,
+        repoid: &RepositoryId,
+    ) -> BoxFuture<Option<HgChangesetId>, Error> {
+        let value = {
+            let mem = self.mem.lock().expect("lock poisoned");
+            mem.get(&(*repoid, name.clone())).cloned()
+        };
+        match value {
+            Some(value) => future::ok(value).boxify(),
+            None => self.inner.get(name, repoid),
+        }
+    }
+
+    fn list_by_prefix(
+        &self,
+        prefix: &BookmarkPrefix,
+        repoid: &RepositoryId,
+    ) -> BoxStream<(Bookmark, HgChangesetId), Error> {
+        let repoid = *repoid;
+        let mem = self.mem.clone();
+        let bookmarks = self.inner.list_by_prefix(prefix, &repoid);
+        let prefix = prefix.to_string();
+        bookmarks
+            .collect()
+            .map(move |bookmarks| {
+                let mem = mem.lock().expect("lock poisoned");
+                let mut bookmarks: Vec<_> = bookmarks
+                    .into_iter()
+                    .filter(|&(ref name, _)| !mem.contains_key(&(repoid, name.clone())))
+                    .collect();
+                bookmarks.extend(mem.iter().filter_map(|(&(id, ref name), value)| {
+                    match *value {
+                        Some(cs_id) if id == repoid && name.to_string().starts_with(&prefix) => {
+                            Some((name.clone(), cs_id))
+                        }
+                        _ => None,
+                    }
+                }));
+                // The SQL stores list bookmarks in name order.
+                bookmarks.sort_by_key(|&(ref name, _)| name.to_string());
+                stream::iter_ok(bookmarks)
+            })
+            .flatten_stream()
+            .boxify()
+    }
+
+    fn create_transaction(&self, repoid: &RepositoryId) -> Box<Transaction> {
+        Box::new(MemWritesTransaction {
+            bookmarks: self.clone(),
+            repo_id: *repoid,
+            ops: HashMap::new(),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BookmarkOp {
+    Update {
+        new_cs: HgChangesetId,
+        old_cs: HgChangesetId,
+    },
+    Create(HgChangesetId),
+    ForceSet(HgChangesetId),
+    Delete(HgChangesetId),
+    ForceDelete,
+}
+
+struct MemWritesTransaction {
+    bookmarks: MemWritesBookmarks,
+    repo_id: RepositoryId,
+    ops: HashMap<Bookmark, BookmarkOp>,
+}
+
+impl MemWritesTransaction {
+    fn add_op(&mut self, key: &Bookmark, op: BookmarkOp) -> Result<()> {
+        if self.ops.contains_key(key) {
+            bail_msg!("{} bookmark was already used", key);
+        }
+        self.ops.insert(key.clone(), op);
+        Ok(())
+    }
+}
+
+impl Transaction for MemWritesTransaction {
+    fn update(
+        &mut self,
+        key: &Bookmark,
+        new_cs: &HgChangesetId,
+        old_cs: &HgChangesetId,
+    ) -> Result<()> {
+        self.add_op(
+            key,
+            BookmarkOp::Update {
+                new_cs: *new_cs,
+                old_cs: *old_cs,
+            },
+        )
+    }
+
+    fn create(&mut self, key: &Bookmark, new_cs: &HgChangesetId) -> Result<()> {
+        self.add_op(key, BookmarkOp::Create(*new_cs))
+    }
+
+    fn force_set(&mut self, key: &Bookmark, new_cs: &HgChangesetId) -> Result<()> {
+        self.add_op(key, BookmarkOp::ForceSet(*new_cs))
+    }
+
+    fn delete(&mut self, key: &Bookmark, old_cs: &HgChangesetId) -> Result<()> {
+        self.add_op(key, BookmarkOp::Delete(*old_cs))
+    }
+
+    fn force_delete(&mut self, key: &Bookmark) -> Result<()> {
+        self.add_op(key, BookmarkOp::ForceDelete)
+    }
+
+    fn commit(&self) -> BoxFuture<bool, Error> {
+        let repo_id = self.repo_id;
+        let ops: Vec<_> = self.ops
+            .iter()
+            .map(|(key, op)| (key.clone(), *op))
+            .collect();
+        let inner_values = ops.iter()
+            .map(|&(ref key, _)| self.bookmarks.inner.get(key, &repo_id))
+            .collect::<Vec<_>>();
+        let mem = self.bookmarks.mem.clone();
+
+        // The values from the underlying store are fetched first, and the in-memory ones are
+        // checked and updated under a single lock, so that the transaction applies atomically.
+        future::join_all(inner_values)
+            .and_then(move |inner_values| -> Result<bool> {
+                let mut mem = mem.lock().expect("lock poisoned");
+                for (&(ref key, op), inner_value) in ops.iter().zip(inner_values) {
+                    let current = match mem.get(&(repo_id, key.clone())) {
+                        Some(value) => *value,
+                        None => inner_value,
+                    };
+                    match op {
+                        BookmarkOp::Create(_) if current.is_some() => {
+                            bail_msg!("{} bookmark already exists", key);
+                        }
+                        BookmarkOp::Update { old_cs, .. } | BookmarkOp::Delete(old_cs)
+                            if current != Some(old_cs) =>
+                        {
+                            return Ok(false); // conflict
+                        }
+                        _ => {}
+                    }
+                }
+
+                for (key, op) in ops {
+                    let value = match op {
+                        BookmarkOp::Update { new_cs, .. }
+                        | BookmarkOp::Create(new_cs)
+                        | BookmarkOp::ForceSet(new_cs) => Some(new_cs),
+                        BookmarkOp::Delete(_) | BookmarkOp::ForceDelete => None,
+                    };
+                    mem.insert((repo_id, key), value);
+                }
+                Ok(true)
+            })
+            .boxify()
+    }
+}
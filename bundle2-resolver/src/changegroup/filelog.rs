@@ -38,6 +38,8 @@ pub struct Filelog {
     pub p2: Option<HgNodeHash>,
     pub linknode: HgNodeHash,
     pub data: Bytes,
+    /// Revlog flags, only sent in changegroup 3.
+    pub flags: u16,
 }
 
 impl UploadableHgBlob for Filelog {
@@ -65,6 +67,7 @@ impl UploadableHgBlob for Filelog {
             p1: self.p1,
             p2: self.p2,
             path,
+            flags: self.flags,
         };
 
         let (cbinfo, fut) = upload.upload(repo)?;
@@ -89,6 +92,7 @@ where
                 p1,
                 p2,
                 linknode,
+                flags,
             } = chunk;
 
             delta_cache
@@ -106,6 +110,7 @@ where
                             p2: p2.into_option(),
                             linknode,
                             data,
+                            flags: flags.unwrap_or(0),
                         })
                     }
                 })
@@ -222,6 +227,7 @@ impl Arbitrary for Filelog {
             p2: HgNodeHash::arbitrary(g).into_option(),
             linknode: HgNodeHash::arbitrary(g),
             data: Bytes::from(Vec::<u8>::arbitrary(g)),
+            flags: u16::arbitrary(g),
         }
     }
 
@@ -263,6 +269,12 @@ impl Arbitrary for Filelog {
             append(&mut result, f);
         }
 
+        if self.flags != 0 {
+            let mut f = self.clone();
+            f.flags = 0;
+            append(&mut result, f);
+        }
+
         Box::new(result.into_iter())
     }
 }
@@ -331,6 +343,7 @@ mod tests {
                 base: NULL_HASH,
                 linknode: f.linknode.clone(),
                 delta: Delta::new_fulltext(f.data.as_ref()),
+                flags: Some(f.flags),
             },
         }
     }
@@ -393,6 +406,7 @@ mod tests {
             p2: Some(THREES_HASH),
             linknode: FOURS_HASH,
             data: Bytes::from("test file content"),
+            flags: 0,
         };
 
         let f2 = Filelog {
@@ -404,6 +418,7 @@ mod tests {
             p2: Some(SEVENS_HASH),
             linknode: EIGHTS_HASH,
             data: Bytes::from("test2 file content"),
+            flags: 0,
         };
 
         check_conversion(
@@ -422,6 +437,7 @@ mod tests {
             p2: Some(THREES_HASH),
             linknode: FOURS_HASH,
             data: Bytes::from("test file content"),
+            flags: 0,
         };
 
         let f2 = Filelog {
@@ -433,6 +449,7 @@ mod tests {
             p2: Some(SEVENS_HASH),
             linknode: EIGHTS_HASH,
             data: Bytes::from("test2 file content"),
+            flags: 0,
         };
 
         let f1_deltaed = filelog_to_deltaed(&f1);
@@ -87,6 +87,13 @@ where
                         seen_path = None;
                         Ok(None)
                     }
+                    // Directory manifests are only sent in changegroup version 03. Trees are
+                    // uploaded from the b2x:treegroup2 part, so these are skipped.
+                    Part::CgChunk(Section::Treemanifest(_), _)
+                    | Part::SectionEnd(Section::Treemanifest(_)) if seen_path.is_none() =>
+                    {
+                        Ok(None)
+                    }
                     // Checking that there is exactly one Part::end is is covered by CheckEnd
                     // wrapper
                     Part::End if seen_path.is_none() => Ok(None),
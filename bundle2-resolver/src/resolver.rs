@@ -22,8 +22,8 @@ use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 use mercurial::changeset::RevlogChangeset;
 use mercurial::manifest::{Details, ManifestContent};
 use mercurial_bundles::{parts, Bundle2EncodeBuilder, Bundle2Item};
-use mercurial_types::{HgChangesetId, HgManifestId, HgNodeHash, HgNodeKey, MPath, RepoPath,
-                      NULL_HASH};
+use mercurial_types::{HgChangesetId, HgManifestId, HgNodeHash, HgNodeKey, HgObsMarker, MPath,
+                      RepoPath, NULL_HASH};
 use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
 use slog::Logger;
 use stats::*;
@@ -75,64 +75,87 @@ fn resolve_push(
             let resolver = resolver.clone();
             move |(cg_push, bundle2)| {
                 resolver
-                    .resolve_multiple_parts(bundle2, Bundle2Resolver::maybe_resolve_pushkey)
-                    .map(move |(pushkeys, bundle2)| {
-                        let bookmark_push: Vec<_> = pushkeys
-                            .into_iter()
-                            .filter_map(|pushkey| match pushkey {
-                                Pushkey::Phases => None,
-                                Pushkey::BookmarkPush(bp) => Some(bp),
-                            })
-                            .collect();
+                    .resolve_multiple_parts(
+                        bundle2,
+                        Bundle2Resolver::maybe_resolve_pushkey_or_obsmarkers,
+                    )
+                    .map(move |(parts, bundle2)| {
+                        let mut bookmark_push = Vec::new();
+                        let mut obsmarkers = Vec::new();
+                        for part in parts {
+                            match part {
+                                PushkeyOrObsmarkers::Pushkey(Pushkey::Phases) => (),
+                                PushkeyOrObsmarkers::Pushkey(Pushkey::BookmarkPush(bp)) => {
+                                    bookmark_push.push(bp)
+                                }
+                                PushkeyOrObsmarkers::Obsmarkers(markers) => {
+                                    obsmarkers.extend(markers)
+                                }
+                            }
+                        }
 
                         STATS::bookmark_pushkeys_count.add_value(bookmark_push.len() as i64);
+                        STATS::obsmarkers_count.add_value(obsmarkers.len() as i64);
 
-                        (cg_push, bookmark_push, bundle2)
+                        (cg_push, (bookmark_push, obsmarkers), bundle2)
                     })
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(cg_push, bookmark_push, bundle2)| {
+            move |(cg_push, pushed_metadata, bundle2)| {
                 if let Some(cg_push) = cg_push {
                     resolver
                         .resolve_b2xtreegroup2(bundle2)
                         .map(|(manifests, bundle2)| {
-                            (Some((cg_push, manifests)), bookmark_push, bundle2)
+                            (Some((cg_push, manifests)), pushed_metadata, bundle2)
                         })
                         .boxify()
                 } else {
-                    ok((None, bookmark_push, bundle2)).boxify()
+                    ok((None, pushed_metadata, bundle2)).boxify()
                 }
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(cg_and_manifests, bookmark_push, bundle2)| {
+            move |(cg_and_manifests, pushed_metadata, bundle2)| {
                 if let Some((cg_push, manifests)) = cg_and_manifests {
                     let changegroup_id = Some(cg_push.part_id);
                     resolver
                         .upload_changesets(cg_push, manifests)
-                        .map(move |()| (changegroup_id, bookmark_push, bundle2))
+                        .map(move |()| (changegroup_id, pushed_metadata, bundle2))
                         .boxify()
                 } else {
-                    ok((None, bookmark_push, bundle2)).boxify()
+                    ok((None, pushed_metadata, bundle2)).boxify()
                 }
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(changegroup_id, bookmark_push, bundle2)| {
+            move |(changegroup_id, pushed_metadata, bundle2)| {
                 resolver
                     .maybe_resolve_infinitepush_bookmarks(bundle2)
-                    .map(move |((), bundle2)| (changegroup_id, bookmark_push, bundle2))
+                    .map(move |((), bundle2)| (changegroup_id, pushed_metadata, bundle2))
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(changegroup_id, bookmark_push, bundle2)| {
+            move |(changegroup_id, pushed_metadata, bundle2)| {
                 resolver
                     .ensure_stream_finished(bundle2)
+                    .map(move |()| (changegroup_id, pushed_metadata))
+            }
+        })
+        .and_then({
+            let resolver = resolver.clone();
+            move |(changegroup_id, (bookmark_push, obsmarkers))| {
+                // Markers are stored after the changesets they refer to have been uploaded,
+                // but before bookmarks are moved.
+                resolver
+                    .repo
+                    .add_obsmarkers(obsmarkers)
+                    .context("While storing obsolescence markers")
+                    .from_err()
                     .map(move |()| (changegroup_id, bookmark_push))
             }
         })
@@ -199,6 +222,12 @@ enum Pushkey {
     Phases,
 }
 
+/// Mercurial sends the obsmarkers part between the phases and the bookmarks pushkey parts.
+enum PushkeyOrObsmarkers {
+    Pushkey(Pushkey),
+    Obsmarkers(Vec<HgObsMarker>),
+}
+
 struct BookmarkPush {
     part_id: PartId,
     name: bookmarks::Bookmark,
@@ -369,6 +398,29 @@ impl Bundle2Resolver {
             .boxify()
     }
 
+    /// Parses either an obsmarkers part or a pushkey part if one of them exists
+    fn maybe_resolve_pushkey_or_obsmarkers(
+        &self,
+        bundle2: BoxStream<Bundle2Item, Error>,
+    ) -> BoxFuture<(Option<PushkeyOrObsmarkers>, BoxStream<Bundle2Item, Error>), Error> {
+        let resolver = self.clone();
+        next_item(bundle2)
+            .and_then(move |(newpart, bundle2)| match newpart {
+                Some(Bundle2Item::Obsmarkers(_header, markers)) => markers
+                    .collect()
+                    .map(move |markers| (Some(PushkeyOrObsmarkers::Obsmarkers(markers)), bundle2))
+                    .context("While resolving Obsmarkers")
+                    .from_err()
+                    .boxify(),
+                Some(part) => resolver
+                    .maybe_resolve_pushkey(stream::once(Ok(part)).chain(bundle2).boxify())
+                    .map(|(pushkey, bundle2)| (pushkey.map(PushkeyOrObsmarkers::Pushkey), bundle2))
+                    .boxify(),
+                None => ok((None, bundle2)).boxify(),
+            })
+            .boxify()
+    }
+
     /// Parse b2xtreegroup2.
     /// The Manifests should be scheduled for uploading to BlobRepo and the Future resolving in
     /// their upload as well as their parsed content should be used for uploading changesets.
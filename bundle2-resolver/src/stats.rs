@@ -13,6 +13,7 @@ define_stats! {
     deltacache_fsize: histogram(400, 0, 100_000, AVG, SUM, COUNT; P 50; P 95; P 99),
     deltacache_fsize_large: histogram(400_000, 0, 100_000_000; P 50; P 95; P 99),
     bookmark_pushkeys_count: timeseries(RATE, AVG, SUM),
+    obsmarkers_count: timeseries(RATE, AVG, SUM),
     changesets_count: timeseries(RATE, AVG, SUM),
     manifests_count: timeseries(RATE, AVG, SUM),
     filelogs_count: timeseries(RATE, AVG, SUM),
@@ -15,18 +15,23 @@ extern crate slog;
 extern crate blobrepo;
 extern crate mercurial_types;
 extern crate metaconfig;
+extern crate reachabilityindex;
+extern crate ready_state;
 extern crate revset;
 
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use blobrepo::BlobRepo;
 use bookmarks::Bookmark;
-use futures::{Future, IntoFuture, Stream};
+use futures::{future, stream, Future, IntoFuture, Stream};
 use futures_ext::{BoxFuture, FutureExt};
 use mercurial_types::{Changeset, HgChangesetId, MPath, RepoPath};
 use mercurial_types::manifest::{Entry, Type};
 use mercurial_types::manifest_utils::recursive_entry_stream;
 use metaconfig::CacheWarmupParams;
+use reachabilityindex::SkiplistIndex;
+use ready_state::ReadyStateBuilder;
 use revset::AncestorsNodeStream;
 use slog::Logger;
 
@@ -84,7 +89,8 @@ fn blobstore_and_filenodes_warmup(
         .boxify()
 }
 
-// Iterate over first parents, and fetch them
+// Iterate over ancestors, and fetch them together with their generation numbers and bonsai
+// mappings.
 fn changesets_warmup(
     start_rev: HgChangesetId,
     repo: Arc<BlobRepo>,
@@ -93,58 +99,230 @@ fn changesets_warmup(
 ) -> BoxFuture<(), Error> {
     info!(logger, "about to start warming up changesets cache");
 
+    let buffer_size = 100;
     AncestorsNodeStream::new(&repo, start_rev.into_nodehash())
         .take(cs_limit as u64)
-        .collect()
-        .map(|_| ())
+        .map({
+            let repo = repo.clone();
+            move |node| repo.get_generation_number(&HgChangesetId::new(node))
+        })
+        .buffered(buffer_size)
+        .for_each(|_| Ok(()))
         .boxify()
 }
 
-fn do_cache_warmup(
+// Fetches the content of the `file_limit` most recently changed files, as of `start_rev`, among
+// the files changed by its first `cs_limit` ancestors.
+fn file_content_warmup(
+    start_rev: HgChangesetId,
     repo: Arc<BlobRepo>,
-    bookmark: Bookmark,
-    commit_limit: usize,
+    cs_limit: usize,
+    file_limit: usize,
     logger: Logger,
 ) -> BoxFuture<(), Error> {
+    info!(logger, "about to start warming up the content of {} files", file_limit);
+
+    let buffer_size = 100;
+    repo.get_changeset_by_changesetid(&start_rev)
+        .and_then(move |cs| {
+            let manifest_id = *cs.manifestid();
+            let mut seen = HashSet::new();
+            AncestorsNodeStream::new(&repo, start_rev.into_nodehash())
+                .take(cs_limit as u64)
+                .map({
+                    let repo = repo.clone();
+                    move |node| repo.get_changeset_by_changesetid(&HgChangesetId::new(node))
+                })
+                .buffered(buffer_size)
+                .map(|cs| stream::iter_ok::<_, Error>(cs.files().to_vec()))
+                .flatten()
+                .filter(move |path| seen.insert(path.clone()))
+                .take(file_limit as u64)
+                .map(move |path| {
+                    let repo = repo.clone();
+                    repo.find_file_in_manifest(&path, manifest_id)
+                        .and_then(move |filenode| match filenode {
+                            Some(filenode) => repo.get_file_content(&filenode.into_nodehash())
+                                .map(|_| ())
+                                .left_future(),
+                            // Deleted since it was changed.
+                            None => Ok(()).into_future().right_future(),
+                        })
+                })
+                .buffered(buffer_size)
+                .for_each(|()| Ok(()))
+        })
+        .boxify()
+}
+
+// Indexes the ancestors of the bookmark in the skiplist index, so that the first reachability
+// queries don't have to do it.
+fn skiplist_warmup(
+    start_rev: HgChangesetId,
+    repo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
+    depth: u64,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    info!(logger, "about to start building the skiplist index");
+
+    skiplist_index
+        .add_node(repo, start_rev.into_nodehash(), depth)
+        .map(move |()| {
+            info!(
+                logger,
+                "skiplist index has {} nodes",
+                skiplist_index.indexed_node_count()
+            );
+        })
+        .boxify()
+}
+
+fn resolve_bookmark(
+    repo: &BlobRepo,
+    bookmark: Bookmark,
+    logger: Logger,
+) -> BoxFuture<HgChangesetId, Error> {
     repo.get_bookmark(&bookmark)
-        .and_then({
-            let logger = logger.clone();
-            let repo = repo.clone();
-            move |bookmark_rev| match bookmark_rev {
-                Some(bookmark_rev) => {
-                    let blobstore_warmup =
-                        blobstore_and_filenodes_warmup(repo.clone(), bookmark_rev, logger.clone());
-                    let cs_warmup =
-                        changesets_warmup(bookmark_rev, repo, commit_limit, logger).boxify();
-                    blobstore_warmup.join(cs_warmup).map(|_| ()).boxify()
-                }
-                None => {
-                    info!(logger, "{} bookmark not found!", bookmark);
-                    Err(errors::ErrorKind::BookmarkNotFound(bookmark).into())
-                        .into_future()
-                        .boxify()
-                }
+        .and_then(move |bookmark_rev| match bookmark_rev {
+            Some(bookmark_rev) => Ok(bookmark_rev),
+            None => {
+                info!(logger, "{} bookmark not found!", bookmark);
+                Err(errors::ErrorKind::BookmarkNotFound(bookmark).into())
             }
         })
-        .map(move |()| {
+        .boxify()
+}
+
+// Every step of the warmup has its own ready handle, so that the server reports being ready only
+// once all of them are done.
+fn warmup_step<F>(
+    repo: &BlobRepo,
+    ready: &mut ReadyStateBuilder,
+    repo_name: &str,
+    step: &'static str,
+    bookmark: &Bookmark,
+    logger: &Logger,
+    warmup: F,
+) -> BoxFuture<(), Error>
+where
+    F: FnOnce(HgChangesetId) -> BoxFuture<(), Error> + Send + 'static,
+{
+    let handle = ready.create_handle(format!(
+        "{} cache warmup: {} for {}",
+        repo_name, step, bookmark
+    ));
+    let logger = logger.clone();
+    let bookmark = bookmark.clone();
+    let warmup = resolve_bookmark(repo, bookmark.clone(), logger.clone())
+        .and_then(warmup)
+        .map(move |()| info!(logger, "finished {} warmup for {}", step, bookmark));
+    handle.wait_for(warmup).boxify()
+}
+
+fn do_cache_warmup(
+    repo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
+    params: CacheWarmupParams,
+    ready: &mut ReadyStateBuilder,
+    repo_name: &str,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    let CacheWarmupParams {
+        bookmarks,
+        commit_limit,
+        file_limit,
+        skiplist_depth,
+    } = params;
+
+    let mut warmups = Vec::new();
+    for bookmark in bookmarks {
+        warmups.push(warmup_step(
+            &repo,
+            ready,
+            repo_name,
+            "manifests and linknodes",
+            &bookmark,
+            &logger,
+            {
+                let repo = repo.clone();
+                let logger = logger.clone();
+                move |rev| blobstore_and_filenodes_warmup(repo, rev, logger)
+            },
+        ));
+        warmups.push(warmup_step(
+            &repo,
+            ready,
+            repo_name,
+            "changesets",
+            &bookmark,
+            &logger,
+            {
+                let repo = repo.clone();
+                let logger = logger.clone();
+                move |rev| changesets_warmup(rev, repo, commit_limit, logger)
+            },
+        ));
+        if file_limit > 0 {
+            warmups.push(warmup_step(
+                &repo,
+                ready,
+                repo_name,
+                "file content",
+                &bookmark,
+                &logger,
+                {
+                    let repo = repo.clone();
+                    let logger = logger.clone();
+                    move |rev| file_content_warmup(rev, repo, commit_limit, file_limit, logger)
+                },
+            ));
+        }
+        if let Some(depth) = skiplist_depth {
+            warmups.push(warmup_step(
+                &repo,
+                ready,
+                repo_name,
+                "skiplist index",
+                &bookmark,
+                &logger,
+                {
+                    let repo = repo.clone();
+                    let skiplist_index = skiplist_index.clone();
+                    let logger = logger.clone();
+                    move |rev| skiplist_warmup(rev, repo, skiplist_index, depth, logger)
+                },
+            ));
+        }
+    }
+
+    future::join_all(warmups)
+        .map(move |_| {
             info!(logger, "finished initial warmup");
             ()
         })
         .boxify()
 }
 
-/// Fetch all manifest entries for a bookmark, and fetches up to `commit_warmup_limit`
-/// ancestors of the bookmark.
+/// Warm up the caches of the repo for each of the bookmarks of `cache_warmup`: fetch all their
+/// manifest entries and linknodes, up to `commit_limit` of their ancestors with their generation
+/// numbers and bonsai mappings, and optionally the content of the most recently changed files
+/// and the skiplist index. Each step registers a handle in `ready`.
 pub fn cache_warmup(
     repo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
     cache_warmup: Option<CacheWarmupParams>,
+    ready: &mut ReadyStateBuilder,
+    repo_name: &str,
     logger: Logger,
 ) -> BoxFuture<(), Error> {
     match cache_warmup {
         Some(cache_warmup) => do_cache_warmup(
             repo,
-            cache_warmup.bookmark,
-            cache_warmup.commit_limit,
+            skiplist_index,
+            cache_warmup,
+            ready,
+            repo_name,
             logger.clone(),
         ),
         None => Ok(()).into_future().boxify(),
@@ -12,3 +12,9 @@ CREATE TABLE csparents (
   seq INTEGER NOT NULL,
   PRIMARY KEY (cs_id, seq)
 );
+
+CREATE TABLE IF NOT EXISTS cschildren (
+  parent_id BIGINT NOT NULL,
+  child_id BIGINT NOT NULL,
+  PRIMARY KEY (parent_id, child_id)
+);
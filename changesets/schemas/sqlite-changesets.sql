@@ -1,4 +1,6 @@
-CREATE TABLE changesets (
+-- open_or_create runs this whole file on existing databases too, so that the tables added since
+-- they were created are added to them.
+CREATE TABLE IF NOT EXISTS changesets (
   -- Sqlite doesn't support autoincrement UNSIGNED BIGINT
   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
   repo_id INTEGER NOT NULL,
@@ -7,9 +9,15 @@ CREATE TABLE changesets (
   UNIQUE (repo_id, cs_id)
 );
 
-CREATE TABLE csparents (
+CREATE TABLE IF NOT EXISTS csparents (
   cs_id BIGINT NOT NULL,
   parent_id BIGINT NOT NULL,
   seq INTEGER NOT NULL,
   PRIMARY KEY (cs_id, seq)
 );
+
+CREATE TABLE IF NOT EXISTS cschildren (
+  parent_id BIGINT NOT NULL,
+  child_id BIGINT NOT NULL,
+  PRIMARY KEY (parent_id, child_id)
+);
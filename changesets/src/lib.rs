@@ -8,6 +8,7 @@
 #![feature(try_from, never_type)]
 
 extern crate asyncmemo;
+extern crate byteorder;
 extern crate db_conn;
 #[macro_use]
 extern crate diesel;
@@ -30,12 +31,14 @@ extern crate stats;
 
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::path::Path;
 use std::result;
 use std::sync::{Arc, MutexGuard};
 
 use asyncmemo::{Asyncmemo, Filler, Weight};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use db_conn::{MysqlConnInner, SqliteConnInner};
-use diesel::{insert_into, Connection, MysqlConnection, SqliteConnection};
+use diesel::{insert_into, insert_or_ignore_into, Connection, MysqlConnection, SqliteConnection};
 use diesel::backend::Backend;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
@@ -43,7 +46,7 @@ use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use diesel::sql_types::HasSqlType;
 use failure::ResultExt;
 
-use futures::Future;
+use futures::{future, Future};
 use futures_ext::{asynchronize, BoxFuture, FutureExt};
 use mercurial_types::RepositoryId;
 use mononoke_types::ChangesetId;
@@ -51,18 +54,30 @@ use mononoke_types::sql_types::ChangesetIdSql;
 use stats::Timeseries;
 
 mod errors;
+mod mem_writes;
 mod schema;
 mod models;
 mod wrappers;
 
 pub use errors::*;
-use models::{ChangesetInsertRow, ChangesetParentRow, ChangesetRow};
-use schema::{changesets, csparents};
+pub use mem_writes::MemWritesChangesets;
+use models::{ChangesetChildRow, ChangesetInsertRow, ChangesetParentRow, ChangesetRow};
+use schema::{changesets, cschildren, csparents};
+
+// SQLite doesn't allow more than 999 parameters in a query, so the changesets are fetched in
+// chunks by `get_many`.
+const GET_MANY_CHUNK_SIZE: usize = 500;
 
 define_stats! {
     prefix = "mononoke.changesets";
     gets: timeseries(RATE, SUM),
     gets_master: timeseries(RATE, SUM),
+    get_many: timeseries(RATE, SUM),
+    get_many_master: timeseries(RATE, SUM),
+    get_all_ids: timeseries(RATE, SUM),
+    get_children: timeseries(RATE, SUM),
+    get_children_master: timeseries(RATE, SUM),
+    backfill_children: timeseries(RATE, SUM),
     adds: timeseries(RATE, SUM),
 }
 
@@ -93,8 +108,39 @@ pub trait Changesets: Send + Sync {
         repo_id: RepositoryId,
         cs_id: ChangesetId,
     ) -> BoxFuture<Option<ChangesetEntry>, Error>;
+
+    /// Retrieve the rows of all the commits that are available, in no particular order. This
+    /// should be preferred to many calls to `get`, as it needs fewer roundtrips to the database.
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<Vec<ChangesetEntry>, Error>;
+
+    /// Retrieve the ids of all the changesets of a repo. This is meant for maintenance tools
+    /// that need to walk the whole repo, it should not be used while serving.
+    fn get_all_ids(&self, repo_id: RepositoryId) -> BoxFuture<Vec<ChangesetId>, Error>;
+
+    /// Retrieve the changesets that have this commit as a parent, in the order they were added.
+    /// A commit that isn't available has no children.
+    fn get_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Vec<ChangesetId>, Error>;
+
+    /// Record the commits as children of their parents. `add` does it for new commits, this is
+    /// for the commits added before the children were stored. Recording a commit again is
+    /// harmless. Returns the number of commits found.
+    fn backfill_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<usize, Error>;
 }
 
+/// Caches the changesets. Their children are not cached: unlike the parents, they change when
+/// commits are added, possibly by other servers or tools, so a cached list could be stale.
 pub struct CachingChangests {
     changesets: Arc<Changesets>,
     cache: asyncmemo::Asyncmemo<ChangesetsFiller>,
@@ -110,6 +156,18 @@ impl CachingChangests {
         );
         Self { changesets, cache }
     }
+
+    /// Save the cached changesets to a file, so that they can be loaded with `load_snapshot`
+    /// after a restart. Returns the number of changesets saved.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.save_snapshot_file(path)?)
+    }
+
+    /// Load the changesets saved by `save_snapshot` into the cache, as far as they fit. Returns
+    /// the number of changesets loaded.
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.load_snapshot_file(path)?)
+    }
 }
 
 impl Changesets for CachingChangests {
@@ -131,6 +189,56 @@ impl Changesets for CachingChangests {
             })
             .boxify()
     }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<Vec<ChangesetEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut missing = Vec::new();
+        for cs_id in cs_ids {
+            match self.cache.get_cached((repo_id, cs_id)) {
+                Some(entry) => entries.push(entry),
+                None => missing.push(cs_id),
+            }
+        }
+        if missing.is_empty() {
+            return future::ok(entries).boxify();
+        }
+
+        let cache = self.cache.clone();
+        self.changesets
+            .get_many(repo_id, missing)
+            .map(move |fetched| {
+                for entry in fetched {
+                    cache.insert((repo_id, entry.cs_id), entry.clone());
+                    entries.push(entry);
+                }
+                entries
+            })
+            .boxify()
+    }
+
+    fn get_all_ids(&self, repo_id: RepositoryId) -> BoxFuture<Vec<ChangesetId>, Error> {
+        self.changesets.get_all_ids(repo_id)
+    }
+
+    fn get_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Vec<ChangesetId>, Error> {
+        self.changesets.get_children(repo_id, cs_id)
+    }
+
+    fn backfill_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<usize, Error> {
+        self.changesets.backfill_children(repo_id, cs_ids)
+    }
 }
 
 pub struct ChangesetsFiller {
@@ -157,6 +265,40 @@ impl Filler for ChangesetsFiller {
             })
             .boxify()
     }
+
+    // An entry is the repo id and the generation number as big-endian integers, surrounding
+    // the changeset id, followed by the parents.
+    fn serialize_entry(&self, _key: &Self::Key, entry: &ChangesetEntry) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(44 + 32 * entry.parents.len());
+        data.write_i32::<BigEndian>(entry.repo_id.id()).ok()?;
+        data.extend_from_slice(entry.cs_id.as_ref());
+        data.write_u64::<BigEndian>(entry.gen).ok()?;
+        for parent in &entry.parents {
+            data.extend_from_slice(parent.as_ref());
+        }
+        Some(data)
+    }
+
+    fn deserialize_entry(&self, data: &[u8]) -> Option<(Self::Key, ChangesetEntry)> {
+        if data.len() < 44 || (data.len() - 44) % 32 != 0 {
+            return None;
+        }
+        let repo_id = RepositoryId::new(BigEndian::read_i32(&data[..4]));
+        let cs_id = ChangesetId::from_bytes(&data[4..36]).ok()?;
+        let gen = BigEndian::read_u64(&data[36..44]);
+        let parents = data[44..]
+            .chunks(32)
+            .map(|parent| ChangesetId::from_bytes(parent).ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        let entry = ChangesetEntry {
+            repo_id,
+            cs_id,
+            parents,
+            gen,
+        };
+        Some(((repo_id, cs_id), entry))
+    }
 }
 
 impl Weight for ChangesetEntry {
@@ -266,6 +408,118 @@ macro_rules! impl_changesets {
                 })
             }
 
+            fn get_many(
+                &self,
+                repo_id: RepositoryId,
+                cs_ids: Vec<ChangesetId>,
+            ) -> BoxFuture<Vec<ChangesetEntry>, Error> {
+                STATS::get_many.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let mut entries = Vec::new();
+                    {
+                        let connection = db.get_conn()?;
+                        for chunk in cs_ids.chunks(GET_MANY_CHUNK_SIZE) {
+                            entries.extend(Self::actual_get_many(&connection, repo_id, chunk)?);
+                        }
+                    }
+
+                    let found: HashSet<_> = entries.iter().map(|entry| entry.cs_id).collect();
+                    let missing: Vec<_> = cs_ids
+                        .into_iter()
+                        .filter(|cs_id| !found.contains(cs_id))
+                        .collect();
+                    if !missing.is_empty() {
+                        STATS::get_many_master.add_value(1);
+                        let connection = db.get_master_conn()?;
+                        for chunk in missing.chunks(GET_MANY_CHUNK_SIZE) {
+                            entries.extend(Self::actual_get_many(&connection, repo_id, chunk)?);
+                        }
+                    }
+                    Ok(entries)
+                })
+            }
+
+            fn get_all_ids(&self, repo_id: RepositoryId) -> BoxFuture<Vec<ChangesetId>, Error> {
+                STATS::get_all_ids.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let rows = changesets::table
+                        .filter(changesets::repo_id.eq(repo_id))
+                        .order(changesets::id.asc())
+                        .load::<ChangesetRow>(&*connection)?;
+                    Ok(rows.into_iter().map(|row| row.cs_id).collect())
+                })
+            }
+
+            fn get_children(
+                &self,
+                repo_id: RepositoryId,
+                cs_id: ChangesetId,
+            ) -> BoxFuture<Vec<ChangesetId>, Error> {
+                STATS::get_children.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let children = {
+                        let connection = db.get_conn()?;
+                        Self::actual_get_children(&connection, repo_id, cs_id)?
+                    };
+
+                    match children {
+                        Some(children) => Ok(children),
+                        None => {
+                            STATS::get_children_master.add_value(1);
+                            let connection = db.get_master_conn()?;
+                            Ok(Self::actual_get_children(&connection, repo_id, cs_id)?
+                                .unwrap_or_default())
+                        }
+                    }
+                })
+            }
+
+            fn backfill_children(
+                &self,
+                repo_id: RepositoryId,
+                cs_ids: Vec<ChangesetId>,
+            ) -> BoxFuture<usize, Error> {
+                STATS::backfill_children.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    let mut found = 0;
+                    for chunk in cs_ids.chunks(GET_MANY_CHUNK_SIZE) {
+                        let ids = changesets::table
+                            .filter(changesets::repo_id.eq(repo_id))
+                            .filter(changesets::cs_id.eq_any(chunk))
+                            .select(changesets::id)
+                            .load::<i64>(&*connection)?;
+                        found += ids.len();
+
+                        let child_rows: Vec<_> = csparents::table
+                            .filter(csparents::cs_id.eq_any(ids))
+                            .load::<ChangesetParentRow>(&*connection)?
+                            .into_iter()
+                            .map(|row| ChangesetChildRow {
+                                parent_id: row.parent_id,
+                                child_id: row.cs_id,
+                            })
+                            .collect();
+                        // Each row takes two parameters.
+                        for rows in child_rows.chunks(GET_MANY_CHUNK_SIZE / 2) {
+                            insert_or_ignore_into(cschildren::table)
+                                .values(rows)
+                                .execute(&*connection)?;
+                        }
+                    }
+                    Ok(found)
+                })
+            }
+
             /// Insert a new changeset into this table. Checks that all parents are already in
             /// storage.
             fn add(&self, cs: ChangesetInsert) -> BoxFuture<bool, Error> {
@@ -380,6 +634,17 @@ macro_rules! impl_changesets {
                             insert_into(csparents::table)
                                 .values(&parent_inserts)
                                 .execute(&*connection)?;
+
+                            let child_inserts: Vec<_> = parent_map
+                                .values()
+                                .map(|parent_id| ChangesetChildRow {
+                                    parent_id: *parent_id,
+                                    child_id: new_cs_row.id,
+                                })
+                                .collect();
+                            insert_into(cschildren::table)
+                                .values(&child_inserts)
+                                .execute(&*connection)?;
                             Ok(true)
                         })
                     })
@@ -427,6 +692,64 @@ macro_rules! impl_changesets {
                     }
                 })
             }
+
+            fn actual_get_children(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_id: ChangesetId,
+            ) -> Result<Option<Vec<ChangesetId>>> {
+                let row = changeset_query(repo_id, cs_id)
+                    .first::<ChangesetRow>(connection)
+                    .optional()?;
+                let row = match row {
+                    Some(row) => row,
+                    None => return Ok(None),
+                };
+
+                let children = cschildren::table
+                    .filter(cschildren::parent_id.eq(row.id))
+                    .inner_join(changesets::table)
+                    .order(changesets::id.asc())
+                    .load::<(ChangesetChildRow, ChangesetRow)>(connection)?;
+                Ok(Some(children.into_iter().map(|(_, child)| child.cs_id).collect()))
+            }
+
+            fn actual_get_many(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_ids: &[ChangesetId],
+            ) -> Result<Vec<ChangesetEntry>> {
+                let rows = changesets::table
+                    .filter(changesets::repo_id.eq(repo_id))
+                    .filter(changesets::cs_id.eq_any(cs_ids))
+                    .load::<ChangesetRow>(connection)?;
+
+                let ids: Vec<_> = rows.iter().map(|row| row.id).collect();
+                let parent_rows = csparents::table
+                    .filter(csparents::cs_id.eq_any(ids))
+                    .order((csparents::cs_id.asc(), csparents::seq.asc()))
+                    .inner_join(changesets::table)
+                    .load::<(ChangesetParentRow, ChangesetRow)>(connection)?;
+                let mut parents: HashMap<i64, Vec<ChangesetId>> = HashMap::new();
+                for (parent_row, parent) in parent_rows {
+                    parents
+                        .entry(parent_row.cs_id)
+                        .or_insert_with(Vec::new)
+                        .push(parent.cs_id);
+                }
+
+                rows.into_iter()
+                    .map(|row| {
+                        let gen = u64::try_from(row.gen).context(ErrorKind::InvalidStoredData)?;
+                        Ok(ChangesetEntry {
+                            repo_id: row.repo_id,
+                            cs_id: row.cs_id,
+                            parents: parents.remove(&row.id).unwrap_or_default(),
+                            gen,
+                        })
+                    })
+                    .collect()
+            }
         }
     }
 }
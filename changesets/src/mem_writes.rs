@@ -0,0 +1,222 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+use mercurial_types::RepositoryId;
+use mononoke_types::ChangesetId;
+
+use {ChangesetEntry, ChangesetInsert, Changesets};
+use errors::*;
+use models::ChangesetRow;
+
+#[derive(Default)]
+struct MemChangesets {
+    entries: HashMap<(RepositoryId, ChangesetId), ChangesetEntry>,
+    // In insertion order, like the SQL stores return them.
+    ids: Vec<(RepositoryId, ChangesetId)>,
+    // The children of the changesets in either store, that were added in memory.
+    children: HashMap<(RepositoryId, ChangesetId), Vec<ChangesetId>>,
+}
+
+/// A changesets store that reads from the underlying store but keeps the changesets it adds in
+/// memory. Their parents can be in either.
+#[derive(Clone)]
+pub struct MemWritesChangesets {
+    inner: Arc<Changesets>,
+    mem: Arc<Mutex<MemChangesets>>,
+}
+
+impl MemWritesChangesets {
+    pub fn new(inner: Arc<Changesets>) -> Self {
+        Self {
+            inner,
+            mem: Arc::new(Mutex::new(MemChangesets::default())),
+        }
+    }
+}
+
+fn to_rows(entries: &[ChangesetEntry]) -> Vec<ChangesetRow> {
+    entries
+        .iter()
+        .map(|entry| ChangesetRow {
+            id: 0,
+            repo_id: entry.repo_id,
+            cs_id: entry.cs_id,
+            gen: entry.gen as i64,
+        })
+        .collect()
+}
+
+impl Changesets for MemWritesChangesets {
+    fn add(&self, cs: ChangesetInsert) -> BoxFuture<bool, Error> {
+        let this = self.clone();
+        self.get_many(cs.repo_id, cs.parents.clone())
+            .join(self.get(cs.repo_id, cs.cs_id))
+            .and_then(move |(parents, existing)| {
+                let missing: Vec<_> = cs.parents
+                    .iter()
+                    .filter(|p| !parents.iter().any(|entry| entry.cs_id == **p))
+                    .cloned()
+                    .collect();
+                if !missing.is_empty() {
+                    return future::err(ErrorKind::MissingParents(missing).into()).left_future();
+                }
+
+                match existing {
+                    Some(ref existing) if existing.parents == cs.parents => {
+                        future::ok(false).left_future()
+                    }
+                    Some(existing) => this.get_many(cs.repo_id, existing.parents)
+                        .and_then(move |old_parents| -> Result<bool> {
+                            Err(ErrorKind::DuplicateInsertionInconsistency(
+                                cs.cs_id,
+                                to_rows(&old_parents),
+                                to_rows(&parents),
+                            ).into())
+                        })
+                        .right_future(),
+                    None => {
+                        // A changeset with no parents has generation number 1.
+                        let gen = parents.iter().map(|entry| entry.gen).max().unwrap_or(0) + 1;
+                        let key = (cs.repo_id, cs.cs_id);
+                        let mut mem = this.mem.lock().expect("lock poisoned");
+                        if mem.entries.contains_key(&key) {
+                            // Added concurrently.
+                            return future::ok(false).left_future();
+                        }
+                        for parent in &cs.parents {
+                            let children = mem.children
+                                .entry((cs.repo_id, *parent))
+                                .or_insert_with(Vec::new);
+                            if !children.contains(&cs.cs_id) {
+                                children.push(cs.cs_id);
+                            }
+                        }
+                        mem.entries.insert(
+                            key,
+                            ChangesetEntry {
+                                repo_id: cs.repo_id,
+                                cs_id: cs.cs_id,
+                                parents: cs.parents,
+                                gen,
+                            },
+                        );
+                        mem.ids.push(key);
+                        future::ok(true).left_future()
+                    }
+                }
+            })
+            .boxify()
+    }
+
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Option<ChangesetEntry>, Error> {
+        let entry = {
+            let mem = self.mem.lock().expect("lock poisoned");
+            mem.entries.get(&(repo_id, cs_id)).cloned()
+        };
+        match entry {
+            Some(entry) => future::ok(Some(entry)).boxify(),
+            None => self.inner.get(repo_id, cs_id),
+        }
+    }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<Vec<ChangesetEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let mem = self.mem.lock().expect("lock poisoned");
+            for cs_id in cs_ids {
+                match mem.entries.get(&(repo_id, cs_id)) {
+                    Some(entry) => entries.push(entry.clone()),
+                    None => missing.push(cs_id),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return future::ok(entries).boxify();
+        }
+
+        self.inner
+            .get_many(repo_id, missing)
+            .map(move |fetched| {
+                entries.extend(fetched);
+                entries
+            })
+            .boxify()
+    }
+
+    fn get_all_ids(&self, repo_id: RepositoryId) -> BoxFuture<Vec<ChangesetId>, Error> {
+        let mem = self.mem.clone();
+        self.inner
+            .get_all_ids(repo_id)
+            .map(move |mut ids| {
+                let mem = mem.lock().expect("lock poisoned");
+                ids.extend(
+                    mem.ids
+                        .iter()
+                        .filter(|&&(id_repo, _)| id_repo == repo_id)
+                        .map(|&(_, cs_id)| cs_id),
+                );
+                ids
+            })
+            .boxify()
+    }
+
+    fn get_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Vec<ChangesetId>, Error> {
+        let mem = self.mem.clone();
+        self.inner
+            .get_children(repo_id, cs_id)
+            .map(move |mut children| {
+                let mem = mem.lock().expect("lock poisoned");
+                if let Some(mem_children) = mem.children.get(&(repo_id, cs_id)) {
+                    children.extend(mem_children.iter().cloned());
+                }
+                children
+            })
+            .boxify()
+    }
+
+    fn backfill_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<usize, Error> {
+        // The changesets added in memory already have their children recorded.
+        let mut mem_count = 0;
+        let mut missing = Vec::new();
+        {
+            let mem = self.mem.lock().expect("lock poisoned");
+            for cs_id in cs_ids {
+                if mem.entries.contains_key(&(repo_id, cs_id)) {
+                    mem_count += 1;
+                } else {
+                    missing.push(cs_id);
+                }
+            }
+        }
+
+        self.inner
+            .backfill_children(repo_id, missing)
+            .map(move |count| count + mem_count)
+            .boxify()
+    }
+}
@@ -7,7 +7,7 @@
 use mercurial_types::RepositoryId;
 use mononoke_types::ChangesetId;
 
-use schema::{changesets, csparents};
+use schema::{changesets, cschildren, csparents};
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
 #[derive(Queryable)]
@@ -29,6 +29,14 @@ pub(crate) struct ChangesetParentRow {
     pub seq: i32,
 }
 
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "cschildren"]
+pub(crate) struct ChangesetChildRow {
+    pub parent_id: i64,
+    pub child_id: i64,
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 #[derive(Insertable)]
 #[table_name = "changesets"]
@@ -29,5 +29,13 @@ table! {
     }
 }
 
+table! {
+    cschildren (parent_id, child_id) {
+        parent_id -> BigInt,
+        child_id -> BigInt,
+    }
+}
+
 joinable!(csparents -> changesets (parent_id));
-allow_tables_to_appear_in_same_query!(changesets, csparents);
+joinable!(cschildren -> changesets (child_id));
+allow_tables_to_appear_in_same_query!(changesets, csparents, cschildren);
@@ -27,4 +27,32 @@ impl Changesets for Arc<Changesets> {
     ) -> BoxFuture<Option<ChangesetEntry>, Error> {
         (**self).get(repo_id, cs_id)
     }
+
+    fn get_many(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<Vec<ChangesetEntry>, Error> {
+        (**self).get_many(repo_id, cs_ids)
+    }
+
+    fn get_all_ids(&self, repo_id: RepositoryId) -> BoxFuture<Vec<ChangesetId>, Error> {
+        (**self).get_all_ids(repo_id)
+    }
+
+    fn get_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Vec<ChangesetId>, Error> {
+        (**self).get_children(repo_id, cs_id)
+    }
+
+    fn backfill_children(
+        &self,
+        repo_id: RepositoryId,
+        cs_ids: Vec<ChangesetId>,
+    ) -> BoxFuture<usize, Error> {
+        (**self).backfill_children(repo_id, cs_ids)
+    }
 }
@@ -14,6 +14,7 @@ extern crate async_unit;
 extern crate diesel;
 extern crate failure_ext as failure;
 extern crate futures;
+extern crate tempdir;
 
 extern crate changesets;
 extern crate mercurial_types_mocks;
@@ -21,10 +22,13 @@ extern crate mononoke_types_mocks;
 
 use std::sync::Arc;
 
+use diesel::{Connection, SqliteConnection};
+use diesel::connection::SimpleConnection;
 use futures::Future;
+use tempdir::TempDir;
 
-use changesets::{ChangesetEntry, ChangesetInsert, Changesets, ErrorKind, MysqlChangesets,
-                 SqliteChangesets};
+use changesets::{CachingChangests, ChangesetEntry, ChangesetInsert, Changesets, ErrorKind,
+                 MemWritesChangesets, MysqlChangesets, SqliteChangesets};
 use mercurial_types_mocks::repo::*;
 use mononoke_types_mocks::changesetid::*;
 
@@ -240,6 +244,263 @@ fn complex<C: Changesets>(changesets: C) {
     );
 }
 
+fn get_all_ids<C: Changesets>(changesets: C) {
+    for (cs_id, parents) in vec![
+        (ONES_CSID, vec![]),
+        (TWOS_CSID, vec![ONES_CSID]),
+        (THREES_CSID, vec![TWOS_CSID]),
+    ] {
+        let row = ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id,
+            parents,
+        };
+        changesets.add(row).wait().expect("Adding row failed");
+    }
+    let row = ChangesetInsert {
+        repo_id: REPO_ONE,
+        cs_id: FOURS_CSID,
+        parents: vec![],
+    };
+    changesets.add(row).wait().expect("Adding row failed");
+
+    assert_eq!(
+        changesets
+            .get_all_ids(REPO_ZERO)
+            .wait()
+            .expect("Get all ids failed"),
+        vec![ONES_CSID, TWOS_CSID, THREES_CSID],
+    );
+    assert_eq!(
+        changesets
+            .get_all_ids(REPO_ONE)
+            .wait()
+            .expect("Get all ids failed"),
+        vec![FOURS_CSID],
+    );
+}
+
+fn get_many<C: Changesets>(changesets: C) {
+    for (cs_id, parents) in vec![
+        (ONES_CSID, vec![]),
+        (TWOS_CSID, vec![ONES_CSID]),
+        (THREES_CSID, vec![TWOS_CSID]),
+        (FOURS_CSID, vec![THREES_CSID, ONES_CSID]),
+    ] {
+        let row = ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id,
+            parents,
+        };
+        changesets.add(row).wait().expect("Adding row failed");
+    }
+
+    // Twice, as the second time the entries may come from a cache.
+    for _ in 0..2 {
+        let mut entries = changesets
+            .get_many(REPO_ZERO, vec![ONES_CSID, FOURS_CSID, FIVES_CSID])
+            .wait()
+            .expect("Get many failed");
+        entries.sort_by_key(|entry| entry.gen);
+        assert_eq!(
+            entries,
+            vec![
+                ChangesetEntry {
+                    repo_id: REPO_ZERO,
+                    cs_id: ONES_CSID,
+                    parents: vec![],
+                    gen: 1,
+                },
+                ChangesetEntry {
+                    repo_id: REPO_ZERO,
+                    cs_id: FOURS_CSID,
+                    parents: vec![THREES_CSID, ONES_CSID],
+                    gen: 4,
+                },
+            ],
+        );
+    }
+
+    assert_eq!(
+        changesets
+            .get_many(REPO_ONE, vec![ONES_CSID])
+            .wait()
+            .expect("Get many failed"),
+        vec![],
+    );
+}
+
+fn mem_writes_overlay<C: Changesets + 'static>(changesets: C) {
+    let inner: Arc<Changesets> = Arc::new(changesets);
+    let row = ChangesetInsert {
+        repo_id: REPO_ZERO,
+        cs_id: ONES_CSID,
+        parents: vec![],
+    };
+    inner.add(row).wait().expect("Adding row to inner failed");
+
+    let overlay = MemWritesChangesets::new(inner.clone());
+    let row = ChangesetInsert {
+        repo_id: REPO_ZERO,
+        cs_id: TWOS_CSID,
+        parents: vec![ONES_CSID],
+    };
+    assert!(overlay.add(row).wait().expect("Adding row to overlay failed"));
+
+    assert_eq!(
+        overlay
+            .get(REPO_ZERO, TWOS_CSID)
+            .wait()
+            .expect("Get from overlay failed"),
+        Some(ChangesetEntry {
+            repo_id: REPO_ZERO,
+            cs_id: TWOS_CSID,
+            parents: vec![ONES_CSID],
+            gen: 2,
+        }),
+    );
+    assert_eq!(
+        overlay
+            .get_all_ids(REPO_ZERO)
+            .wait()
+            .expect("Get all ids failed"),
+        vec![ONES_CSID, TWOS_CSID],
+    );
+    assert_eq!(
+        inner
+            .get(REPO_ZERO, TWOS_CSID)
+            .wait()
+            .expect("Get from inner failed"),
+        None,
+        "the overlay must not write to the inner store"
+    );
+}
+
+fn get_children<C: Changesets>(changesets: C) {
+    let rows = vec![
+        ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id: ONES_CSID,
+            parents: vec![],
+        },
+        ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id: TWOS_CSID,
+            parents: vec![ONES_CSID],
+        },
+        ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id: THREES_CSID,
+            parents: vec![ONES_CSID],
+        },
+        ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id: FOURS_CSID,
+            parents: vec![TWOS_CSID, THREES_CSID],
+        },
+    ];
+    for row in rows {
+        changesets.add(row).wait().expect("Adding new entry failed");
+    }
+
+    let get_children = |cs_id| {
+        changesets
+            .get_children(REPO_ZERO, cs_id)
+            .wait()
+            .expect("Get children failed")
+    };
+    assert_eq!(get_children(ONES_CSID), vec![TWOS_CSID, THREES_CSID]);
+    assert_eq!(get_children(TWOS_CSID), vec![FOURS_CSID]);
+    assert_eq!(get_children(THREES_CSID), vec![FOURS_CSID]);
+    assert_eq!(get_children(FOURS_CSID), vec![]);
+    assert_eq!(get_children(FIVES_CSID), vec![]);
+
+    // The children of these changesets were recorded when they were added.
+    assert_eq!(
+        changesets
+            .backfill_children(REPO_ZERO, vec![TWOS_CSID, FOURS_CSID, FIVES_CSID])
+            .wait()
+            .expect("Backfilling children failed"),
+        2,
+    );
+    assert_eq!(get_children(ONES_CSID), vec![TWOS_CSID, THREES_CSID]);
+    assert_eq!(get_children(THREES_CSID), vec![FOURS_CSID]);
+
+    assert_eq!(
+        changesets
+            .get_children(REPO_ONE, ONES_CSID)
+            .wait()
+            .expect("Get children failed"),
+        vec![],
+    );
+}
+
+// The schema of databases created before children were recorded.
+const SQLITE_SCHEMA_WITHOUT_CHILDREN: &str = "
+CREATE TABLE changesets (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  repo_id INTEGER NOT NULL,
+  cs_id VARBINARY(32) NOT NULL,
+  gen BIGINT NOT NULL,
+  UNIQUE (repo_id, cs_id)
+);
+
+CREATE TABLE csparents (
+  cs_id BIGINT NOT NULL,
+  parent_id BIGINT NOT NULL,
+  seq INTEGER NOT NULL,
+  PRIMARY KEY (cs_id, seq)
+);
+";
+
+#[test]
+fn test_sqlite_open_without_children_table() {
+    async_unit::tokio_unit_test(|| {
+        let dir = TempDir::new("changesets").expect("Creating a temporary directory failed");
+        let path = dir.path().join("changesets");
+        let path = path.to_str().expect("Temporary path is not UTF-8");
+
+        SqliteConnection::establish(path)
+            .expect("Opening the SQLite database failed")
+            .batch_execute(SQLITE_SCHEMA_WITHOUT_CHILDREN)
+            .expect("Creating the old schema failed");
+
+        let changesets =
+            SqliteChangesets::open_or_create(path).expect("Opening the changesets store failed");
+        get_children(changesets);
+    });
+}
+
+#[test]
+fn test_caching_children_added_elsewhere() {
+    async_unit::tokio_unit_test(|| {
+        let inner = new_sqlite_arced();
+        let caching = CachingChangests::new(inner.clone(), 1_000_000);
+        let add = |cs_id, parents| {
+            inner
+                .add(ChangesetInsert {
+                    repo_id: REPO_ZERO,
+                    cs_id,
+                    parents,
+                })
+                .wait()
+                .expect("Adding new entry failed")
+        };
+        let get_children = || {
+            caching
+                .get_children(REPO_ZERO, ONES_CSID)
+                .wait()
+                .expect("Get children failed")
+        };
+
+        add(ONES_CSID, vec![]);
+        assert_eq!(get_children(), vec![]);
+        // A child added by another server or tool, not through this cache.
+        add(TWOS_CSID, vec![ONES_CSID]);
+        assert_eq!(get_children(), vec![TWOS_CSID]);
+    });
+}
+
 macro_rules! changesets_test_impl {
     ($mod_name: ident => {
         new: $new_cb: expr,
@@ -288,6 +549,34 @@ macro_rules! changesets_test_impl {
                     complex($new_cb());
                 });
             }
+
+            #[test]
+            fn test_get_all_ids() {
+                async_unit::tokio_unit_test(|| {
+                    get_all_ids($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_get_many() {
+                async_unit::tokio_unit_test(|| {
+                    get_many($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_get_children() {
+                async_unit::tokio_unit_test(|| {
+                    get_children($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_mem_writes_overlay() {
+                async_unit::tokio_unit_test(|| {
+                    mem_writes_overlay($new_cb());
+                });
+            }
         }
     }
 }
@@ -304,6 +593,18 @@ changesets_test_impl! {
     }
 }
 
+changesets_test_impl! {
+    caching_test => {
+        new: new_caching,
+    }
+}
+
+changesets_test_impl! {
+    mem_writes_test => {
+        new: new_mem_writes,
+    }
+}
+
 changesets_test_impl! {
     mysql_test => {
         new: new_mysql,
@@ -325,6 +626,14 @@ fn new_sqlite_arced() -> Arc<Changesets> {
     Arc::new(new_sqlite())
 }
 
+fn new_caching() -> CachingChangests {
+    CachingChangests::new(new_sqlite_arced(), 1_000_000)
+}
+
+fn new_mem_writes() -> MemWritesChangesets {
+    MemWritesChangesets::new(new_sqlite_arced())
+}
+
 fn new_mysql() -> MysqlChangesets {
     MysqlChangesets::create_test_db("changesets_test").expect("Failed to create test database")
 }
@@ -16,7 +16,8 @@ use sloggers::types::{Format, Severity, SourceLocation};
 
 use slog_glog_fmt::default_drain as glog_drain;
 
-use blobrepo::{BlobRepo, ManifoldArgs};
+use blobrepo::{BlobRepo, CompressionArgs, ManifoldArgs};
+use blobstore::Keyring;
 use mercurial_types::RepositoryId;
 
 const CACHE_ARGS: &[(&str, &str)] = &[
@@ -142,6 +143,27 @@ impl MononokeApp {
                     .long("data-dir")
                     .value_name("DIR")
                     .help("local data directory (used for local blobstores)"),
+            ).arg(
+                Arg::with_name("encryption-keyfile")
+                    .long("encryption-keyfile")
+                    .value_name("PATH")
+                    .help("keys the blobs of local blobstores are encrypted with"),
+            ).arg(
+                Arg::with_name("encryption-allow-plaintext")
+                    .long("encryption-allow-plaintext")
+                    .requires("encryption-keyfile")
+                    .help("read the blobs written before encryption was enabled (migration only)"),
+            ).arg(
+                Arg::with_name("compression-threshold")
+                    .long("compression-threshold")
+                    .value_name("BYTES")
+                    .help("compress the blobs of local blobstores larger than this"),
+            ).arg(
+                Arg::with_name("compression-level")
+                    .long("compression-level")
+                    .value_name("LEVEL")
+                    .requires("compression-threshold")
+                    .help("zstd level the blobs of local blobstores are compressed with"),
             );
         }
 
@@ -185,6 +207,31 @@ pub fn get_repo_id<'a>(matches: &ArgMatches<'a>) -> RepositoryId {
     RepositoryId::new(repo_id as i32)
 }
 
+/// The keys the blobs of local instances are encrypted with, if any.
+pub fn get_keyring<'a>(matches: &ArgMatches<'a>) -> Result<Option<Keyring>> {
+    match matches.value_of("encryption-keyfile") {
+        Some(keyfile) => Ok(Some(Keyring::open(keyfile)?
+            .allow_plaintext(matches.is_present("encryption-allow-plaintext")))),
+        None => Ok(None),
+    }
+}
+
+/// How the blobs of local instances are compressed, if they are. Repos whose blobs were
+/// written compressed must be opened with compression.
+pub fn get_compression<'a>(matches: &ArgMatches<'a>) -> Option<CompressionArgs> {
+    get_usize_opt(matches, "compression-threshold").map(|threshold| {
+        let level = matches.value_of("compression-level").map(|level| {
+            level
+                .parse::<i32>()
+                .expect("compression-level must be integer")
+        });
+        CompressionArgs {
+            threshold,
+            level: level.unwrap_or(CompressionArgs::default().level),
+        }
+    })
+}
+
 /// Create a new `BlobRepo` -- for local instances, expect its contents to be empty.
 #[inline]
 pub fn create_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
@@ -247,6 +294,9 @@ fn open_blobrepo_internal<'a>(logger: &Logger, matches: &ArgMatches<'a>, create:
                 logger.new(o!["BlobRepo:Files" => data_dir.to_string_lossy().into_owned()]),
                 &data_dir,
                 repo_id,
+                get_keyring(matches).expect("failed to load encryption keyfile"),
+                None,
+                get_compression(matches).as_ref(),
             ).expect("failed to create file blobrepo")
         }
         Some("rocksdb") => {
@@ -262,6 +312,9 @@ fn open_blobrepo_internal<'a>(logger: &Logger, matches: &ArgMatches<'a>, create:
                 logger.new(o!["BlobRepo:Rocksdb" => data_dir.to_string_lossy().into_owned()]),
                 &data_dir,
                 repo_id,
+                get_keyring(matches).expect("failed to load encryption keyfile"),
+                None,
+                get_compression(matches).as_ref(),
             ).expect("failed to create rocksdb blobrepo")
         }
         None | Some("manifold") => {
@@ -301,6 +354,7 @@ pub fn parse_manifold_args<'a>(
             "max-concurrent-request-per-io-thread",
             5,
         ),
+        cache_snapshot_dir: None,
     }
 }
 
@@ -194,6 +194,7 @@ fn upload_entry(
                         p1: p1.cloned(),
                         p2: p2.cloned(),
                         path,
+                        flags: 0,
                     };
                     let (_, upload_fut) = try_boxfuture!(upload.upload(&blobrepo));
                     upload_fut
@@ -26,6 +26,7 @@ extern crate sloggers;
 extern crate slog_glog_fmt;
 
 extern crate blobrepo;
+extern crate blobstore;
 extern crate bookmarks;
 extern crate mercurial;
 extern crate mercurial_types;
@@ -0,0 +1,62 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Records the children of the changesets of a repo that were added before children were
+//! recorded, so that descendants can be found from them.
+
+use clap::{App, ArgMatches};
+use failure::Error;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use cmdlib::args;
+
+pub fn prepare_command<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("record the children of all the changesets of the repo")
+        .args_from_usage(
+            "--chunk-size [N]             'number of changesets backfilled at once [default: 1000]'
+             --progress-interval [N]      'report progress every N changesets [default: 100000]'",
+        )
+}
+
+pub fn handle_command<'a>(
+    sub_m: &ArgMatches<'a>,
+    repo: BlobRepo,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    let chunk_size = args::get_usize(sub_m, "chunk-size", 1000);
+    let progress_interval = args::get_usize(sub_m, "progress-interval", 100000);
+
+    repo.get_all_changesets()
+        .and_then(move |cs_ids| {
+            info!(logger, "backfilling the children of {} changesets", cs_ids.len());
+            let chunks: Vec<_> = cs_ids
+                .chunks(chunk_size)
+                .map(|chunk| chunk.to_vec())
+                .collect();
+            iter_ok::<_, Error>(chunks)
+                .fold(0, {
+                    cloned!(logger);
+                    move |done, chunk| {
+                        let new_done = done + chunk.len();
+                        repo.backfill_changeset_children(chunk).map({
+                            cloned!(logger);
+                            move |_| {
+                                if new_done / progress_interval > done / progress_interval {
+                                    info!(logger, "backfilled {} changesets", new_done);
+                                }
+                                new_done
+                            }
+                        })
+                    }
+                })
+                .map(move |done| info!(logger, "backfilled {} changesets", done))
+        })
+        .boxify()
+}
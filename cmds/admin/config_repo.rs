@@ -324,6 +324,9 @@ fn import(logger: Logger, src: PathBuf, dest: PathBuf) -> BoxFuture<(), Error> {
         logger.new(o!["BlobRepo:Rocksdb" => dest.to_string_lossy().into_owned()]),
         &dest,
         RepositoryId::new(0),
+        None,
+        None,
+        None,
     )));
 
     Blobimport {
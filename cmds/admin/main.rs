@@ -19,17 +19,27 @@ extern crate blobrepo;
 extern crate blobstore;
 extern crate bookmarks;
 extern crate cmdlib;
+extern crate fileblob;
 #[macro_use]
 extern crate futures_ext;
 extern crate manifoldblob;
 extern crate mercurial_types;
 extern crate mononoke_types;
+extern crate reachabilityindex;
+extern crate redacted_blobs;
+extern crate revset;
+extern crate rocksblob;
 #[macro_use]
 extern crate slog;
 extern crate tempdir;
 extern crate tokio;
 
+mod backfill_children;
 mod config_repo;
+mod redaction;
+mod reencrypt;
+mod scrub;
+mod skiplist;
 
 use std::fmt;
 use std::str::FromStr;
@@ -53,10 +63,15 @@ use mercurial_types::manifest::Content;
 use mononoke_types::{BlobstoreBytes, BlobstoreValue, BonsaiChangeset, FileContents};
 use slog::Logger;
 
+const BACKFILL_CHILDREN: &'static str = "backfill-children";
 const BLOBSTORE_FETCH: &'static str = "blobstore-fetch";
 const BONSAI_FETCH: &'static str = "bonsai-fetch";
 const CONTENT_FETCH: &'static str = "content-fetch";
 const CONFIG_REPO: &'static str = "config";
+const REDACTION: &'static str = "redaction";
+const REENCRYPT: &'static str = "reencrypt";
+const SCRUB: &'static str = "scrub";
+const SKIPLIST: &'static str = "skiplist";
 const MAX_CONCURRENT_REQUESTS_PER_IO_THREAD: usize = 4;
 
 fn setup_app<'a, 'b>() -> App<'a, 'b> {
@@ -104,18 +119,29 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
     let app = args::MononokeApp {
         safe_writes: false,
         hide_advanced_args: true,
-        local_instances: false,
+        local_instances: true,
         default_glog: false,
     };
     app.build("Mononoke admin command line tool")
         .version("0.0.0")
         .about("Poke at mononoke internals for debugging and investigating data structures.")
+        .subcommand(backfill_children::prepare_command(SubCommand::with_name(
+            BACKFILL_CHILDREN,
+        )))
         .subcommand(blobstore_fetch)
         .subcommand(bonsai_fetch)
         .subcommand(content_fetch)
         .subcommand(config_repo::prepare_command(SubCommand::with_name(
             CONFIG_REPO,
         )))
+        .subcommand(redaction::prepare_command(SubCommand::with_name(
+            REDACTION,
+        )))
+        .subcommand(reencrypt::prepare_command(SubCommand::with_name(
+            REENCRYPT,
+        )))
+        .subcommand(scrub::prepare_command(SubCommand::with_name(SCRUB)))
+        .subcommand(skiplist::prepare_command(SubCommand::with_name(SKIPLIST)))
 }
 
 fn fetch_content_from_manifest(
@@ -314,13 +340,11 @@ fn main() {
                         Content::Executable(_) => {
                             println!("Binary file");
                         }
-                        Content::File(contents) | Content::Symlink(contents) => match contents {
-                            FileContents::Bytes(bytes) => {
-                                let content = String::from_utf8(bytes.to_vec())
-                                    .expect("non-utf8 file content");
-                                println!("{}", content);
-                            }
-                        },
+                        Content::File(contents) | Content::Symlink(contents) => {
+                            let content = String::from_utf8(contents.into_bytes().to_vec())
+                                .expect("non-utf8 file content");
+                            println!("{}", content);
+                        }
                         Content::Tree(mf) => {
                             let entries: Vec<_> = mf.list().collect();
                             let mut longest_len = 0;
@@ -351,7 +375,27 @@ fn main() {
                 })
                 .boxify()
         }
+        (BACKFILL_CHILDREN, Some(sub_m)) => {
+            let repo = args::open_blobrepo(&logger, &matches);
+            backfill_children::handle_command(sub_m, repo, logger)
+        }
         (CONFIG_REPO, Some(sub_m)) => config_repo::handle_command(sub_m, logger),
+        (REDACTION, Some(sub_m)) => {
+            let repo = args::open_blobrepo(&logger, &matches);
+            redaction::handle_command(sub_m, repo, logger)
+        }
+        (REENCRYPT, Some(sub_m)) => reencrypt::handle_command(sub_m, &matches, logger),
+        (SCRUB, Some(sub_m)) => {
+            let repo = Arc::new(args::open_blobrepo(&logger, &matches));
+            let start = sub_m
+                .value_of("HG_CHANGESET_OR_BOOKMARK")
+                .map(|rev| resolve_hg_rev(&repo, rev).boxify());
+            scrub::handle_command(sub_m, (*repo).clone(), start, logger)
+        }
+        (SKIPLIST, Some(sub_m)) => {
+            let repo = Arc::new(args::open_blobrepo(&logger, &matches));
+            skiplist::handle_command(sub_m, repo, logger)
+        }
         _ => {
             println!("{}", matches.usage());
             ::std::process::exit(1);
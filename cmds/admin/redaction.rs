@@ -0,0 +1,115 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Manages the list of the blobs of a repo that must not be served. The list is loaded when a
+//! server opens the repo, so changes to it are seen after a restart.
+
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+use futures::prelude::*;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use blobstore::Blobstore;
+use mononoke_types::DateTime;
+use redacted_blobs::{RedactedBlob, RedactedBlobs};
+
+const ADD_CMD: &'static str = "add";
+const REMOVE_CMD: &'static str = "remove";
+const LIST_CMD: &'static str = "list";
+
+pub fn prepare_command<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    let add = SubCommand::with_name(ADD_CMD)
+        .about("prevent a blob from being served")
+        .args_from_usage(
+            "<KEY>       'key of the blob, without the repo prefix'
+             <REASON>    'why the blob is redacted, shown to the clients that try to read it'",
+        );
+
+    let remove = SubCommand::with_name(REMOVE_CMD)
+        .about("serve a redacted blob again")
+        .args_from_usage("<KEY>    'key of the blob, without the repo prefix'");
+
+    let list = SubCommand::with_name(LIST_CMD).about("list the redacted blobs of the repo");
+
+    app.about("manage the list of redacted blobs")
+        .subcommand(add)
+        .subcommand(remove)
+        .subcommand(list)
+}
+
+pub fn handle_command<'a>(
+    matches: &ArgMatches<'a>,
+    repo: BlobRepo,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    match matches.subcommand() {
+        (ADD_CMD, Some(sub_m)) => handle_add(sub_m, repo, logger),
+        (REMOVE_CMD, Some(sub_m)) => handle_remove(sub_m, repo, logger),
+        (LIST_CMD, Some(_)) => handle_list(repo),
+        _ => {
+            println!("{}", matches.usage());
+            ::std::process::exit(1);
+        }
+    }
+}
+
+fn handle_add<'a>(args: &ArgMatches<'a>, repo: BlobRepo, logger: Logger) -> BoxFuture<(), Error> {
+    let key = args.value_of("KEY").unwrap().to_string();
+    let reason = args.value_of("REASON").unwrap().to_string();
+    let entry = RedactedBlob::new(repo.get_repoid(), key.clone(), reason, DateTime::now());
+
+    // A typo in the key would leave the blob readable, so warn about keys that don't exist.
+    repo.get_blobstore()
+        .is_present(key.clone())
+        .and_then(move |present| {
+            if !present {
+                warn!(logger, "blob {} is not in the blobstore", key);
+            }
+            repo.get_redacted_blobs()
+                .add(entry)
+                .map(move |()| {
+                    info!(logger, "redacted {}, servers see it after a restart", key)
+                })
+        })
+        .boxify()
+}
+
+fn handle_remove<'a>(
+    args: &ArgMatches<'a>,
+    repo: BlobRepo,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    let key = args.value_of("KEY").unwrap().to_string();
+
+    repo.get_redacted_blobs()
+        .remove(repo.get_repoid(), key.clone())
+        .map(move |removed| {
+            if removed {
+                info!(logger, "{} is not redacted anymore, servers see it after a restart", key);
+            } else {
+                warn!(logger, "{} was not redacted", key);
+            }
+        })
+        .boxify()
+}
+
+fn handle_list(repo: BlobRepo) -> BoxFuture<(), Error> {
+    repo.get_redacted_blobs()
+        .get_all(repo.get_repoid())
+        .map(|entries| {
+            for entry in entries {
+                println!(
+                    "{}\t{}\t{}",
+                    entry.blobstore_key,
+                    entry.timestamp.as_chrono().to_rfc3339(),
+                    entry.reason
+                );
+            }
+        })
+        .boxify()
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Rewrites the blobs of a local repo encrypted with the newest key of its keyfile, so that the
+//! older keys can be retired. Blobs written before encryption was enabled get encrypted too.
+
+use std::path::Path;
+
+use clap::{App, ArgMatches};
+use failure::{err_msg, Error, Result};
+use futures::future;
+use futures::prelude::*;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobstore::{BlobstoreEnumerable, EncryptedBlobstore, Keyring};
+use cmdlib::args;
+use fileblob::Fileblob;
+use rocksblob::Rocksblob;
+
+pub fn prepare_command<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("re-encrypt the blobs of a local repo with the newest key of the keyfile")
+        .args_from_usage(
+            "--after [KEY]                'only re-encrypt the blobs after this key'
+             --concurrency [N]            'number of blobs re-encrypted at once [default: 100]'
+             --progress-interval [N]      'report progress every N blobs [default: 10000]'",
+        )
+}
+
+pub fn handle_command<'a>(
+    sub_m: &ArgMatches<'a>,
+    matches: &ArgMatches<'a>,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    let keyring = match args::get_keyring(matches) {
+        Ok(Some(keyring)) => keyring,
+        Ok(None) => return future::err(err_msg("an encryption keyfile must be given")).boxify(),
+        Err(err) => return future::err(err).boxify(),
+    };
+    let params = Params {
+        keyring,
+        prefix: args::get_repo_id(matches).prefix(),
+        after: sub_m.value_of("after").map(|key| key.to_string()),
+        concurrency: args::get_usize(sub_m, "concurrency", 100),
+        progress_interval: args::get_usize(sub_m, "progress-interval", 10000),
+        logger,
+    };
+
+    let blobs = match matches.value_of("data-dir") {
+        Some(data_dir) => Path::new(data_dir).join("blobs"),
+        None => {
+            return future::err(err_msg("local data directory must be specified")).boxify();
+        }
+    };
+    let opened: Result<_> = match matches.value_of("blobstore") {
+        Some("files") => Fileblob::open(blobs).map(|blobstore| params.reencrypt(blobstore)),
+        Some("rocksdb") => Rocksblob::open(blobs).map(|blobstore| params.reencrypt(blobstore)),
+        _ => Err(err_msg("only the blobs of local repos can be re-encrypted")),
+    };
+    opened.into_future().flatten().boxify()
+}
+
+struct Params {
+    keyring: Keyring,
+    prefix: String,
+    after: Option<String>,
+    concurrency: usize,
+    progress_interval: usize,
+    logger: Logger,
+}
+
+#[derive(Debug, Default)]
+struct ReencryptStats {
+    checked: usize,
+    reencrypted: usize,
+}
+
+impl Params {
+    fn reencrypt<B: BlobstoreEnumerable + Clone>(self, blobstore: B) -> BoxFuture<(), Error> {
+        let Params {
+            keyring,
+            prefix,
+            after,
+            concurrency,
+            progress_interval,
+            logger,
+        } = self;
+        let blobstore = try_boxfuture!(EncryptedBlobstore::new(blobstore, keyring));
+
+        // The results stay in key order, so that the last key logged is a safe point to resume
+        // from with --after.
+        blobstore
+            .enumerate(prefix, after)
+            .map({
+                let blobstore = blobstore.clone();
+                move |key| {
+                    blobstore
+                        .reencrypt(key.clone())
+                        .map(move |reencrypted| (key, reencrypted))
+                }
+            })
+            .buffered(concurrency)
+            .fold(ReencryptStats::default(), {
+                let logger = logger.clone();
+                move |mut stats, (key, reencrypted)| {
+                    stats.checked += 1;
+                    if reencrypted {
+                        stats.reencrypted += 1;
+                    }
+                    if stats.checked % progress_interval == 0 {
+                        info!(
+                            logger,
+                            "checked {} blobs, re-encrypted {}, last key {}",
+                            stats.checked,
+                            stats.reencrypted,
+                            key
+                        );
+                    }
+                    Ok::<_, Error>(stats)
+                }
+            })
+            .map(move |stats| {
+                info!(
+                    logger,
+                    "checked {} blobs, re-encrypted {}", stats.checked, stats.reencrypted
+                );
+            })
+            .boxify()
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Walks a repo and checks that every blob it refers to is present and matches its hash.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+use clap::{App, ArgMatches};
+use failure::Error;
+use futures::future::{self, Loop};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::{BlobRepo, RepoBlobstore};
+use blobstore::Blobstore;
+use cmdlib::args;
+use mercurial_types::{Changeset, HgBlob, HgBlobNode, HgChangesetEnvelope, HgChangesetId,
+                      HgFileEnvelope, HgFileNodeId, HgManifestEnvelope, HgManifestId, HgNodeHash,
+                      MPath, Type, NULL_HASH};
+use mononoke_types::{Blob, BlobstoreBytes, BlobstoreValue, BonsaiChangeset, ChangesetId,
+                     ContentChunk, ContentChunkId, ContentId, FileContents, MononokeId};
+
+pub fn prepare_command<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.about("check that the blobs of the repo are present and not corrupt")
+        .args_from_usage(
+            "[HG_CHANGESET_OR_BOOKMARK]   'only check the ancestors of this revision'
+             --concurrency [N]            'number of blobs checked at once [default: 100]'
+             --progress-interval [N]      'report progress every N blobs [default: 10000]'",
+        )
+}
+
+pub fn handle_command<'a>(
+    sub_m: &ArgMatches<'a>,
+    repo: BlobRepo,
+    start: Option<BoxFuture<HgChangesetId, Error>>,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    // Redacted blobs are checked like the others.
+    let repo = repo.unredacted();
+    let scrubber = Scrubber {
+        blobstore: repo.get_blobstore(),
+        repo,
+        logger: logger.clone(),
+        concurrency: args::get_usize(sub_m, "concurrency", 100),
+        progress_interval: args::get_usize(sub_m, "progress-interval", 10000),
+    };
+
+    let roots = match start {
+        Some(start) => {
+            let scrubber = scrubber.clone();
+            start
+                .and_then(move |hg_cs_id| scrubber.ancestors_roots(hg_cs_id))
+                .boxify()
+        }
+        None => scrubber.all_roots(),
+    };
+
+    roots
+        .and_then(move |roots| {
+            info!(logger, "checking from {} changeset blobs", roots.len());
+            scrubber.scrub(roots).and_then(move |stats| {
+                info!(
+                    logger,
+                    "checked {} blobs: {} missing, {} corrupt, {} failed to fetch",
+                    stats.checked,
+                    stats.missing,
+                    stats.corrupt,
+                    stats.failed
+                );
+                // Fail, so that scripts running the scrub notice the problems.
+                let problems = stats.missing + stats.corrupt + stats.failed;
+                if problems > 0 {
+                    Err(format_err!("{} of {} blobs have problems", problems, stats.checked))
+                } else {
+                    Ok(())
+                }
+            })
+        })
+        .boxify()
+}
+
+/// A blob referred to by the repo.
+#[derive(Clone, Debug)]
+enum Node {
+    Bonsai(ChangesetId),
+    HgChangeset(HgChangesetId),
+    HgManifest(HgManifestId),
+    HgFilenode(HgNodeHash),
+    Content(ContentId),
+    ContentChunk(ContentChunkId),
+}
+
+impl Node {
+    fn key(&self) -> String {
+        match *self {
+            Node::Bonsai(ref cs_id) => cs_id.blobstore_key(),
+            Node::HgChangeset(ref hg_cs_id) => hg_cs_id.blobstore_key(),
+            Node::HgManifest(ref manifest_id) => manifest_id.blobstore_key(),
+            Node::HgFilenode(ref nodehash) => HgFileNodeId::new(*nodehash).blobstore_key(),
+            Node::Content(ref content_id) => content_id.blobstore_key(),
+            Node::ContentChunk(ref chunk_id) => chunk_id.blobstore_key(),
+        }
+    }
+}
+
+/// A blob to check, along with where it was first found.
+#[derive(Clone, Debug)]
+struct Check {
+    node: Node,
+    changeset: Arc<String>,
+    path: Option<MPath>,
+}
+
+impl Check {
+    fn root(node: Node) -> Self {
+        let changeset = match node {
+            Node::Bonsai(cs_id) => format!("bonsai changeset {}", cs_id),
+            Node::HgChangeset(hg_cs_id) => format!("hg changeset {}", hg_cs_id),
+            _ => format!("{:?}", node),
+        };
+        Check {
+            node,
+            changeset: Arc::new(changeset),
+            path: None,
+        }
+    }
+
+    fn child(&self, node: Node, path: Option<MPath>) -> Self {
+        Check {
+            node,
+            changeset: self.changeset.clone(),
+            path,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (from {}", self.node.key(), self.changeset)?;
+        if let Some(ref path) = self.path {
+            write!(f, ", path {}", path)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug)]
+enum Problem {
+    Missing,
+    Corrupt(String),
+    FetchFailed(String),
+}
+
+#[derive(Debug, Default)]
+struct ScrubStats {
+    checked: usize,
+    missing: usize,
+    corrupt: usize,
+    failed: usize,
+}
+
+struct ScrubState {
+    queue: VecDeque<Check>,
+    visited: HashSet<String>,
+    stats: ScrubStats,
+}
+
+#[derive(Clone)]
+struct Scrubber {
+    repo: BlobRepo,
+    blobstore: RepoBlobstore,
+    logger: Logger,
+    concurrency: usize,
+    progress_interval: usize,
+}
+
+fn corrupt<E: fmt::Display>(err: E) -> Problem {
+    Problem::Corrupt(format!("{}", err))
+}
+
+/// Checks that `contents` hashes to `expected` with the given parents, the way Mercurial
+/// computes node hashes.
+fn check_hg_hash(
+    contents: HgBlob,
+    parents: (Option<&HgNodeHash>, Option<&HgNodeHash>),
+    expected: &HgNodeHash,
+) -> Option<Problem> {
+    let computed = HgBlobNode::new(contents, parents.0, parents.1).nodeid();
+    if computed.as_ref() == Some(expected) {
+        None
+    } else {
+        Some(Problem::Corrupt(format!(
+            "hash mismatch: expected {}, computed {:?}",
+            expected, computed
+        )))
+    }
+}
+
+impl Scrubber {
+    /// Every changeset of the repo, both as bonsai and as hg changeset.
+    fn all_roots(&self) -> BoxFuture<Vec<Check>, Error> {
+        let repo = self.repo.clone();
+        let concurrency = self.concurrency;
+        self.repo
+            .get_all_changesets()
+            .map(iter_ok::<_, Error>)
+            .flatten_stream()
+            .map(move |cs_id| {
+                repo.get_hg_from_bonsai(cs_id)
+                    .map(move |hg_cs_id| (cs_id, hg_cs_id))
+            })
+            .buffer_unordered(concurrency)
+            .fold(Vec::new(), |mut roots, (cs_id, hg_cs_id)| {
+                roots.push(Check::root(Node::Bonsai(cs_id)));
+                if let Some(hg_cs_id) = hg_cs_id {
+                    roots.push(Check::root(Node::HgChangeset(hg_cs_id)));
+                }
+                Ok::<_, Error>(roots)
+            })
+            .boxify()
+    }
+
+    /// `start` and all its ancestors, found through the changesets table rather than the blobs
+    /// so that corrupt blobs don't hide parts of the history.
+    fn ancestors_roots(&self, start: HgChangesetId) -> BoxFuture<Vec<Check>, Error> {
+        let repo = self.repo.clone();
+        let mut seen = HashSet::new();
+        seen.insert(start);
+
+        future::loop_fn(
+            (vec![start], seen, Vec::new()),
+            move |(frontier, mut seen, mut found)| {
+                if frontier.is_empty() {
+                    return future::ok(Loop::Break(found)).left_future();
+                }
+                found.extend(frontier.iter().cloned());
+                let parents = frontier
+                    .into_iter()
+                    .map(|hg_cs_id| repo.get_changeset_parents(&hg_cs_id));
+                future::join_all(parents)
+                    .map(move |parents| {
+                        let frontier: Vec<_> = parents
+                            .into_iter()
+                            .flat_map(|parents| parents)
+                            .filter(|parent| seen.insert(*parent))
+                            .collect();
+                        Loop::Continue((frontier, seen, found))
+                    })
+                    .right_future()
+            },
+        ).and_then({
+            let repo = self.repo.clone();
+            move |hg_cs_ids| {
+                let lookups = hg_cs_ids.into_iter().map(move |hg_cs_id| {
+                    repo.get_bonsai_from_hg(&hg_cs_id)
+                        .map(move |cs_id| (cs_id, hg_cs_id))
+                });
+                future::join_all(lookups)
+            }
+        })
+            .map(|changesets| {
+                let mut roots = Vec::new();
+                for (cs_id, hg_cs_id) in changesets {
+                    if let Some(cs_id) = cs_id {
+                        roots.push(Check::root(Node::Bonsai(cs_id)));
+                    }
+                    roots.push(Check::root(Node::HgChangeset(hg_cs_id)));
+                }
+                roots
+            })
+            .boxify()
+    }
+
+    /// Checks the blobs reachable from `roots`, `concurrency` at a time. Each blob is only
+    /// checked once, and reported with the first changeset and path it was found from.
+    fn scrub(&self, roots: Vec<Check>) -> BoxFuture<ScrubStats, Error> {
+        let visited = roots.iter().map(|check| check.node.key()).collect();
+        let state = ScrubState {
+            queue: roots.into_iter().collect(),
+            visited,
+            stats: ScrubStats::default(),
+        };
+
+        let this = self.clone();
+        future::loop_fn(state, move |mut state| {
+            if state.queue.is_empty() {
+                return future::ok(Loop::Break(state.stats)).left_future();
+            }
+
+            let batch_size = this.concurrency.min(state.queue.len());
+            let checks = state
+                .queue
+                .drain(..batch_size)
+                .map(|check| this.check(check))
+                .collect::<Vec<_>>();
+
+            let logger = this.logger.clone();
+            let progress_interval = this.progress_interval.max(1);
+            future::join_all(checks)
+                .map(move |results| {
+                    for (check, problem, children) in results {
+                        state.stats.checked += 1;
+                        if state.stats.checked % progress_interval == 0 {
+                            info!(
+                                logger,
+                                "checked {} blobs, {} queued, {} problems",
+                                state.stats.checked,
+                                state.queue.len(),
+                                state.stats.missing + state.stats.corrupt + state.stats.failed
+                            );
+                        }
+
+                        match problem {
+                            Some(Problem::Missing) => {
+                                state.stats.missing += 1;
+                                println!("missing: {}", check);
+                            }
+                            Some(Problem::Corrupt(reason)) => {
+                                state.stats.corrupt += 1;
+                                println!("corrupt: {}: {}", check, reason);
+                            }
+                            Some(Problem::FetchFailed(reason)) => {
+                                state.stats.failed += 1;
+                                println!("failed to fetch: {}: {}", check, reason);
+                            }
+                            None => {}
+                        }
+
+                        for child in children {
+                            if state.visited.insert(child.node.key()) {
+                                state.queue.push_back(child);
+                            }
+                        }
+                    }
+                    Loop::Continue(state)
+                })
+                .right_future()
+        }).boxify()
+    }
+
+    /// Checks a blob. Never fails: problems with the blob are part of the result, along with the
+    /// blobs it refers to.
+    fn check(&self, check: Check) -> BoxFuture<(Check, Option<Problem>, Vec<Check>), Error> {
+        let this = self.clone();
+        self.blobstore
+            .get(check.node.key())
+            .then(move |fetched| {
+                let checked = match fetched {
+                    Err(err) => future::err(Problem::FetchFailed(format!("{}", err))).boxify(),
+                    Ok(None) => future::err(Problem::Missing).boxify(),
+                    Ok(Some(bytes)) => this.check_blob(&check, bytes),
+                };
+                checked.then(move |result| match result {
+                    Ok(children) => Ok((check, None, children)),
+                    Err(problem) => Ok((check, Some(problem), vec![])),
+                })
+            })
+            .boxify()
+    }
+
+    /// Verifies the hash of a fetched blob and returns the blobs it refers to.
+    fn check_blob(&self, check: &Check, bytes: BlobstoreBytes) -> BoxFuture<Vec<Check>, Problem> {
+        match check.node {
+            Node::Bonsai(cs_id) => {
+                let blob: Blob<ChangesetId> = bytes.into();
+                if *blob.id() != cs_id {
+                    let problem = Problem::Corrupt(format!("hash is {}", blob.id()));
+                    return future::err(problem).boxify();
+                }
+                let bcs = try_boxfuture!(BonsaiChangeset::from_blob(blob).map_err(corrupt));
+                let children = bcs.file_changes()
+                    .filter_map(|(path, change)| {
+                        change.map(|change| {
+                            check.child(Node::Content(*change.content_id()), Some(path.clone()))
+                        })
+                    })
+                    .collect();
+                future::ok(children).boxify()
+            }
+            Node::Content(content_id) => {
+                let blob: Blob<ContentId> = bytes.into();
+                let blob_id = *blob.id();
+                match try_boxfuture!(FileContents::from_blob(blob).map_err(corrupt)) {
+                    FileContents::Bytes(_) => {
+                        if blob_id != content_id {
+                            let problem = Problem::Corrupt(format!("hash is {}", blob_id));
+                            return future::err(problem).boxify();
+                        }
+                        future::ok(vec![]).boxify()
+                    }
+                    // Chunked contents are stored under the id of the whole contents, which
+                    // isn't verified here since that needs all the chunks. Each chunk is checked
+                    // on its own.
+                    FileContents::Chunked(chunked) => {
+                        if chunked.content_id() != content_id {
+                            let problem = Problem::Corrupt(format!(
+                                "chunked contents are for {}",
+                                chunked.content_id()
+                            ));
+                            return future::err(problem).boxify();
+                        }
+                        let children = chunked
+                            .chunks()
+                            .iter()
+                            .map(|chunk| {
+                                let node = Node::ContentChunk(chunk.chunk_id());
+                                check.child(node, check.path.clone())
+                            })
+                            .collect();
+                        future::ok(children).boxify()
+                    }
+                }
+            }
+            Node::ContentChunk(chunk_id) => {
+                let blob: Blob<ContentChunkId> = bytes.into();
+                if *blob.id() != chunk_id {
+                    let problem = Problem::Corrupt(format!("hash is {}", blob.id()));
+                    return future::err(problem).boxify();
+                }
+                try_boxfuture!(ContentChunk::from_blob(blob).map_err(corrupt));
+                future::ok(vec![]).boxify()
+            }
+            Node::HgChangeset(hg_cs_id) => {
+                let envelope =
+                    try_boxfuture!(HgChangesetEnvelope::from_blob(bytes.into()).map_err(corrupt));
+                if let Some(problem) = check_hg_hash(
+                    HgBlob::from(envelope.contents().clone()),
+                    envelope.parents(),
+                    hg_cs_id.as_nodehash(),
+                ) {
+                    return future::err(problem).boxify();
+                }
+
+                let check = check.clone();
+                self.repo
+                    .get_changeset_by_changesetid(&hg_cs_id)
+                    .map(move |cs| {
+                        let manifest_id = *cs.manifestid();
+                        if manifest_id.into_nodehash() == NULL_HASH {
+                            // Empty changesets have no root manifest.
+                            vec![]
+                        } else {
+                            vec![check.child(Node::HgManifest(manifest_id), None)]
+                        }
+                    })
+                    .map_err(corrupt)
+                    .boxify()
+            }
+            Node::HgManifest(manifest_id) => {
+                let envelope =
+                    try_boxfuture!(HgManifestEnvelope::from_blob(bytes.into()).map_err(corrupt));
+                if envelope.node_id() != &manifest_id.into_nodehash() {
+                    return future::err(Problem::Corrupt(format!(
+                        "envelope is for {}",
+                        envelope.node_id()
+                    ))).boxify();
+                }
+                if let Some(problem) = check_hg_hash(
+                    HgBlob::from(envelope.contents().clone()),
+                    envelope.parents(),
+                    envelope.computed_node_id(),
+                ) {
+                    return future::err(problem).boxify();
+                }
+
+                let check = check.clone();
+                self.repo
+                    .get_manifest_by_nodeid(&manifest_id.into_nodehash())
+                    .map(move |manifest| {
+                        manifest
+                            .list()
+                            .map(|entry| {
+                                let path =
+                                    MPath::join_element_opt(check.path.as_ref(), entry.get_name());
+                                let hash = entry.get_hash().into_nodehash();
+                                let node = match entry.get_type() {
+                                    Type::Tree => Node::HgManifest(HgManifestId::new(hash)),
+                                    Type::File(_) => Node::HgFilenode(hash),
+                                };
+                                check.child(node, path)
+                            })
+                            .collect()
+                    })
+                    .map_err(corrupt)
+                    .boxify()
+            }
+            Node::HgFilenode(nodehash) => {
+                let envelope =
+                    try_boxfuture!(HgFileEnvelope::from_blob(bytes.into()).map_err(corrupt));
+                if envelope.node_id() != &nodehash {
+                    return future::err(Problem::Corrupt(format!(
+                        "envelope is for {}",
+                        envelope.node_id()
+                    ))).boxify();
+                }
+
+                // The file node hash covers the file contents, so it can only be verified if
+                // the contents are fine. Problems with the contents are reported for the
+                // contents blob.
+                let content_check =
+                    check.child(Node::Content(*envelope.content_id()), check.path.clone());
+                let repo = self.repo.clone();
+                self.blobstore
+                    .get(content_check.node.key())
+                    .then(move |fetched| {
+                        let contents = match fetched {
+                            Ok(Some(bytes)) => FileContents::from_blob(bytes.into()).ok(),
+                            _ => None,
+                        };
+                        match contents {
+                            // Only the list of the chunks was fetched.
+                            Some(FileContents::Chunked(_)) => repo
+                                .get_file_content(&nodehash)
+                                .then(|fetched| Ok(fetched.ok()))
+                                .left_future(),
+                            contents => future::ok(contents).right_future(),
+                        }
+                    })
+                    .and_then(move |contents| {
+                        let problem = contents.and_then(|contents| {
+                            let mut data = envelope.metadata().to_vec();
+                            data.extend_from_slice(contents.into_bytes().as_ref());
+                            check_hg_hash(HgBlob::from(data), envelope.parents(), &nodehash)
+                        });
+                        match problem {
+                            Some(problem) => Err(problem),
+                            None => Ok(vec![content_check]),
+                        }
+                    })
+                    .boxify()
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Builds the skiplist index of a repo and stores it in the blobstore, from where the servers
+//! configured with its key load it on startup.
+
+use std::sync::Arc;
+
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use cmdlib::args;
+use reachabilityindex::SkiplistIndex;
+use revset::AncestorsNodeStream;
+
+const BUILD_CMD: &'static str = "build";
+
+pub fn prepare_command<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    let build = SubCommand::with_name(BUILD_CMD)
+        .about("index the history of a bookmark, adding to the stored index if there is one")
+        .args_from_usage(
+            "<BOOKMARK>                   'bookmark whose history is indexed'
+             <KEY>                        'key of the blob of the index, without the repo prefix'
+             --progress-interval [N]      'report progress every N commits [default: 100000]'",
+        );
+
+    app.about("manage the skiplist index of the repo")
+        .subcommand(build)
+}
+
+pub fn handle_command<'a>(
+    matches: &ArgMatches<'a>,
+    repo: Arc<BlobRepo>,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    match matches.subcommand() {
+        (BUILD_CMD, Some(sub_m)) => handle_build(sub_m, repo, logger),
+        _ => {
+            println!("{}", matches.usage());
+            ::std::process::exit(1);
+        }
+    }
+}
+
+fn handle_build<'a>(
+    args: &ArgMatches<'a>,
+    repo: Arc<BlobRepo>,
+    logger: Logger,
+) -> BoxFuture<(), Error> {
+    let bookmark = try_boxfuture!(Bookmark::new(args.value_of("BOOKMARK").unwrap()));
+    let key = args.value_of("KEY").unwrap().to_string();
+    let progress_interval = args::get_usize(args, "progress-interval", 100000);
+    let skiplist_index = Arc::new(SkiplistIndex::new());
+
+    skiplist_index
+        .load_from_blobstore(&repo, key.clone())
+        .and_then({
+            cloned!(repo, logger);
+            move |loaded| {
+                match loaded {
+                    Some(count) => info!(logger, "loaded the stored index with {} nodes", count),
+                    None => info!(logger, "no index is stored yet"),
+                }
+                repo.get_bookmark(&bookmark).and_then(move |cs_id| {
+                    cs_id.ok_or_else(|| format_err!("bookmark {} not found", bookmark))
+                })
+            }
+        })
+        .and_then({
+            cloned!(repo, logger);
+            move |head| {
+                info!(logger, "walking the history of {}", head);
+                AncestorsNodeStream::new(&repo, *head.as_nodehash()).collect()
+            }
+        })
+        .and_then({
+            cloned!(repo, logger, skiplist_index);
+            move |mut nodes| {
+                // The ancestors are walked newest first. Indexing the parents of each commit
+                // before it means that its skip edges are computed from indexed parents, without
+                // recursing into the history.
+                nodes.reverse();
+                info!(logger, "indexing {} commits", nodes.len());
+                iter_ok(nodes.into_iter().enumerate()).for_each(move |(i, node)| {
+                    if (i + 1) % progress_interval == 0 {
+                        info!(logger, "indexed {} commits", i + 1);
+                    }
+                    skiplist_index.add_node(repo.clone(), node, 1)
+                })
+            }
+        })
+        .and_then(move |()| {
+            info!(
+                logger,
+                "storing the index with {} nodes",
+                skiplist_index.indexed_node_count()
+            );
+            skiplist_index.save_to_blobstore(&repo, key)
+        })
+        .boxify()
+}
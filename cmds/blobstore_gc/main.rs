@@ -0,0 +1,167 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Mark-and-sweep garbage collection for the blobstore of a local repo. Every blob reachable
+//! from the changesets of the repo is marked, then the unmarked blobs older than a grace period
+//! are deleted. Only the kinds of blobs that can be reached from changesets are swept, so other
+//! blobs, like the skiplist index, are kept.
+//!
+//! The grace period keeps the blobs of pushes that are still in progress, so it needs to know
+//! when blobs were written. RocksDB blobstores don't record it, so they are only swept with
+//! `--sweep-without-mtimes`, which deletes all their unreachable blobs regardless of the grace
+//! period and must only be used while nothing is writing to the repo.
+
+#![deny(warnings)]
+
+extern crate clap;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate slog;
+extern crate tokio;
+
+extern crate futures_ext;
+
+extern crate blobrepo;
+extern crate blobstore;
+extern crate cmdlib;
+extern crate fileblob;
+extern crate mercurial_types;
+extern crate mononoke_types;
+extern crate rocksblob;
+
+#[cfg(test)]
+extern crate async_unit;
+#[cfg(test)]
+extern crate fixtures;
+#[cfg(test)]
+extern crate tempdir;
+
+mod mark;
+mod sweep;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{App, ArgMatches};
+use failure::Result;
+use futures::Future;
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use blobstore::{BlobstoreDeletable, PrefixBlobstore};
+use cmdlib::args;
+use fileblob::Fileblob;
+use rocksblob::Rocksblob;
+
+use mark::{Marker, MARKED_KEY_PREFIXES};
+use sweep::{sweep, SweepOptions, SweepStats};
+
+fn setup_app<'a, 'b>() -> App<'a, 'b> {
+    let app = args::MononokeApp {
+        safe_writes: false,
+        hide_advanced_args: true,
+        local_instances: true,
+        default_glog: false,
+    };
+    app.build("blobstore gc")
+        .version("0.0.0")
+        .about("Delete the blobs that no changeset of the repo refers to.")
+        .args_from_usage(
+            r#"
+            --report-only           'only report the blobs that would be deleted'
+            --grace-period [SECS]   'keep unreachable blobs younger than this [default: 86400]'
+            --concurrency [N]       'number of blobs processed at once [default: 100]'
+            --sweep-without-mtimes  'sweep stores lacking write times, ignoring the grace period'
+        "#,
+        )
+}
+
+fn get_u64<'a>(matches: &ArgMatches<'a>, key: &str, default: u64) -> u64 {
+    matches
+        .value_of(key)
+        .map(|val| {
+            val.parse::<u64>()
+                .expect(&format!("{} must be integer", key))
+        })
+        .unwrap_or(default)
+}
+
+fn log_stats(logger: &Logger, stats: &SweepStats) {
+    info!(
+        logger,
+        "{} blobs: {} reachable, {} unreachable but too recent, {} would be deleted, {} deleted",
+        stats.keys,
+        stats.reachable,
+        stats.too_recent,
+        stats.would_delete,
+        stats.deleted
+    );
+}
+
+fn run<B>(logger: Logger, matches: &ArgMatches, data_dir: &Path, blobstore: B) -> Result<()>
+where
+    B: BlobstoreDeletable + Clone,
+{
+    let repo_id = args::get_repo_id(matches);
+    let repo = BlobRepo::new_local(
+        logger.clone(),
+        data_dir,
+        Arc::new(blobstore.clone()),
+        repo_id,
+        args::get_keyring(matches)?,
+        None,
+        args::get_compression(matches).as_ref(),
+    )?;
+    // The marked keys are relative to the repo, so only look at the keys of this repo.
+    let blobstore = PrefixBlobstore::new(blobstore, repo_id.prefix());
+
+    let options = SweepOptions {
+        grace_period: Duration::from_secs(get_u64(matches, "grace-period", 86400)),
+        report_only: matches.is_present("report-only"),
+        without_mtimes: matches.is_present("sweep-without-mtimes"),
+        concurrency: args::get_usize(matches, "concurrency", 100),
+    };
+
+    // Redacted blobs are still live, so read them to mark what they refer to.
+    let gc = Marker::new(logger.clone(), repo.unredacted(), options.concurrency)
+        .mark()
+        .and_then({
+            let logger = logger.clone();
+            move |marked| {
+                info!(logger, "{} reachable blobs, sweeping", marked.len());
+                sweep(logger, blobstore, MARKED_KEY_PREFIXES, marked, options)
+            }
+        });
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    let stats = runtime.block_on(gc)?;
+    runtime.shutdown_on_idle();
+
+    log_stats(&logger, &stats);
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let matches = setup_app().get_matches();
+    let logger = args::get_logger(&matches);
+
+    let data_dir = Path::new(
+        matches
+            .value_of("data-dir")
+            .expect("local data directory must be specified"),
+    ).canonicalize()?;
+    let blobs = data_dir.join("blobs");
+
+    match matches.value_of("blobstore") {
+        Some("files") => run(logger, &matches, &data_dir, Fileblob::open(blobs)?),
+        Some("rocksdb") => run(logger, &matches, &data_dir, Rocksblob::open(blobs)?),
+        Some(other) => bail_msg!("blobstore {} can't be garbage collected", other),
+        None => bail_msg!("blobstore type must be specified"),
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashSet;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use failure::Error;
+use futures::{future, stream, Future, Stream};
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::{lfs_alias_key, BlobRepo};
+use mercurial_types::{Changeset, HgFileNodeId, HgManifestId, HgNodeHash, LfsPointer, Type,
+                      NULL_HASH};
+use mononoke_types::{ChangesetId, ContentId, FileContents, MononokeId};
+
+/// The prefixes of the keys of the kinds of blobs that are marked. Only these are swept, so that
+/// the blobs that no changeset refers to but that are still used, like the skiplist index, are
+/// kept.
+pub const MARKED_KEY_PREFIXES: &[&str] = &[
+    "changeset.blake2.",
+    "content.blake2.",
+    "content_chunk.blake2.",
+    "hgchangeset.sha1.",
+    "hgmanifest.sha1.",
+    "hgfilenode.sha1.",
+    "alias.sha256.",
+];
+
+/// Collects the keys of all the blobs reachable from the changesets of a repo: bonsai
+/// changesets, hg changesets, manifests, filenodes, file contents and the chunks of chunked file
+/// contents. The LFS objects that pointer files refer to are marked along with their aliases.
+/// The keys are relative to the repo, without the repo prefix.
+///
+/// The repo must be able to read redacted blobs (see `BlobRepo::unredacted`), or marking fails
+/// as soon as it reaches one of them.
+#[derive(Clone)]
+pub struct Marker {
+    logger: Logger,
+    repo: BlobRepo,
+    marked: Arc<Mutex<HashSet<String>>>,
+    concurrency: usize,
+}
+
+impl Marker {
+    pub fn new(logger: Logger, repo: BlobRepo, concurrency: usize) -> Self {
+        Self {
+            logger,
+            repo,
+            marked: Arc::new(Mutex::new(HashSet::new())),
+            concurrency,
+        }
+    }
+
+    /// Marks everything reachable from the changesets of the repo and returns the marked keys.
+    /// Fails if any of the reachable blobs can't be read, as sweeping after an incomplete mark
+    /// would delete live blobs.
+    pub fn mark(self) -> BoxFuture<HashSet<String>, Error> {
+        let this = self.clone();
+        let concurrency = self.concurrency;
+        self.repo
+            .get_all_changesets()
+            .map({
+                let logger = self.logger.clone();
+                move |cs_ids| {
+                    info!(logger, "marking the blobs of {} changesets", cs_ids.len());
+                    stream::iter_ok(cs_ids)
+                }
+            })
+            .flatten_stream()
+            .map(move |cs_id| this.mark_changeset(cs_id))
+            .buffer_unordered(concurrency)
+            .for_each(|()| Ok(()))
+            .map(move |()| {
+                let mut marked = self.marked.lock().expect("lock poisoned");
+                mem::replace(&mut *marked, HashSet::new())
+            })
+            .boxify()
+    }
+
+    /// Returns true if `key` wasn't marked yet.
+    fn mark_key(&self, key: String) -> bool {
+        self.marked.lock().expect("lock poisoned").insert(key)
+    }
+
+    fn mark_changeset(&self, cs_id: ChangesetId) -> BoxFuture<(), Error> {
+        self.mark_key(cs_id.blobstore_key());
+
+        let bonsai = self.repo.get_bonsai_changeset(cs_id).and_then({
+            let this = self.clone();
+            move |bcs| {
+                let contents: Vec<_> = bcs.file_changes()
+                    .filter_map(|(_, change)| {
+                        change.map(|change| this.mark_content(*change.content_id()))
+                    })
+                    .collect();
+                future::join_all(contents)
+            }
+        });
+
+        let this = self.clone();
+        let hg = self.repo
+            .get_hg_from_bonsai(cs_id)
+            .and_then(move |hg_cs_id| match hg_cs_id {
+                Some(hg_cs_id) => {
+                    this.mark_key(hg_cs_id.blobstore_key());
+                    this.repo
+                        .get_changeset_by_changesetid(&hg_cs_id)
+                        .and_then(move |cs| this.mark_manifest(*cs.manifestid()))
+                        .left_future()
+                }
+                // Hg changesets that were never generated don't have any blobs.
+                None => future::ok(()).right_future(),
+            });
+
+        bonsai.join(hg).map(|_| ()).boxify()
+    }
+
+    /// Marks a manifest and everything below it. Manifests that are already marked are not
+    /// visited again, so that the trees shared between changesets are only walked once.
+    fn mark_manifest(&self, manifest_id: HgManifestId) -> BoxFuture<(), Error> {
+        let nodehash = manifest_id.into_nodehash();
+        if nodehash == NULL_HASH || !self.mark_key(manifest_id.blobstore_key()) {
+            return future::ok(()).boxify();
+        }
+
+        let this = self.clone();
+        self.repo
+            .get_manifest_by_nodeid(&nodehash)
+            .and_then(move |manifest| {
+                let children: Vec<_> = manifest
+                    .list()
+                    .map(|entry| {
+                        let hash = entry.get_hash().into_nodehash();
+                        match entry.get_type() {
+                            Type::Tree => this.mark_manifest(HgManifestId::new(hash)),
+                            Type::File(_) => this.mark_filenode(hash),
+                        }
+                    })
+                    .collect();
+                future::join_all(children).map(|_| ())
+            })
+            .boxify()
+    }
+
+    fn mark_filenode(&self, nodehash: HgNodeHash) -> BoxFuture<(), Error> {
+        if !self.mark_key(HgFileNodeId::new(nodehash).blobstore_key()) {
+            return future::ok(()).boxify();
+        }
+
+        let this = self.clone();
+        self.repo
+            .get_file_content_id(&nodehash)
+            .and_then(move |content_id| this.mark_content(content_id))
+            .boxify()
+    }
+
+    /// Marks file contents, and their chunks if they are chunked. If the contents are an LFS
+    /// pointer, the object it refers to is marked as well.
+    fn mark_content(&self, content_id: ContentId) -> BoxFuture<(), Error> {
+        if !self.mark_key(content_id.blobstore_key()) {
+            return future::ok(()).boxify();
+        }
+
+        let this = self.clone();
+        self.repo
+            .get_stored_file_contents(content_id)
+            .and_then(move |contents| match contents {
+                FileContents::Chunked(chunked) => {
+                    for chunk in chunked.chunks() {
+                        this.mark_key(chunk.chunk_id().blobstore_key());
+                    }
+                    future::ok(()).left_future()
+                }
+                FileContents::Bytes(bytes) => match LfsPointer::from_bytes(&bytes) {
+                    Ok(pointer) => this.mark_lfs_object(pointer).right_future(),
+                    Err(_) => future::ok(()).left_future(),
+                },
+            })
+            .boxify()
+    }
+
+    /// Marks the object an LFS pointer refers to, if it is in the repo. Pointers to objects that
+    /// were never uploaded are fine: the object might be in another LFS store.
+    fn mark_lfs_object(&self, pointer: LfsPointer) -> BoxFuture<(), Error> {
+        let oid = *pointer.oid();
+        let this = self.clone();
+        self.repo
+            .get_lfs_content_id(&oid)
+            .and_then(move |content_id| match content_id {
+                Some(content_id) => {
+                    this.mark_key(lfs_alias_key(&oid));
+                    this.mark_content(content_id).left_future()
+                }
+                None => future::ok(()).right_future(),
+            })
+            .boxify()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use async_unit;
+    use fixtures::linear;
+    use slog::Discard;
+
+    #[test]
+    fn mark_reachable() {
+        async_unit::tokio_unit_test(|| {
+            let repo = linear::getrepo(None);
+            let marked = Marker::new(Logger::root(Discard, o!()), repo.clone(), 10)
+                .mark()
+                .wait()
+                .expect("mark failed");
+
+            let cs_ids = repo.get_all_changesets().wait().unwrap();
+            assert!(!cs_ids.is_empty());
+            for cs_id in cs_ids {
+                assert!(marked.contains(&cs_id.blobstore_key()));
+                let hg_cs_id = repo.get_hg_from_bonsai(cs_id)
+                    .wait()
+                    .unwrap()
+                    .expect("no hg changeset");
+                assert!(marked.contains(&hg_cs_id.blobstore_key()));
+                let cs = repo.get_changeset_by_changesetid(&hg_cs_id).wait().unwrap();
+                assert!(marked.contains(&cs.manifestid().blobstore_key()));
+            }
+
+            for prefix in &["content.blake2.", "hgfilenode.sha1."] {
+                assert!(marked.iter().any(|key| key.starts_with(prefix)));
+            }
+            // Everything that is marked is swept, so marking anything else would be pointless.
+            assert!(marked.iter().all(|key| {
+                MARKED_KEY_PREFIXES
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix))
+            }));
+        });
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use failure::{err_msg, Error};
+use futures::{future, stream, Future, Stream};
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobstore::BlobstoreDeletable;
+
+/// What happened to a key of the blobstore.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SweepOutcome {
+    Reachable,
+    /// The blob is unreachable, but might be part of a push that is still in progress.
+    TooRecent,
+    WouldDelete,
+    Deleted,
+}
+
+/// Statistics of a sweep.
+#[derive(Clone, Debug, Default)]
+pub struct SweepStats {
+    pub keys: usize,
+    pub reachable: usize,
+    pub too_recent: usize,
+    pub would_delete: usize,
+    pub deleted: usize,
+}
+
+impl SweepStats {
+    fn add(&mut self, outcome: SweepOutcome) {
+        self.keys += 1;
+        match outcome {
+            SweepOutcome::Reachable => self.reachable += 1,
+            SweepOutcome::TooRecent => self.too_recent += 1,
+            SweepOutcome::WouldDelete => self.would_delete += 1,
+            SweepOutcome::Deleted => self.deleted += 1,
+        }
+    }
+}
+
+/// How to sweep the unreachable blobs.
+#[derive(Clone, Debug)]
+pub struct SweepOptions {
+    /// Keep the unreachable blobs that were written less than this long ago.
+    pub grace_period: Duration,
+    /// Only count the blobs that would be deleted.
+    pub report_only: bool,
+    /// Delete the unreachable blobs of blobstores that don't record when blobs were written,
+    /// regardless of the grace period. Only safe while nothing is writing to the repo.
+    pub without_mtimes: bool,
+    pub concurrency: usize,
+}
+
+/// Deletes the keys of `blobstore` starting with one of `prefixes` that are not in `marked` and
+/// were last written more than `options.grace_period` ago.
+///
+/// Blobstores that don't know when keys were written (`last_modified` returns None, e.g.
+/// RocksDB) can't honour the grace period, so sweeping them fails unless
+/// `options.without_mtimes` is set.
+pub fn sweep<B>(
+    logger: Logger,
+    blobstore: B,
+    prefixes: &[&str],
+    marked: HashSet<String>,
+    options: SweepOptions,
+) -> BoxFuture<SweepStats, Error>
+where
+    B: BlobstoreDeletable + Clone,
+{
+    let marked = Arc::new(marked);
+    let started = SystemTime::now();
+    let concurrency = options.concurrency;
+
+    let keys: Vec<_> = prefixes
+        .iter()
+        .map(|prefix| blobstore.enumerate(prefix.to_string(), None))
+        .collect();
+
+    stream::iter_ok::<_, Error>(keys)
+        .flatten()
+        .map(move |key| {
+            if marked.contains(&key) {
+                return future::ok(SweepOutcome::Reachable).boxify();
+            }
+
+            let blobstore = blobstore.clone();
+            let logger = logger.clone();
+            let options = options.clone();
+            blobstore
+                .last_modified(key.clone())
+                .and_then(move |last_modified| {
+                    let old_enough = match last_modified {
+                        Some(last_modified) => started
+                            .duration_since(last_modified)
+                            .map(|age| age >= options.grace_period)
+                            .unwrap_or(false),
+                        None if options.without_mtimes => true,
+                        None => {
+                            return future::err(err_msg(format!(
+                                "the blobstore doesn't record when {} was written, so the grace \
+                                 period can't be honoured",
+                                key
+                            ))).left_future()
+                        }
+                    };
+
+                    if !old_enough {
+                        debug!(logger, "{} is unreachable but too recent", key);
+                        future::ok(SweepOutcome::TooRecent).left_future()
+                    } else if options.report_only {
+                        info!(logger, "would delete {}", key);
+                        future::ok(SweepOutcome::WouldDelete).left_future()
+                    } else {
+                        debug!(logger, "deleting {}", key);
+                        blobstore
+                            .delete(key)
+                            .map(|()| SweepOutcome::Deleted)
+                            .right_future()
+                    }
+                })
+                .boxify()
+        })
+        .buffer_unordered(concurrency)
+        .fold(SweepStats::default(), |mut stats, outcome| {
+            stats.add(outcome);
+            Ok::<_, Error>(stats)
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use slog::Discard;
+    use tempdir::TempDir;
+
+    use blobstore::{Blobstore, EagerMemblob};
+    use fileblob::Fileblob;
+    use mononoke_types::BlobstoreBytes;
+
+    const LIVE: &str = "content.blake2.live";
+    const DEAD: &str = "content.blake2.dead";
+    // Not one of the swept prefixes, so it's kept although it isn't marked.
+    const OTHER: &str = "skiplist.index";
+
+    fn options(grace_period: u64) -> SweepOptions {
+        SweepOptions {
+            grace_period: Duration::from_secs(grace_period),
+            report_only: false,
+            without_mtimes: false,
+            concurrency: 10,
+        }
+    }
+
+    fn run_sweep<B>(blobstore: &B, options: SweepOptions) -> Result<SweepStats, Error>
+    where
+        B: BlobstoreDeletable + Clone,
+    {
+        for key in &[LIVE, DEAD, OTHER] {
+            blobstore
+                .put(key.to_string(), BlobstoreBytes::from_bytes("blob"))
+                .wait()
+                .expect("put failed");
+        }
+        let marked = vec![LIVE.to_string()].into_iter().collect();
+        sweep(
+            Logger::root(Discard, o!()),
+            blobstore.clone(),
+            &["content.blake2."],
+            marked,
+            options,
+        ).wait()
+    }
+
+    fn has_key<B: Blobstore>(blobstore: &B, key: &str) -> bool {
+        blobstore
+            .get(key.to_string())
+            .wait()
+            .expect("get failed")
+            .is_some()
+    }
+
+    #[test]
+    fn sweep_unreachable() {
+        let dir = TempDir::new("blobstore_gc").unwrap();
+        let blobstore = Fileblob::open(dir.path()).unwrap();
+
+        let stats = run_sweep(&blobstore, options(0)).expect("sweep failed");
+        assert_eq!((stats.keys, stats.reachable, stats.deleted), (2, 1, 1));
+        assert!(has_key(&blobstore, LIVE));
+        assert!(!has_key(&blobstore, DEAD));
+        assert!(has_key(&blobstore, OTHER));
+    }
+
+    #[test]
+    fn sweep_grace_period() {
+        let dir = TempDir::new("blobstore_gc").unwrap();
+        let blobstore = Fileblob::open(dir.path()).unwrap();
+
+        let stats = run_sweep(&blobstore, options(3600)).expect("sweep failed");
+        assert_eq!((stats.keys, stats.reachable, stats.too_recent), (2, 1, 1));
+        assert_eq!(stats.deleted, 0);
+        assert!(has_key(&blobstore, LIVE));
+        assert!(has_key(&blobstore, DEAD));
+    }
+
+    #[test]
+    fn sweep_report_only() {
+        let dir = TempDir::new("blobstore_gc").unwrap();
+        let blobstore = Fileblob::open(dir.path()).unwrap();
+
+        let options = SweepOptions {
+            report_only: true,
+            ..options(0)
+        };
+        let stats = run_sweep(&blobstore, options).expect("sweep failed");
+        assert_eq!((stats.would_delete, stats.deleted), (1, 0));
+        assert!(has_key(&blobstore, DEAD));
+    }
+
+    #[test]
+    fn sweep_without_mtimes() {
+        // Memblobs don't record when keys were written, like RocksDB.
+        let blobstore = EagerMemblob::new();
+        assert!(run_sweep(&blobstore, options(0)).is_err());
+        assert!(has_key(&blobstore, DEAD));
+
+        let options = SweepOptions {
+            without_mtimes: true,
+            ..options(3600)
+        };
+        let stats = run_sweep(&blobstore, options).expect("sweep failed");
+        assert_eq!((stats.reachable, stats.deleted), (1, 1));
+        assert!(has_key(&blobstore, LIVE));
+        assert!(!has_key(&blobstore, DEAD));
+    }
+}
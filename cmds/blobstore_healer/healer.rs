@@ -0,0 +1,467 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use failure::{err_msg, Error};
+use futures::{future, stream, Future, Stream};
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+use tokio::timer::Delay;
+
+use blobstore::{Blobstore, BlobstoreId};
+use blobstore_sync_queue::{BlobstoreSyncQueue, BlobstoreSyncQueueEntry};
+use mercurial_types::RepositoryId;
+use mononoke_types::DateTime;
+
+/// What happened to a key of the queue.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HealOutcome {
+    /// All the blobstores have the blob, the entries were removed from the queue.
+    Healed,
+    /// The blob is missing from some blobstores, but this is a dry run.
+    WouldHeal,
+    /// No blobstore has the blob, so it can't be copied.
+    Unhealable,
+    /// Some of the blobstores failed, the entries were left in the queue to be retried.
+    Failed,
+}
+
+/// Statistics of a healing pass.
+#[derive(Clone, Debug, Default)]
+pub struct HealStats {
+    pub entries: usize,
+    pub healed: usize,
+    pub would_heal: usize,
+    pub unhealable: usize,
+    pub failed: usize,
+    pub copies: usize,
+}
+
+impl HealStats {
+    fn add(&mut self, outcome: HealOutcome, copies: usize) {
+        match outcome {
+            HealOutcome::Healed => self.healed += 1,
+            HealOutcome::WouldHeal => self.would_heal += 1,
+            HealOutcome::Unhealable => self.unhealable += 1,
+            HealOutcome::Failed => self.failed += 1,
+        }
+        self.copies += copies;
+    }
+
+    fn merge(&mut self, other: HealStats) {
+        self.entries += other.entries;
+        self.healed += other.healed;
+        self.would_heal += other.would_heal;
+        self.unhealable += other.unhealable;
+        self.failed += other.failed;
+        self.copies += other.copies;
+    }
+}
+
+/// Copies the blobs recorded in the sync queue to the blobstores that miss them.
+#[derive(Clone)]
+pub struct Healer {
+    logger: Logger,
+    repo_id: RepositoryId,
+    blobstores: Arc<HashMap<BlobstoreId, Arc<Blobstore>>>,
+    queue: Arc<BlobstoreSyncQueue>,
+    /// Entries more recent than this are left alone, as the puts they were created for may
+    /// still be in flight.
+    grace_period: Duration,
+    batch_size: usize,
+    /// Maximum number of keys healed per second.
+    rate_limit: Option<usize>,
+    dry_run: bool,
+}
+
+impl Healer {
+    pub fn new(
+        logger: Logger,
+        repo_id: RepositoryId,
+        blobstores: HashMap<BlobstoreId, Arc<Blobstore>>,
+        queue: Arc<BlobstoreSyncQueue>,
+        grace_period: Duration,
+        batch_size: usize,
+        rate_limit: Option<usize>,
+        dry_run: bool,
+    ) -> Self {
+        Self {
+            logger,
+            repo_id,
+            blobstores: Arc::new(blobstores),
+            queue,
+            grace_period,
+            batch_size,
+            rate_limit,
+            dry_run,
+        }
+    }
+
+    /// Heals the old enough entries of the queue, one batch after the other. Entries that can't
+    /// be healed are left in the queue and skipped, so they are only retried by the next call.
+    pub fn heal(&self) -> BoxFuture<HealStats, Error> {
+        let this = self.clone();
+        future::loop_fn((HealStats::default(), None), move |(mut total, after_id)| {
+            let batch_size = this.batch_size;
+            this.heal_batch(after_id).map(move |(stats, last_id)| {
+                let done = stats.entries < batch_size;
+                total.merge(stats);
+                if done {
+                    future::Loop::Break(total)
+                } else {
+                    future::Loop::Continue((total, last_id))
+                }
+            })
+        }).boxify()
+    }
+
+    /// Heals the batch of entries that comes after the entry `after_id`. Returns what happened to
+    /// them, and the id of the last entry of the batch.
+    fn heal_batch(&self, after_id: Option<i64>) -> BoxFuture<(HealStats, Option<i64>), Error> {
+        let older_than = try_boxfuture!(DateTime::from_timestamp(
+            DateTime::now().timestamp_secs() - self.grace_period.as_secs() as i64,
+            0,
+        ));
+
+        let this = self.clone();
+        self.queue
+            .iter(self.repo_id, older_than, after_id, self.batch_size)
+            .and_then(move |entries| {
+                let entries_count = entries.len();
+                let last_id = entries.iter().filter_map(|entry| entry.id).max().or(after_id);
+                let mut by_key: HashMap<String, Vec<BlobstoreSyncQueueEntry>> = HashMap::new();
+                for entry in entries {
+                    by_key
+                        .entry(entry.blobstore_key.clone())
+                        .or_insert_with(Vec::new)
+                        .push(entry);
+                }
+                debug!(
+                    this.logger,
+                    "healing {} entries for {} keys",
+                    entries_count,
+                    by_key.len()
+                );
+
+                let chunk_size = this.rate_limit.unwrap_or(by_key.len()).max(1);
+                stream::iter_ok::<_, Error>(by_key.into_iter())
+                    .chunks(chunk_size)
+                    .and_then(move |chunk| {
+                        let started = Instant::now();
+                        let rate_limited = this.rate_limit.is_some();
+                        let heals = chunk
+                            .into_iter()
+                            .map(|(key, entries)| this.heal_key(key, entries));
+                        future::join_all(heals).and_then(move |outcomes| {
+                            // Keep at most `rate_limit` keys per second.
+                            let delay = if rate_limited {
+                                Delay::new(started + Duration::from_secs(1))
+                                    .from_err()
+                                    .left_future()
+                            } else {
+                                future::ok(()).right_future()
+                            };
+                            delay.map(move |()| outcomes)
+                        })
+                    })
+                    .fold(
+                        HealStats {
+                            entries: entries_count,
+                            ..Default::default()
+                        },
+                        |mut stats, outcomes| {
+                            for (outcome, copies) in outcomes {
+                                stats.add(outcome, copies);
+                            }
+                            Ok::<_, Error>(stats)
+                        },
+                    )
+                    .map(move |stats| (stats, last_id))
+            })
+            .boxify()
+    }
+
+    /// Makes sure that all the blobstores have `key`. Returns what happened to the key and to how
+    /// many blobstores it was copied.
+    fn heal_key(
+        &self,
+        key: String,
+        entries: Vec<BlobstoreSyncQueueEntry>,
+    ) -> BoxFuture<(HealOutcome, usize), Error> {
+        let this = self.clone();
+        self.check_presence(key.clone())
+            .and_then(move |presence| {
+                let (present, missing): (Vec<_>, Vec<_>) = presence
+                    .into_iter()
+                    .partition(|&(_, is_present)| is_present);
+                let present: Vec<_> = present.into_iter().map(|(id, _)| id).collect();
+                let missing: Vec<_> = missing.into_iter().map(|(id, _)| id).collect();
+
+                if missing.is_empty() {
+                    debug!(this.logger, "{} is in all the blobstores", key);
+                    return this.remove_entries(entries)
+                        .map(|()| (HealOutcome::Healed, 0))
+                        .boxify();
+                }
+                if present.is_empty() {
+                    warn!(this.logger, "{} is not in any blobstore, can't heal it", key);
+                    return future::ok((HealOutcome::Unhealable, 0)).boxify();
+                }
+                if this.dry_run {
+                    info!(
+                        this.logger,
+                        "would copy {} from blobstore {} to blobstores {:?}",
+                        key,
+                        present[0],
+                        missing
+                    );
+                    return future::ok((HealOutcome::WouldHeal, 0)).boxify();
+                }
+
+                this.copy(key, present, missing)
+                    .and_then(move |(key, copies)| {
+                        // Only remove the entries once all the blobstores agree that they have
+                        // the blob.
+                        this.check_presence(key.clone()).and_then(move |presence| {
+                            if presence.iter().all(|&(_, is_present)| is_present) {
+                                this.remove_entries(entries)
+                                    .map(move |()| (HealOutcome::Healed, copies))
+                                    .left_future()
+                            } else {
+                                warn!(this.logger, "{} is still missing after the copy", key);
+                                future::ok((HealOutcome::Failed, copies)).right_future()
+                            }
+                        })
+                    })
+                    .boxify()
+            })
+            .or_else({
+                let logger = self.logger.clone();
+                move |err| {
+                    warn!(logger, "failed to heal: {}", err);
+                    Ok((HealOutcome::Failed, 0))
+                }
+            })
+            .boxify()
+    }
+
+    /// Returns whether each of the blobstores has `key`.
+    fn check_presence(&self, key: String) -> BoxFuture<Vec<(BlobstoreId, bool)>, Error> {
+        let checks = self.blobstores.iter().map(|(blobstore_id, blobstore)| {
+            let blobstore_id = *blobstore_id;
+            blobstore
+                .is_present(key.clone())
+                .map(move |is_present| (blobstore_id, is_present))
+        });
+        future::join_all(checks).boxify()
+    }
+
+    /// Copies `key` from one of the `present` blobstores to all of the `missing` ones.
+    fn copy(
+        &self,
+        key: String,
+        present: Vec<BlobstoreId>,
+        missing: Vec<BlobstoreId>,
+    ) -> BoxFuture<(String, usize), Error> {
+        let blobstores = self.blobstores.clone();
+        let sources: Vec<_> = present
+            .into_iter()
+            .map(|blobstore_id| blobstores[&blobstore_id].clone())
+            .collect();
+
+        // Try the blobstores that have the blob one after the other, a blob can be present but
+        // fail to be fetched.
+        let fetch = stream::iter_ok::<_, Error>(sources)
+            .and_then({
+                let key = key.clone();
+                move |source| source.get(key.clone()).or_else(|_| Ok::<_, Error>(None))
+            })
+            .filter_map(|value| value)
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then({
+                let key = key.clone();
+                move |(value, _)| value.ok_or_else(|| err_msg(format!("failed to fetch {}", key)))
+            });
+
+        fetch
+            .and_then(move |value| {
+                let copies = missing.len();
+                let puts: Vec<_> = missing
+                    .into_iter()
+                    .map(|blobstore_id| blobstores[&blobstore_id].put(key.clone(), value.clone()))
+                    .collect();
+                future::join_all(puts).map(move |_| (key, copies))
+            })
+            .boxify()
+    }
+
+    fn remove_entries(&self, entries: Vec<BlobstoreSyncQueueEntry>) -> BoxFuture<(), Error> {
+        if self.dry_run {
+            return future::ok(()).boxify();
+        }
+        self.queue.del(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use async_unit;
+    use slog::Discard;
+
+    use blobstore::EagerMemblob;
+    use blobstore_sync_queue::SqliteBlobstoreSyncQueue;
+    use mononoke_types::BlobstoreBytes;
+
+    fn repo_id() -> RepositoryId {
+        RepositoryId::new(0)
+    }
+
+    fn healer(
+        blobstores: &[EagerMemblob],
+        batch_size: usize,
+        dry_run: bool,
+    ) -> (Healer, Arc<BlobstoreSyncQueue>) {
+        let queue: Arc<BlobstoreSyncQueue> = Arc::new(
+            SqliteBlobstoreSyncQueue::in_memory().expect("failed to create the sync queue"),
+        );
+        let blobstores = blobstores
+            .iter()
+            .enumerate()
+            .map(|(id, blobstore)| {
+                let blobstore: Arc<Blobstore> = Arc::new(blobstore.clone());
+                (BlobstoreId::new(id as u64), blobstore)
+            })
+            .collect();
+        let healer = Healer::new(
+            Logger::root(Discard, o!()),
+            repo_id(),
+            blobstores,
+            queue.clone(),
+            Duration::from_secs(0),
+            batch_size,
+            None,
+            dry_run,
+        );
+        (healer, queue)
+    }
+
+    /// Record in the queue that `key` is missing from the blobstore `blobstore_id`.
+    fn enqueue(queue: &Arc<BlobstoreSyncQueue>, key: &str, blobstore_id: u64) {
+        let timestamp = DateTime::from_timestamp(DateTime::now().timestamp_secs() - 10, 0)
+            .expect("invalid timestamp");
+        queue
+            .add(BlobstoreSyncQueueEntry::new(
+                repo_id(),
+                key.to_string(),
+                BlobstoreId::new(blobstore_id),
+                timestamp,
+            ))
+            .wait()
+            .expect("sync queue add failed");
+    }
+
+    fn queued_keys(queue: &Arc<BlobstoreSyncQueue>) -> Vec<String> {
+        let older_than = DateTime::from_timestamp(DateTime::now().timestamp_secs() + 10, 0)
+            .expect("invalid timestamp");
+        queue
+            .iter(repo_id(), older_than, None, 100)
+            .wait()
+            .expect("sync queue iter failed")
+            .into_iter()
+            .map(|entry| entry.blobstore_key)
+            .collect()
+    }
+
+    fn put(blobstore: &EagerMemblob, key: &str) {
+        blobstore
+            .put(key.to_string(), BlobstoreBytes::from_bytes(key.to_string()))
+            .wait()
+            .expect("put failed");
+    }
+
+    fn has(blobstore: &EagerMemblob, key: &str) -> bool {
+        blobstore
+            .is_present(key.to_string())
+            .wait()
+            .expect("is_present failed")
+    }
+
+    #[test]
+    fn test_heal() {
+        async_unit::tokio_unit_test(|| {
+            let blobstores = [EagerMemblob::new(), EagerMemblob::new()];
+            let (healer, queue) = healer(&blobstores, 10, false);
+            put(&blobstores[0], "missing");
+            enqueue(&queue, "missing", 1);
+            // The put to the second blobstore eventually succeeded
+            put(&blobstores[0], "present");
+            put(&blobstores[1], "present");
+            enqueue(&queue, "present", 1);
+
+            let stats = healer.heal().wait().expect("heal failed");
+
+            assert_eq!(stats.entries, 2);
+            assert_eq!(stats.healed, 2);
+            assert_eq!(stats.copies, 1);
+            assert!(has(&blobstores[1], "missing"));
+            assert_eq!(queued_keys(&queue), Vec::<String>::new());
+        });
+    }
+
+    #[test]
+    fn test_heal_skips_unhealable() {
+        async_unit::tokio_unit_test(|| {
+            let blobstores = [EagerMemblob::new(), EagerMemblob::new()];
+            // One entry per batch, so that the unhealable entries fill whole batches
+            let (healer, queue) = healer(&blobstores, 1, false);
+            enqueue(&queue, "lost1", 1);
+            enqueue(&queue, "lost2", 1);
+            put(&blobstores[0], "key");
+            enqueue(&queue, "key", 1);
+
+            let stats = healer.heal().wait().expect("heal failed");
+
+            assert_eq!(stats.entries, 3);
+            assert_eq!(stats.unhealable, 2);
+            assert_eq!(stats.healed, 1);
+            assert!(has(&blobstores[1], "key"));
+            // The unhealable entries are kept for the next pass
+            assert_eq!(
+                queued_keys(&queue),
+                vec!["lost1".to_string(), "lost2".to_string()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_heal_dry_run() {
+        async_unit::tokio_unit_test(|| {
+            let blobstores = [EagerMemblob::new(), EagerMemblob::new()];
+            let (healer, queue) = healer(&blobstores, 1, true);
+            put(&blobstores[0], "key1");
+            enqueue(&queue, "key1", 1);
+            put(&blobstores[1], "key2");
+            enqueue(&queue, "key2", 0);
+
+            let stats = healer.heal().wait().expect("heal failed");
+
+            assert_eq!(stats.entries, 2);
+            assert_eq!(stats.would_heal, 2);
+            assert!(!has(&blobstores[1], "key1"));
+            assert!(!has(&blobstores[0], "key2"));
+            assert_eq!(
+                queued_keys(&queue),
+                vec!["key1".to_string(), "key2".to_string()]
+            );
+        });
+    }
+}
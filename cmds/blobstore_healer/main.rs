@@ -0,0 +1,209 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Drains the sync queue of a multiplexed blobstore: every blob that some of the blobstores
+//! missed is copied to them from a blobstore that has it. The blobstores and the queue are found
+//! in the config of the repo, which must be a multiplexed one.
+//!
+//! The healer runs alongside the server, so it only supports `files` blobstores: a RocksDb
+//! database can only be opened by one process, and the server holds its lock.
+
+#![deny(warnings)]
+
+extern crate clap;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate slog;
+extern crate tokio;
+
+#[macro_use]
+extern crate futures_ext;
+
+extern crate blobrepo;
+extern crate blobstore;
+extern crate blobstore_sync_queue;
+extern crate bookmarks;
+extern crate cmdlib;
+extern crate mercurial_types;
+extern crate metaconfig;
+extern crate mononoke_types;
+
+#[cfg(test)]
+extern crate async_unit;
+
+mod healer;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, ArgMatches};
+use failure::{Result, ResultExt};
+use futures::Future;
+use slog::Logger;
+
+use blobrepo::{BlobRepo, ComponentBlobstoreType};
+use blobstore::{Blobstore, BlobstoreId};
+use blobstore_sync_queue::{BlobstoreSyncQueue, SqliteBlobstoreSyncQueue};
+use bookmarks::Bookmark;
+use cmdlib::args;
+use mercurial_types::{HgChangesetId, RepositoryId};
+use metaconfig::RepoConfigs;
+use metaconfig::repoconfig::{RepoConfig, RepoType};
+
+use healer::{HealStats, Healer};
+
+fn setup_app<'a, 'b>() -> App<'a, 'b> {
+    let app = args::MononokeApp {
+        safe_writes: false,
+        hide_advanced_args: true,
+        local_instances: false,
+        default_glog: false,
+    };
+    app.build("blobstore healer")
+        .version("0.0.0")
+        .about("Copy the blobs that some blobstores of a multiplexed blobstore missed to them.")
+        .args_from_usage(
+            r#"
+            <REPONAME>                      'name of the multiplexed repo in the config'
+            --config-path <PATH>            'directory of the config repository'
+            --config-bookmark [BOOKMARK]    'bookmark of the config repository'
+            --config-commit [HASH]          'commit hash of the config repository'
+            --dry-run                       'only report what would be copied'
+            --rate-limit [KEYS]             'maximum number of keys healed per second'
+            --batch-size [ENTRIES]          'number of queue entries read at once [default: 100]'
+            --grace-period [SECS]           'skip entries more recent than this [default: 60]'
+            --interval [SECS]               'keep running, healing the queue every SECS seconds'
+        "#,
+        )
+}
+
+fn read_repo_config<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<RepoConfig> {
+    let config_path = Path::new(matches.value_of("config-path").expect("config path is not set"));
+    let config_repo = BlobRepo::new_rocksdb(
+        logger.new(o!["repo" => "Config repo"]),
+        config_path,
+        RepositoryId::new(0),
+        None,
+        None,
+        None,
+    )?;
+
+    let changeset = match matches.value_of("config-bookmark") {
+        Some(bookmark) => config_repo
+            .get_bookmark(&Bookmark::new(bookmark)?)
+            .wait()?
+            .ok_or_else(|| format_err!("bookmark {} not found", bookmark))?,
+        None => HgChangesetId::from_str(matches
+            .value_of("config-commit")
+            .expect("config bookmark and commit are not set"))?,
+    };
+    info!(logger, "Reading config from commit: {}", changeset);
+
+    let reponame = matches.value_of("REPONAME").expect("repo name is not set");
+    let mut configs = RepoConfigs::read_config_repo(config_repo, changeset).wait()?;
+    configs
+        .repos
+        .remove(reponame)
+        .ok_or_else(|| format_err!("repo {} not found in the config", reponame))
+}
+
+fn get_u64<'a>(matches: &ArgMatches<'a>, key: &str, default: u64) -> u64 {
+    matches
+        .value_of(key)
+        .map(|val| {
+            val.parse::<u64>()
+                .expect(&format!("{} must be integer", key))
+        })
+        .unwrap_or(default)
+}
+
+fn log_stats(logger: &Logger, stats: &HealStats) {
+    info!(
+        logger,
+        "{} entries: {} keys healed ({} copies), {} would be healed, {} not in any blobstore, \
+         {} failed",
+        stats.entries,
+        stats.healed,
+        stats.copies,
+        stats.would_heal,
+        stats.unhealable,
+        stats.failed
+    );
+}
+
+fn main() -> Result<()> {
+    let matches = setup_app().get_matches();
+    let logger = args::get_logger(&matches);
+    let config = read_repo_config(&logger, &matches)?;
+    let repo_id = RepositoryId::new(config.repoid);
+
+    let (data_dir, blobstores) = match config.repotype {
+        RepoType::BlobMultiplexed {
+            path, blobstores, ..
+        } => (path, blobstores),
+        _ => bail_msg!("the repo doesn't have a multiplexed blobstore"),
+    };
+    let blobstores = blobstores
+        .iter()
+        .map(|args| {
+            if args.blobstore_type != ComponentBlobstoreType::Files {
+                bail_msg!(
+                    "blobstore {} is a {:?} blobstore, but only files blobstores can be healed \
+                     while the server holds them open",
+                    args.blobstore_id,
+                    args.blobstore_type
+                );
+            }
+            let blobstore = args.open()
+                .with_context(|_| format!("failed to open blobstore {}", args.blobstore_id))?;
+            Ok((args.blobstore_id, blobstore))
+        })
+        .collect::<Result<HashMap<BlobstoreId, Arc<Blobstore>>>>()?;
+    if blobstores.len() < 2 {
+        bail_msg!("at least two blobstores are needed to heal");
+    }
+
+    // Same place as the multiplexed blobstore of the repo puts it
+    let queue = SqliteBlobstoreSyncQueue::open_or_create(
+        data_dir.join("blobstore_sync_queue").to_string_lossy(),
+    )?;
+    let queue: Arc<BlobstoreSyncQueue> = Arc::new(queue);
+
+    let dry_run = matches.is_present("dry-run");
+    let healer = Healer::new(
+        logger.clone(),
+        repo_id,
+        blobstores,
+        queue,
+        Duration::from_secs(get_u64(&matches, "grace-period", 60)),
+        args::get_usize(&matches, "batch-size", 100),
+        args::get_usize_opt(&matches, "rate-limit"),
+        dry_run,
+    );
+    let interval = matches
+        .value_of("interval")
+        .map(|_| Duration::from_secs(get_u64(&matches, "interval", 0)));
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    loop {
+        let stats = runtime.block_on(healer.heal())?;
+        log_stats(&logger, &stats);
+
+        match interval {
+            Some(interval) if !dry_run => thread::sleep(interval),
+            _ => break,
+        }
+    }
+    runtime.shutdown_on_idle();
+
+    Ok(())
+}
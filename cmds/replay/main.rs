@@ -0,0 +1,236 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Replays wire protocol requests recorded by the server (see `wireproto_recording_dir` in the
+//! repo config) against a repo, and reports how the latencies and the responses compare to the
+//! recorded ones.
+
+#![deny(warnings)]
+
+extern crate bytes;
+extern crate clap;
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate slog;
+extern crate tokio;
+extern crate uuid;
+
+extern crate cmdlib;
+extern crate futures_ext;
+extern crate hgproto;
+extern crate mercurial_types;
+extern crate repo_client;
+extern crate scuba_ext;
+extern crate tracing;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::Bytes;
+use clap::App;
+use failure::Result;
+use futures::{future, stream, Future, Stream};
+use slog::Logger;
+use uuid::Uuid;
+
+use cmdlib::args;
+use futures_ext::{BoxFuture, FutureExt};
+use hgproto::{sshproto, HgProtoHandler, RecordedRequest};
+use mercurial_types::hash::Context;
+use repo_client::{MononokeRepo, RepoClient};
+use scuba_ext::ScubaSampleBuilder;
+use tracing::TraceContext;
+
+fn setup_app<'a, 'b>() -> App<'a, 'b> {
+    let app = args::MononokeApp {
+        safe_writes: true,
+        hide_advanced_args: false,
+        local_instances: true,
+        default_glog: false,
+    };
+    app.build("wire protocol replay")
+        .version("0.0.0")
+        .about("Replay recorded wire protocol requests and compare latencies and responses.")
+        .args_from_usage(
+            r#"
+            <RECORDING>     'file with the recorded requests of a connection'
+            --show-diffs    'log every request whose response differs from the recorded one'
+        "#,
+        )
+}
+
+/// Outcome of replaying a single request.
+struct ReplayResult {
+    duration_ms: u64,
+    response_sha1: String,
+    response_size: u64,
+    error: Option<String>,
+}
+
+fn as_millis(start: Instant) -> u64 {
+    let duration = start.elapsed();
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+fn replay_request(
+    repo: Arc<MononokeRepo>,
+    logger: Logger,
+    record: &RecordedRequest,
+) -> BoxFuture<ReplayResult, ()> {
+    let client = RepoClient::new(
+        repo,
+        logger.clone(),
+        ScubaSampleBuilder::with_discard(),
+        TraceContext::new(Uuid::new_v4(), Instant::now()),
+    );
+    let input = stream::once::<_, io::Error>(Ok(Bytes::from(record.request.clone())));
+    let handler = HgProtoHandler::new(
+        input,
+        client,
+        sshproto::HgSshCommandDecode,
+        sshproto::HgSshCommandEncode,
+        &logger,
+        Arc::new(Mutex::new(Vec::new())),
+        None,
+    );
+
+    let start = Instant::now();
+    handler
+        .fold((Context::new(), 0), |(mut response, size), bytes| {
+            response.update(&bytes);
+            Ok::<_, hgproto::Error>((response, size + bytes.len() as u64))
+        })
+        .then(move |res| {
+            let duration_ms = as_millis(start);
+            Ok(match res {
+                Ok((response, response_size)) => ReplayResult {
+                    duration_ms,
+                    response_sha1: response.finish().to_hex().to_string(),
+                    response_size,
+                    error: None,
+                },
+                Err(err) => ReplayResult {
+                    duration_ms,
+                    response_sha1: String::new(),
+                    response_size: 0,
+                    error: Some(format!("{}", err)),
+                },
+            })
+        })
+        .boxify()
+}
+
+#[derive(Default)]
+struct Summary {
+    replayed: usize,
+    skipped: usize,
+    same_response: usize,
+    different_response: usize,
+    recorded_ms: u64,
+    replayed_ms: u64,
+}
+
+fn main() -> Result<()> {
+    let matches = setup_app().get_matches();
+    let logger = args::get_logger(&matches);
+    let show_diffs = matches.is_present("show-diffs");
+
+    let recording = matches
+        .value_of("RECORDING")
+        .expect("recording is not specified");
+    let records = RecordedRequest::read_all(recording)?;
+    info!(logger, "replaying {} requests from {}", records.len(), recording);
+
+    let repo = Arc::new(MononokeRepo::from_blobrepo(
+        recording,
+        Arc::new(args::open_blobrepo(&logger, &matches)),
+    ));
+
+    // Requests are replayed one by one, in the order they were received, so that their latencies
+    // don't affect each other.
+    let replay = stream::iter_ok::<_, ()>(records.into_iter().enumerate())
+        .fold(Summary::default(), {
+            let logger = logger.clone();
+            move |mut summary, (idx, record)| {
+                let commands = record.commands.join(",");
+                if record.payload_sha1.is_some() {
+                    // The bundle of an unbundle isn't recorded, so it can't be replayed.
+                    info!(logger, "#{} {}: skipped, has a payload", idx, commands);
+                    summary.skipped += 1;
+                    return future::ok(summary).left_future();
+                }
+
+                let logger = logger.clone();
+                replay_request(repo.clone(), logger.clone(), &record)
+                    .map(move |result| {
+                        let same_response = result.error == record.error
+                            && result.response_sha1 == record.response_sha1
+                            && result.response_size == record.response_size;
+
+                        info!(
+                            logger,
+                            "#{} {}: recorded {} ms, replayed {} ms, {}",
+                            idx,
+                            commands,
+                            record.duration_ms,
+                            result.duration_ms,
+                            if same_response {
+                                "same response"
+                            } else {
+                                "different response"
+                            }
+                        );
+                        if !same_response && show_diffs {
+                            info!(
+                                logger,
+                                "#{} {}: recorded response {} ({} bytes, error: {:?}), \
+                                 replayed response {} ({} bytes, error: {:?})",
+                                idx,
+                                commands,
+                                record.response_sha1,
+                                record.response_size,
+                                record.error,
+                                result.response_sha1,
+                                result.response_size,
+                                result.error
+                            );
+                        }
+
+                        summary.replayed += 1;
+                        summary.recorded_ms += record.duration_ms;
+                        summary.replayed_ms += result.duration_ms;
+                        if same_response {
+                            summary.same_response += 1;
+                        } else {
+                            summary.different_response += 1;
+                        }
+                        summary
+                    })
+                    .right_future()
+            }
+        });
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(replay);
+    runtime.shutdown_on_idle();
+
+    let summary = result.expect("replaying requests never fails");
+    info!(
+        logger,
+        "replayed {} requests ({} skipped): total latency recorded {} ms, replayed {} ms; \
+         {} same responses, {} different responses",
+        summary.replayed,
+        summary.skipped,
+        summary.recorded_ms,
+        summary.replayed_ms,
+        summary.same_response,
+        summary.different_response
+    );
+
+    Ok(())
+}
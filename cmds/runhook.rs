@@ -156,6 +156,7 @@ fn create_blobrepo(logger: &Logger, matches: &ArgMatches) -> BlobRepo {
             bonsai_hg_mapping_cache_size: default_cache_size,
             io_threads,
             max_concurrent_requests_per_io_thread: MAX_CONCURRENT_REQUESTS_PER_IO_THREAD,
+            cache_snapshot_dir: None,
         },
         RepositoryId::new(0),
     ).expect("failed to create blobrepo instance")
@@ -20,4 +20,6 @@ pub enum ErrorKind {
     CopydataNotFound(HgFileNodeId, RepoPath),
     #[fail(display = "Internal error: failure while fetching file nodes for {}", _0)]
     FailRangeFetch(RepoPath),
+    #[fail(display = "Internal error: failure while fetching file nodes for {} paths", _0)]
+    FailRangeFetchMany(usize),
 }
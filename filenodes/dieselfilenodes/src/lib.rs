@@ -40,6 +40,7 @@ use mercurial_types::{HgFileNodeId, RepoPath, RepositoryId};
 use mercurial_types::sql_types::HgFileNodeIdSql;
 use stats::Timeseries;
 
+use std::collections::HashMap;
 use std::result;
 use std::sync::MutexGuard;
 
@@ -50,12 +51,16 @@ mod schema;
 use errors::ErrorKind;
 
 pub const DEFAULT_INSERT_CHUNK_SIZE: usize = 100;
+// SQLite doesn't allow more than 999 parameters in a query, so the paths are looked up in chunks
+// by `get_all_filenodes_many`.
+const GET_MANY_CHUNK_SIZE: usize = 500;
 
 define_stats! {
     prefix = "mononoke.filenodes";
     gets: timeseries(RATE, SUM),
     gets_master: timeseries(RATE, SUM),
     range_gets: timeseries(RATE, SUM),
+    range_gets_many: timeseries(RATE, SUM),
     adds: timeseries(RATE, SUM),
 }
 
@@ -230,6 +235,56 @@ macro_rules! impl_filenodes {
                     Ok(res)
                 })
             }
+
+            fn get_all_filenodes_many(
+                &self,
+                paths: Vec<RepoPath>,
+                repo_id: &RepositoryId,
+            ) -> BoxFuture<HashMap<RepoPath, Vec<FilenodeInfo>>, Error> {
+                STATS::range_gets_many.add_value(1);
+                let db = self.clone();
+                let repo_id = *repo_id;
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let mut res = HashMap::new();
+                    for chunk in paths.chunks(GET_MANY_CHUNK_SIZE) {
+                        let mut paths_by_hash = HashMap::new();
+                        for path in chunk {
+                            let (path_bytes, is_tree) = convert_from_repo_path(path);
+                            let path_hash = Vec::from(blake2_path_hash(&path_bytes).as_ref());
+                            paths_by_hash.insert((path_hash, is_tree), path.clone());
+                            res.insert(path.clone(), vec![]);
+                        }
+
+                        let path_hashes: Vec<_> = paths_by_hash
+                            .keys()
+                            .map(|&(ref path_hash, _)| path_hash.clone())
+                            .collect();
+                        let filenode_rows = schema::filenodes::table
+                            .filter(schema::filenodes::repo_id.eq(repo_id))
+                            .filter(schema::filenodes::path_hash.eq_any(path_hashes))
+                            .load::<models::FilenodeRow>(&*connection)
+                            .context(ErrorKind::FailRangeFetchMany(chunk.len()))?;
+                        for row in filenode_rows {
+                            // A file and a directory with the same path share the hash, and
+                            // only one of them may have been asked for.
+                            let key = (row.path_hash.clone(), row.is_tree);
+                            if let Some(path) = paths_by_hash.get(&key) {
+                                let info = Self::convert_to_filenode_info(
+                                    &connection,
+                                    path,
+                                    &repo_id,
+                                    &row,
+                                )?;
+                                res.entry(path.clone()).or_insert_with(Vec::new).push(info);
+                            }
+                        }
+                    }
+
+                    Ok(res)
+                })
+            }
         }
 
         impl $struct {
@@ -19,8 +19,11 @@ extern crate mercurial_types;
 extern crate mercurial_types_mocks;
 extern crate tokio;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use dieselfilenodes::{MysqlFilenodes, SqliteFilenodes};
-use filenodes::{FilenodeInfo, Filenodes};
+use filenodes::{FilenodeInfo, Filenodes, MemWritesFilenodes};
 use futures::future::Future;
 use futures_ext::StreamExt;
 use mercurial_types::{HgFileNodeId, RepoPath, RepositoryId};
@@ -469,6 +472,48 @@ macro_rules! filenodes_test_impl {
                     Ok(())
                 }).expect("test failed");
             }
+
+            #[test]
+            fn get_all_filenodes_many() {
+                async_unit::tokio_unit_test(|| -> Result<_, !> {
+                    let filenodes = &$new_cb();
+                    do_add_filenodes(
+                        filenodes,
+                        vec![root_first_filenode(), root_second_filenode(), root_merge_filenode()],
+                        &REPO_ZERO
+                    );
+                    do_add_filenodes(
+                        filenodes,
+                        vec![file_a_first_filenode(), copied_from_filenode(), copied_filenode()],
+                        &REPO_ZERO
+                    );
+                    do_add_filenodes(filenodes, vec![file_b_first_filenode()], &REPO_ONE);
+
+                    let paths = vec![
+                        RepoPath::root(),
+                        RepoPath::file("a").unwrap(),
+                        RepoPath::dir("a").unwrap(),
+                        RepoPath::file("b").unwrap(),
+                        RepoPath::file("copiedto").unwrap(),
+                    ];
+                    let res = filenodes
+                        .get_all_filenodes_many(paths, &REPO_ZERO)
+                        .wait()
+                        .expect("error while fetching filenodes");
+
+                    let mut expected = HashMap::new();
+                    expected.insert(
+                        RepoPath::root(),
+                        vec![root_first_filenode(), root_second_filenode(), root_merge_filenode()],
+                    );
+                    expected.insert(RepoPath::file("a").unwrap(), vec![file_a_first_filenode()]);
+                    expected.insert(RepoPath::dir("a").unwrap(), vec![]);
+                    expected.insert(RepoPath::file("b").unwrap(), vec![]);
+                    expected.insert(RepoPath::file("copiedto").unwrap(), vec![copied_filenode()]);
+                    assert_eq!(res, expected);
+                    Ok(())
+                }).expect("test failed");
+            }
         }
     }
 }
@@ -477,6 +522,10 @@ filenodes_test_impl!(sqlite_tests => {
      new: create_sqlite,
  });
 
+filenodes_test_impl!(mem_writes_tests => {
+     new: create_mem_writes,
+ });
+
 filenodes_test_impl!(mysql_tests => {
      new: create_mysql,
  });
@@ -485,6 +534,10 @@ fn create_sqlite() -> SqliteFilenodes {
     SqliteFilenodes::in_memory().unwrap()
 }
 
+fn create_mem_writes() -> MemWritesFilenodes {
+    MemWritesFilenodes::new(Arc::new(create_sqlite()))
+}
+
 fn create_mysql() -> MysqlFilenodes {
     MysqlFilenodes::create_test_db("mononokefilenodestest").unwrap()
 }
@@ -4,12 +4,14 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use std::usize;
 
 use asyncmemo::{Asyncmemo, Filler};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use failure::{Error, Result};
 use futures::{future, Future, IntoFuture};
 use futures_ext::{BoxFuture, BoxStream, FutureExt};
@@ -83,6 +85,18 @@ impl CachingFilenodes {
             keygen: KeyGen::new(key_prefix, MC_CODEVER, MC_SITEVER),
         }
     }
+
+    /// Save the cached filenodes to a file, so that they can be loaded with `load_snapshot`
+    /// after a restart. Returns the number of filenodes saved.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.save_snapshot_file(path)?)
+    }
+
+    /// Load the filenodes saved by `save_snapshot` into the cache, as far as they fit. Returns
+    /// the number of filenodes loaded.
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        Ok(self.cache.load_snapshot_file(path)?)
+    }
 }
 
 impl Filenodes for CachingFilenodes {
@@ -115,13 +129,7 @@ impl Filenodes for CachingFilenodes {
         path: &RepoPath,
         repo_id: &RepositoryId,
     ) -> BoxFuture<Vec<FilenodeInfo>, Error> {
-        let path_hash = PathHash({
-            let path = match path.mpath() {
-                Some(path) => path.to_vec(),
-                None => Vec::new(),
-            };
-            blake2_path_hash(&path).to_string()
-        });
+        let path_hash = get_path_hash(path);
 
         cloned!(self.filenodes, self.memcache, self.keygen, path, repo_id);
 
@@ -150,6 +158,75 @@ impl Filenodes for CachingFilenodes {
         })
             .boxify()
     }
+
+    fn get_all_filenodes_many(
+        &self,
+        paths: Vec<RepoPath>,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<HashMap<RepoPath, Vec<FilenodeInfo>>, Error> {
+        cloned!(self.filenodes, self.memcache, self.keygen);
+        let repo_id = *repo_id;
+
+        let from_memcache = paths.into_iter().map({
+            cloned!(memcache, keygen);
+            move |path| {
+                let path_hash = get_path_hash(&path);
+                get_all_filenodes_from_memcache(
+                    memcache.clone(),
+                    keygen.clone(),
+                    repo_id,
+                    path_hash.clone(),
+                ).then(move |from_memcache| Ok::<_, Error>((path, path_hash, from_memcache.ok())))
+            }
+        });
+
+        future::join_all(from_memcache)
+            .and_then(move |from_memcache| {
+                let mut found = HashMap::new();
+                let mut missing = HashMap::new();
+                for (path, path_hash, all_filenodes) in from_memcache {
+                    match all_filenodes {
+                        Some(all_filenodes) => {
+                            found.insert(path, all_filenodes);
+                        }
+                        None => {
+                            missing.insert(path, path_hash);
+                        }
+                    }
+                }
+                if missing.is_empty() {
+                    return future::ok(found).left_future();
+                }
+
+                filenodes
+                    .get_all_filenodes_many(missing.keys().cloned().collect(), &repo_id)
+                    .map(move |fetched| {
+                        for (path, all_filenodes) in fetched {
+                            if let Some(path_hash) = missing.remove(&path) {
+                                schedule_fill_all_filenodes_memcache(
+                                    &all_filenodes,
+                                    memcache.clone(),
+                                    keygen.clone(),
+                                    repo_id,
+                                    path_hash,
+                                );
+                            }
+                            found.insert(path, all_filenodes);
+                        }
+                        found
+                    })
+                    .right_future()
+            })
+            .boxify()
+    }
+}
+
+fn get_path_hash(path: &RepoPath) -> PathHash {
+    let path = match path.mpath() {
+        Some(path) => path.to_vec(),
+        None => Vec::new(),
+    };
+    PathHash(blake2_path_hash(&path).to_string())
 }
 
 fn get_mc_key_for_filenodes(
@@ -376,4 +453,27 @@ impl Filler for FilenodesFiller {
             })
             .boxify()
     }
+
+    // An entry is the repo id as a big-endian integer followed by the filenode as compact thrift,
+    // which has the rest of the key.
+    fn serialize_entry(
+        &self,
+        &(_, _, ref repo_id): &Self::Key,
+        info: &FilenodeInfo,
+    ) -> Option<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_i32::<BigEndian>(repo_id.id()).ok()?;
+        data.extend_from_slice(&compact_protocol::serialize(&info.clone().into_thrift()));
+        Some(data)
+    }
+
+    fn deserialize_entry(&self, data: &[u8]) -> Option<(Self::Key, FilenodeInfo)> {
+        if data.len() < 4 {
+            return None;
+        }
+        let repo_id = RepositoryId::new(BigEndian::read_i32(&data[..4]));
+        let info: thrift::FilenodeInfo = compact_protocol::deserialize(data[4..].to_vec()).ok()?;
+        let info = FilenodeInfo::from_thrift(info).ok()?;
+        Some(((info.path.clone(), info.filenode, repo_id), info))
+    }
 }
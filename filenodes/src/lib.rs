@@ -7,6 +7,7 @@
 #![deny(warnings)]
 
 extern crate asyncmemo;
+extern crate byteorder;
 #[macro_use]
 extern crate cloned;
 extern crate failure_ext as failure;
@@ -28,6 +29,9 @@ extern crate futures_ext;
 extern crate mercurial_types;
 
 mod caching;
+mod mem_writes;
+
+use std::collections::HashMap;
 
 use failure::{Error, Result};
 use futures_ext::{BoxFuture, BoxStream};
@@ -36,6 +40,7 @@ use mononoke_types::hash;
 use quickcheck::{Arbitrary, Gen};
 
 pub use caching::CachingFilenodes;
+pub use mem_writes::MemWritesFilenodes;
 
 mod thrift {
     pub use filenodes_if::*;
@@ -135,6 +140,14 @@ pub trait Filenodes: Send + Sync {
         path: &RepoPath,
         repo_id: &RepositoryId,
     ) -> BoxFuture<Vec<FilenodeInfo>, Error>;
+
+    /// Batched version of `get_all_filenodes`. Every path is in the result, with no filenodes if
+    /// it has none.
+    fn get_all_filenodes_many(
+        &self,
+        paths: Vec<RepoPath>,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<HashMap<RepoPath, Vec<FilenodeInfo>>, Error>;
 }
 
 #[cfg(test)]
@@ -0,0 +1,125 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use failure::Error;
+use futures::{future, Future, Stream};
+use futures_ext::{BoxFuture, BoxStream, FutureExt};
+use mercurial_types::{HgFileNodeId, RepoPath, RepositoryId};
+
+use {FilenodeInfo, Filenodes};
+
+/// A filenodes store that reads from the underlying store but keeps the filenodes it adds in
+/// memory.
+#[derive(Clone)]
+pub struct MemWritesFilenodes {
+    inner: Arc<Filenodes>,
+    // The filenodes of each path, in insertion order.
+    mem: Arc<Mutex<HashMap<(RepositoryId, RepoPath), Vec<FilenodeInfo>>>>,
+}
+
+impl MemWritesFilenodes {
+    pub fn new(inner: Arc<Filenodes>) -> Self {
+        Self {
+            inner,
+            mem: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+fn add_mem_filenodes(filenodes: &mut Vec<FilenodeInfo>, mem: Option<&Vec<FilenodeInfo>>) {
+    for info in mem.into_iter().flat_map(|infos| infos.iter()) {
+        if !filenodes
+            .iter()
+            .any(|filenode| filenode.filenode == info.filenode)
+        {
+            filenodes.push(info.clone());
+        }
+    }
+}
+
+impl Filenodes for MemWritesFilenodes {
+    fn add_filenodes(
+        &self,
+        info: BoxStream<FilenodeInfo, Error>,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<(), Error> {
+        let repo_id = *repo_id;
+        let mem = self.mem.clone();
+        info.for_each(move |info| {
+            let mut mem = mem.lock().expect("lock poisoned");
+            let filenodes = mem.entry((repo_id, info.path.clone()))
+                .or_insert_with(Vec::new);
+            // Like the SQL stores, ignore filenodes that were already added.
+            if !filenodes
+                .iter()
+                .any(|filenode| filenode.filenode == info.filenode)
+            {
+                filenodes.push(info);
+            }
+            Ok(())
+        }).boxify()
+    }
+
+    fn get_filenode(
+        &self,
+        path: &RepoPath,
+        filenode: &HgFileNodeId,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<Option<FilenodeInfo>, Error> {
+        let info = {
+            let mem = self.mem.lock().expect("lock poisoned");
+            mem.get(&(*repo_id, path.clone())).and_then(|filenodes| {
+                filenodes
+                    .iter()
+                    .find(|info| info.filenode == *filenode)
+                    .cloned()
+            })
+        };
+        match info {
+            Some(info) => future::ok(Some(info)).boxify(),
+            None => self.inner.get_filenode(path, filenode, repo_id),
+        }
+    }
+
+    fn get_all_filenodes(
+        &self,
+        path: &RepoPath,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<Vec<FilenodeInfo>, Error> {
+        let key = (*repo_id, path.clone());
+        let mem = self.mem.clone();
+        self.inner
+            .get_all_filenodes(path, repo_id)
+            .map(move |mut filenodes| {
+                let mem = mem.lock().expect("lock poisoned");
+                add_mem_filenodes(&mut filenodes, mem.get(&key));
+                filenodes
+            })
+            .boxify()
+    }
+
+    fn get_all_filenodes_many(
+        &self,
+        paths: Vec<RepoPath>,
+        repo_id: &RepositoryId,
+    ) -> BoxFuture<HashMap<RepoPath, Vec<FilenodeInfo>>, Error> {
+        let repo_id = *repo_id;
+        let mem = self.mem.clone();
+        self.inner
+            .get_all_filenodes_many(paths, &repo_id)
+            .map(move |mut all_filenodes| {
+                let mem = mem.lock().expect("lock poisoned");
+                for (path, filenodes) in all_filenodes.iter_mut() {
+                    add_mem_filenodes(filenodes, mem.get(&(repo_id, path.clone())));
+                }
+                all_filenodes
+            })
+            .boxify()
+    }
+}
@@ -29,7 +29,7 @@ use tokio::timer::{Deadline, DeadlineError};
 use tokio_io::AsyncWrite;
 use tokio_io::codec::{Decoder, Encoder};
 
-use std::{io as std_io, fmt::Debug, time::{Duration, Instant}};
+use std::{io as std_io, mem, fmt::Debug, time::{Duration, Instant}};
 
 mod bytes_stream;
 mod futures_ordered;
@@ -261,6 +261,16 @@ pub trait StreamExt: Stream {
         Enumerate::new(self)
     }
 
+    /// Like `chunks`, but a chunk is returned as soon as the stream has no more items ready,
+    /// instead of waiting for `capacity` items. This batches the items without delaying them,
+    /// e.g. for requests of a client that waits for the responses before sending more.
+    fn ready_chunks(self, capacity: usize) -> ReadyChunks<Self>
+    where
+        Self: Sized,
+    {
+        ReadyChunks::new(self, capacity)
+    }
+
     /// Creates a stream wrapper and a future. The future will resolve into the wrapped stream when
     /// the stream wrapper returns None. It uses ConservativeReceiver to ensure that deadlocks are
     /// easily caught when one tries to poll on the receiver before consuming the stream.
@@ -377,6 +387,63 @@ impl<In: Stream> Stream for Enumerate<In> {
     }
 }
 
+pub struct ReadyChunks<In: Stream> {
+    inner: In,
+    items: Vec<In::Item>,
+    err: Option<In::Error>,
+    done: bool,
+    capacity: usize,
+}
+
+impl<In: Stream> ReadyChunks<In> {
+    fn new(inner: In, capacity: usize) -> Self {
+        assert!(capacity > 0, "chunks must hold at least one item");
+        ReadyChunks {
+            inner,
+            items: Vec::with_capacity(capacity),
+            err: None,
+            done: false,
+            capacity,
+        }
+    }
+}
+
+impl<In: Stream> Stream for ReadyChunks<In> {
+    type Item = Vec<In::Item>;
+    type Error = In::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // An error that came after some items is returned after them.
+        if let Some(err) = self.err.take() {
+            return Err(err);
+        }
+
+        while !self.done && self.items.len() < self.capacity {
+            match self.inner.poll() {
+                Ok(Async::Ready(Some(item))) => self.items.push(item),
+                Ok(Async::Ready(None)) => self.done = true,
+                Ok(Async::NotReady) => break,
+                Err(err) => {
+                    if self.items.is_empty() {
+                        return Err(err);
+                    }
+                    self.err = Some(err);
+                    break;
+                }
+            }
+        }
+
+        if !self.items.is_empty() {
+            let items = mem::replace(&mut self.items, Vec::with_capacity(self.capacity));
+            Ok(Async::Ready(Some(items)))
+        } else if self.done {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
 /// This is a wrapper around oneshot::Receiver that will return error when the receiver was polled
 /// and the result was not ready. This is a very strict way of preventing deadlocks in code when
 /// receiver is polled before the sender has send the result
@@ -693,6 +760,27 @@ mod test {
         assert_eq!(v, Ok(vec![(0, "hello"), (1, "there"), (2, "world")]));
     }
 
+    #[test]
+    fn ready_chunks() {
+        let s = stream::iter_ok::<_, ()>(vec![1, 2, 3, 4, 5]);
+        let v = s.ready_chunks(2).collect().wait();
+        assert_eq!(v, Ok(vec![vec![1, 2], vec![3, 4], vec![5]]));
+
+        // Items that are ready are returned without waiting for the chunk to be full.
+        let (tx, rx) = mpsc::unbounded::<i32>();
+        tx.unbounded_send(1).unwrap();
+        tx.unbounded_send(2).unwrap();
+        let (chunk, s) = rx.ready_chunks(10)
+            .into_future()
+            .wait()
+            .map_err(|(err, _)| err)
+            .unwrap();
+        assert_eq!(chunk, Some(vec![1, 2]));
+        tx.unbounded_send(3).unwrap();
+        drop(tx);
+        assert_eq!(s.collect().wait(), Ok(vec![vec![3]]));
+    }
+
     #[test]
     fn empty() {
         let mut s = stream::empty::<(), ()>();
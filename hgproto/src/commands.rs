@@ -24,6 +24,7 @@ use futures::sync::oneshot;
 
 use HgNodeHash;
 use dechunker::Dechunker;
+use record::{PayloadHasher, RequestRecord};
 use futures_ext::{BoxFuture, BoxStream, BytesStream, FutureExt, StreamExt};
 use mercurial_bundles::Bundle2Item;
 use mercurial_bundles::bundle2::{self, Bundle2Stream, StreamEvent};
@@ -49,11 +50,13 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
 
     /// Handles a single command (not batched) by returning a stream of responses and a future
     /// resolving to the remainder unused input available only after the entire stream of responses
-    /// have been consumed.
+    /// have been consumed. If the request is being recorded, its streamed arguments are added to
+    /// `record`.
     pub fn handle<S>(
         &self,
         req: SingleRequest,
         instream: BytesStream<S>,
+        record: Option<RequestRecord>,
     ) -> (
         BoxStream<SingleResponse, Error>,
         BoxFuture<BytesStream<S>, Error>,
@@ -145,6 +148,20 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
                     .boxify(),
                 ok(instream).boxify(),
             ),
+            SingleRequest::Pushkey {
+                namespace,
+                key,
+                old,
+                new,
+            } => (
+                hgcmds
+                    .pushkey(namespace, key, old, new)
+                    .map(SingleResponse::Pushkey)
+                    .map_err(self::Error::into)
+                    .into_stream()
+                    .boxify(),
+                ok(instream).boxify(),
+            ),
             SingleRequest::Known { nodes } => (
                 hgcmds
                     .known(nodes)
@@ -155,12 +172,12 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
                 ok(instream).boxify(),
             ),
             SingleRequest::Unbundle { heads } => {
-                let bundle2stream =
-                    Bundle2Stream::new(Dechunker::new(instream), self.logger.new(o!()));
+                let payload = PayloadHasher::new(Dechunker::new(instream), record.is_some());
+                let bundle2stream = Bundle2Stream::new(payload, self.logger.new(o!()));
                 let (bundle2stream, remainder) = extract_remainder_from_bundle2(bundle2stream);
 
                 let remainder = remainder
-                    .then(|rest| {
+                    .then(move |rest| {
                         let (bytes, remainder) = match rest {
                             Err(e) => return Either::A(err(e)),
                             Ok(rest) => rest,
@@ -170,6 +187,10 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
                                 String::from_utf8_lossy(bytes.as_ref()).into_owned(),
                             ).into()))
                         } else {
+                            let (remainder, payload) = remainder.into_parts();
+                            if let (Some(record), Some((sha1, size))) = (record, payload) {
+                                record.set_payload(sha1, size);
+                            }
                             Either::B(remainder.check_is_done().from_err())
                         }
                     })
@@ -207,6 +228,12 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
             ),
             SingleRequest::Getfiles => {
                 let (reqs, instream) = decode_getfiles_arg_stream(instream);
+                let reqs = match record {
+                    Some(record) => reqs.inspect(move |&(ref node, ref path)| {
+                        record.add_getfiles_arg(node, path)
+                    }).boxify(),
+                    None => reqs,
+                };
                 (
                     hgcmds
                         .getfiles(reqs)
@@ -494,6 +521,17 @@ pub trait HgCommands {
         unimplemented("lookup")
     }
 
+    // @wireprotocommand('pushkey', 'namespace key old new')
+    fn pushkey(
+        &self,
+        _namespace: String,
+        _key: String,
+        _old: String,
+        _new: String,
+    ) -> HgCommandRes<bool> {
+        unimplemented("pushkey")
+    }
+
     // @wireprotocommand('known', 'nodes *')
     fn known(&self, _nodes: Vec<HgNodeHash>) -> HgCommandRes<Vec<bool>> {
         unimplemented("known")
@@ -554,7 +592,7 @@ mod test {
         let logger = Logger::root(Discard, o!());
         let handler = HgCommandHandler::new(Dummy, logger);
 
-        let (r, _) = handler.handle(SingleRequest::Hello, BytesStream::new(stream::empty()));
+        let (r, _) = handler.handle(SingleRequest::Hello, BytesStream::new(stream::empty()), None);
         let r = assert_one(r.wait().collect::<Vec<_>>());
         println!("hello r = {:?}", r);
 
@@ -572,7 +610,7 @@ mod test {
         let logger = Logger::root(Discard, o!());
         let handler = HgCommandHandler::new(Dummy, logger);
 
-        let (r, _) = handler.handle(SingleRequest::Heads, BytesStream::new(stream::empty()));
+        let (r, _) = handler.handle(SingleRequest::Heads, BytesStream::new(stream::empty()), None);
         let r = assert_one(r.wait().collect::<Vec<_>>());
         println!("heads r = {:?}", r);
 
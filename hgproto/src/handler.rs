@@ -18,6 +18,7 @@ use slog::{self, Logger};
 
 use {HgCommands, Request, Response};
 use commands::HgCommandHandler;
+use record::{RecordResponse, RequestRecord, RequestRecorder};
 
 use errors::*;
 
@@ -37,6 +38,7 @@ struct HgProtoHandlerInner<H, Dec, Enc> {
     respenc: Enc,
     _logger: Logger,
     wireproto_calls: Arc<Mutex<Vec<String>>>,
+    recorder: Option<Arc<RequestRecorder>>,
 }
 
 impl HgProtoHandler {
@@ -47,6 +49,7 @@ impl HgProtoHandler {
         respenc: Enc,
         logger: L,
         wireproto_calls: Arc<Mutex<Vec<String>>>,
+        recorder: Option<RequestRecorder>,
     ) -> Self
     where
         In: Stream<Item = Bytes, Error = io::Error> + Send + 'static,
@@ -68,6 +71,7 @@ impl HgProtoHandler {
             respenc,
             _logger: logger,
             wireproto_calls,
+            recorder: recorder.map(Arc::new),
         });
 
         HgProtoHandler {
@@ -127,15 +131,17 @@ where
                                 ).into())
                             }),
                             Some(req) => {
+                                let record = handler
+                                    .recorder
+                                    .as_ref()
+                                    .map(|recorder| RequestRecord::start(recorder, &req));
                                 let (resps, remainder) =
-                                    handle_request(req, remainder, handler.clone());
+                                    handle_request(req, remainder, handler.clone(), record.clone());
+                                let resps = resps
+                                    .map(move |resp| handler.respenc.encode(resp))
+                                    .flatten();
                                 Either::B(ok((
-                                    Some(
-                                        resps
-                                            .map(move |resp| handler.respenc.encode(resp))
-                                            .flatten()
-                                            .boxify(),
-                                    ),
+                                    Some(RecordResponse::new(resps, record).boxify()),
                                     Some(remainder),
                                 )))
                             }
@@ -159,6 +165,7 @@ fn handle_request<In, H, Dec, Enc>(
     req: Request,
     input: BytesStream<In>,
     handler: Arc<HgProtoHandlerInner<H, Dec, Enc>>,
+    record: Option<RequestRecord>,
 ) -> (
     BoxStream<Response, Error>,
     BoxFuture<BytesStream<In>, Error>,
@@ -184,8 +191,10 @@ where
                     }
                     Some(req) => Some(input.map({
                         let handler = handler.clone();
+                        let record = record.clone();
                         move |input| {
-                            let (resps, remainder) = handler.commands_handler.handle(req, input);
+                            let (resps, remainder) =
+                                handler.commands_handler.handle(req, input, record);
                             (resps, (reqs, remainder, send))
                         }
                     })),
@@ -200,7 +209,7 @@ where
             )
         }
         Request::Single(req) => {
-            let (resps, remainder) = handler.commands_handler.handle(req, input);
+            let (resps, remainder) = handler.commands_handler.handle(req, input, record);
             (resps.map(Response::Single).boxify(), remainder)
         }
     }
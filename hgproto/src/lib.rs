@@ -30,6 +30,9 @@ extern crate failure_ext as failure;
 extern crate maplit;
 #[macro_use]
 extern crate nom;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 extern crate futures_ext;
 extern crate mercurial;
@@ -57,6 +60,7 @@ mod dechunker;
 mod errors;
 mod handler;
 mod commands;
+mod record;
 pub mod sshproto;
 
 const MAX_NODES_TO_LOG: usize = 5;
@@ -100,6 +104,12 @@ pub enum SingleRequest {
     Lookup {
         key: String,
     },
+    Pushkey {
+        namespace: String,
+        key: String,
+        old: String,
+        new: String,
+    },
     Known {
         nodes: Vec<HgNodeHash>,
     },
@@ -122,6 +132,7 @@ impl SingleRequest {
             &SingleRequest::Hello => "hello",
             &SingleRequest::Listkeys { .. } => "listkeys",
             &SingleRequest::Lookup { .. } => "lookup",
+            &SingleRequest::Pushkey { .. } => "pushkey",
             &SingleRequest::Known { .. } => "known",
             &SingleRequest::Unbundle { .. } => "unbundle",
             &SingleRequest::Gettreepack(_) => "gettreepack",
@@ -138,6 +149,12 @@ pub struct GetbundleArgs {
     pub common: Vec<HgNodeHash>,
     pub bundlecaps: Vec<Vec<u8>>,
     pub listkeys: Vec<Vec<u8>>,
+    /// Whether the client asked for the obsolescence markers of the pulled changesets.
+    pub obsmarkers: bool,
+    /// Narrow clone patterns of the paths the client wants (f.e. `path:foo/bar`).
+    pub includepats: Vec<Vec<u8>>,
+    /// Narrow clone patterns of the paths the client doesn't want.
+    pub excludepats: Vec<Vec<u8>>,
 }
 
 impl Debug for GetbundleArgs {
@@ -150,6 +167,14 @@ impl Debug for GetbundleArgs {
             .iter()
             .map(|s| String::from_utf8_lossy(&s))
             .collect();
+        let includepats: Vec<_> = self.includepats
+            .iter()
+            .map(|s| String::from_utf8_lossy(&s))
+            .collect();
+        let excludepats: Vec<_> = self.excludepats
+            .iter()
+            .map(|s| String::from_utf8_lossy(&s))
+            .collect();
         let heads: Vec<_> = self.heads.iter().take(MAX_NODES_TO_LOG).collect();
         let common: Vec<_> = self.common.iter().take(MAX_NODES_TO_LOG).collect();
         fmt.debug_struct("GetbundleArgs")
@@ -157,6 +182,9 @@ impl Debug for GetbundleArgs {
             .field("common", &common)
             .field("bundlecaps", &bcaps)
             .field("listkeys", &listkeys)
+            .field("obsmarkers", &self.obsmarkers)
+            .field("includepats", &includepats)
+            .field("excludepats", &excludepats)
             .finish()
     }
 }
@@ -175,6 +203,10 @@ pub struct GettreepackArgs {
     ///  The fullpath (not relative path) of directories underneath
     /// the rootdir that should be sent.
     pub directories: Vec<Bytes>,
+    /// Narrow clone patterns of the paths the client wants. Trees outside of them aren't sent.
+    pub includepats: Vec<Vec<u8>>,
+    /// Narrow clone patterns of the paths the client doesn't want.
+    pub excludepats: Vec<Vec<u8>>,
 }
 
 #[derive(Debug)]
@@ -194,6 +226,7 @@ pub enum SingleResponse {
     Hello(HashMap<String, Vec<String>>),
     Listkeys(HashMap<Vec<u8>, Vec<u8>>),
     Lookup(Bytes),
+    Pushkey(bool),
     Known(Vec<bool>),
     ReadyForStream,
     Unbundle(Bytes),
@@ -219,3 +252,4 @@ impl SingleResponse {
 pub use commands::{HgCommandRes, HgCommands};
 pub use errors::{Error, ErrorKind, Result};
 pub use handler::HgProtoHandler;
+pub use record::{RecordedRequest, RequestRecorder};
@@ -0,0 +1,379 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Recording of wire protocol requests.
+//!
+//! When recording is enabled for a connection, every request received on it is written to a file,
+//! one JSON object per line. Besides the request itself the record contains the timing of the
+//! request and hashes of the payload and of the response, so that a recording can be replayed
+//! later against another repo and the latencies and responses can be compared.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::{Async, Poll, Stream};
+use serde_json;
+use slog::Logger;
+use tokio_io::AsyncRead;
+
+use mercurial_types::{HgNodeHash, MPath};
+use mercurial_types::hash::{Context, Sha1};
+
+use {Request, SingleRequest};
+use sshproto::request::encode_request;
+
+use errors::*;
+
+/// A single recorded request.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// Names of the commands of the request. Batched requests have more than one command.
+    pub commands: Vec<String>,
+    /// The request in the ssh protocol format. The streamed arguments of `getfiles` are included,
+    /// the bundle sent with `unbundle` is not.
+    pub request: Vec<u8>,
+    /// Hex sha1 of the bundle sent with `unbundle`.
+    pub payload_sha1: Option<String>,
+    /// Size of the bundle sent with `unbundle`.
+    pub payload_size: Option<u64>,
+    /// When the request was received, in milliseconds since the start of the connection.
+    pub start_ms: u64,
+    /// Time it took to send the full response, in milliseconds.
+    pub duration_ms: u64,
+    /// Hex sha1 of the response, as sent over the wire.
+    pub response_sha1: String,
+    /// Size of the response, as sent over the wire.
+    pub response_size: u64,
+    /// Error that the request failed with, if any.
+    pub error: Option<String>,
+}
+
+impl RecordedRequest {
+    /// Read all the requests recorded in a file.
+    pub fn read_all<P: AsRef<Path>>(path: P) -> Result<Vec<RecordedRequest>> {
+        let file = File::open(path)?;
+        BufReader::new(file)
+            .lines()
+            .filter(|line| match line {
+                Ok(line) => !line.is_empty(),
+                Err(_) => true,
+            })
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+}
+
+/// Records all the requests received on a single connection.
+///
+/// The records are written to the file by a dedicated thread, so that handling a request never
+/// waits on the disk. The thread exits once the recorder is dropped and the remaining records are
+/// written.
+pub struct RequestRecorder {
+    start: Instant,
+    sender: Mutex<mpsc::Sender<RecordedRequest>>,
+}
+
+impl RequestRecorder {
+    pub fn new<P: AsRef<Path>>(path: P, logger: Logger) -> Result<Self> {
+        let file = File::create(path)?;
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("request_recorder".to_owned())
+            .spawn(move || write_records(BufWriter::new(file), receiver, logger))?;
+        Ok(RequestRecorder {
+            start: Instant::now(),
+            sender: Mutex::new(sender),
+        })
+    }
+
+    fn write(&self, record: RecordedRequest) {
+        let sender = self.sender.lock().expect("lock poisoned");
+        // The writer thread only stops early if it panicked, in which case the record is lost.
+        let _ = sender.send(record);
+    }
+}
+
+/// Writes the records received on `receiver` to `out` until the channel is closed.
+fn write_records<W: Write>(
+    mut out: W,
+    receiver: mpsc::Receiver<RecordedRequest>,
+    logger: Logger,
+) {
+    for record in receiver {
+        let res = (|| -> Result<()> {
+            serde_json::to_writer(&mut out, &record)?;
+            out.write_all(b"\n")?;
+            Ok(())
+        })();
+        if let Err(err) = res {
+            warn!(logger, "failed to record request: {}", err);
+        }
+    }
+
+    if let Err(err) = out.flush() {
+        warn!(logger, "failed to record requests: {}", err);
+    }
+}
+
+fn as_millis(duration: ::std::time::Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+struct RecordState {
+    recorder: Arc<RequestRecorder>,
+    record: RecordedRequest,
+    started: Instant,
+    streams_getfiles: bool,
+    getfiles_args: Vec<u8>,
+    // None once the record was written
+    response: Option<Context>,
+}
+
+/// The record of a request that is being handled.
+#[derive(Clone)]
+pub(crate) struct RequestRecord {
+    inner: Arc<Mutex<RecordState>>,
+}
+
+impl RequestRecord {
+    /// Start recording a request that was just received.
+    pub(crate) fn start(recorder: &Arc<RequestRecorder>, req: &Request) -> Self {
+        let commands = match req {
+            &Request::Batch(ref reqs) => reqs.iter().map(|req| req.name().to_string()).collect(),
+            &Request::Single(ref req) => vec![req.name().to_string()],
+        };
+        let streams_getfiles = match req {
+            &Request::Single(SingleRequest::Getfiles) => true,
+            _ => false,
+        };
+
+        let now = Instant::now();
+        let record = RecordedRequest {
+            commands,
+            request: encode_request(req).to_vec(),
+            start_ms: as_millis(now - recorder.start),
+            ..Default::default()
+        };
+
+        RequestRecord {
+            inner: Arc::new(Mutex::new(RecordState {
+                recorder: recorder.clone(),
+                record,
+                started: now,
+                streams_getfiles,
+                getfiles_args: Vec::new(),
+                response: Some(Context::new()),
+            })),
+        }
+    }
+
+    pub(crate) fn add_getfiles_arg(&self, node: &HgNodeHash, path: &MPath) {
+        let mut state = self.inner.lock().expect("lock poisoned");
+        state
+            .getfiles_args
+            .extend_from_slice(node.to_hex().as_bytes());
+        state.getfiles_args.extend(path.to_vec());
+        state.getfiles_args.push(b'\n');
+    }
+
+    pub(crate) fn set_payload(&self, sha1: Sha1, size: u64) {
+        let mut state = self.inner.lock().expect("lock poisoned");
+        state.record.payload_sha1 = Some(sha1.to_hex().to_string());
+        state.record.payload_size = Some(size);
+    }
+
+    fn add_response(&self, bytes: &Bytes) {
+        let mut state = self.inner.lock().expect("lock poisoned");
+        state.record.response_size += bytes.len() as u64;
+        if let Some(ref mut response) = state.response {
+            response.update(bytes);
+        }
+    }
+
+    fn finish(&self, error: Option<&Error>) {
+        let mut state = self.inner.lock().expect("lock poisoned");
+        let response = match state.response.take() {
+            Some(response) => response,
+            None => return,
+        };
+
+        state.record.duration_ms = as_millis(state.started.elapsed());
+        state.record.response_sha1 = response.finish().to_hex().to_string();
+        state.record.error = error.map(|err| format!("{}", err));
+        if state.streams_getfiles {
+            let mut args = state.getfiles_args.clone();
+            args.push(b'\n');
+            state.record.request.extend(args);
+        }
+        state.recorder.write(state.record.clone());
+    }
+}
+
+/// Stream of the encoded responses to a request, that completes its record once the response
+/// was fully sent.
+pub(crate) struct RecordResponse<S> {
+    inner: S,
+    record: Option<RequestRecord>,
+}
+
+impl<S> RecordResponse<S> {
+    pub(crate) fn new(inner: S, record: Option<RequestRecord>) -> Self {
+        RecordResponse { inner, record }
+    }
+}
+
+impl<S> Stream for RecordResponse<S>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let res = self.inner.poll();
+        if let Some(ref record) = self.record {
+            match res {
+                Ok(Async::Ready(Some(ref bytes))) => record.add_response(bytes),
+                Ok(Async::Ready(None)) => record.finish(None),
+                Err(ref err) => record.finish(Some(err)),
+                Ok(Async::NotReady) => {}
+            }
+        }
+        res
+    }
+}
+
+/// Reader that optionally computes the sha1 of everything read through it, used to record the
+/// bundle sent with `unbundle` without keeping it in memory.
+pub(crate) struct PayloadHasher<R> {
+    inner: R,
+    context: Option<Context>,
+    size: u64,
+}
+
+impl<R: BufRead> PayloadHasher<R> {
+    pub(crate) fn new(inner: R, enabled: bool) -> Self {
+        PayloadHasher {
+            inner,
+            context: if enabled { Some(Context::new()) } else { None },
+            size: 0,
+        }
+    }
+
+    /// Returns the inner reader and the sha1 and size of the data read, if hashing was enabled.
+    pub(crate) fn into_parts(self) -> (R, Option<(Sha1, u64)>) {
+        let size = self.size;
+        (
+            self.inner,
+            self.context.map(|context| (context.finish(), size)),
+        )
+    }
+}
+
+impl<R: BufRead> Read for PayloadHasher<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amt = self.inner.read(buf)?;
+        if let Some(ref mut context) = self.context {
+            context.update(&buf[..amt]);
+        }
+        self.size += amt as u64;
+        Ok(amt)
+    }
+}
+
+impl<R: BufRead> BufRead for PayloadHasher<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(ref mut context) = self.context {
+            // The data was already buffered by the preceding fill_buf, so this doesn't block.
+            if let Ok(buf) = self.inner.fill_buf() {
+                context.update(&buf[..cmp::min(amt, buf.len())]);
+            }
+        }
+        self.size += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+impl<R: AsyncRead + BufRead> AsyncRead for PayloadHasher<R> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use slog::Discard;
+
+    #[test]
+    fn test_write_records() {
+        let records = vec![
+            RecordedRequest {
+                commands: vec!["hello".to_string()],
+                request: b"hello\n".to_vec(),
+                ..Default::default()
+            },
+            RecordedRequest {
+                commands: vec!["between".to_string()],
+                request: b"between\n".to_vec(),
+                start_ms: 10,
+                ..Default::default()
+            },
+        ];
+
+        let (sender, receiver) = mpsc::channel();
+        for record in records.clone() {
+            sender.send(record).unwrap();
+        }
+        drop(sender);
+
+        let mut out = Vec::new();
+        write_records(&mut out, receiver, Logger::root(Discard, o!()));
+
+        let written: Vec<RecordedRequest> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(written, records);
+    }
+
+    #[test]
+    fn test_payload_hasher() {
+        let data = b"some bundle data".to_vec();
+        let mut hasher = PayloadHasher::new(Cursor::new(data.clone()), true);
+
+        let mut start = [0; 4];
+        hasher.read_exact(&mut start).unwrap();
+        let rest = hasher.fill_buf().unwrap().len();
+        hasher.consume(rest);
+
+        let (_, payload) = hasher.into_parts();
+        assert_eq!(
+            payload,
+            Some((Sha1::from(data.as_slice()), data.len() as u64))
+        );
+    }
+
+    #[test]
+    fn test_payload_hasher_disabled() {
+        let mut hasher = PayloadHasher::new(Cursor::new(b"data".to_vec()), false);
+        let mut out = Vec::new();
+        hasher.read_to_end(&mut out).unwrap();
+
+        let (_, payload) = hasher.into_parts();
+        assert_eq!(payload, None);
+    }
+}
@@ -218,6 +218,12 @@ fn commavalues(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
     }
 }
 
+/// A boolean, encoded as "0" or "1".
+named!(
+    boolean<bool>,
+    alt!(map!(tag!("0"), |_| false) | map!(tag!("1"), |_| true))
+);
+
 fn notsemi(b: u8) -> bool {
     b != b';'
 }
@@ -464,7 +470,6 @@ fn parse_with_params(
         | call!(parse_command, "getbundle", parse_params, 0+1,
             |kv| Ok(Getbundle(GetbundleArgs {
                 // Some params are currently ignored, like:
-                // - obsmarkers
                 // - cg
                 // - cbattempted
                 // If those params are needed, they should be parsed here.
@@ -472,6 +477,9 @@ fn parse_with_params(
                 common: parseval_default(&kv, "common", hashlist)?,
                 bundlecaps: parseval_default(&kv, "bundlecaps", commavalues)?,
                 listkeys: parseval_default(&kv, "listkeys", commavalues)?,
+                obsmarkers: parseval_default(&kv, "obsmarkers", boolean)?,
+                includepats: parseval_default(&kv, "includepats", commavalues)?,
+                excludepats: parseval_default(&kv, "excludepats", commavalues)?,
             })))
         | command!("heads", Heads, parse_params, {})
         | command!("hello", Hello, parse_params, {})
@@ -481,6 +489,12 @@ fn parse_with_params(
         | command!("lookup", Lookup, parse_params, {
               key => utf8_string_complete,
           })
+        | command!("pushkey", Pushkey, parse_params, {
+              namespace => ident_string,
+              key => utf8_string_complete,
+              old => utf8_string_complete,
+              new => utf8_string_complete,
+          })
         | command_star!("known", Known, parse_params, {
               nodes => hashlist,
           })
@@ -493,11 +507,180 @@ fn parse_with_params(
                 mfnodes: parseval(&kv, "mfnodes", hashlist)?,
                 basemfnodes: parseval(&kv, "basemfnodes", hashlist)?,
                 directories: parseval(&kv, "directories", gettreepack_directories)?,
+                includepats: parseval_default(&kv, "includepats", commavalues)?,
+                excludepats: parseval_default(&kv, "excludepats", commavalues)?,
             })))
         | command!("getfiles", Getfiles, parse_params, {})
     )
 }
 
+type Params = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Returns the parameters of a command, split into the ones that are always sent and the ones
+/// that are sent as part of a "*" parameter (`None` if the command doesn't take one).
+fn request_params(req: &SingleRequest) -> (Params, Option<Params>) {
+    use SingleRequest::*;
+
+    fn param<K: AsRef<[u8]>, V: Into<Vec<u8>>>(key: K, val: V) -> (Vec<u8>, Vec<u8>) {
+        (key.as_ref().to_vec(), val.into())
+    }
+
+    fn hashes(nodes: &[HgNodeHash]) -> String {
+        nodes
+            .iter()
+            .map(|node| node.to_hex().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn pattern_params(includepats: &[Vec<u8>], excludepats: &[Vec<u8>]) -> Params {
+        let mut params = vec![];
+        if !includepats.is_empty() {
+            params.push(param("includepats", includepats.join(&b',')));
+        }
+        if !excludepats.is_empty() {
+            params.push(param("excludepats", excludepats.join(&b',')));
+        }
+        params
+    }
+
+    match req {
+        &Between { ref pairs } => {
+            let pairs: Vec<_> = pairs
+                .iter()
+                .map(|&(ref a, ref b)| format!("{}-{}", a.to_hex(), b.to_hex()))
+                .collect();
+            (vec![param("pairs", pairs.join(" "))], None)
+        }
+        &Branchmap | &Capabilities | &Heads | &Hello | &Getfiles => (vec![], None),
+        &Debugwireargs {
+            ref one,
+            ref two,
+            ref all_args,
+        } => {
+            let mut rest: Params = all_args
+                .iter()
+                .filter(|&(key, _)| key.as_slice() != b"one" && key.as_slice() != b"two")
+                .map(|(key, val)| (key.clone(), val.clone()))
+                .collect();
+            rest.sort();
+            (
+                vec![param("one", one.clone()), param("two", two.clone())],
+                Some(rest),
+            )
+        }
+        &Getbundle(ref args) => {
+            let mut star = vec![];
+            if !args.heads.is_empty() {
+                star.push(param("heads", hashes(&args.heads)));
+            }
+            if !args.common.is_empty() {
+                star.push(param("common", hashes(&args.common)));
+            }
+            if !args.bundlecaps.is_empty() {
+                star.push(param("bundlecaps", args.bundlecaps.join(&b',')));
+            }
+            if !args.listkeys.is_empty() {
+                star.push(param("listkeys", args.listkeys.join(&b',')));
+            }
+            if args.obsmarkers {
+                star.push(param("obsmarkers", "1"));
+            }
+            star.extend(pattern_params(&args.includepats, &args.excludepats));
+            (vec![], Some(star))
+        }
+        &Listkeys { ref namespace } => (vec![param("namespace", namespace.as_str())], None),
+        &Lookup { ref key } => (vec![param("key", key.as_str())], None),
+        &Pushkey {
+            ref namespace,
+            ref key,
+            ref old,
+            ref new,
+        } => (
+            vec![
+                param("namespace", namespace.as_str()),
+                param("key", key.as_str()),
+                param("old", old.as_str()),
+                param("new", new.as_str()),
+            ],
+            None,
+        ),
+        &Known { ref nodes } => (vec![param("nodes", hashes(nodes))], Some(vec![])),
+        &Unbundle { ref heads } => (vec![param("heads", heads.join(" "))], None),
+        &Gettreepack(ref args) => {
+            let directories: Vec<_> = args.directories
+                .iter()
+                .map(|dir| batch::escape(dir))
+                .collect();
+            let mut star = vec![
+                param("rootdir", args.rootdir.to_vec()),
+                param("mfnodes", hashes(&args.mfnodes)),
+                param("basemfnodes", hashes(&args.basemfnodes)),
+                param("directories", directories.join(&b',')),
+            ];
+            star.extend(pattern_params(&args.includepats, &args.excludepats));
+            (vec![], Some(star))
+        }
+    }
+}
+
+fn encode_param(out: &mut Vec<u8>, key: &[u8], val: &[u8]) {
+    out.extend_from_slice(key);
+    out.extend_from_slice(format!(" {}\n", val.len()).as_bytes());
+    out.extend_from_slice(val);
+}
+
+fn encode_singlerequest(out: &mut Vec<u8>, req: &SingleRequest) {
+    let (params, star) = request_params(req);
+
+    out.extend_from_slice(req.name().as_bytes());
+    out.push(b'\n');
+    if let Some(star) = star {
+        out.extend_from_slice(format!("* {}\n", star.len()).as_bytes());
+        for (key, val) in star {
+            encode_param(out, &key, &val);
+        }
+    }
+    for (key, val) in params {
+        encode_param(out, &key, &val);
+    }
+}
+
+fn encode_batchcmd(req: &SingleRequest) -> Vec<u8> {
+    let (params, star) = request_params(req);
+    let params: Vec<_> = params
+        .into_iter()
+        .chain(star.unwrap_or_default())
+        .map(|(key, val)| {
+            let mut param = batch::escape(&Bytes::from(key));
+            param.push(b'=');
+            param.extend(batch::escape(&Bytes::from(val)));
+            param
+        })
+        .collect();
+
+    let mut cmd = req.name().as_bytes().to_vec();
+    cmd.push(b' ');
+    cmd.extend(params.join(&b','));
+    cmd
+}
+
+/// Encode a request in the ssh protocol format, so that `parse_request` parses it back into an
+/// equal request. Streamed arguments (f.e. the bundle of `unbundle`) are not part of the request
+/// and are not encoded.
+pub fn encode_request(req: &Request) -> Bytes {
+    let mut out = Vec::new();
+    match req {
+        &Request::Single(ref req) => encode_singlerequest(&mut out, req),
+        &Request::Batch(ref reqs) => {
+            let cmds: Vec<_> = reqs.iter().map(encode_batchcmd).collect();
+            out.extend_from_slice(b"batch\n* 0\n");
+            encode_param(&mut out, b"cmds", &cmds.join(&b';'));
+        }
+    }
+    Bytes::from(out)
+}
+
 /// Test individual combinators
 #[cfg(test)]
 mod test {
@@ -897,6 +1080,13 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_boolean() {
+        assert_eq!(boolean(b"0"), IResult::Done(&b""[..], false));
+        assert_eq!(boolean(b"1"), IResult::Done(&b""[..], true));
+        assert!(boolean(b"2").is_err());
+    }
+
     #[test]
     fn test_commavalues() {
         // Empty list
@@ -1155,13 +1345,16 @@ mod test_parse {
                 common: vec![],
                 bundlecaps: vec![],
                 listkeys: vec![],
+                obsmarkers: false,
+                includepats: vec![],
+                excludepats: vec![],
             })),
         );
 
         // with arguments
         let inp =
             "getbundle\n\
-             * 5\n\
+             * 6\n\
              heads 40\n\
              1111111111111111111111111111111111111111\
              common 81\n\
@@ -1170,6 +1363,8 @@ mod test_parse {
              cap1,CAP2,cap3\
              listkeys 9\n\
              key1,key2\
+             obsmarkers 1\n\
+             1\
              extra 5\n\
              extra";
         test_parse(
@@ -1179,6 +1374,31 @@ mod test_parse {
                 common: vec![hash_twos(), hash_threes()],
                 bundlecaps: vec![b"cap1".to_vec(), b"CAP2".to_vec(), b"cap3".to_vec()],
                 listkeys: vec![b"key1".to_vec(), b"key2".to_vec()],
+                obsmarkers: true,
+                includepats: vec![],
+                excludepats: vec![],
+            })),
+        );
+    }
+
+    #[test]
+    fn test_parse_getbundle_narrow() {
+        let inp = "getbundle\n\
+                   * 2\n\
+                   includepats 24\n\
+                   path:foo,rootfilesin:bar\
+                   excludepats 12\n\
+                   path:foo/baz";
+        test_parse(
+            inp,
+            Request::Single(SingleRequest::Getbundle(GetbundleArgs {
+                heads: vec![],
+                common: vec![],
+                bundlecaps: vec![],
+                listkeys: vec![],
+                obsmarkers: false,
+                includepats: vec![b"path:foo".to_vec(), b"rootfilesin:bar".to_vec()],
+                excludepats: vec![b"path:foo/baz".to_vec()],
             })),
         );
     }
@@ -1239,6 +1459,51 @@ mod test_parse {
         );
     }
 
+    #[test]
+    fn test_parse_pushkey() {
+        let inp = "pushkey\n\
+                   namespace 9\n\
+                   bookmarks\
+                   key 6\n\
+                   master\
+                   old 40\n\
+                   1111111111111111111111111111111111111111\
+                   new 40\n\
+                   2222222222222222222222222222222222222222";
+
+        test_parse(
+            inp,
+            Request::Single(SingleRequest::Pushkey {
+                namespace: "bookmarks".to_string(),
+                key: "master".to_string(),
+                old: "1111111111111111111111111111111111111111".to_string(),
+                new: "2222222222222222222222222222222222222222".to_string(),
+            }),
+        );
+    }
+
+    #[test]
+    fn test_parse_pushkey_create() {
+        let inp = "pushkey\n\
+                   namespace 9\n\
+                   bookmarks\
+                   key 6\n\
+                   master\
+                   old 0\n\
+                   new 40\n\
+                   2222222222222222222222222222222222222222";
+
+        test_parse(
+            inp,
+            Request::Single(SingleRequest::Pushkey {
+                namespace: "bookmarks".to_string(),
+                key: "master".to_string(),
+                old: "".to_string(),
+                new: "2222222222222222222222222222222222222222".to_string(),
+            }),
+        );
+    }
+
     #[test]
     fn test_parse_gettreepack() {
         let inp = "gettreepack\n\
@@ -1257,6 +1522,8 @@ mod test_parse {
                 mfnodes: vec![hash_ones()],
                 basemfnodes: vec![hash_ones()],
                 directories: vec![],
+                includepats: vec![],
+                excludepats: vec![],
             })),
         );
 
@@ -1279,6 +1546,8 @@ mod test_parse {
                 mfnodes: vec![hash_ones(), hash_twos()],
                 basemfnodes: vec![hash_twos(), hash_ones()],
                 directories: vec![Bytes::from(",".as_bytes()), Bytes::from(";".as_bytes())],
+                includepats: vec![],
+                excludepats: vec![],
             })),
         );
     }
@@ -1369,4 +1638,72 @@ mod test_parse {
         );
     }
 
+    fn test_roundtrip(req: Request) {
+        let encoded = encode_request(&req);
+        test_parse(encoded, req);
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        test_roundtrip(Request::Single(SingleRequest::Heads));
+        test_roundtrip(Request::Single(SingleRequest::Between {
+            pairs: vec![(hash_ones(), hash_twos()), (hash_threes(), hash_fours())],
+        }));
+        test_roundtrip(Request::Single(SingleRequest::Debugwireargs {
+            one: b"ONE".to_vec(),
+            two: b"TWO".to_vec(),
+            all_args: hashmap! {
+                b"one".to_vec() => b"ONE".to_vec(),
+                b"two".to_vec() => b"TWO".to_vec(),
+                b"three".to_vec() => b"THREE".to_vec(),
+            },
+        }));
+        test_roundtrip(Request::Single(SingleRequest::Getbundle(GetbundleArgs {
+            heads: vec![hash_ones()],
+            common: vec![hash_twos(), hash_threes()],
+            bundlecaps: vec![b"HG20".to_vec(), b"bundle2=HG20%0Achangegroup%3D02".to_vec()],
+            listkeys: vec![b"bookmarks".to_vec()],
+            obsmarkers: true,
+            includepats: vec![b"path:foo".to_vec(), b"rootfilesin:bar".to_vec()],
+            excludepats: vec![b"path:foo/baz".to_vec()],
+        })));
+        test_roundtrip(Request::Single(SingleRequest::Pushkey {
+            namespace: "bookmarks".to_string(),
+            key: "master".to_string(),
+            old: "".to_string(),
+            new: "1111111111111111111111111111111111111111".to_string(),
+        }));
+        test_roundtrip(Request::Single(SingleRequest::Known {
+            nodes: vec![hash_ones(), hash_twos()],
+        }));
+        test_roundtrip(Request::Single(SingleRequest::Gettreepack(GettreepackArgs {
+            rootdir: Bytes::from("dir"),
+            mfnodes: vec![hash_ones()],
+            basemfnodes: vec![],
+            directories: vec![Bytes::from("dir/a,b"), Bytes::from("dir/c")],
+            includepats: vec![],
+            excludepats: vec![b"path:dir/d".to_vec()],
+        })));
+    }
+
+    #[test]
+    fn test_encode_roundtrip_batch() {
+        test_roundtrip(Request::Batch(vec![
+            SingleRequest::Heads,
+            SingleRequest::Lookup {
+                key: "1234".to_string(),
+            },
+            SingleRequest::Known {
+                nodes: vec![hash_ones(), hash_twos()],
+            },
+            SingleRequest::Gettreepack(GettreepackArgs {
+                rootdir: Bytes::new(),
+                mfnodes: vec![hash_ones()],
+                basemfnodes: vec![hash_twos()],
+                directories: vec![Bytes::from("a,b;c=d:e")],
+                includepats: vec![b"path:a".to_vec()],
+                excludepats: vec![],
+            }),
+        ]));
+    }
 }
@@ -120,6 +120,12 @@ fn encode_cmd(response: &SingleResponse) -> Bytes {
 
         &Lookup(ref res) => res.clone(),
 
+        &Pushkey(ref res) => if *res {
+            Bytes::from(b"1\n".as_ref())
+        } else {
+            Bytes::from(b"0\n".as_ref())
+        },
+
         &Listkeys(ref res) => {
             let mut bytes = BytesMut::new();
             for (name, key) in res.iter() {
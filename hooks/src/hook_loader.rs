@@ -105,6 +105,12 @@ mod test {
                         hook_type: HookType::PerChangeset,
                     },
                 ]),
+                wireproto_recording_dir: None,
+                encryption_keyfile: None,
+                encryption_allow_plaintext: false,
+                disk_cache: None,
+                compression: None,
+                skiplist_index_blobstore_key: None,
             };
 
             let mut hm = hook_manager_blobrepo();
@@ -138,6 +144,12 @@ mod test {
                         hook_type: HookType::PerFile,
                     },
                 ]),
+                wireproto_recording_dir: None,
+                encryption_keyfile: None,
+                encryption_allow_plaintext: false,
+                disk_cache: None,
+                compression: None,
+                skiplist_index_blobstore_key: None,
             };
 
             let mut hm = hook_manager_blobrepo();
@@ -57,7 +57,6 @@ use failure::Error;
 use futures::{failed, finished, Future};
 use futures_ext::{BoxFuture, FutureExt};
 use mercurial_types::{Changeset, HgChangesetId, HgParents, MPath};
-use mononoke_types::FileContents;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::mem;
@@ -495,6 +494,10 @@ impl InMemoryFileContentStore {
     }
 }
 
+/// The largest file whose content hooks can look at. Contents are loaded whole in memory, so
+/// larger files are refused rather than fetched.
+const MAX_FILE_CONTENT_SIZE: u64 = 100 * 1024 * 1024;
+
 // TODO this can cache file content locally to prevent unnecessary lookup of changeset,
 // manifest and walk of manifest each time
 // It's likely that multiple hooks will want to bsee the same content for the same changeset
@@ -518,11 +521,10 @@ impl FileContentStore for BlobRepoFileContentStore {
             .and_then(move |opt| {
                 opt.ok_or(ErrorKind::NoFileContent(changesetid, path2.into()).into())
             })
-            .and_then(move |hash| repo2.get_file_content(&hash.into_nodehash()))
-            .and_then(|content| {
-                let FileContents::Bytes(bytes) = content;
-                Ok(bytes)
+            .and_then(move |hash| {
+                repo2.get_file_content_bounded(&hash.into_nodehash(), MAX_FILE_CONTENT_SIZE)
             })
+            .map(|content| content.into_bytes())
             .boxify()
     }
 }
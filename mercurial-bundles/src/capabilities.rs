@@ -41,6 +41,18 @@ impl Decoder for CapabilitiesUnpacker {
     }
 
     fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let caps = Capabilities::decode(buf)?;
+        buf.clear(); // all buf was consumed
+
+        Ok(Some(caps))
+    }
+}
+
+impl Capabilities {
+    /// Decode capabilities in the format described in `CapabilitiesUnpacker`. This is also the
+    /// format of the blob sent by clients as the `bundle2` entry of `getbundle` bundlecaps (once
+    /// that entry has been url decoded).
+    pub fn decode(buf: &[u8]) -> Result<Self> {
         let mut caps = HashMap::new();
         for kv in buf.split(|b| b == &b'\n') {
             let mut kv = kv.splitn(2, |b| b == &b'=');
@@ -63,8 +75,18 @@ impl Decoder for CapabilitiesUnpacker {
             caps.insert(key, values);
         }
 
-        buf.clear(); // all buf was consumed
+        Ok(Capabilities { caps })
+    }
+
+    /// Returns the values of capability `key`, or None if the capability is absent.
+    pub fn get(&self, key: &str) -> Option<&[String]> {
+        self.caps.get(key).map(|values| values.as_slice())
+    }
 
-        Ok(Some(Capabilities { caps }))
+    /// Returns true if capability `key` is present and includes `value`.
+    pub fn has_value(&self, key: &str, value: &str) -> bool {
+        self.get(key)
+            .map(|values| values.iter().any(|v| v == value))
+            .unwrap_or(false)
     }
 }
@@ -6,13 +6,48 @@
 
 use mercurial_types::{Delta, HgNodeHash, MPath};
 
+use errors::*;
+
 pub mod packer;
 pub mod unpacker;
 
+/// Changegroup versions that Mononoke knows how to encode and decode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum CgVersion {
+    /// Changegroup version 02: adds an explicit delta base to every chunk.
+    Cg2Version,
+    /// Changegroup version 03: adds revlog flags to every chunk and directory (tree) manifest
+    /// sections between the root manifest and the filelogs.
+    Cg3Version,
+}
+
+impl CgVersion {
+    pub fn from_bytes<T: AsRef<[u8]>>(version: T) -> Result<Self> {
+        match version.as_ref() {
+            b"02" => Ok(CgVersion::Cg2Version),
+            b"03" => Ok(CgVersion::Cg3Version),
+            bad => bail_err!(ErrorKind::CgDecode(format!(
+                "unsupported changegroup version {:?}",
+                String::from_utf8_lossy(bad)
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            &CgVersion::Cg2Version => "02",
+            &CgVersion::Cg3Version => "03",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Section {
     Changeset,
     Manifest,
+    /// A directory manifest, only present in changegroup version 03. The path doesn't include the
+    /// trailing '/' that Mercurial puts on the wire.
+    Treemanifest(MPath),
     Filelog(MPath),
 }
 
@@ -40,13 +75,16 @@ pub struct CgDeltaChunk {
     pub base: HgNodeHash,
     pub linknode: HgNodeHash,
     pub delta: Delta,
+    /// Revlog flags of this revision. Only sent over the wire in changegroup version 03, so this
+    /// is always `None` for version 02 changegroups.
+    pub flags: Option<u16>,
 }
 
 #[cfg(test)]
 mod test {
     use std::io::{self, Cursor};
 
-    use futures::{Future, Stream};
+    use futures::{stream, Future, Stream};
     use quickcheck::{QuickCheck, StdGen, TestResult};
     use quickcheck::rand;
     use slog::{Drain, Logger};
@@ -55,10 +93,11 @@ mod test {
     use tokio_codec::{FramedRead, FramedWrite};
 
     use futures_ext::StreamLayeredExt;
+    use mercurial_types_mocks::nodehash::{NULL_HASH, ONES_HASH, TWOS_HASH};
     use partial_io::{GenWouldBlock, PartialAsyncRead, PartialAsyncWrite, PartialWithErrors};
 
     use chunk::{ChunkDecoder, ChunkEncoder};
-    use quickcheck_types::Cg2PartSequence;
+    use quickcheck_types::{Cg2PartSequence, Cg3PartSequence};
 
     use super::*;
 
@@ -98,15 +137,82 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_roundtrip_cg3() {
+        // Directory manifests make version 03 sequences even bigger, so keep the size down.
+        let rng = StdGen::new(rand::thread_rng(), 30);
+        let mut quickcheck = QuickCheck::new().gen(rng).tests(50);
+        quickcheck.quickcheck(
+            roundtrip_cg3
+                as fn(
+                    Cg3PartSequence,
+                    PartialWithErrors<GenWouldBlock>,
+                    PartialWithErrors<GenWouldBlock>,
+                ) -> TestResult,
+        );
+    }
+
+    #[test]
+    fn test_cg2_rejects_treemanifests() {
+        let path = MPath::new("dir").unwrap();
+        let parts = vec![
+            Part::SectionEnd(Section::Changeset),
+            Part::SectionEnd(Section::Manifest),
+            Part::CgChunk(
+                Section::Treemanifest(path.clone()),
+                CgDeltaChunk {
+                    node: ONES_HASH,
+                    p1: NULL_HASH,
+                    p2: NULL_HASH,
+                    base: NULL_HASH,
+                    linknode: TWOS_HASH,
+                    delta: Delta::new_fulltext(&b"content"[..]),
+                    flags: None,
+                },
+            ),
+            Part::SectionEnd(Section::Treemanifest(path)),
+            Part::End,
+        ];
+        let packer = packer::CgPacker::new(
+            stream::iter_ok::<_, Error>(parts),
+            CgVersion::Cg2Version,
+        );
+        assert!(packer.collect().wait().is_err());
+    }
+
     fn roundtrip(
         seq: Cg2PartSequence,
         write_ops: PartialWithErrors<GenWouldBlock>,
         read_ops: PartialWithErrors<GenWouldBlock>,
     ) -> TestResult {
+        let parts = seq.to_stream().and_then(|x| x);
+        roundtrip_version(seq, parts, CgVersion::Cg2Version, write_ops, read_ops)
+    }
+
+    fn roundtrip_cg3(
+        seq: Cg3PartSequence,
+        write_ops: PartialWithErrors<GenWouldBlock>,
+        read_ops: PartialWithErrors<GenWouldBlock>,
+    ) -> TestResult {
+        let parts = seq.to_stream().and_then(|x| x);
+        roundtrip_version(seq, parts, CgVersion::Cg3Version, write_ops, read_ops)
+    }
+
+    fn roundtrip_version<Seq, S>(
+        seq: Seq,
+        parts: S,
+        version: CgVersion,
+        write_ops: PartialWithErrors<GenWouldBlock>,
+        read_ops: PartialWithErrors<GenWouldBlock>,
+    ) -> TestResult
+    where
+        Seq: PartialEq<[Part]> + Send + 'static,
+        S: Stream<Item = Part, Error = Error> + Send + 'static,
+    {
         // Encode this sequence.
         let cursor = Cursor::new(Vec::with_capacity(32 * 1024));
         let partial_write = PartialAsyncWrite::new(cursor, write_ops);
-        let packer = packer::Cg2Packer::new(seq.to_stream().and_then(|x| x));
+        let packer = packer::CgPacker::new(parts, version);
         let sink = FramedWrite::new(partial_write, ChunkEncoder);
 
         let fut = packer
@@ -123,7 +229,7 @@ mod test {
                     .map(|chunk| chunk.into_bytes().expect("expected normal chunk"));
 
                 let logger = make_root_logger();
-                let unpacker = unpacker::Cg2Unpacker::new(logger);
+                let unpacker = unpacker::CgUnpacker::new(logger, version);
                 let part_stream = chunks.decode(unpacker);
 
                 let parts = Vec::new();
@@ -15,23 +15,49 @@ use chunk::Chunk;
 use delta;
 use errors::*;
 
-use super::{CgDeltaChunk, Part, Section};
+use super::{CgDeltaChunk, CgVersion, Part, Section};
 
-pub struct Cg2Packer<S> {
+pub struct CgPacker<S> {
     delta_stream: S,
+    version: CgVersion,
     last_seen: Section,
+    // In changegroup version 03 the list of directory manifests is terminated by an empty chunk
+    // that sits between the root manifest and the filelogs. These track whether it still has to
+    // be written out.
+    seen_manifest_end: bool,
+    treemanifests_closed: bool,
 }
 
-impl<S> Cg2Packer<S> {
-    pub fn new(delta_stream: S) -> Self {
-        Cg2Packer {
+impl<S> CgPacker<S> {
+    pub fn new(delta_stream: S, version: CgVersion) -> Self {
+        CgPacker {
             delta_stream: delta_stream,
+            version: version,
             last_seen: Section::Changeset,
+            seen_manifest_end: false,
+            treemanifests_closed: false,
+        }
+    }
+
+    /// Returns true if the terminator of the directory manifests list has to be written before
+    /// the part that is about to be encoded.
+    fn needs_treemanifests_end(&mut self, part: &Part) -> bool {
+        if self.version != CgVersion::Cg3Version || self.treemanifests_closed
+            || !self.seen_manifest_end
+        {
+            return false;
+        }
+        match part {
+            &Part::CgChunk(Section::Filelog(_), _) | &Part::End => {
+                self.treemanifests_closed = true;
+                true
+            }
+            _ => false,
         }
     }
 }
 
-impl<S> Stream for Cg2Packer<S>
+impl<S> Stream for CgPacker<S>
 where
     S: Stream<Item = Part>,
     Error: From<S::Error>,
@@ -42,19 +68,47 @@ where
     fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
         use self::Part::*;
 
-        match try_ready!(self.delta_stream.poll()) {
-            None => Ok(Async::Ready(None)),
-            Some(CgChunk(section, delta_chunk)) => {
-                let mut builder = ChunkBuilder::new();
+        let part = match try_ready!(self.delta_stream.poll()) {
+            None => return Ok(Async::Ready(None)),
+            Some(part) => part,
+        };
+        let treemanifests_end = self.needs_treemanifests_end(&part);
+
+        match part {
+            CgChunk(section, delta_chunk) => {
+                if let Section::Treemanifest(_) = section {
+                    if self.version != CgVersion::Cg3Version {
+                        let msg = format!(
+                            "directory manifests are not supported in changegroup version {}",
+                            self.version.as_str()
+                        );
+                        bail_err!(ErrorKind::CgEncode(msg));
+                    }
+                }
+                let mut builder = ChunkBuilder::new(self.version);
+                if treemanifests_end {
+                    builder.encode_empty();
+                }
                 if self.last_seen != section {
                     builder.encode_section(&section)?;
                     self.last_seen = section;
                 }
-                builder.encode_delta_chunk(delta_chunk);
+                builder.encode_delta_chunk(delta_chunk)?;
                 Ok(Async::Ready(Some(builder.build()?)))
             }
-            Some(SectionEnd(_section)) => Ok(Async::Ready(Some(empty_cg_chunk()))),
-            Some(End) => Ok(Async::Ready(Some(empty_cg_chunk()))),
+            SectionEnd(section) => {
+                if section == Section::Manifest {
+                    self.seen_manifest_end = true;
+                }
+                Ok(Async::Ready(Some(empty_cg_chunk())))
+            }
+            End => {
+                if treemanifests_end {
+                    Ok(Async::Ready(Some(empty_cg_chunks(2))))
+                } else {
+                    Ok(Async::Ready(Some(empty_cg_chunk())))
+                }
+            }
         }
     }
 }
@@ -67,37 +121,72 @@ fn empty_cg_chunk() -> Chunk {
     Chunk::new(vec![0, 0, 0, 0]).expect("Chunk::new should not fail for a 4-byte chunk")
 }
 
+/// Produce `count` empty changegroup chunks packed together into a single chunk.
+fn empty_cg_chunks(count: usize) -> Chunk {
+    Chunk::new(vec![0; 4 * count]).expect("Chunk::new should not fail for empty chunks")
+}
+
 #[derive(Debug)]
 struct ChunkBuilder {
+    version: CgVersion,
     inner: Vec<u8>,
+    // Offset of the length field the header was written into. Anything before it (empty chunks,
+    // section headers) is already complete.
+    start_offset: usize,
     len_offset: usize,
 }
 
 impl ChunkBuilder {
-    pub fn new() -> Self {
+    pub fn new(version: CgVersion) -> Self {
         ChunkBuilder {
+            version,
             // Reserve four bytes in the beginning for the length.
             inner: vec![0, 0, 0, 0],
+            start_offset: 0,
             len_offset: 0,
         }
     }
 
+    /// Encode an empty changegroup chunk before anything else. This is used to terminate the
+    /// list of directory manifests in changegroup version 03.
+    pub fn encode_empty(&mut self) -> &mut Self {
+        assert_eq!(
+            self.inner.len(),
+            4,
+            "encode_empty must only be called once at the start"
+        );
+        // The four zero bytes already reserved become the empty chunk, reserve four more.
+        self.inner.put_slice(&[0, 0, 0, 0]);
+        self.start_offset = 4;
+        self.len_offset = 4;
+        self
+    }
+
     /// Encode the beginning of a section. This should always happen before any
     /// delta chunks are encoded.
     pub fn encode_section(&mut self, section: &Section) -> Result<&mut Self> {
         assert_eq!(
             self.inner.len(),
-            4,
+            self.start_offset + 4,
             "encode_section must only be called once at the start"
         );
         // Changeset and manifest sections are implicitly encoded, so we don't
         // need to do anything there.
-        // TODO: will need to encode tree manifests here as well
-        if let &Section::Filelog(ref f) = section {
-            let f_vec = f.to_vec();
+        let name = match section {
+            &Section::Filelog(ref f) => Some(f.to_vec()),
+            &Section::Treemanifest(ref d) => {
+                // Directory names are sent with a trailing '/'.
+                let mut d_vec = d.to_vec();
+                d_vec.push(b'/');
+                Some(d_vec)
+            }
+            &Section::Changeset | &Section::Manifest => None,
+        };
+        if let Some(name) = name {
             // Note that the filename length must include the four bytes for itself.
-            BigEndian::write_i32(&mut self.inner[0..], (f_vec.len() + 4) as i32);
-            self.inner.put_slice(f_vec.as_slice());
+            let start = self.start_offset;
+            BigEndian::write_i32(&mut self.inner[start..], (name.len() + 4) as i32);
+            self.inner.put_slice(name.as_slice());
             // Add four more bytes for the start of the section.
             self.len_offset = self.inner.len();
             self.inner.put_slice(&[0, 0, 0, 0]);
@@ -105,16 +194,24 @@ impl ChunkBuilder {
         Ok(self)
     }
 
-    pub fn encode_delta_chunk(&mut self, chunk: CgDeltaChunk) -> &mut Self {
+    pub fn encode_delta_chunk(&mut self, chunk: CgDeltaChunk) -> Result<&mut Self> {
         self.inner.put_slice(chunk.node.as_ref());
         self.inner.put_slice(chunk.p1.as_ref());
         self.inner.put_slice(chunk.p2.as_ref());
         self.inner.put_slice(chunk.base.as_ref());
         self.inner.put_slice(chunk.linknode.as_ref());
 
+        match self.version {
+            CgVersion::Cg2Version => if chunk.flags.unwrap_or(0) != 0 {
+                let msg = format!("revlog flags for {} can't be sent in changegroup 02", chunk.node);
+                bail_err!(ErrorKind::CgEncode(msg));
+            },
+            CgVersion::Cg3Version => self.inner.put_u16_be(chunk.flags.unwrap_or(0)),
+        }
+
         delta::encode_delta(&chunk.delta, &mut self.inner);
 
-        self
+        Ok(self)
     }
 
     pub fn build(self) -> Result<Chunk> {
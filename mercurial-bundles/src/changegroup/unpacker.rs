@@ -20,12 +20,13 @@ use delta;
 use errors::*;
 use utils::BytesExt;
 
-use super::{CgDeltaChunk, Part, Section};
+use super::{CgDeltaChunk, CgVersion, Part, Section};
 
 #[derive(Debug)]
-pub struct Cg2Unpacker {
+pub struct CgUnpacker {
     logger: slog::Logger,
     state: State,
+    version: CgVersion,
 }
 
 impl Part {
@@ -58,13 +59,15 @@ impl Part {
 // See the chunk header definition below for the first 100 bytes. The last 4 is
 // for the length field itself.
 const CHUNK_HEADER_LEN: usize = 20 + 20 + 20 + 20 + 20 + 4;
+// Changegroup version 03 adds 2 bytes of revlog flags to the chunk header.
+const CHUNK_HEADER3_LEN: usize = CHUNK_HEADER_LEN + 2;
 
-impl Decoder for Cg2Unpacker {
+impl Decoder for CgUnpacker {
     type Item = Part;
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
-        match Self::decode_next(buf, self.state.take()) {
+        match Self::decode_next(buf, self.state.take(), self.version) {
             Err(e) => {
                 self.state = State::Invalid;
                 Err(e)
@@ -90,14 +93,14 @@ impl Decoder for Cg2Unpacker {
                          buffer. State: {:?}, First 128 bytes: {:?}",
                         len, self.state, bytes,
                     );
-                    bail_err!(ErrorKind::Cg2Decode(msg));
+                    bail_err!(ErrorKind::CgDecode(msg));
                 }
                 if self.state != State::End {
                     let msg = format!(
                         "incomplete changegroup: expected state End, found {:?}",
                         self.state
                     );
-                    bail_err!(ErrorKind::Cg2Decode(msg));
+                    bail_err!(ErrorKind::CgDecode(msg));
                 }
                 Ok(None)
             }
@@ -106,17 +109,22 @@ impl Decoder for Cg2Unpacker {
     }
 }
 
-impl Cg2Unpacker {
-    pub fn new(logger: slog::Logger) -> Self {
-        Cg2Unpacker {
+impl CgUnpacker {
+    pub fn new(logger: slog::Logger, version: CgVersion) -> Self {
+        CgUnpacker {
             logger: logger,
             state: State::Changeset,
+            version: version,
         }
     }
 
-    fn decode_next(buf: &mut BytesMut, state: State) -> Result<(Option<Part>, State)> {
+    fn decode_next(
+        buf: &mut BytesMut,
+        state: State,
+        version: CgVersion,
+    ) -> Result<(Option<Part>, State)> {
         match state {
-            State::Changeset => match Self::decode_chunk(buf)? {
+            State::Changeset => match Self::decode_chunk(buf, version)? {
                 None => Ok((None, State::Changeset)),
                 Some(CgChunk::Empty) => {
                     Ok((Some(Part::SectionEnd(Section::Changeset)), State::Manifest))
@@ -126,32 +134,69 @@ impl Cg2Unpacker {
                     State::Changeset,
                 )),
             },
-            State::Manifest => match Self::decode_chunk(buf)? {
+            State::Manifest => match Self::decode_chunk(buf, version)? {
                 None => Ok((None, State::Manifest)),
                 Some(CgChunk::Empty) => {
-                    Ok((Some(Part::SectionEnd(Section::Manifest)), State::Filename))
+                    let next_state = match version {
+                        CgVersion::Cg2Version => State::Filename,
+                        CgVersion::Cg3Version => State::Dirname,
+                    };
+                    Ok((Some(Part::SectionEnd(Section::Manifest)), next_state))
                 }
                 Some(CgChunk::Delta(chunk)) => Ok((
                     Some(Part::CgChunk(Section::Manifest, chunk)),
                     State::Manifest,
                 )),
             },
+            State::Dirname => {
+                let dirname = Self::decode_filename(buf)?;
+                match dirname {
+                    DecodeRes::None => Ok((None, State::Dirname)),
+                    DecodeRes::Some(d) => Self::decode_treemanifest_chunk(buf, d, version),
+                    // This is the end of the list of directory manifests, not the end of the
+                    // changegroup, so carry on with the filelogs.
+                    DecodeRes::End => Self::decode_next(buf, State::Filename, version),
+                }
+            }
+            State::Treemanifest(dirname) => Self::decode_treemanifest_chunk(buf, dirname, version),
             State::Filename => {
                 let filename = Self::decode_filename(buf)?;
                 match filename {
                     DecodeRes::None => Ok((None, State::Filename)),
-                    DecodeRes::Some(f) => Self::decode_filelog_chunk(buf, f),
+                    DecodeRes::Some(f) => Self::decode_filelog_chunk(buf, f, version),
                     DecodeRes::End => Ok((Some(Part::End), State::End)),
                 }
             }
-            State::Filelog(filename) => Self::decode_filelog_chunk(buf, filename),
+            State::Filelog(filename) => Self::decode_filelog_chunk(buf, filename, version),
             State::End => Ok((None, State::End)),
-            State::Invalid => Err(ErrorKind::Cg2Decode("byte stream corrupt".into()).into()),
+            State::Invalid => Err(ErrorKind::CgDecode("byte stream corrupt".into()).into()),
         }
     }
 
-    fn decode_filelog_chunk(buf: &mut BytesMut, f: MPath) -> Result<(Option<Part>, State)> {
-        match Self::decode_chunk(buf)? {
+    fn decode_treemanifest_chunk(
+        buf: &mut BytesMut,
+        d: MPath,
+        version: CgVersion,
+    ) -> Result<(Option<Part>, State)> {
+        match Self::decode_chunk(buf, version)? {
+            None => Ok((None, State::Treemanifest(d))),
+            Some(CgChunk::Empty) => Ok((
+                Some(Part::SectionEnd(Section::Treemanifest(d))),
+                State::Dirname,
+            )),
+            Some(CgChunk::Delta(chunk)) => Ok((
+                Some(Part::CgChunk(Section::Treemanifest(d.clone()), chunk)),
+                State::Treemanifest(d),
+            )),
+        }
+    }
+
+    fn decode_filelog_chunk(
+        buf: &mut BytesMut,
+        f: MPath,
+        version: CgVersion,
+    ) -> Result<(Option<Part>, State)> {
+        match Self::decode_chunk(buf, version)? {
             None => Ok((None, State::Filelog(f))),
             Some(CgChunk::Empty) => {
                 Ok((Some(Part::SectionEnd(Section::Filelog(f))), State::Filename))
@@ -163,11 +208,16 @@ impl Cg2Unpacker {
         }
     }
 
-    fn decode_chunk(buf: &mut BytesMut) -> Result<Option<CgChunk>> {
+    fn decode_chunk(buf: &mut BytesMut, version: CgVersion) -> Result<Option<CgChunk>> {
         if buf.len() < 4 {
             return Ok(None);
         }
 
+        let header_len = match version {
+            CgVersion::Cg2Version => CHUNK_HEADER_LEN,
+            CgVersion::Cg3Version => CHUNK_HEADER3_LEN,
+        };
+
         let chunk_len = buf.peek_i32();
         // Note that chunk_len includes the 4 bytes consumed by itself
         // TODO: chunk_len < 0 = error
@@ -176,12 +226,12 @@ impl Cg2Unpacker {
             let _ = buf.drain_i32();
             return Ok(Some(CgChunk::Empty));
         }
-        if chunk_len < CHUNK_HEADER_LEN {
+        if chunk_len < header_len {
             let msg = format!(
                 "invalid chunk: length >= {} required, found {}",
-                CHUNK_HEADER_LEN, chunk_len
+                header_len, chunk_len
             );
-            bail_err!(ErrorKind::Cg2Decode(msg));
+            bail_err!(ErrorKind::CgDecode(msg));
         }
 
         if buf.len() < chunk_len {
@@ -196,6 +246,7 @@ impl Cg2Unpacker {
         // p2: HgNodeHash (20 bytes) -- NULL_HASH if only 1 parent
         // base node: HgNodeHash (20 bytes) (new in changegroup2)
         // link node: HgNodeHash (20 bytes)
+        // flags: u16 (2 bytes) (new in changegroup3)
         // ---
 
         let node = buf.drain_node();
@@ -203,8 +254,12 @@ impl Cg2Unpacker {
         let p2 = buf.drain_node();
         let base = buf.drain_node();
         let linknode = buf.drain_node();
+        let flags = match version {
+            CgVersion::Cg2Version => None,
+            CgVersion::Cg3Version => Some(buf.drain_u16()),
+        };
 
-        let delta = delta::decode_delta(buf.split_to(chunk_len - CHUNK_HEADER_LEN))?;
+        let delta = delta::decode_delta(buf.split_to(chunk_len - header_len))?;
         return Ok(Some(CgChunk::Delta(CgDeltaChunk {
             node: node,
             p1: p1,
@@ -212,6 +267,7 @@ impl Cg2Unpacker {
             base: base,
             linknode: linknode,
             delta: delta,
+            flags: flags,
         })));
     }
 
@@ -233,7 +289,7 @@ impl Cg2Unpacker {
         let _ = buf.split_to(4);
         let filename = buf.drain_path(filename_len - 4).with_context(|_| {
             let msg = format!("invalid filename of length {}", filename_len);
-            ErrorKind::Cg2Decode(msg)
+            ErrorKind::CgDecode(msg)
         })?;
         Ok(DecodeRes::Some(filename))
     }
@@ -254,6 +310,8 @@ enum CgChunk {
 enum State {
     Changeset,
     Manifest,
+    Dirname,
+    Treemanifest(MPath),
     Filename,
     Filelog(MPath),
     End,
@@ -54,6 +54,7 @@ pub mod bundle2;
 pub mod bundle2_encode;
 pub mod changegroup;
 pub mod infinitepush;
+pub mod obsmarkers;
 mod capabilities;
 mod chunk;
 mod delta;
@@ -85,6 +86,7 @@ use std::fmt;
 use futures_ext::{BoxFuture, BoxStream};
 
 pub use bundle2_encode::Bundle2EncodeBuilder;
+pub use capabilities::Capabilities;
 pub use part_header::{PartHeader, PartHeaderType};
 pub use types::StreamHeader;
 
@@ -98,6 +100,7 @@ pub enum Bundle2Item {
     B2xInfinitepushBookmarks(PartHeader, BoxStream<bytes::Bytes, Error>),
     Replycaps(PartHeader, BoxFuture<capabilities::Capabilities, Error>),
     Pushkey(PartHeader, BoxFuture<(), Error>),
+    Obsmarkers(PartHeader, BoxStream<mercurial_types::HgObsMarker, Error>),
 }
 
 impl Bundle2Item {
@@ -139,6 +142,9 @@ impl fmt::Debug for Bundle2Item {
             }
             &Replycaps(ref header, _) => write!(f, "Bundle2Item::Replycaps({:?}, ...)", header),
             &Pushkey(ref header, _) => write!(f, "Bundle2Item::Pushkey({:?}, ...)", header),
+            &Obsmarkers(ref header, _) => {
+                write!(f, "Bundle2Item::Obsmarkers({:?}, ...)", header)
+            }
         }
     }
 }
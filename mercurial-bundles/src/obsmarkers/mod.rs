@@ -0,0 +1,97 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// Codecs for the obsmarkers part, which carries obsolescence markers.
+
+use bytes::BytesMut;
+use tokio_io::codec::Decoder;
+
+use mercurial_types::HgObsMarker;
+use mercurial_types::obsmarker::OBSMARKERS_VERSION_1;
+
+use errors::*;
+
+#[derive(Debug)]
+pub struct ObsmarkersUnpacker {
+    version_checked: bool,
+}
+
+impl ObsmarkersUnpacker {
+    pub fn new() -> Self {
+        Self {
+            version_checked: false,
+        }
+    }
+}
+
+impl Decoder for ObsmarkersUnpacker {
+    type Item = HgObsMarker;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if !self.version_checked {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let version = buf.split_to(1)[0];
+            if version != OBSMARKERS_VERSION_1 {
+                bail_msg!("unsupported obsmarkers version: {}", version);
+            }
+            self.version_checked = true;
+        }
+
+        match HgObsMarker::encoded_size(buf) {
+            Some(size) if buf.len() >= size => {
+                let marker = buf.split_to(size).freeze();
+                Ok(Some(HgObsMarker::decode(&marker)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::{BufMut, Bytes};
+    use mercurial_types::obsmarker::encode_obsmarkers;
+    use mercurial_types_mocks::nodehash::{ONES_CSID, TWOS_CSID};
+
+    #[test]
+    fn test_unpack() {
+        let marker = HgObsMarker {
+            predecessor: ONES_CSID,
+            successors: vec![TWOS_CSID],
+            flags: 0,
+            date: 0.0,
+            tz_offset_secs: 0,
+            parents: None,
+            metadata: vec![(Bytes::from("user"), Bytes::from("test"))],
+        };
+        let encoded = encode_obsmarkers(&[marker.clone(), marker.clone()]).unwrap();
+
+        let mut unpacker = ObsmarkersUnpacker::new();
+        let mut buf = BytesMut::with_capacity(encoded.len());
+        // Feed the input byte by byte to make sure partial markers are handled.
+        let mut decoded = vec![];
+        for byte in encoded.iter() {
+            buf.put_u8(*byte);
+            while let Some(marker) = unpacker.decode(&mut buf).unwrap() {
+                decoded.push(marker);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(decoded, vec![marker.clone(), marker]);
+    }
+
+    #[test]
+    fn test_unpack_bad_version() {
+        let mut unpacker = ObsmarkersUnpacker::new();
+        let mut buf = BytesMut::from(&b"\x00"[..]);
+        assert!(unpacker.decode(&mut buf).is_err());
+    }
+}
@@ -47,6 +47,8 @@ pub enum PartHeaderType {
     Pushkey,
     /// Respond to a corresponding pushkey part
     ReplyPushkey,
+    /// Contains obsolescence markers, both on push and in getbundle responses.
+    Obsmarkers,
     // RemoteChangegroup,       // We don't wish to support this functionality
     // CheckBookmarks,          // TODO Do we want to support this?
     // CheckHeads,              // TODO Do we want to support this?
@@ -61,7 +63,6 @@ pub enum PartHeaderType {
     // Bookmarks,               // TODO Do we want to support this?
     // PhaseHeads,              // TODO Do we want to support this?
     // ReplyPushkey,            // TODO Do we want to support this?
-    // Obsmarkers,              // TODO Do we want to support this?
     // ReplyObsmarkers,         // TODO Do we want to support this?
     // HgtagsFnodes,            // TODO Do we want to support this?
     // Pushvars,                // TODO Do we want to support this?
@@ -82,6 +83,7 @@ impl PartHeaderType {
             "check:heads" => Ok(CheckHeads),
             "pushkey" => Ok(Pushkey),
             "reply:pushkey" => Ok(ReplyPushkey),
+            "obsmarkers" => Ok(Obsmarkers),
             bad => bail_msg!("unknown header type {}", bad),
         }
     }
@@ -100,6 +102,7 @@ impl PartHeaderType {
             CheckHeads => "check:heads",
             Pushkey => "pushkey",
             ReplyPushkey => "reply:pushkey",
+            Obsmarkers => "obsmarkers",
         }
     }
 }
@@ -25,6 +25,7 @@ use changegroup;
 use errors::*;
 use futures_ext::{StreamExt, StreamLayeredExt};
 use infinitepush;
+use obsmarkers;
 use part_header::{PartHeader, PartHeaderType};
 use part_outer::{OuterFrame, OuterStream};
 use pushrebase;
@@ -46,6 +47,7 @@ lazy_static! {
         m.insert(PartHeaderType::B2xTreegroup2, hashset!{"version", "cache", "category"});
         m.insert(PartHeaderType::Replycaps, hashset!{});
         m.insert(PartHeaderType::Pushkey, hashset!{ "namespace", "key", "old", "new" });
+        m.insert(PartHeaderType::Obsmarkers, hashset!{});
         m
     };
 }
@@ -66,6 +68,13 @@ pub fn validate_header(header: PartHeader) -> Result<Option<PartHeader>> {
                     unknown_params,
                 ));
             }
+            // Reject changegroup versions we can't decode before starting to read the payload.
+            match header.part_type() {
+                &PartHeaderType::Changegroup | &PartHeaderType::B2xInfinitepush => {
+                    let _ = get_cg_version(&header)?;
+                }
+                _ => (),
+            }
             Ok(Some(header))
         }
         None => {
@@ -77,6 +86,23 @@ pub fn validate_header(header: PartHeader) -> Result<Option<PartHeader>> {
     }
 }
 
+/// Get the changegroup version of a Changegroup or B2xInfinitepush part. Parts that don't specify
+/// a version are assumed to be changegroup version 02.
+fn get_cg_version(header: &PartHeader) -> Result<changegroup::CgVersion> {
+    let field = match header.part_type() {
+        &PartHeaderType::B2xInfinitepush => "cgversion",
+        _ => "version",
+    };
+    match header
+        .mparams()
+        .get(field)
+        .or_else(|| header.aparams().get(field))
+    {
+        Some(version) => changegroup::CgVersion::from_bytes(version),
+        None => Ok(changegroup::CgVersion::Cg2Version),
+    }
+}
+
 /// Convert an OuterStream into an InnerStream using the part header.
 pub fn inner_stream<R: AsyncRead + BufRead + 'static + Send>(
     header: PartHeader,
@@ -90,20 +116,24 @@ pub fn inner_stream<R: AsyncRead + BufRead + 'static + Send>(
 
     let bundle2item = match header.part_type() {
         &PartHeaderType::Changegroup => {
-            let cg2_stream = wrapped_stream.decode(changegroup::unpacker::Cg2Unpacker::new(
-                logger.new(o!("stream" => "cg2")),
+            let version = get_cg_version(&header).expect("version checked in validate_header");
+            let cg_stream = wrapped_stream.decode(changegroup::unpacker::CgUnpacker::new(
+                logger.new(o!("stream" => "cg")),
+                version,
             ));
-            Bundle2Item::Changegroup(header, Box::new(cg2_stream))
+            Bundle2Item::Changegroup(header, Box::new(cg_stream))
         }
         &PartHeaderType::B2xCommonHeads => {
             let heads_stream = wrapped_stream.decode(pushrebase::CommonHeadsUnpacker::new());
             Bundle2Item::B2xCommonHeads(header, Box::new(heads_stream))
         }
         &PartHeaderType::B2xInfinitepush => {
-            let cg2_stream = wrapped_stream.decode(changegroup::unpacker::Cg2Unpacker::new(
-                logger.new(o!("stream" => "cg2")),
+            let version = get_cg_version(&header).expect("version checked in validate_header");
+            let cg_stream = wrapped_stream.decode(changegroup::unpacker::CgUnpacker::new(
+                logger.new(o!("stream" => "cg")),
+                version,
             ));
-            Bundle2Item::B2xInfinitepush(header, Box::new(cg2_stream))
+            Bundle2Item::B2xInfinitepush(header, Box::new(cg_stream))
         }
         &PartHeaderType::B2xInfinitepushBookmarks => {
             let bookmarks_stream =
@@ -135,6 +165,10 @@ pub fn inner_stream<R: AsyncRead + BufRead + 'static + Send>(
             let empty = wrapped_stream.decode(EmptyUnpacker).for_each(|_| Ok(()));
             Bundle2Item::Pushkey(header, Box::new(empty))
         }
+        &PartHeaderType::Obsmarkers => {
+            let markers_stream = wrapped_stream.decode(obsmarkers::ObsmarkersUnpacker::new());
+            Bundle2Item::Obsmarkers(header, Box::new(markers_stream))
+        }
         _ => panic!("TODO: make this an error"),
     };
 
@@ -11,13 +11,15 @@ use futures::{Future, Stream};
 use futures::stream::{iter_ok, once};
 use futures_ext::BoxFuture;
 
-use super::changegroup::{CgDeltaChunk, Part, Section};
-use super::changegroup::packer::Cg2Packer;
+use super::changegroup::{CgDeltaChunk, CgVersion, Part, Section};
+use super::changegroup::packer::CgPacker;
 use super::wirepack;
 use super::wirepack::packer::WirePackPacker;
 
 use errors::*;
-use mercurial_types::{Delta, HgBlobNode, HgNodeHash, MPath, MPathElement, RepoPath, NULL_HASH};
+use mercurial_types::{Delta, HgBlobNode, HgNodeHash, HgObsMarker, MPath, MPathElement, RepoPath,
+                      NULL_HASH};
+use mercurial_types::obsmarker::encode_obsmarkers;
 use part_encode::PartEncodeBuilder;
 use part_header::PartHeaderType;
 
@@ -47,12 +49,30 @@ where
     Ok(builder)
 }
 
-pub fn changegroup_part<S>(changelogentries: S) -> Result<PartEncodeBuilder>
+/// A revision of a file, sent in the filelog section of a changegroup.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FilelogEntry {
+    pub node: HgNodeHash,
+    pub p1: Option<HgNodeHash>,
+    pub p2: Option<HgNodeHash>,
+    pub linknode: HgNodeHash,
+    /// The revision as stored in the filelog, including the copy metadata.
+    pub content: Bytes,
+}
+
+/// A changegroup with the changelog entries and the `filelogs`, which are the revisions of each
+/// file, parents first. Clients that fetch the files separately get no `filelogs`.
+pub fn changegroup_part<CS, FS>(
+    changelogentries: CS,
+    filelogs: FS,
+    version: CgVersion,
+) -> Result<PartEncodeBuilder>
 where
-    S: Stream<Item = (HgNodeHash, HgBlobNode), Error = Error> + Send + 'static,
+    CS: Stream<Item = (HgNodeHash, HgBlobNode), Error = Error> + Send + 'static,
+    FS: Stream<Item = (MPath, Vec<FilelogEntry>), Error = Error> + Send + 'static,
 {
     let mut builder = PartEncodeBuilder::mandatory(PartHeaderType::Changegroup)?;
-    builder.add_mparam("version", "02")?;
+    builder.add_mparam("version", version.as_str())?;
 
     let changelogentries = changelogentries.map(|(node, blobnode)| {
         let parents = blobnode.parents().get_nodes();
@@ -75,24 +95,61 @@ where
             base,
             linknode,
             delta,
+            flags: None,
         };
         Part::CgChunk(Section::Changeset, deltachunk)
     });
 
+    let filelogs = filelogs
+        .map(|(path, entries)| {
+            let section = Section::Filelog(path);
+            let chunks = entries
+                .into_iter()
+                .map({
+                    let section = section.clone();
+                    move |entry| {
+                        let deltachunk = CgDeltaChunk {
+                            node: entry.node,
+                            p1: entry.p1.unwrap_or(NULL_HASH),
+                            p2: entry.p2.unwrap_or(NULL_HASH),
+                            base: NULL_HASH,
+                            linknode: entry.linknode,
+                            delta: Delta::new_fulltext(entry.content.to_vec()),
+                            flags: None,
+                        };
+                        Part::CgChunk(section.clone(), deltachunk)
+                    }
+                })
+                .collect::<Vec<_>>();
+            iter_ok::<_, Error>(chunks.into_iter().chain(Some(Part::SectionEnd(section))))
+        })
+        .flatten();
+
     let changelogentries = changelogentries
         .chain(once(Ok(Part::SectionEnd(Section::Changeset))))
         // One more SectionEnd entry is necessary because hg client excepts filelog section
         // even if it's empty. Add a fake SectionEnd part (the choice of
         // Manifest is just for convenience).
         .chain(once(Ok(Part::SectionEnd(Section::Manifest))))
+        .chain(filelogs)
         .chain(once(Ok(Part::End)));
 
-    let cgdata = Cg2Packer::new(changelogentries);
+    let cgdata = CgPacker::new(changelogentries, version);
     builder.set_data_generated(cgdata);
 
     Ok(builder)
 }
 
+pub fn obsmarkers_part<F>(markers: F) -> Result<PartEncodeBuilder>
+where
+    F: Future<Item = Vec<HgObsMarker>, Error = Error> + Send + 'static,
+{
+    let mut builder = PartEncodeBuilder::mandatory(PartHeaderType::Obsmarkers)?;
+    builder.set_data_future(markers.and_then(|markers| encode_obsmarkers(&markers)));
+
+    Ok(builder)
+}
+
 pub struct TreepackPartInput {
     pub node: HgNodeHash,
     pub p1: Option<HgNodeHash>,
@@ -47,19 +47,42 @@ impl Arbitrary for QCBytes {
 }
 
 #[derive(Clone, Debug)]
-pub struct Cg2PartSequence {
+pub struct CgPartSequence {
     // Storing the ends in here bypasses a number of lifetime issues.
     changesets: Vec<changegroup::Part>,
     changesets_end: changegroup::Part,
     manifests: Vec<changegroup::Part>,
     manifests_end: changegroup::Part,
+    // Always empty for changegroup version 02.
+    treemanifests: Vec<(Vec<changegroup::Part>, changegroup::Part)>,
     filelogs: Vec<(Vec<changegroup::Part>, changegroup::Part)>,
     end: changegroup::Part,
 }
 
-impl Cg2PartSequence {
+/// A sequence of parts that can be encoded as changegroup version 02.
+#[derive(Clone, Debug)]
+pub struct Cg2PartSequence(CgPartSequence);
+
+/// A sequence of parts that can be encoded as changegroup version 03.
+#[derive(Clone, Debug)]
+pub struct Cg3PartSequence(CgPartSequence);
+
+impl CgPartSequence {
     /// Combine all the changesets, manifests and filelogs into a single iterator.
     pub fn as_iter<'a>(&'a self) -> Box<Iterator<Item = &'a changegroup::Part> + 'a> {
+        // If there are no parts in a filelog or a directory manifest, it isn't valid to return a
+        // SectionEnd since that won't be referring to anything. So just skip the whole section.
+        fn non_empty_sections<'a>(
+            sections: &'a [(Vec<changegroup::Part>, changegroup::Part)],
+        ) -> Box<Iterator<Item = &'a changegroup::Part> + 'a> {
+            Box::new(
+                sections
+                    .iter()
+                    .filter(|&&(ref parts, _)| !parts.is_empty())
+                    .flat_map(|&(ref parts, ref end)| parts.iter().chain(iter::once(end))),
+            )
+        }
+
         // Trying to describe the type here is madness. Just box it.
         Box::new(
             self.changesets
@@ -67,17 +90,8 @@ impl Cg2PartSequence {
                 .chain(iter::once(&self.changesets_end))
                 .chain(self.manifests.iter())
                 .chain(iter::once(&self.manifests_end))
-                .chain(
-                    self.filelogs
-                        .iter()
-                        .filter(|&&(ref parts, _)| {
-                            // If there are no filelog parts, it isn't valid to return a
-                            // SectionEnd since that won't be referring to anything. So
-                            // just skip the whole filelog.
-                            !parts.is_empty()
-                        })
-                        .flat_map(|&(ref parts, ref end)| parts.iter().chain(iter::once(end))),
-                )
+                .chain(non_empty_sections(&self.treemanifests))
+                .chain(non_empty_sections(&self.filelogs))
                 .chain(iter::once(&self.end)),
         )
     }
@@ -93,23 +107,28 @@ impl Cg2PartSequence {
         let part_results: Vec<_> = self.as_iter().cloned().map(|x| Ok(x)).collect();
         stream::iter_ok(part_results.into_iter())
     }
-}
 
-impl PartialEq<[changegroup::Part]> for Cg2PartSequence {
-    fn eq(&self, other: &[changegroup::Part]) -> bool {
-        self.as_iter().eq(other.iter())
-    }
-}
-
-impl Arbitrary for Cg2PartSequence {
-    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+    fn arbitrary_with_version<G: Gen>(g: &mut G, version: changegroup::CgVersion) -> Self {
         use changegroup::*;
 
-        // Generate a valid part sequence (changegroup, then manifest, then filelogs).
+        // Generate a valid part sequence (changegroup, then manifest, then directory manifests
+        // for version 03, then filelogs).
         let size = g.size();
 
-        let changesets = gen_parts(Section::Changeset, g);
-        let manifests = gen_parts(Section::Manifest, g);
+        let changesets = gen_parts(Section::Changeset, version, g);
+        let manifests = gen_parts(Section::Manifest, version, g);
+
+        let mut treemanifests = Vec::new();
+        if version == CgVersion::Cg3Version {
+            for _ in 0..g.gen_range(0, size) {
+                let path = MPath::arbitrary(g);
+                let section_end = Part::SectionEnd(Section::Treemanifest(path.clone()));
+                treemanifests.push((
+                    gen_parts(Section::Treemanifest(path), version, g),
+                    section_end,
+                ));
+            }
+        }
 
         let nfilelogs = g.gen_range(0, size);
         let mut filelogs = Vec::with_capacity(nfilelogs);
@@ -118,20 +137,21 @@ impl Arbitrary for Cg2PartSequence {
             // Changegroups can't support empty paths, so skip over those.
             let path = MPath::arbitrary(g);
             let section_end = Part::SectionEnd(Section::Filelog(path.clone()));
-            filelogs.push((gen_parts(Section::Filelog(path), g), section_end));
+            filelogs.push((gen_parts(Section::Filelog(path), version, g), section_end));
         }
 
-        Cg2PartSequence {
+        CgPartSequence {
             changesets: changesets,
             changesets_end: Part::SectionEnd(Section::Changeset),
             manifests: manifests,
             manifests_end: Part::SectionEnd(Section::Manifest),
+            treemanifests: treemanifests,
             filelogs: filelogs,
             end: Part::End,
         }
     }
 
-    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+    fn shrink_parts(&self) -> Box<Iterator<Item = Self>> {
         use changegroup::*;
 
         // All the parts can be shrinked independently as long as the section
@@ -141,13 +161,15 @@ impl Arbitrary for Cg2PartSequence {
             (
                 self.changesets.clone(),
                 self.manifests.clone(),
+                self.treemanifests.clone(),
                 self.filelogs.clone(),
             ).shrink()
-                .map(|(c, m, f)| Cg2PartSequence {
+                .map(|(c, m, t, f)| CgPartSequence {
                     changesets: c,
                     changesets_end: Part::SectionEnd(Section::Changeset),
                     manifests: m,
                     manifests_end: Part::SectionEnd(Section::Manifest),
+                    treemanifests: t,
                     filelogs: f,
                     end: Part::End,
                 }),
@@ -155,11 +177,76 @@ impl Arbitrary for Cg2PartSequence {
     }
 }
 
-fn gen_parts<G: Gen>(section: changegroup::Section, g: &mut G) -> Vec<changegroup::Part> {
+impl Cg2PartSequence {
+    #[cfg(test)]
+    pub fn to_stream(
+        &self,
+    ) -> stream::IterOk<IntoIter<result::Result<changegroup::Part, Error>>, Error> {
+        self.0.to_stream()
+    }
+}
+
+impl Cg3PartSequence {
+    #[cfg(test)]
+    pub fn to_stream(
+        &self,
+    ) -> stream::IterOk<IntoIter<result::Result<changegroup::Part, Error>>, Error> {
+        self.0.to_stream()
+    }
+}
+
+impl PartialEq<[changegroup::Part]> for Cg2PartSequence {
+    fn eq(&self, other: &[changegroup::Part]) -> bool {
+        self.0.as_iter().eq(other.iter())
+    }
+}
+
+impl PartialEq<[changegroup::Part]> for Cg3PartSequence {
+    fn eq(&self, other: &[changegroup::Part]) -> bool {
+        self.0.as_iter().eq(other.iter())
+    }
+}
+
+impl Arbitrary for Cg2PartSequence {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Cg2PartSequence(CgPartSequence::arbitrary_with_version(
+            g,
+            changegroup::CgVersion::Cg2Version,
+        ))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        Box::new(self.0.shrink_parts().map(Cg2PartSequence))
+    }
+}
+
+impl Arbitrary for Cg3PartSequence {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Cg3PartSequence(CgPartSequence::arbitrary_with_version(
+            g,
+            changegroup::CgVersion::Cg3Version,
+        ))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        Box::new(self.0.shrink_parts().map(Cg3PartSequence))
+    }
+}
+
+fn gen_parts<G: Gen>(
+    section: changegroup::Section,
+    version: changegroup::CgVersion,
+    g: &mut G,
+) -> Vec<changegroup::Part> {
     let size = g.size();
     (0..g.gen_range(0, size))
         .map(|_| {
-            changegroup::Part::CgChunk(section.clone(), changegroup::CgDeltaChunk::arbitrary(g))
+            let mut chunk = changegroup::CgDeltaChunk::arbitrary(g);
+            // Only changegroup version 03 carries revlog flags.
+            if version == changegroup::CgVersion::Cg3Version {
+                chunk.flags = Some(u16::arbitrary(g));
+            }
+            changegroup::Part::CgChunk(section.clone(), chunk)
         })
         .collect()
 }
@@ -197,6 +284,7 @@ impl Arbitrary for changegroup::CgDeltaChunk {
             base: HgNodeHash::arbitrary(g),
             linknode: HgNodeHash::arbitrary(g),
             delta: Delta::arbitrary(g),
+            flags: None,
         }
     }
 
@@ -213,6 +301,7 @@ impl Arbitrary for changegroup::CgDeltaChunk {
                     base: clone.base.clone(),
                     linknode: clone.linknode.clone(),
                     delta: delta,
+                    flags: clone.flags,
                 }),
         )
     }
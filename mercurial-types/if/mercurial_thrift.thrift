@@ -43,4 +43,7 @@ struct HgFileEnvelope {
   // the metadata
   5: required i64 content_size,
   6: optional binary metadata,
+  // The revlog flags of the file node, a u16 stored as an i16. Only sent by
+  // clients in changegroup 3; missing means no flags.
+  7: optional i16 flags,
 }
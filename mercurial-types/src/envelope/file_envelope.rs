@@ -30,6 +30,7 @@ pub struct HgFileEnvelopeMut {
     pub content_id: ContentId,
     pub content_size: u64,
     pub metadata: Bytes,
+    pub flags: u16,
 }
 
 impl HgFileEnvelopeMut {
@@ -45,7 +46,8 @@ impl fmt::Display for HgFileEnvelopeMut {
         writeln!(f, "p2: {}", HgNodeHash::display_opt(self.p2.as_ref()))?;
         writeln!(f, "content id: {}", self.content_id)?;
         writeln!(f, "content size: {}", self.content_size)?;
-        writeln!(f, "metadata: {:?}", self.metadata)
+        writeln!(f, "metadata: {:?}", self.metadata)?;
+        writeln!(f, "flags: {}", self.flags)
     }
 }
 
@@ -69,6 +71,7 @@ impl HgFileEnvelope {
                     // metadata will always be stored, even if it's length 0
                     metadata: Bytes::from(fe.metadata
                         .ok_or_else(|| err_msg("missing metadata field"))?),
+                    flags: fe.flags.unwrap_or(0) as u16,
                 },
             })
         };
@@ -117,6 +120,13 @@ impl HgFileEnvelope {
         &self.inner.metadata
     }
 
+    /// The revlog flags of this node as they were pushed, e.g. `lfs::REVIDX_EXTSTORED` for the
+    /// pointers of the `lfs` extension.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.inner.flags
+    }
+
     /// Convert into a mutable representation.
     #[inline]
     pub fn into_mut(self) -> HgFileEnvelopeMut {
@@ -132,6 +142,11 @@ impl HgFileEnvelope {
             content_id: Some(inner.content_id.into_thrift()),
             content_size: inner.content_size as i64,
             metadata: Some(inner.metadata.to_vec()),
+            flags: if inner.flags == 0 {
+                None
+            } else {
+                Some(inner.flags as i16)
+            },
         }
     }
 
@@ -160,6 +175,7 @@ impl Arbitrary for HgFileEnvelope {
                 content_id: Arbitrary::arbitrary(g),
                 content_size: Arbitrary::arbitrary(g),
                 metadata: Bytes::from(Vec::arbitrary(g)),
+                flags: Arbitrary::arbitrary(g),
             },
         }
     }
@@ -199,6 +215,7 @@ mod test {
             content_id: None,
             content_size: 42,
             metadata: Some(vec![].into()),
+            flags: None,
         };
 
         HgFileEnvelope::from_thrift(thrift_fe.clone())
@@ -8,7 +8,7 @@ use std::fmt::{self, Debug, Display};
 use std::str::FromStr;
 
 use ascii::{AsciiStr, AsciiString};
-use crypto::{digest::Digest, sha1};
+use crypto::{digest::Digest, sha1, sha2};
 use quickcheck::{single_shrinker, Arbitrary, Gen};
 
 use thrift;
@@ -182,6 +182,129 @@ impl Context {
     }
 }
 
+/// Raw SHA-256 hash
+///
+/// Mercurial itself doesn't use SHA-256, but the `lfs` extension identifies the contents of
+/// large files by their SHA-256, as Git LFS does.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(HeapSizeOf)]
+pub struct Sha256([u8; 32]);
+
+impl Sha256 {
+    /// Construct a `Sha256` from an array of 32 bytes containing a
+    /// SHA-256 (ie, *not* a hash of the bytes).
+    pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Sha256> {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 32 {
+            bail_err!(ErrorKind::InvalidSha256Input("need exactly 32 bytes".into()));
+        } else {
+            let mut ret = Sha256([0; 32]);
+            &mut ret.0[..].copy_from_slice(bytes);
+            Ok(ret)
+        }
+    }
+
+    pub fn to_hex(&self) -> AsciiString {
+        let mut v = Vec::with_capacity(64);
+        for &byte in self.as_ref() {
+            v.push(HEX_CHARS[(byte >> 4) as usize]);
+            v.push(HEX_CHARS[(byte & 0xf) as usize]);
+        }
+
+        unsafe {
+            // A hex string is always a pure ASCII string.
+            AsciiString::from_ascii_unchecked(v)
+        }
+    }
+}
+
+/// Compute the `Sha256` for a slice of bytes.
+impl<'a> From<&'a [u8]> for Sha256 {
+    fn from(data: &[u8]) -> Sha256 {
+        let mut context = Sha256Context::new();
+        context.update(data);
+        context.finish()
+    }
+}
+
+impl AsRef<[u8]> for Sha256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl FromStr for Sha256 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sha256> {
+        // Unlike for Sha1, trailing data isn't allowed: SHA-256 hashes come from outside
+        // Mercurial, where they are never followed by anything else.
+        if s.len() != 64 {
+            bail_err!(ErrorKind::InvalidSha256Input(
+                "need exactly 64 hex digits".into()
+            ));
+        }
+
+        let mut ret = Sha256([0; 32]);
+
+        for idx in 0..ret.0.len() {
+            ret.0[idx] = match u8::from_str_radix(&s[(idx * 2)..(idx * 2 + 2)], 16) {
+                Ok(v) => v,
+                Err(_) => bail_err!(ErrorKind::InvalidSha256Input("bad digit".into())),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Display for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.to_hex(), fmt)
+    }
+}
+
+impl Debug for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Sha256({})", self)
+    }
+}
+
+impl Arbitrary for Sha256 {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut bytes = [0; 32];
+        g.fill_bytes(&mut bytes);
+        Sha256(bytes)
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        single_shrinker(Sha256([0; 32]))
+    }
+}
+
+/// Context for incrementally computing a `Sha256` hash.
+#[derive(Clone)]
+pub struct Sha256Context(sha2::Sha256);
+
+impl Sha256Context {
+    pub fn new() -> Sha256Context {
+        Sha256Context(sha2::Sha256::new())
+    }
+
+    pub fn update<T>(&mut self, data: T)
+    where
+        T: AsRef<[u8]>,
+    {
+        self.0.input(data.as_ref())
+    }
+
+    pub fn finish(mut self) -> Sha256 {
+        let mut ret = Sha256([0; 32]);
+        self.0.result(&mut ret.0[..]);
+        ret
+    }
+}
+
 #[cfg(test)]
 mod test {
     use quickcheck::TestResult;
@@ -281,7 +404,28 @@ mod test {
         Sha1::from_thrift(thrift::Sha1(vec![0; 21])).expect_err("unexpected OK - too long");
     }
 
+    #[test]
+    fn sha256_nil() {
+        assert_eq!(
+            format!("{}", Sha256::from(&[][..])),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn sha256_parse_bad() {
+        Sha256::from_str("").expect_err("unexpected OK - zero len");
+        Sha256::from_str(&"0".repeat(63)).expect_err("unexpected OK - trunc");
+        Sha256::from_str(&"0".repeat(65)).expect_err("unexpected OK - too long");
+        Sha256::from_str(&"x".repeat(64)).expect_err("unexpected OK - badchar");
+    }
+
     quickcheck! {
+        fn sha256_parse_roundtrip(h: Sha256) -> bool {
+            let s = format!("{}", h);
+            Sha256::from_str(&s).expect("hex of a hash should always parse") == h
+        }
+
         fn parse_roundtrip(v: Vec<u8>) -> TestResult {
             if v.len() != 20 {
                 return TestResult::discard()
@@ -0,0 +1,170 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Pointer files of the Mercurial `lfs` extension. The extension stores the contents of large
+//! files in a Git LFS store, and only keeps a pointer to them in the filelog.
+
+use std::collections::BTreeMap;
+use std::str::{self, FromStr};
+
+use bytes::Bytes;
+
+use errors::*;
+use hash::Sha256;
+
+/// The version line every pointer starts with.
+pub const LFS_POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+
+/// The revlog flag of the revisions whose contents are stored outside of the filelog. Clients
+/// with the `lfs` extension expect it on the revisions that are pointers.
+pub const REVIDX_EXTSTORED: u16 = 1 << 13;
+
+const OID_PREFIX: &str = "sha256:";
+
+fn invalid<S: Into<String>>(msg: S) -> Error {
+    ErrorKind::InvalidLfsPointer(msg.into()).into()
+}
+
+/// A pointer to the contents of a file stored in a Git LFS store.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LfsPointer {
+    oid: Sha256,
+    size: u64,
+    // The other keys, like `x-is-binary` or `x-hg-copy`, so that pointers roundtrip.
+    extra: BTreeMap<String, String>,
+}
+
+impl LfsPointer {
+    pub fn new(oid: Sha256, size: u64) -> Self {
+        Self {
+            oid,
+            size,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Whether `data` looks like a pointer. This is cheap, unlike parsing it.
+    #[inline]
+    pub fn is_pointer(data: &[u8]) -> bool {
+        data.starts_with(b"version ") && data[8..].starts_with(LFS_POINTER_VERSION.as_bytes())
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if !Self::is_pointer(data) {
+            return Err(invalid("missing version"));
+        }
+        let text = str::from_utf8(data).map_err(|_| invalid("not UTF-8"))?;
+        if !text.ends_with('\n') {
+            return Err(invalid("missing final newline"));
+        }
+
+        let mut fields = BTreeMap::new();
+        for line in text[..text.len() - 1].split('\n') {
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or("");
+            let value = parts
+                .next()
+                .ok_or_else(|| invalid(format!("no value in line '{}'", line)))?;
+            let valid_key = !key.is_empty() && key.bytes().all(|b| {
+                b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'.' || b == b'-'
+            });
+            if !valid_key {
+                return Err(invalid(format!("bad key '{}'", key)));
+            }
+            if fields.insert(key.to_string(), value.to_string()).is_some() {
+                return Err(invalid(format!("duplicate key '{}'", key)));
+            }
+        }
+
+        if fields.remove("version").as_ref().map(String::as_str) != Some(LFS_POINTER_VERSION) {
+            return Err(invalid("unsupported version"));
+        }
+        let oid = fields.remove("oid").ok_or_else(|| invalid("missing oid"))?;
+        if !oid.starts_with(OID_PREFIX) {
+            return Err(invalid(format!("unsupported oid '{}'", oid)));
+        }
+        let oid = Sha256::from_str(&oid[OID_PREFIX.len()..])?;
+        let size = fields.remove("size").ok_or_else(|| invalid("missing size"))?;
+        let size = size.parse()
+            .map_err(|_| invalid(format!("bad size '{}'", size)))?;
+
+        Ok(Self {
+            oid,
+            size,
+            extra: fields,
+        })
+    }
+
+    #[inline]
+    pub fn oid(&self) -> &Sha256 {
+        &self.oid
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Serializes the pointer the way the `lfs` extension does: the version first, then the
+    /// other keys in order.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut fields = self.extra.clone();
+        fields.insert("oid".into(), format!("{}{}", OID_PREFIX, self.oid));
+        fields.insert("size".into(), format!("{}", self.size));
+
+        let mut out = format!("version {}\n", LFS_POINTER_VERSION);
+        for (key, value) in fields {
+            out.push_str(&format!("{} {}\n", key, value));
+        }
+        Bytes::from(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POINTER: &[u8] = b"version https://git-lfs.github.com/spec/v1\n\
+        oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+        size 12345\n\
+        x-is-binary 0\n";
+
+    #[test]
+    fn parse() {
+        let pointer = LfsPointer::from_bytes(POINTER).expect("valid pointer");
+        assert_eq!(
+            format!("{}", pointer.oid()),
+            "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393"
+        );
+        assert_eq!(pointer.size(), 12345);
+        assert_eq!(pointer.to_bytes().as_ref(), POINTER);
+    }
+
+    #[test]
+    fn parse_bad() {
+        let bad: &[&[u8]] = &[
+            b"",
+            b"some file contents\n",
+            b"version https://git-lfs.github.com/spec/v1\nsize 1\n",
+            b"version https://git-lfs.github.com/spec/v1\noid sha256:00\nsize 1\n",
+            b"version https://git-lfs.github.com/spec/v1\nsize 1\nsize 2\n",
+            b"version https://git-lfs.github.com/spec/v1\nBad key\n",
+        ];
+        for data in bad {
+            LfsPointer::from_bytes(data).expect_err("unexpected OK - invalid pointer");
+        }
+
+        let no_newline = &POINTER[..POINTER.len() - 1];
+        LfsPointer::from_bytes(no_newline).expect_err("unexpected OK - missing final newline");
+    }
+
+    quickcheck! {
+        fn roundtrip(oid: Sha256, size: u64) -> bool {
+            let pointer = LfsPointer::new(oid, size);
+            LfsPointer::from_bytes(&pointer.to_bytes()).expect("valid pointer") == pointer
+        }
+    }
+}
@@ -86,13 +86,16 @@ pub mod delta;
 pub mod errors;
 pub mod fsencode;
 pub mod hash;
+pub mod lfs;
 pub mod nodehash;
 pub mod utils;
 pub mod manifest;
 pub mod manifest_utils;
+pub mod narrow;
 pub mod blob;
 pub mod blobnode;
 pub mod changeset;
+pub mod obsmarker;
 pub mod repo;
 pub mod sql_types;
 mod node;
@@ -105,12 +108,15 @@ pub use delta::Delta;
 pub use envelope::{HgChangesetEnvelope, HgChangesetEnvelopeMut, HgFileEnvelope, HgFileEnvelopeMut,
                    HgManifestEnvelope, HgManifestEnvelopeMut};
 pub use fsencode::{fncache_fsencode, simple_fsencode};
+pub use lfs::LfsPointer;
 pub use manifest::{Entry, Manifest, Type};
+pub use narrow::{NarrowMatcher, NarrowPattern};
 pub use node::Node;
+pub use obsmarker::HgObsMarker;
 pub use nodehash::{HgChangesetId, HgEntryId, HgFileNodeId, HgManifestId, HgNodeHash, HgNodeKey,
                    NULL_HASH};
 pub use repo::RepositoryId;
-pub use utils::percent_encode;
+pub use utils::{b85encode, percent_encode};
 
 // Re-exports from mononoke-types. Eventually these should go away and everything should depend
 // directly on mononoke-types;
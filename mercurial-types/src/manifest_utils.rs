@@ -17,6 +17,7 @@ use futures_ext::{select_all, BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use super::{Entry, MPath, MPathElement, Manifest};
 use super::manifest::{Content, EmptyManifest, Type};
+use super::narrow::NarrowMatcher;
 
 use errors::*;
 
@@ -284,6 +285,30 @@ pub fn visited_pruner() -> impl FnMut(&ChangedEntry) -> bool + Send + Clone + 's
     }
 }
 
+/// Prunes the entries that don't belong to a narrow clone: files that aren't matched and
+/// directories that can't contain matched files.
+pub fn narrow_pruner(
+    matcher: Arc<NarrowMatcher>,
+) -> impl FnMut(&ChangedEntry) -> bool + Send + Clone + 'static {
+    move |entry: &ChangedEntry| {
+        let entry_name = match entry.status {
+            EntryStatus::Added(ref entry) | EntryStatus::Deleted(ref entry) => entry.get_name(),
+            EntryStatus::Modified { ref to_entry, .. } => to_entry.get_name(),
+        };
+        let path = match MPath::join_element_opt(entry.dirname.as_ref(), entry_name) {
+            Some(path) => path,
+            // The root of the repo
+            None => return true,
+        };
+
+        if entry.status.is_tree() {
+            matcher.visit_dir(Some(&path))
+        } else {
+            matcher.matches_file(&path)
+        }
+    }
+}
+
 pub fn and_pruner_combinator<P1, P2>(
     mut p1: P1,
     mut p2: P2,
@@ -0,0 +1,244 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Include and exclude patterns of narrow clones.
+//!
+//! Only the pattern kinds that Mercurial's narrow extension accepts are supported:
+//! `path:<dir>`, that matches everything under `<dir>`, and `rootfilesin:<dir>`, that matches the
+//! files directly in `<dir>`. `.` is the root of the repo.
+
+use std::fmt;
+
+use super::MPath;
+use errors::*;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NarrowPattern {
+    /// Everything under the directory (`None` is the root of the repo).
+    Path(Option<MPath>),
+    /// The files directly in the directory (`None` is the root of the repo).
+    RootFilesIn(Option<MPath>),
+}
+
+impl NarrowPattern {
+    pub fn parse(pattern: &[u8]) -> Result<Self> {
+        let invalid = || ErrorKind::InvalidNarrowPattern(String::from_utf8_lossy(pattern).into());
+
+        let colon = pattern
+            .iter()
+            .position(|b| *b == b':')
+            .ok_or_else(invalid)?;
+        let (kind, path) = (&pattern[..colon], &pattern[colon + 1..]);
+
+        let path = match path {
+            b"" | b"." => None,
+            path => {
+                let path = if path.ends_with(b"/") {
+                    &path[..path.len() - 1]
+                } else {
+                    path
+                };
+                Some(MPath::new(path).map_err(|_| invalid())?)
+            }
+        };
+
+        match kind {
+            b"path" => Ok(NarrowPattern::Path(path)),
+            b"rootfilesin" => Ok(NarrowPattern::RootFilesIn(path)),
+            _ => Err(invalid().into()),
+        }
+    }
+
+    fn matches_file(&self, file: &MPath) -> bool {
+        match self {
+            &NarrowPattern::Path(None) => true,
+            &NarrowPattern::Path(Some(ref dir)) => dir.is_prefix_of(file),
+            &NarrowPattern::RootFilesIn(ref dir) => &file.split_dirname().0 == dir,
+        }
+    }
+
+    /// Whether the pattern can match anything under `dir`.
+    fn matches_under(&self, dir: &MPath) -> bool {
+        match self {
+            &NarrowPattern::Path(None) => true,
+            &NarrowPattern::Path(Some(ref pattern_dir)) => {
+                pattern_dir.is_prefix_of(dir) || dir.is_prefix_of(pattern_dir)
+            }
+            &NarrowPattern::RootFilesIn(None) => false,
+            &NarrowPattern::RootFilesIn(Some(ref pattern_dir)) => dir.is_prefix_of(pattern_dir),
+        }
+    }
+
+    /// Whether the pattern matches everything under `dir`.
+    fn matches_all_under(&self, dir: &MPath) -> bool {
+        match self {
+            &NarrowPattern::Path(None) => true,
+            &NarrowPattern::Path(Some(ref pattern_dir)) => pattern_dir.is_prefix_of(dir),
+            &NarrowPattern::RootFilesIn(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for NarrowPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, path) = match self {
+            &NarrowPattern::Path(ref path) => ("path", path),
+            &NarrowPattern::RootFilesIn(ref path) => ("rootfilesin", path),
+        };
+        match path {
+            &Some(ref path) => write!(f, "{}:{}", kind, path),
+            &None => write!(f, "{}:.", kind),
+        }
+    }
+}
+
+/// Decides which paths belong to a narrow clone. A path belongs to it if it's matched by one of
+/// the include patterns and by none of the exclude patterns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NarrowMatcher {
+    includes: Vec<NarrowPattern>,
+    excludes: Vec<NarrowPattern>,
+}
+
+impl NarrowMatcher {
+    pub fn new(includes: Vec<NarrowPattern>, excludes: Vec<NarrowPattern>) -> Self {
+        NarrowMatcher { includes, excludes }
+    }
+
+    /// Build a matcher from the patterns sent by a client. Returns `None` if there are no patterns,
+    /// i.e. the client wants everything. Only exclude patterns means everything but them.
+    pub fn from_patterns(includes: &[Vec<u8>], excludes: &[Vec<u8>]) -> Result<Option<Self>> {
+        if includes.is_empty() && excludes.is_empty() {
+            return Ok(None);
+        }
+
+        let mut parsed_includes = includes
+            .iter()
+            .map(|pattern| NarrowPattern::parse(pattern))
+            .collect::<Result<Vec<_>>>()?;
+        if parsed_includes.is_empty() {
+            parsed_includes.push(NarrowPattern::Path(None));
+        }
+        let parsed_excludes = excludes
+            .iter()
+            .map(|pattern| NarrowPattern::parse(pattern))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(Self::new(parsed_includes, parsed_excludes)))
+    }
+
+    pub fn matches_file(&self, file: &MPath) -> bool {
+        self.includes.iter().any(|pattern| pattern.matches_file(file))
+            && !self.excludes.iter().any(|pattern| pattern.matches_file(file))
+    }
+
+    /// Whether anything under `dir` can belong to the narrow clone, i.e. whether it's worth
+    /// walking into `dir`. `None` is the root of the repo, that always has to be visited.
+    pub fn visit_dir(&self, dir: Option<&MPath>) -> bool {
+        match dir {
+            None => true,
+            Some(dir) => {
+                self.includes
+                    .iter()
+                    .any(|pattern| pattern.matches_under(dir))
+                    && !self.excludes
+                        .iter()
+                        .any(|pattern| pattern.matches_all_under(dir))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(p: &str) -> MPath {
+        MPath::new(p).unwrap()
+    }
+
+    fn matcher(includes: &[&str], excludes: &[&str]) -> NarrowMatcher {
+        let to_vecs = |patterns: &[&str]| -> Vec<Vec<u8>> {
+            patterns.iter().map(|p| p.as_bytes().to_vec()).collect()
+        };
+        NarrowMatcher::from_patterns(&to_vecs(includes), &to_vecs(excludes))
+            .expect("invalid patterns")
+            .expect("no patterns")
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            NarrowPattern::parse(b"path:foo/bar/").unwrap(),
+            NarrowPattern::Path(Some(path("foo/bar")))
+        );
+        assert_eq!(
+            NarrowPattern::parse(b"path:.").unwrap(),
+            NarrowPattern::Path(None)
+        );
+        assert_eq!(
+            NarrowPattern::parse(b"rootfilesin:foo").unwrap(),
+            NarrowPattern::RootFilesIn(Some(path("foo")))
+        );
+        assert!(NarrowPattern::parse(b"glob:foo/*").is_err());
+        assert!(NarrowPattern::parse(b"foo").is_err());
+    }
+
+    #[test]
+    fn test_no_patterns() {
+        assert_eq!(NarrowMatcher::from_patterns(&[], &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_path_include() {
+        let m = matcher(&["path:foo/bar"], &[]);
+        assert!(m.matches_file(&path("foo/bar/baz")));
+        assert!(m.matches_file(&path("foo/bar/baz/qux")));
+        assert!(!m.matches_file(&path("foo/barbaz")));
+        assert!(!m.matches_file(&path("foo/baz")));
+
+        assert!(m.visit_dir(None));
+        assert!(m.visit_dir(Some(&path("foo"))));
+        assert!(m.visit_dir(Some(&path("foo/bar"))));
+        assert!(m.visit_dir(Some(&path("foo/bar/baz"))));
+        assert!(!m.visit_dir(Some(&path("foo/baz"))));
+        assert!(!m.visit_dir(Some(&path("qux"))));
+    }
+
+    #[test]
+    fn test_rootfilesin_include() {
+        let m = matcher(&["rootfilesin:foo"], &[]);
+        assert!(m.matches_file(&path("foo/file")));
+        assert!(!m.matches_file(&path("foo/bar/file")));
+        assert!(!m.matches_file(&path("file")));
+
+        assert!(m.visit_dir(Some(&path("foo"))));
+        assert!(!m.visit_dir(Some(&path("foo/bar"))));
+
+        let m = matcher(&["rootfilesin:."], &[]);
+        assert!(m.matches_file(&path("file")));
+        assert!(!m.matches_file(&path("foo/file")));
+        assert!(!m.visit_dir(Some(&path("foo"))));
+    }
+
+    #[test]
+    fn test_excludes() {
+        let m = matcher(&["path:foo"], &["path:foo/bar", "rootfilesin:foo/baz"]);
+        assert!(m.matches_file(&path("foo/file")));
+        assert!(!m.matches_file(&path("foo/bar/file")));
+        assert!(!m.matches_file(&path("foo/baz/file")));
+        assert!(m.matches_file(&path("foo/baz/qux/file")));
+
+        assert!(!m.visit_dir(Some(&path("foo/bar"))));
+        assert!(!m.visit_dir(Some(&path("foo/bar/qux"))));
+        assert!(m.visit_dir(Some(&path("foo/baz"))));
+
+        let m = matcher(&[], &["path:foo"]);
+        assert!(m.matches_file(&path("bar/file")));
+        assert!(!m.matches_file(&path("foo/file")));
+        assert!(!m.visit_dir(Some(&path("foo"))));
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Mercurial obsolescence markers.
+//!
+//! An obsolescence marker records that a changeset (the predecessor) was rewritten into zero or
+//! more successors, f.e. by `hg amend`, `hg rebase` or `hg prune`. Only the version 1 binary
+//! format is supported; it is what Mercurial uses for the bundle2 `obsmarkers` part.
+
+use std::io::Cursor;
+
+use bytes::{Buf, BufMut, Bytes};
+
+use errors::*;
+use hash::{Context, Sha1};
+use nodehash::{HgChangesetId, HgNodeHash};
+
+/// Version byte that prefixes a list of markers encoded with the version 1 format.
+pub const OBSMARKERS_VERSION_1: u8 = 1;
+
+/// The marker fixes a "bumped" (phase-divergent) changeset.
+pub const OBSMARKER_FLAG_BUMPEDFIX: u16 = 1;
+/// Nodes in the marker are sha256 hashes. Not supported.
+pub const OBSMARKER_FLAG_USINGSHA256: u16 = 2;
+
+// size (u32), date (f64), tz (i16), flags (u16), numsuc (u8), numpar (u8), nummeta (u8) and the
+// predecessor node.
+const FIXED_SIZE: usize = 4 + 8 + 2 + 2 + 1 + 1 + 1 + 20;
+// Value of `numpar` meaning that parents were not recorded.
+const PARENTS_NONE: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HgObsMarker {
+    pub predecessor: HgChangesetId,
+    /// Empty for "prune" markers.
+    pub successors: Vec<HgChangesetId>,
+    pub flags: u16,
+    /// Seconds since the epoch.
+    pub date: f64,
+    /// Offset from UTC in seconds, positive west of UTC (as in Mercurial).
+    pub tz_offset_secs: i32,
+    /// Parents of the predecessor, if recorded. Mostly used by prune markers.
+    pub parents: Option<Vec<HgChangesetId>>,
+    pub metadata: Vec<(Bytes, Bytes)>,
+}
+
+impl HgObsMarker {
+    /// Identifier of the marker - the sha1 of its encoded form. Markers are immutable, so two
+    /// markers with the same id are the same marker.
+    pub fn id(&self) -> Result<Sha1> {
+        let mut context = Context::new();
+        context.update(self.encode()?);
+        Ok(context.finish())
+    }
+
+    /// Changesets that this marker is relevant to: the predecessor, the successors and the
+    /// parents of the predecessor.
+    pub fn related_changesets(&self) -> Vec<HgChangesetId> {
+        let mut res = vec![self.predecessor];
+        res.extend(self.successors.iter().cloned());
+        if let Some(ref parents) = self.parents {
+            res.extend(parents.iter().cloned());
+        }
+        res
+    }
+
+    /// Encode a single marker (without the version header).
+    pub fn encode(&self) -> Result<Bytes> {
+        if self.flags & OBSMARKER_FLAG_USINGSHA256 != 0 {
+            bail_err!(ErrorKind::InvalidObsMarker(
+                "sha256 markers are not supported".into()
+            ));
+        }
+        if self.successors.len() > 255 {
+            bail_err!(ErrorKind::InvalidObsMarker(format!(
+                "too many successors: {}",
+                self.successors.len()
+            )));
+        }
+        let numpar = match self.parents {
+            Some(ref parents) if parents.len() > 2 => bail_err!(ErrorKind::InvalidObsMarker(
+                format!("too many parents: {}", parents.len())
+            )),
+            Some(ref parents) => parents.len() as u8,
+            None => PARENTS_NONE,
+        };
+        if self.metadata.len() > 255 {
+            bail_err!(ErrorKind::InvalidObsMarker(format!(
+                "too many metadata entries: {}",
+                self.metadata.len()
+            )));
+        }
+
+        let parents: &[HgChangesetId] = match self.parents {
+            Some(ref parents) => parents,
+            None => &[],
+        };
+        let mut size = FIXED_SIZE + 20 * (self.successors.len() + parents.len());
+        for &(ref key, ref value) in self.metadata.iter() {
+            if key.len() > 255 || value.len() > 255 {
+                bail_err!(ErrorKind::InvalidObsMarker(format!(
+                    "metadata entry is too long: {:?}",
+                    key
+                )));
+            }
+            size += 2 + key.len() + value.len();
+        }
+
+        let mut buf = Vec::with_capacity(size);
+        buf.put_u32_be(size as u32);
+        buf.put_f64_be(self.date);
+        buf.put_i16_be(tz_to_minutes(self.tz_offset_secs));
+        buf.put_u16_be(self.flags);
+        buf.put_u8(self.successors.len() as u8);
+        buf.put_u8(numpar);
+        buf.put_u8(self.metadata.len() as u8);
+        buf.put_slice(self.predecessor.as_nodehash().as_bytes());
+        for node in self.successors.iter().chain(parents.iter()) {
+            buf.put_slice(node.as_nodehash().as_bytes());
+        }
+        for &(ref key, ref value) in self.metadata.iter() {
+            buf.put_u8(key.len() as u8);
+            buf.put_u8(value.len() as u8);
+        }
+        for &(ref key, ref value) in self.metadata.iter() {
+            buf.put_slice(key);
+            buf.put_slice(value);
+        }
+        Ok(Bytes::from(buf))
+    }
+
+    /// Size of the next encoded marker in `data`, or None if `data` is too short to tell.
+    pub fn encoded_size(data: &[u8]) -> Option<usize> {
+        if data.len() < 4 {
+            None
+        } else {
+            Some(Cursor::new(data).get_u32_be() as usize)
+        }
+    }
+
+    /// Decode a single marker (without the version header). `data` must contain exactly one
+    /// marker.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let invalid = |msg: String| Error::from(ErrorKind::InvalidObsMarker(msg));
+
+        if data.len() < FIXED_SIZE {
+            return Err(invalid(format!("marker is too short: {} bytes", data.len())));
+        }
+        let mut cursor = Cursor::new(data);
+        let size = cursor.get_u32_be() as usize;
+        if size != data.len() {
+            return Err(invalid(format!(
+                "marker size mismatch: expected {}, got {}",
+                size,
+                data.len()
+            )));
+        }
+        let date = cursor.get_f64_be();
+        let tz_offset_secs = i32::from(cursor.get_i16_be()) * 60;
+        let flags = cursor.get_u16_be();
+        if flags & OBSMARKER_FLAG_USINGSHA256 != 0 {
+            return Err(invalid("sha256 markers are not supported".into()));
+        }
+        let numsuc = cursor.get_u8() as usize;
+        let numpar = cursor.get_u8();
+        let nummeta = cursor.get_u8() as usize;
+
+        let numpar_nodes = match numpar {
+            PARENTS_NONE => 0,
+            0...2 => numpar as usize,
+            _ => return Err(invalid(format!("invalid number of parents: {}", numpar))),
+        };
+        if cursor.remaining() < 20 * (1 + numsuc + numpar_nodes) + 2 * nummeta {
+            return Err(invalid("marker is truncated".into()));
+        }
+
+        let read_node = |cursor: &mut Cursor<&[u8]>| -> Result<HgChangesetId> {
+            let mut node = [0u8; 20];
+            cursor.copy_to_slice(&mut node);
+            Ok(HgChangesetId::new(HgNodeHash::from_bytes(&node)?))
+        };
+
+        let predecessor = read_node(&mut cursor)?;
+        let successors = (0..numsuc)
+            .map(|_| read_node(&mut cursor))
+            .collect::<Result<Vec<_>>>()?;
+        let parents = if numpar == PARENTS_NONE {
+            None
+        } else {
+            Some((0..numpar_nodes)
+                .map(|_| read_node(&mut cursor))
+                .collect::<Result<Vec<_>>>()?)
+        };
+
+        let sizes: Vec<_> = (0..nummeta)
+            .map(|_| (cursor.get_u8() as usize, cursor.get_u8() as usize))
+            .collect();
+        let metasize: usize = sizes.iter().map(|&(k, v)| k + v).sum();
+        if cursor.remaining() != metasize {
+            return Err(invalid(format!(
+                "metadata size mismatch: expected {}, got {}",
+                metasize,
+                cursor.remaining()
+            )));
+        }
+        let mut offset = cursor.position() as usize;
+        let mut metadata = Vec::with_capacity(nummeta);
+        for (keylen, valuelen) in sizes {
+            let key = Bytes::from(&data[offset..offset + keylen]);
+            offset += keylen;
+            let value = Bytes::from(&data[offset..offset + valuelen]);
+            offset += valuelen;
+            metadata.push((key, value));
+        }
+
+        Ok(HgObsMarker {
+            predecessor,
+            successors,
+            flags,
+            date,
+            tz_offset_secs,
+            parents,
+            metadata,
+        })
+    }
+}
+
+/// Encode a list of markers, prefixed with the format version.
+pub fn encode_obsmarkers<'a, I>(markers: I) -> Result<Bytes>
+where
+    I: IntoIterator<Item = &'a HgObsMarker>,
+{
+    let mut buf = vec![OBSMARKERS_VERSION_1];
+    for marker in markers {
+        buf.extend_from_slice(&marker.encode()?);
+    }
+    Ok(Bytes::from(buf))
+}
+
+/// Decode a list of markers prefixed with the format version.
+pub fn decode_obsmarkers(data: &[u8]) -> Result<Vec<HgObsMarker>> {
+    match data.first() {
+        Some(&OBSMARKERS_VERSION_1) => (),
+        Some(version) => bail_err!(ErrorKind::InvalidObsMarker(format!(
+            "unsupported markers version: {}",
+            version
+        ))),
+        None => bail_err!(ErrorKind::InvalidObsMarker("missing markers version".into())),
+    }
+
+    let mut data = &data[1..];
+    let mut markers = Vec::new();
+    while !data.is_empty() {
+        let size = match HgObsMarker::encoded_size(data) {
+            Some(size) if size <= data.len() => size,
+            _ => bail_err!(ErrorKind::InvalidObsMarker("marker is truncated".into())),
+        };
+        markers.push(HgObsMarker::decode(&data[..size])?);
+        data = &data[size..];
+    }
+    Ok(markers)
+}
+
+// Mercurial stores the timezone in minutes, rounding towards negative infinity.
+fn tz_to_minutes(tz_offset_secs: i32) -> i16 {
+    let minutes = if tz_offset_secs >= 0 {
+        tz_offset_secs / 60
+    } else {
+        -((-tz_offset_secs + 59) / 60)
+    };
+    minutes as i16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn csid(byte: u8) -> HgChangesetId {
+        HgChangesetId::new(HgNodeHash::new(Sha1::from_byte_array([byte; 20])))
+    }
+
+    fn marker() -> HgObsMarker {
+        HgObsMarker {
+            predecessor: csid(0x11),
+            successors: vec![csid(0x22), csid(0x33)],
+            flags: 0,
+            date: 1500000000.5,
+            tz_offset_secs: -3600,
+            parents: Some(vec![csid(0x44)]),
+            metadata: vec![
+                (Bytes::from("operation"), Bytes::from("amend")),
+                (Bytes::from("user"), Bytes::from("test")),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let prune = HgObsMarker {
+            successors: vec![],
+            parents: None,
+            metadata: vec![],
+            ..marker()
+        };
+        let markers = vec![marker(), prune];
+
+        let encoded = encode_obsmarkers(&markers).expect("encoding failed");
+        assert_eq!(encoded[0], OBSMARKERS_VERSION_1);
+        let decoded = decode_obsmarkers(&encoded).expect("decoding failed");
+        assert_eq!(decoded, markers);
+    }
+
+    #[test]
+    fn test_encoded_size() {
+        let encoded = marker().encode().expect("encoding failed");
+        assert_eq!(
+            encoded.len(),
+            FIXED_SIZE + 3 * 20 + 2 * 2 + "operation".len() + "amend".len() + "user".len()
+                + "test".len()
+        );
+        assert_eq!(HgObsMarker::encoded_size(&encoded), Some(encoded.len()));
+    }
+
+    #[test]
+    fn test_id_is_stable() {
+        assert_eq!(marker().id().unwrap(), marker().id().unwrap());
+        let other = HgObsMarker {
+            flags: OBSMARKER_FLAG_BUMPEDFIX,
+            ..marker()
+        };
+        assert_ne!(marker().id().unwrap(), other.id().unwrap());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode_obsmarkers(b"").is_err());
+        assert!(decode_obsmarkers(b"\x00").is_err());
+
+        let encoded = encode_obsmarkers(&[marker()]).expect("encoding failed");
+        assert!(decode_obsmarkers(&encoded[..encoded.len() - 1]).is_err());
+
+        let sha256 = HgObsMarker {
+            flags: OBSMARKER_FLAG_USINGSHA256,
+            ..marker()
+        };
+        assert!(sha256.encode().is_err());
+    }
+}
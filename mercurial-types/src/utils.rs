@@ -17,3 +17,41 @@ pub fn percent_encode(input: &str) -> String {
     // one.
     percent_encoding::utf8_percent_encode(input, HG_ENCODE_SET).collect::<String>()
 }
+
+const B85_CHARS: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Encode bytes the same way Mercurial's `util.b85encode(text, pad=False)` does. This is used
+/// for pushkey values that can contain arbitrary bytes, f.e. obsolescence markers dumps.
+pub fn b85encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity((input.len() + 3) / 4 * 5);
+    for chunk in input.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        let mut word = u32::from(word[0]) << 24 | u32::from(word[1]) << 16
+            | u32::from(word[2]) << 8 | u32::from(word[3]);
+
+        let mut encoded = [0u8; 5];
+        for c in encoded.iter_mut().rev() {
+            *c = B85_CHARS[(word % 85) as usize];
+            word /= 85;
+        }
+        // Trim the padding - a partial chunk of n bytes is encoded as n + 1 characters.
+        let len = if chunk.len() == 4 { 5 } else { chunk.len() + 1 };
+        output.extend_from_slice(&encoded[..len]);
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_b85encode() {
+        assert_eq!(b85encode(b""), b"".to_vec());
+        assert_eq!(b85encode(b"\0\0\0\0"), b"00000".to_vec());
+        assert_eq!(b85encode(b"abcd"), b"VPa!s".to_vec());
+        assert_eq!(b85encode(b"abcde"), b"VPa!sWd".to_vec());
+    }
+}
@@ -26,6 +26,7 @@ extern crate serde_derive;
 extern crate toml;
 
 extern crate blobrepo;
+extern crate blobstore;
 extern crate mercurial;
 extern crate mercurial_types;
 #[cfg(test)]
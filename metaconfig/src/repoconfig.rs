@@ -7,7 +7,9 @@
 //! Contains structures describing configuration of the entire repo. Those structures are
 //! deserialized from TOML files from metaconfig repo
 
-use blobrepo::{BlobRepo, ManifoldArgs};
+use blobrepo::{BlobRepo, ComponentBlobstoreArgs, ComponentBlobstoreType, CompressionArgs,
+               DiskCacheArgs, ManifoldArgs};
+use blobstore::BlobstoreId;
 use bookmarks::Bookmark;
 use bytes::Bytes;
 use errors::*;
@@ -19,7 +21,7 @@ use mercurial_types::{Changeset, MPath, MPathElement, Manifest};
 use mercurial_types::manifest::Content;
 use mercurial_types::nodehash::HgChangesetId;
 use mononoke_types::FileContents;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::str;
 use toml;
@@ -44,16 +46,38 @@ pub struct RepoConfig {
     pub bookmarks: Option<Vec<BookmarkParams>>,
     /// Configuration for hooks
     pub hooks: Option<Vec<HookParams>>,
+    /// If set, all wire protocol requests are recorded to files in this directory, one file per
+    /// connection. The recordings can be replayed with the `replay` tool.
+    pub wireproto_recording_dir: Option<PathBuf>,
+    /// If set, the blobs of local repos are encrypted with the keys in this file. See
+    /// `blobstore::Keyring` for its format.
+    pub encryption_keyfile: Option<PathBuf>,
+    /// If set, the blobs written before encryption was enabled can still be read. This is only
+    /// meant for migrating a repo to encryption, until all its blobs are re-encrypted.
+    pub encryption_allow_plaintext: bool,
+    /// If set, the blobs of local repos are cached in this directory on local disk.
+    pub disk_cache: Option<DiskCacheArgs>,
+    /// If set, the large blobs of local repos are compressed. Once set, it must stay set, or
+    /// the blobs written compressed can't be read anymore.
+    pub compression: Option<CompressionArgs>,
+    /// If set, the skiplist index is loaded from the blob with this key on startup. The blob is
+    /// written by the `skiplist build` command of the admin tool.
+    pub skiplist_index_blobstore_key: Option<String>,
 }
 
 /// Configuration of warming up the Mononoke cache. This warmup happens on startup
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CacheWarmupParams {
-    /// Bookmark to warmup cache for at the startup. If not set then the cache will be cold.
-    pub bookmark: Bookmark,
+    /// Bookmarks to warmup cache for at the startup. If not set then the cache will be cold.
+    pub bookmarks: Vec<Bookmark>,
     /// Max number to fetch during commit warmup. If not set in the config, then set to a default
     /// value.
     pub commit_limit: usize,
+    /// Number of the most recently changed files of each bookmark whose content is fetched. If
+    /// not set in the config, then no file content is fetched.
+    pub file_limit: usize,
+    /// If set, the skiplist index is built for the ancestors of each bookmark up to this depth.
+    pub skiplist_depth: Option<u64>,
 }
 
 /// Configuration for a bookmark
@@ -104,6 +128,16 @@ pub enum RepoType {
     /// RocksDb database, and a log-normal delay is applied to access to simulate a remote store
     /// like Manifold. Params are path, mean microseconds, stddev microseconds.
     TestBlobDelayRocks(PathBuf, u64, u64),
+    /// Blob repository whose blobs are stored in several local blobstores, so that losing one of
+    /// them doesn't lose data. Path is where the rest of the repo state is stored.
+    BlobMultiplexed {
+        /// Path to the on-disk state that isn't blobs, including the blobstore sync queue.
+        path: PathBuf,
+        /// The blobstores that store the blobs.
+        blobstores: Vec<ComponentBlobstoreArgs>,
+        /// How many blobstores have to store a blob for a write to succeed.
+        write_quorum: usize,
+    },
 }
 
 /// Configuration of a metaconfig repository
@@ -290,6 +324,7 @@ impl RepoConfigs {
                         io_threads: this.io_thread_num.unwrap_or(5),
                         max_concurrent_requests_per_io_thread:
                             this.max_concurrent_requests_per_io_thread.unwrap_or(4),
+                        cache_snapshot_dir: this.cache_snapshot_dir,
                     },
                     path: this.path,
                 }
@@ -299,15 +334,77 @@ impl RepoConfigs {
                 this.delay_mean.expect("mean delay must be specified"),
                 this.delay_stddev.expect("stddev delay must be specified"),
             ),
+            RawRepoType::BlobMultiplexed => {
+                let blobstores: Vec<_> = this.blobstores
+                    .ok_or(ErrorKind::InvalidConfig(
+                        "blobstores must be specified for a multiplexed repo".into(),
+                    ))?
+                    .into_iter()
+                    .map(|blobstore| ComponentBlobstoreArgs {
+                        blobstore_id: BlobstoreId::new(blobstore.blobstore_id),
+                        blobstore_type: match blobstore.blobstore_type {
+                            RawBlobstoreType::Files => ComponentBlobstoreType::Files,
+                            RawBlobstoreType::Rocks => ComponentBlobstoreType::Rocks,
+                        },
+                        path: blobstore.path,
+                    })
+                    .collect();
+                // The ids identify the blobstores in the sync queue, so they must be unique.
+                let mut ids = HashSet::new();
+                for blobstore in &blobstores {
+                    if !ids.insert(blobstore.blobstore_id) {
+                        return Err(ErrorKind::InvalidConfig(format!(
+                            "blobstore id {} is used by several blobstores",
+                            blobstore.blobstore_id
+                        )).into());
+                    }
+                }
+                // By default a majority of the blobstores has to store a blob.
+                let write_quorum = this.write_quorum.unwrap_or(blobstores.len() / 2 + 1);
+                if write_quorum == 0 || write_quorum > blobstores.len() {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "write quorum {} is not between 1 and the number of blobstores ({})",
+                        write_quorum,
+                        blobstores.len()
+                    )).into());
+                }
+                RepoType::BlobMultiplexed {
+                    path: this.path,
+                    blobstores,
+                    write_quorum,
+                }
+            }
         };
 
         let enabled = this.enabled.unwrap_or(true);
         let generation_cache_size = this.generation_cache_size.unwrap_or(10 * 1024 * 1024);
         let repoid = this.repoid;
         let scuba_table = this.scuba_table;
+        let wireproto_recording_dir = this.wireproto_recording_dir;
+        let encryption_keyfile = this.encryption_keyfile;
+        let encryption_allow_plaintext = this.encryption_allow_plaintext.unwrap_or(false);
+        let skiplist_index_blobstore_key = this.skiplist_index_blobstore_key;
+        let disk_cache = this.disk_cache.map(|disk_cache| DiskCacheArgs {
+            path: disk_cache.path,
+            max_size: disk_cache.max_size.unwrap_or(10 * 1024 * 1024 * 1024),
+        });
+        let compression = this.compression.map(|compression| {
+            let default = CompressionArgs::default();
+            CompressionArgs {
+                threshold: compression.threshold.unwrap_or(default.threshold),
+                level: compression.level.unwrap_or(default.level),
+            }
+        });
         let cache_warmup = this.cache_warmup.map(|cache_warmup| CacheWarmupParams {
-            bookmark: Bookmark::new(cache_warmup.bookmark).expect("bookmark name must be ascii"),
+            bookmarks: cache_warmup
+                .bookmark
+                .into_iter()
+                .chain(cache_warmup.bookmarks.unwrap_or_default())
+                .map(|bookmark| Bookmark::new(bookmark).expect("bookmark name must be ascii"))
+                .collect(),
             commit_limit: cache_warmup.commit_limit.unwrap_or(200000),
+            file_limit: cache_warmup.file_limit.unwrap_or(0),
+            skiplist_depth: cache_warmup.skiplist_depth,
         });
         let bookmarks = match this.bookmarks {
             Some(bookmarks) => Some(
@@ -343,6 +440,12 @@ impl RepoConfigs {
             cache_warmup,
             bookmarks,
             hooks: hooks_opt,
+            wireproto_recording_dir,
+            encryption_keyfile,
+            encryption_allow_plaintext,
+            disk_cache,
+            compression,
+            skiplist_index_blobstore_key,
         })
     }
 }
@@ -367,14 +470,38 @@ struct RawRepoConfig {
     io_thread_num: Option<usize>,
     cache_warmup: Option<RawCacheWarmupConfig>,
     max_concurrent_requests_per_io_thread: Option<usize>,
+    cache_snapshot_dir: Option<PathBuf>,
     bookmarks: Option<Vec<RawBookmarkConfig>>,
     hooks: Option<Vec<RawHookConfig>>,
+    wireproto_recording_dir: Option<PathBuf>,
+    encryption_keyfile: Option<PathBuf>,
+    encryption_allow_plaintext: Option<bool>,
+    disk_cache: Option<RawDiskCacheConfig>,
+    compression: Option<RawCompressionConfig>,
+    skiplist_index_blobstore_key: Option<String>,
+    blobstores: Option<Vec<RawBlobstoreConfig>>,
+    write_quorum: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 struct RawCacheWarmupConfig {
-    bookmark: String,
+    bookmark: Option<String>,
+    bookmarks: Option<Vec<String>>,
     commit_limit: Option<usize>,
+    file_limit: Option<usize>,
+    skiplist_depth: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RawDiskCacheConfig {
+    path: PathBuf,
+    max_size: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RawCompressionConfig {
+    threshold: Option<usize>,
+    level: Option<i32>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -388,6 +515,13 @@ struct RawBookmarkHook {
     hook_name: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+struct RawBlobstoreConfig {
+    blobstore_id: u64,
+    blobstore_type: RawBlobstoreType,
+    path: PathBuf,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 struct RawHookConfig {
     name: String,
@@ -402,6 +536,14 @@ enum RawRepoType {
     #[serde(rename = "blob:rocks")] BlobRocks,
     #[serde(rename = "blob:testmanifold")] TestBlobManifold,
     #[serde(rename = "blob:testdelay")] TestBlobDelayRocks,
+    #[serde(rename = "blob:multiplexed")] BlobMultiplexed,
+}
+
+/// Types of the blobstores of a multiplexed repo
+#[derive(Clone, Debug, Deserialize)]
+enum RawBlobstoreType {
+    #[serde(rename = "files")] Files,
+    #[serde(rename = "rocks")] Rocks,
 }
 
 #[cfg(test)]
@@ -421,9 +563,15 @@ mod test {
             generation_cache_size=1048576
             repoid=0
             scuba_table="scuba_table"
+            wireproto_recording_dir="/tmp/fbsource_recordings"
+            encryption_keyfile="/etc/mononoke/fbsource_keys"
+            encryption_allow_plaintext=true
+            skiplist_index_blobstore_key="skiplist_index"
             [cache_warmup]
-            bookmark="master"
+            bookmarks=["master", "release"]
             commit_limit=100
+            file_limit=1000
+            skiplist_depth=100000
             [[bookmarks]]
             name="master"
             [[bookmarks.hooks]]
@@ -445,12 +593,31 @@ mod test {
             repoid=1
             scuba_table="scuba_table"
         "#;
+        let multiplexed_content = r#"
+            path="/tmp/multiplexed"
+            repotype="blob:multiplexed"
+            repoid=2
+            [[blobstores]]
+            blobstore_id=1
+            blobstore_type="rocks"
+            path="/data1/multiplexed"
+            [[blobstores]]
+            blobstore_id=2
+            blobstore_type="files"
+            path="/data2/multiplexed"
+            [disk_cache]
+            path="/ssd/multiplexed_cache"
+            max_size=1000000
+            [compression]
+            threshold=4096
+        "#;
 
         let paths = btreemap! {
             "common/hooks/hook1.lua" => (FileType::Regular, hook1_content),
             "repos/fbsource/server.toml" => (FileType::Regular, fbsource_content),
             "repos/fbsource/hooks/hook2.lua" => (FileType::Regular, hook2_content),
             "repos/www/server.toml" => (FileType::Regular, www_content),
+            "repos/multiplexed/server.toml" => (FileType::Regular, multiplexed_content),
             "my_path/my_files" => (FileType::Regular, ""),
         };
         let root_manifest = MockManifest::from_paths(paths).expect("manifest is valid");
@@ -468,8 +635,13 @@ mod test {
                 repoid: 0,
                 scuba_table: Some("scuba_table".to_string()),
                 cache_warmup: Some(CacheWarmupParams {
-                    bookmark: Bookmark::new("master").unwrap(),
+                    bookmarks: vec![
+                        Bookmark::new("master").unwrap(),
+                        Bookmark::new("release").unwrap(),
+                    ],
                     commit_limit: 100,
+                    file_limit: 1000,
+                    skiplist_depth: Some(100000),
                 }),
                 bookmarks: Some(vec![
                     BookmarkParams {
@@ -489,6 +661,12 @@ mod test {
                         hook_type: HookType::PerChangeset,
                     },
                 ]),
+                wireproto_recording_dir: Some("/tmp/fbsource_recordings".into()),
+                encryption_keyfile: Some("/etc/mononoke/fbsource_keys".into()),
+                encryption_allow_plaintext: true,
+                disk_cache: None,
+                compression: None,
+                skiplist_index_blobstore_key: Some("skiplist_index".to_string()),
             },
         );
         repos.insert(
@@ -502,6 +680,52 @@ mod test {
                 cache_warmup: None,
                 bookmarks: None,
                 hooks: None,
+                wireproto_recording_dir: None,
+                encryption_keyfile: None,
+                encryption_allow_plaintext: false,
+                disk_cache: None,
+                compression: None,
+                skiplist_index_blobstore_key: None,
+            },
+        );
+        repos.insert(
+            "multiplexed".to_string(),
+            RepoConfig {
+                enabled: true,
+                repotype: RepoType::BlobMultiplexed {
+                    path: "/tmp/multiplexed".into(),
+                    blobstores: vec![
+                        ComponentBlobstoreArgs {
+                            blobstore_id: BlobstoreId::new(1),
+                            blobstore_type: ComponentBlobstoreType::Rocks,
+                            path: "/data1/multiplexed".into(),
+                        },
+                        ComponentBlobstoreArgs {
+                            blobstore_id: BlobstoreId::new(2),
+                            blobstore_type: ComponentBlobstoreType::Files,
+                            path: "/data2/multiplexed".into(),
+                        },
+                    ],
+                    write_quorum: 2,
+                },
+                generation_cache_size: 10 * 1024 * 1024,
+                repoid: 2,
+                scuba_table: None,
+                cache_warmup: None,
+                bookmarks: None,
+                hooks: None,
+                wireproto_recording_dir: None,
+                encryption_keyfile: None,
+                encryption_allow_plaintext: false,
+                disk_cache: Some(DiskCacheArgs {
+                    path: "/ssd/multiplexed_cache".into(),
+                    max_size: 1_000_000,
+                }),
+                compression: Some(CompressionArgs {
+                    threshold: 4096,
+                    level: 3,
+                }),
+                skiplist_index_blobstore_key: None,
             },
         );
         assert_eq!(
@@ -512,4 +736,33 @@ mod test {
             }
         )
     }
+
+    #[test]
+    fn test_multiplexed_duplicate_blobstore_id() {
+        let multiplexed_content = r#"
+            path="/tmp/multiplexed"
+            repotype="blob:multiplexed"
+            repoid=2
+            [[blobstores]]
+            blobstore_id=1
+            blobstore_type="files"
+            path="/data1/multiplexed"
+            [[blobstores]]
+            blobstore_id=1
+            blobstore_type="files"
+            path="/data2/multiplexed"
+        "#;
+
+        let paths = btreemap! {
+            "repos/multiplexed/server.toml" => (FileType::Regular, multiplexed_content),
+        };
+        let root_manifest = MockManifest::from_paths(paths).expect("manifest is valid");
+        let err = RepoConfigs::read_manifest(&root_manifest)
+            .wait()
+            .expect_err("duplicate blobstore ids were accepted");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::InvalidConfig(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 }
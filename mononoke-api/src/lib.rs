@@ -17,19 +17,21 @@ extern crate futures;
 extern crate futures_ext;
 extern crate mercurial_types;
 extern crate mononoke_types;
+extern crate revset;
 
 pub mod errors;
 
 use std::sync::Arc;
 
-use failure::Error;
-use futures::Future;
+use failure::{Error, Result};
+use futures::{Future, Stream};
 
 use blobrepo::BlobRepo;
 use bookmarks::Bookmark;
 use mercurial_types::{Changeset, HgChangesetId};
 use mercurial_types::manifest::Content;
 use mononoke_types::MPath;
+use revset::{BisectStep, RevsetExpr};
 
 use errors::ErrorKind;
 
@@ -67,3 +69,24 @@ pub fn get_changeset_by_bookmark(
             }
         })
 }
+
+/// The changesets of a revset expression, from the highest generation. See `RevsetExpr` for the
+/// syntax. Invalid expressions and unknown revisions are reported with `revset::ErrorKind`.
+pub fn get_changesets_by_revset(
+    repo: Arc<BlobRepo>,
+    revset: &str,
+) -> Result<impl Stream<Item = HgChangesetId, Error = Error>> {
+    let expr = RevsetExpr::parse(revset)?;
+    Ok(expr.evaluate(&repo).map(HgChangesetId::new))
+}
+
+/// The next changeset to test to find the first bad changeset, given all the changesets found
+/// good so far and the last one found bad, and how many changesets may still be the first bad one.
+pub fn get_bisect_step(
+    repo: Arc<BlobRepo>,
+    goods: Vec<HgChangesetId>,
+    bad: HgChangesetId,
+) -> impl Future<Item = BisectStep, Error = Error> {
+    let goods = goods.iter().map(|good| *good.as_nodehash()).collect();
+    revset::bisect(&repo, goods, *bad.as_nodehash())
+}
@@ -38,6 +38,7 @@ union IdType {
 
 typedef IdType ChangesetId (hs.newtype)
 typedef IdType ContentId (hs.newtype)
+typedef IdType ContentChunkId (hs.newtype)
 
 // mercurial_types defines Sha1, and it's most convenient to stick this in here.
 // This can be moved away in the future if necessary.
@@ -113,6 +114,26 @@ struct DateTime {
 
 union FileContents {
   1: binary Bytes,
+  2: ChunkedFileContents Chunked,
+}
+
+// Contents too large to be stored in a single blob are split into chunks,
+// each stored as a separate ContentChunk blob.
+union ContentChunk {
+  1: binary Bytes,
+}
+
+struct ContentChunkPointer {
+  1: ContentChunkId chunk_id,
+  2: i64 size,
+}
+
+// The id of chunked contents is the id the same contents would have if they
+// were stored as Bytes, so that ids don't depend on how contents are stored.
+struct ChunkedFileContents {
+  1: ContentId content_id,
+  2: i64 size,
+  3: list<ContentChunkPointer> chunks,
 }
 
 enum FileType {
@@ -0,0 +1,109 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::fmt::{self, Debug};
+
+use bytes::Bytes;
+use failure::SyncFailure;
+use quickcheck::{single_shrinker, Arbitrary, Gen};
+
+use rust_thrift::compact_protocol;
+
+use blob::{Blob, BlobstoreValue, ContentChunkBlob};
+use errors::*;
+use thrift;
+use typed_hash::{ContentChunkId, ContentChunkIdContext};
+
+/// A chunk of the contents of a file that is too large to be stored as a single blob. See
+/// `FileContents::Chunked`.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ContentChunk(Bytes);
+
+impl ContentChunk {
+    pub fn new_bytes<B: Into<Bytes>>(b: B) -> Self {
+        ContentChunk(b.into())
+    }
+
+    pub(crate) fn from_thrift(cc: thrift::ContentChunk) -> Result<Self> {
+        match cc {
+            thrift::ContentChunk::Bytes(bytes) => Ok(ContentChunk(bytes.into())),
+            thrift::ContentChunk::UnknownField(x) => bail_err!(ErrorKind::InvalidThrift(
+                "ContentChunk".into(),
+                format!("unknown content chunk field: {}", x)
+            )),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ContentChunk {
+        // TODO (T26959816) -- allow Thrift to represent binary as Bytes
+        thrift::ContentChunk::Bytes(self.0.to_vec())
+    }
+}
+
+impl BlobstoreValue for ContentChunk {
+    type Key = ContentChunkId;
+
+    fn into_blob(self) -> ContentChunkBlob {
+        let thrift = self.into_thrift();
+        let data = compact_protocol::serialize(&thrift);
+        let mut context = ContentChunkIdContext::new();
+        context.update(&data);
+        let id = context.finish();
+        Blob::new(id, data)
+    }
+
+    fn from_blob(blob: Blob<Self::Key>) -> Result<Self> {
+        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
+        let thrift_tc = compact_protocol::deserialize(blob.data().as_ref())
+            .map_err(SyncFailure::new)
+            .context(ErrorKind::BlobDeserializeError("ContentChunk".into()))?;
+        Self::from_thrift(thrift_tc)
+    }
+}
+
+impl Debug for ContentChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ContentChunk(length {})", self.0.len())
+    }
+}
+
+impl Arbitrary for ContentChunk {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        ContentChunk::new_bytes(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        single_shrinker(ContentChunk::new_bytes(vec![]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    quickcheck! {
+        fn blob_roundtrip(cc: ContentChunk) -> bool {
+            let blob = cc.clone().into_blob();
+            let cc2 = ContentChunk::from_blob(blob)
+                .expect("blob roundtrips should always be valid");
+            cc == cc2
+        }
+    }
+
+    #[test]
+    fn bad_thrift() {
+        let thrift_cc = thrift::ContentChunk::UnknownField(-1);
+        ContentChunk::from_thrift(thrift_cc).expect_err("unexpected OK - unknown field");
+    }
+}
@@ -6,7 +6,7 @@
 
 use std::fmt::{self, Display};
 
-use chrono::{DateTime as ChronoDateTime, FixedOffset, LocalResult, TimeZone};
+use chrono::{DateTime as ChronoDateTime, FixedOffset, LocalResult, TimeZone, Utc};
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use errors::*;
@@ -21,6 +21,11 @@ impl DateTime {
         DateTime(dt)
     }
 
+    /// The current time, in UTC.
+    pub fn now() -> Self {
+        Self::new(Utc::now().with_timezone(&FixedOffset::east(0)))
+    }
+
     pub fn from_timestamp(secs: i64, tz_offset_secs: i32) -> Result<Self> {
         let tz = FixedOffset::west_opt(tz_offset_secs).ok_or_else(|| {
             ErrorKind::InvalidDateTime(format!("timezone offset out of range: {}", tz_offset_secs))
@@ -19,6 +19,8 @@ pub enum ErrorKind {
     #[fail(display = "not path-conflict-free: changed path '{}' is a prefix of '{}'", _0, _1)]
     NotPathConflictFree(MPath, MPath),
     #[fail(display = "invalid bonsai changeset: {}", _0)] InvalidBonsaiChangeset(String),
+    #[fail(display = "file contents have {} bytes, expected {}", _0, _1)]
+    FileContentsSizeMismatch(u64, u64),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
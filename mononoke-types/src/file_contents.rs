@@ -5,23 +5,31 @@
 // GNU General Public License version 2 or any later version.
 
 use std::fmt::{self, Debug};
+use std::mem;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use failure::SyncFailure;
 use quickcheck::{single_shrinker, Arbitrary, Gen};
 
 use rust_thrift::compact_protocol;
 
-use blob::{Blob, BlobstoreValue, ContentBlob};
+use blob::{Blob, BlobstoreValue, ContentBlob, ContentChunkBlob};
+use content_chunk::ContentChunk;
 use errors::*;
 use thrift;
-use typed_hash::{ContentId, ContentIdContext};
+use typed_hash::{ContentChunkId, ContentId, ContentIdContext};
 
-/// An enum representing contents for a file. In the future this may have
-/// special support for very large files.
+/// Contents larger than this are split into chunks of this size when they are stored.
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// An enum representing contents for a file.
 #[derive(Clone, Eq, PartialEq)]
 pub enum FileContents {
     Bytes(Bytes),
+    /// Contents split into chunks that are stored as separate blobs. Only the list of the chunks
+    /// is in memory. This is what is stored for very large files; `BlobRepo` fetches the chunks,
+    /// and the APIs that return whole contents return them as `Bytes`.
+    Chunked(ChunkedFileContents),
 }
 
 impl FileContents {
@@ -32,6 +40,9 @@ impl FileContents {
     pub(crate) fn from_thrift(fc: thrift::FileContents) -> Result<Self> {
         match fc {
             thrift::FileContents::Bytes(bytes) => Ok(FileContents::Bytes(bytes.into())),
+            thrift::FileContents::Chunked(chunked) => Ok(FileContents::Chunked(
+                ChunkedFileContents::from_thrift(chunked)?,
+            )),
             thrift::FileContents::UnknownField(x) => bail_err!(ErrorKind::InvalidThrift(
                 "FileContents".into(),
                 format!("unknown file contents field: {}", x)
@@ -42,20 +53,50 @@ impl FileContents {
     pub fn size(&self) -> usize {
         match *self {
             FileContents::Bytes(ref bytes) => bytes.len(),
+            FileContents::Chunked(ref chunked) => chunked.size() as usize,
+        }
+    }
+
+    /// The id of the contents. It doesn't depend on whether the contents are chunked.
+    pub fn content_id(&self) -> ContentId {
+        match *self {
+            FileContents::Bytes(ref bytes) => {
+                let mut context = FileContentsIdContext::new(bytes.len() as u64);
+                context.update(bytes);
+                context
+                    .finish()
+                    .expect("the size of the contents was given upfront")
+            }
+            FileContents::Chunked(ref chunked) => chunked.content_id(),
         }
     }
 
     /// Whether this starts with a particular string.
+    ///
+    /// Panics if the contents are chunked.
     #[inline]
     pub fn starts_with(&self, needle: &[u8]) -> bool {
-        match self {
-            FileContents::Bytes(b) => b.starts_with(needle),
-        }
+        self.as_bytes().starts_with(needle)
     }
 
+    /// Panics if the contents are chunked: they have to be fetched first.
     pub fn into_bytes(self) -> Bytes {
         match self {
             FileContents::Bytes(bytes) => bytes,
+            FileContents::Chunked(chunked) => panic!(
+                "contents {} are chunked and were not fetched",
+                chunked.content_id()
+            ),
+        }
+    }
+
+    fn as_bytes(&self) -> &Bytes {
+        match *self {
+            FileContents::Bytes(ref bytes) => bytes,
+            FileContents::Chunked(ref chunked) => panic!(
+                "contents {} are chunked and were not fetched",
+                chunked.content_id()
+            ),
         }
     }
 
@@ -63,6 +104,7 @@ impl FileContents {
         match self {
             // TODO (T26959816) -- allow Thrift to represent binary as Bytes
             FileContents::Bytes(bytes) => thrift::FileContents::Bytes(bytes.to_vec()),
+            FileContents::Chunked(chunked) => thrift::FileContents::Chunked(chunked.into_thrift()),
         }
     }
 }
@@ -71,11 +113,20 @@ impl BlobstoreValue for FileContents {
     type Key = ContentId;
 
     fn into_blob(self) -> ContentBlob {
+        // Chunked contents are stored under the id the whole contents have, not under the hash
+        // of the list of chunks.
+        let chunked_id = match self {
+            FileContents::Bytes(_) => None,
+            FileContents::Chunked(ref chunked) => Some(chunked.content_id()),
+        };
+
         let thrift = self.into_thrift();
         let data = compact_protocol::serialize(&thrift);
-        let mut context = ContentIdContext::new();
-        context.update(&data);
-        let id = context.finish();
+        let id = chunked_id.unwrap_or_else(|| {
+            let mut context = ContentIdContext::new();
+            context.update(&data);
+            context.finish()
+        });
         Blob::new(id, data)
     }
 
@@ -94,6 +145,12 @@ impl Debug for FileContents {
             FileContents::Bytes(ref bytes) => {
                 write!(f, "FileContents::Bytes(length {})", bytes.len())
             }
+            FileContents::Chunked(ref chunked) => write!(
+                f,
+                "FileContents::Chunked(length {}, {} chunks)",
+                chunked.size(),
+                chunked.chunks().len()
+            ),
         }
     }
 }
@@ -108,10 +165,268 @@ impl Arbitrary for FileContents {
     }
 }
 
+/// The list of the chunks of file contents. The chunks are in order, and their sizes add up to
+/// the size of the contents.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkedFileContents {
+    content_id: ContentId,
+    size: u64,
+    chunks: Vec<ContentChunkPointer>,
+}
+
+impl ChunkedFileContents {
+    pub fn new(content_id: ContentId, chunks: Vec<ContentChunkPointer>) -> Self {
+        let size = chunks.iter().map(|chunk| chunk.size()).sum();
+        Self {
+            content_id,
+            size,
+            chunks,
+        }
+    }
+
+    pub(crate) fn from_thrift(cfc: thrift::ChunkedFileContents) -> Result<Self> {
+        let chunks = cfc.chunks
+            .into_iter()
+            .map(ContentChunkPointer::from_thrift)
+            .collect::<Result<Vec<_>>>()?;
+        let chunked = Self::new(ContentId::from_thrift(cfc.content_id)?, chunks);
+        if chunked.size as i64 != cfc.size {
+            bail_err!(ErrorKind::InvalidThrift(
+                "ChunkedFileContents".into(),
+                format!(
+                    "size is {}, but the chunks have {} bytes",
+                    cfc.size, chunked.size
+                )
+            ));
+        }
+        Ok(chunked)
+    }
+
+    #[inline]
+    pub fn content_id(&self) -> ContentId {
+        self.content_id
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn chunks(&self) -> &[ContentChunkPointer] {
+        &self.chunks
+    }
+
+    #[inline]
+    pub fn into_chunks(self) -> Vec<ContentChunkPointer> {
+        self.chunks
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ChunkedFileContents {
+        thrift::ChunkedFileContents {
+            content_id: self.content_id.into_thrift(),
+            size: self.size as i64,
+            chunks: self.chunks
+                .into_iter()
+                .map(ContentChunkPointer::into_thrift)
+                .collect(),
+        }
+    }
+}
+
+/// Refers to one of the chunks of chunked file contents.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ContentChunkPointer {
+    chunk_id: ContentChunkId,
+    size: u64,
+}
+
+impl ContentChunkPointer {
+    pub fn new(chunk_id: ContentChunkId, size: u64) -> Self {
+        Self { chunk_id, size }
+    }
+
+    pub(crate) fn from_thrift(ccp: thrift::ContentChunkPointer) -> Result<Self> {
+        if ccp.size < 0 {
+            bail_err!(ErrorKind::InvalidThrift(
+                "ContentChunkPointer".into(),
+                format!("negative size: {}", ccp.size)
+            ));
+        }
+        Ok(Self::new(
+            ContentChunkId::from_thrift(ccp.chunk_id)?,
+            ccp.size as u64,
+        ))
+    }
+
+    #[inline]
+    pub fn chunk_id(&self) -> ContentChunkId {
+        self.chunk_id
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ContentChunkPointer {
+        thrift::ContentChunkPointer {
+            chunk_id: self.chunk_id.into_thrift(),
+            size: self.size as i64,
+        }
+    }
+}
+
+/// Incrementally computes the id of file contents without having all of them in memory. The id
+/// is the one `FileContents::Bytes` has, i.e. the hash of its Thrift serialization, so the size
+/// of the contents, which comes first in the serialization, has to be known upfront.
+#[derive(Clone)]
+pub struct FileContentsIdContext {
+    context: ContentIdContext,
+    size: u64,
+    written: u64,
+}
+
+impl FileContentsIdContext {
+    pub fn new(size: u64) -> Self {
+        let mut context = ContentIdContext::new();
+        // Compact protocol field header of the Bytes field: id 1 (as a delta from 0) in the high
+        // nibble, type binary (8) in the low one.
+        context.update(&[0x18]);
+        // The length of the binary, as a varint.
+        let mut varint = Vec::with_capacity(10);
+        let mut n = size;
+        while n >= 0x80 {
+            varint.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        varint.push(n as u8);
+        context.update(&varint);
+
+        Self {
+            context,
+            size,
+            written: 0,
+        }
+    }
+
+    pub fn update<T: AsRef<[u8]>>(&mut self, data: T) {
+        let data = data.as_ref();
+        self.written += data.len() as u64;
+        self.context.update(data);
+    }
+
+    /// Fails if the contents didn't have the size given upfront.
+    pub fn finish(mut self) -> Result<ContentId> {
+        if self.written != self.size {
+            bail_err!(ErrorKind::FileContentsSizeMismatch(
+                self.written,
+                self.size
+            ));
+        }
+        // Compact protocol field stop.
+        self.context.update(&[0x00]);
+        Ok(self.context.finish())
+    }
+}
+
+/// Splits file contents into chunks as they arrive, so that large contents can be stored
+/// without having all of them in memory.
+pub struct ChunkedFileContentsBuilder {
+    chunk_size: usize,
+    id_context: FileContentsIdContext,
+    // The start of the next chunk, when the data written so far doesn't end on a chunk boundary.
+    pending: BytesMut,
+    chunks: Vec<ContentChunkPointer>,
+}
+
+impl ChunkedFileContentsBuilder {
+    /// `size` is the size of the whole contents.
+    pub fn new(size: u64, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        Self {
+            chunk_size,
+            id_context: FileContentsIdContext::new(size),
+            pending: BytesMut::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Adds the next part of the contents, and returns the chunks it completed. The chunks are
+    /// sliced out of `data` whenever possible, without copying it.
+    pub fn write(&mut self, mut data: Bytes) -> Vec<ContentChunkBlob> {
+        self.id_context.update(&data);
+        let mut blobs = Vec::new();
+
+        if !self.pending.is_empty() {
+            let missing = self.chunk_size - self.pending.len();
+            let len = data.len();
+            let head = data.split_to(missing.min(len));
+            self.pending.extend_from_slice(&head);
+            if self.pending.len() == self.chunk_size {
+                let chunk = mem::replace(&mut self.pending, BytesMut::new()).freeze();
+                blobs.push(self.add_chunk(chunk));
+            }
+        }
+
+        while data.len() >= self.chunk_size {
+            let chunk = data.split_to(self.chunk_size);
+            blobs.push(self.add_chunk(chunk));
+        }
+
+        if !data.is_empty() {
+            self.pending.extend_from_slice(&data);
+        }
+        blobs
+    }
+
+    /// Returns the last chunk, if it wasn't returned by `write` yet, and the contents that refer
+    /// to all the chunks. Fails if the contents didn't have the size given upfront.
+    pub fn finish(mut self) -> Result<(Option<ContentChunkBlob>, FileContents)> {
+        let last = if self.pending.is_empty() {
+            None
+        } else {
+            let chunk = mem::replace(&mut self.pending, BytesMut::new()).freeze();
+            Some(self.add_chunk(chunk))
+        };
+
+        let content_id = self.id_context.finish()?;
+        let chunked = ChunkedFileContents::new(content_id, self.chunks);
+        Ok((last, FileContents::Chunked(chunked)))
+    }
+
+    fn add_chunk(&mut self, chunk: Bytes) -> ContentChunkBlob {
+        let size = chunk.len() as u64;
+        let blob = ContentChunk::new_bytes(chunk).into_blob();
+        self.chunks.push(ContentChunkPointer::new(*blob.id(), size));
+        blob
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn chunk(data: &[u8], chunk_size: usize, write_size: usize) -> (Vec<Bytes>, FileContents) {
+        let mut builder = ChunkedFileContentsBuilder::new(data.len() as u64, chunk_size);
+        let mut blobs = Vec::new();
+        for part in data.chunks(write_size) {
+            blobs.extend(builder.write(Bytes::from(part)));
+        }
+        let (last, contents) = builder.finish().expect("size should match");
+        blobs.extend(last);
+
+        let chunks = blobs
+            .into_iter()
+            .map(|blob| {
+                ContentChunk::from_blob(blob)
+                    .expect("chunk blobs should be valid")
+                    .into_bytes()
+            })
+            .collect();
+        (chunks, contents)
+    }
+
     quickcheck! {
         fn thrift_roundtrip(fc: FileContents) -> bool {
             let thrift_fc = fc.clone().into_thrift();
@@ -126,6 +441,46 @@ mod test {
                 .expect("blob roundtrips should always be valid");
             cs == cs2
         }
+
+        fn incremental_content_id(fc: FileContents) -> bool {
+            let id = *fc.clone().into_blob().id();
+            fc.content_id() == id
+        }
+
+        fn chunked_roundtrip(data: Vec<u8>, chunk_size: usize, write_size: usize) -> bool {
+            let chunk_size = chunk_size % 16 + 1;
+            let write_size = write_size % 16 + 1;
+            let (chunks, contents) = chunk(&data, chunk_size, write_size);
+
+            let expected_id = FileContents::new_bytes(data.clone()).content_id();
+            let blob = contents.clone().into_blob();
+            let contents2 = FileContents::from_blob(blob)
+                .expect("blob roundtrips should always be valid");
+
+            chunks.iter().all(|chunk| chunk.len() <= chunk_size)
+                && chunks.concat() == data
+                && contents.size() == data.len()
+                && contents.content_id() == expected_id
+                && contents == contents2
+        }
+    }
+
+    #[test]
+    fn chunked_blob_key() {
+        let data = b"some file contents";
+        let (chunks, contents) = chunk(data, 5, 7);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(
+            *contents.into_blob().id(),
+            *FileContents::new_bytes(&data[..]).into_blob().id()
+        );
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let mut builder = ChunkedFileContentsBuilder::new(10, 4);
+        builder.write(Bytes::from(&b"too short"[..]));
+        builder.finish().expect_err("unexpected OK - wrong size");
     }
 
     #[test]
@@ -32,6 +32,11 @@ impl Generation {
         Generation(u64::MAX)
     }
 
+    /// The generation number as an integer
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
     /// The difference from this generation to the other as the difference in their
     /// generation numbers.
     /// If this Generation is smaller than the other, return None.
@@ -42,6 +42,7 @@ extern crate mononoke_types_thrift;
 
 pub mod blob;
 pub mod bonsai_changeset;
+pub mod content_chunk;
 pub mod datetime;
 pub mod errors;
 pub mod file_change;
@@ -52,14 +53,17 @@ pub mod path;
 pub mod sql_types;
 pub mod typed_hash;
 
-pub use blob::{Blob, BlobstoreBytes, BlobstoreValue, ChangesetBlob, ContentBlob};
+pub use blob::{Blob, BlobstoreBytes, BlobstoreValue, ChangesetBlob, ContentBlob,
+               ContentChunkBlob};
 pub use bonsai_changeset::{BonsaiChangeset, BonsaiChangesetMut};
+pub use content_chunk::ContentChunk;
 pub use datetime::DateTime;
 pub use file_change::{FileChange, FileType};
-pub use file_contents::FileContents;
+pub use file_contents::{ChunkedFileContents, ChunkedFileContentsBuilder, ContentChunkPointer,
+                        FileContents, FileContentsIdContext, DEFAULT_CHUNK_SIZE};
 pub use generation::Generation;
 pub use path::{MPath, MPathElement, RepoPath};
-pub use typed_hash::{ChangesetId, ContentId, MononokeId};
+pub use typed_hash::{ChangesetId, ContentChunkId, ContentId, MononokeId};
 
 mod thrift {
     pub use mononoke_types_thrift::*;
@@ -13,6 +13,7 @@ use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use blob::BlobstoreValue;
 use bonsai_changeset::BonsaiChangeset;
+use content_chunk::ContentChunk;
 use errors::*;
 use file_contents::FileContents;
 use hash::{Blake2, Context};
@@ -45,6 +46,11 @@ pub struct ChangesetId(Blake2);
 #[derive(HeapSizeOf)]
 pub struct ContentId(Blake2);
 
+/// An identifier for a chunk of file contents in Mononoke.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+#[derive(HeapSizeOf)]
+pub struct ContentChunkId(Blake2);
+
 /// Implementations of typed hashes.
 macro_rules! impl_typed_hash {
     {
@@ -203,6 +209,13 @@ impl_typed_hash! {
     context_key => "content",
 }
 
+impl_typed_hash! {
+    hash_type => ContentChunkId,
+    value_type => ContentChunk,
+    context_type => ContentChunkIdContext,
+    context_key => "content_chunk",
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -221,6 +234,13 @@ mod test {
                 .expect("converting a valid Thrift structure should always work");
             h == sh
         }
+
+        fn contentchunkid_thrift_roundtrip(h: ContentChunkId) -> bool {
+            let v = h.into_thrift();
+            let sh = ContentChunkId::from_thrift(v)
+                .expect("converting a valid Thrift structure should always work");
+            h == sh
+        }
     }
 
     #[test]
@@ -232,5 +252,8 @@ mod test {
 
         let id = ContentId::new(Blake2::from_byte_array([1; 32]));
         assert_eq!(id.blobstore_key(), format!("content.blake2.{}", id));
+
+        let id = ContentChunkId::new(Blake2::from_byte_array([1; 32]));
+        assert_eq!(id.blobstore_key(), format!("content_chunk.blake2.{}", id));
     }
 }
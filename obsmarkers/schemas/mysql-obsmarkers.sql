@@ -0,0 +1,12 @@
+CREATE TABLE obsmarkers (
+  repo_id INT UNSIGNED NOT NULL,
+  marker_id VARBINARY(20) NOT NULL,
+  marker BLOB NOT NULL,
+  PRIMARY KEY (repo_id, marker_id)
+);
+CREATE TABLE obsmarkers_related (
+  repo_id INT UNSIGNED NOT NULL,
+  changeset_id VARBINARY(20) NOT NULL,
+  marker_id VARBINARY(20) NOT NULL,
+  PRIMARY KEY (repo_id, changeset_id, marker_id)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE obsmarkers (
+  repo_id INTEGER NOT NULL,
+  marker_id BINARY(20) NOT NULL,
+  marker BLOB NOT NULL,
+  PRIMARY KEY (repo_id, marker_id)
+);
+CREATE TABLE obsmarkers_related (
+  repo_id INTEGER NOT NULL,
+  changeset_id BINARY(20) NOT NULL,
+  marker_id BINARY(20) NOT NULL,
+  PRIMARY KEY (repo_id, changeset_id, marker_id)
+);
@@ -0,0 +1,278 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Storage for Mercurial obsolescence markers.
+//!
+//! Markers are stored encoded in the Mercurial version 1 format, keyed by their id. A separate
+//! table maps every changeset a marker is relevant to (predecessor, successors and parents) to
+//! the marker, so that markers for a set of changesets can be found quickly.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+extern crate db_conn;
+#[macro_use]
+extern crate diesel;
+extern crate failure_ext as failure;
+extern crate futures;
+
+#[macro_use]
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+#[macro_use]
+extern crate stats;
+
+use std::collections::HashSet;
+use std::result;
+use std::sync::{Arc, MutexGuard};
+
+use db_conn::{MysqlConnInner, SqliteConnInner};
+use diesel::{insert_or_ignore_into, MysqlConnection, SqliteConnection};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+
+use futures_ext::{asynchronize, BoxFuture};
+use mercurial_types::{HgChangesetId, HgObsMarker, RepositoryId};
+use stats::Timeseries;
+
+mod mem_writes;
+mod models;
+mod schema;
+
+pub use failure::{Error, Result};
+pub use mem_writes::MemWritesObsMarkers;
+use models::{ObsMarkerRelatedRow, ObsMarkerRow};
+use schema::{obsmarkers, obsmarkers_related};
+
+/// Maximum number of values used in a single `IN` clause. SQLite doesn't allow more than 999
+/// variables in a query.
+const MAX_IN_CLAUSE_SIZE: usize = 500;
+
+define_stats! {
+    prefix = "mononoke.obsmarkers";
+    gets: timeseries(RATE, SUM),
+    gets_all: timeseries(RATE, SUM),
+    adds: timeseries(RATE, SUM),
+}
+
+pub trait ObsMarkers: Send + Sync {
+    /// Store the markers. Markers that are already stored are ignored.
+    fn add(&self, repo_id: RepositoryId, markers: Vec<HgObsMarker>) -> BoxFuture<(), Error>;
+
+    /// Returns all the markers stored for the repo.
+    fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<HgObsMarker>, Error>;
+
+    /// Returns the markers that are relevant to any of the `changesets`, i.e. markers that have
+    /// one of them as their predecessor, one of their successors or one of their parents.
+    fn get_relevant(
+        &self,
+        repo_id: RepositoryId,
+        changesets: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<HgObsMarker>, Error>;
+}
+
+impl ObsMarkers for Arc<ObsMarkers> {
+    fn add(&self, repo_id: RepositoryId, markers: Vec<HgObsMarker>) -> BoxFuture<(), Error> {
+        (**self).add(repo_id, markers)
+    }
+
+    fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        (**self).get_all(repo_id)
+    }
+
+    fn get_relevant(
+        &self,
+        repo_id: RepositoryId,
+        changesets: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        (**self).get_relevant(repo_id, changesets)
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteObsMarkers {
+    inner: SqliteConnInner,
+}
+
+impl SqliteObsMarkers {
+    fn from(inner: SqliteConnInner) -> Self {
+        Self { inner }
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/sqlite-obsmarkers.sql")
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::in_memory(
+            Self::get_up_query(),
+        )?))
+    }
+
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::open_or_create(
+            path,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_master_conn()
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlObsMarkers {
+    inner: MysqlConnInner,
+}
+
+impl MysqlObsMarkers {
+    fn from(inner: MysqlConnInner) -> Self {
+        Self { inner }
+    }
+
+    pub fn open(db_address: &str) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::open(db_address)?))
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/mysql-obsmarkers.sql")
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::create_test_db(
+            prefix,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_master_conn()
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_obsmarkers {
+    ($struct:ty) => {
+        impl ObsMarkers for $struct {
+            fn add(
+                &self,
+                repo_id: RepositoryId,
+                markers: Vec<HgObsMarker>,
+            ) -> BoxFuture<(), Error> {
+                STATS::adds.add_value(markers.len() as i64);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let mut marker_rows = Vec::with_capacity(markers.len());
+                    let mut related_rows = Vec::new();
+                    for marker in markers {
+                        let marker_id = marker.id()?.as_ref().to_vec();
+                        for changeset_id in marker.related_changesets() {
+                            related_rows.push(ObsMarkerRelatedRow {
+                                repo_id,
+                                changeset_id,
+                                marker_id: marker_id.clone(),
+                            });
+                        }
+                        marker_rows.push(ObsMarkerRow {
+                            repo_id,
+                            marker_id,
+                            marker: marker.encode()?.to_vec(),
+                        });
+                    }
+
+                    if marker_rows.is_empty() {
+                        return Ok(());
+                    }
+
+                    let connection = db.get_master_conn()?;
+                    connection.transaction::<_, Error, _>(|| {
+                        insert_or_ignore_into(obsmarkers::table)
+                            .values(&marker_rows)
+                            .execute(&*connection)?;
+                        insert_or_ignore_into(obsmarkers_related::table)
+                            .values(&related_rows)
+                            .execute(&*connection)?;
+                        Ok(())
+                    })
+                })
+            }
+
+            fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<HgObsMarker>, Error> {
+                STATS::gets_all.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let rows = obsmarkers::table
+                        .filter(obsmarkers::repo_id.eq(repo_id))
+                        .load::<ObsMarkerRow>(&*connection)?;
+                    decode_rows(rows)
+                })
+            }
+
+            fn get_relevant(
+                &self,
+                repo_id: RepositoryId,
+                changesets: Vec<HgChangesetId>,
+            ) -> BoxFuture<Vec<HgObsMarker>, Error> {
+                STATS::gets.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+
+                    let mut marker_ids = HashSet::new();
+                    for chunk in changesets.chunks(MAX_IN_CLAUSE_SIZE) {
+                        let ids = obsmarkers_related::table
+                            .filter(obsmarkers_related::repo_id.eq(repo_id))
+                            .filter(obsmarkers_related::changeset_id.eq_any(chunk))
+                            .select(obsmarkers_related::marker_id)
+                            .load::<Vec<u8>>(&*connection)?;
+                        marker_ids.extend(ids);
+                    }
+
+                    let marker_ids: Vec<_> = marker_ids.into_iter().collect();
+                    let mut rows = Vec::with_capacity(marker_ids.len());
+                    for chunk in marker_ids.chunks(MAX_IN_CLAUSE_SIZE) {
+                        rows.extend(
+                            obsmarkers::table
+                                .filter(obsmarkers::repo_id.eq(repo_id))
+                                .filter(obsmarkers::marker_id.eq_any(chunk))
+                                .load::<ObsMarkerRow>(&*connection)?,
+                        );
+                    }
+                    decode_rows(rows)
+                })
+            }
+        }
+    };
+}
+
+impl_obsmarkers!(MysqlObsMarkers);
+impl_obsmarkers!(SqliteObsMarkers);
+
+fn decode_rows(mut rows: Vec<ObsMarkerRow>) -> Result<Vec<HgObsMarker>> {
+    // Return markers in a stable order.
+    rows.sort_by(|a, b| a.marker_id.cmp(&b.marker_id));
+    rows.into_iter()
+        .map(|row| HgObsMarker::decode(&row.marker))
+        .collect()
+}
@@ -0,0 +1,104 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use failure::{Error, Result};
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+use mercurial_types::{HgChangesetId, HgObsMarker, RepositoryId};
+use mercurial_types::hash::Sha1;
+
+use ObsMarkers;
+
+/// An obsmarkers store that reads from the underlying store but keeps the markers added through
+/// it in memory.
+#[derive(Clone)]
+pub struct MemWritesObsMarkers {
+    inner: Arc<ObsMarkers>,
+    mem: Arc<Mutex<HashMap<RepositoryId, BTreeMap<Sha1, HgObsMarker>>>>,
+}
+
+impl MemWritesObsMarkers {
+    pub fn new(inner: Arc<ObsMarkers>) -> Self {
+        Self {
+            inner,
+            mem: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The markers added in memory for the repo that `filter` accepts.
+    fn mem_markers<F>(&self, repo_id: RepositoryId, filter: F) -> Vec<(Sha1, HgObsMarker)>
+    where
+        F: Fn(&HgObsMarker) -> bool,
+    {
+        let mem = self.mem.lock().expect("lock poisoned");
+        match mem.get(&repo_id) {
+            Some(markers) => markers
+                .iter()
+                .filter(|&(_, marker)| filter(marker))
+                .map(|(id, marker)| (*id, marker.clone()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Adds the markers kept in memory to the fetched ones, in the order of their ids like the
+/// underlying stores return them. A marker can be in both.
+fn merge(fetched: Vec<HgObsMarker>, mem: Vec<(Sha1, HgObsMarker)>) -> Result<Vec<HgObsMarker>> {
+    let mut markers = BTreeMap::new();
+    for marker in fetched {
+        markers.insert(marker.id()?, marker);
+    }
+    markers.extend(mem);
+    Ok(markers.into_iter().map(|(_, marker)| marker).collect())
+}
+
+impl ObsMarkers for MemWritesObsMarkers {
+    fn add(&self, repo_id: RepositoryId, markers: Vec<HgObsMarker>) -> BoxFuture<(), Error> {
+        let markers = try_boxfuture!(
+            markers
+                .into_iter()
+                .map(|marker| Ok((marker.id()?, marker)))
+                .collect::<Result<Vec<_>>>()
+        );
+        let mut mem = self.mem.lock().expect("lock poisoned");
+        mem.entry(repo_id)
+            .or_insert_with(BTreeMap::new)
+            .extend(markers);
+        future::ok(()).boxify()
+    }
+
+    fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        let mem = self.mem_markers(repo_id, |_| true);
+        self.inner
+            .get_all(repo_id)
+            .and_then(move |fetched| merge(fetched, mem))
+            .boxify()
+    }
+
+    fn get_relevant(
+        &self,
+        repo_id: RepositoryId,
+        changesets: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<HgObsMarker>, Error> {
+        let mem = {
+            let changesets: HashSet<_> = changesets.iter().collect();
+            self.mem_markers(repo_id, |marker| {
+                marker
+                    .related_changesets()
+                    .iter()
+                    .any(|cs| changesets.contains(cs))
+            })
+        };
+        self.inner
+            .get_relevant(repo_id, changesets)
+            .and_then(move |fetched| merge(fetched, mem))
+            .boxify()
+    }
+}
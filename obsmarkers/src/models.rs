@@ -0,0 +1,27 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use mercurial_types::{HgChangesetId, RepositoryId};
+
+use schema::{obsmarkers, obsmarkers_related};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "obsmarkers"]
+pub(crate) struct ObsMarkerRow {
+    pub repo_id: RepositoryId,
+    pub marker_id: Vec<u8>,
+    pub marker: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "obsmarkers_related"]
+pub(crate) struct ObsMarkerRelatedRow {
+    pub repo_id: RepositoryId,
+    pub changeset_id: HgChangesetId,
+    pub marker_id: Vec<u8>,
+}
@@ -0,0 +1,30 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! The `table!` macros in this module describe the schemas for these tables in SQL storage
+//! (MySQL or SQLite). These descriptions are *not* the source of truth, so if the schema ever
+//! changes it will need to be updated here as well.
+
+table! {
+    use diesel::sql_types::{Binary, Integer};
+
+    obsmarkers (repo_id, marker_id) {
+        repo_id -> Integer,
+        marker_id -> Binary,
+        marker -> Binary,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Binary, Integer};
+    use mercurial_types::sql_types::HgChangesetIdSql;
+
+    obsmarkers_related (repo_id, changeset_id, marker_id) {
+        repo_id -> Integer,
+        changeset_id -> HgChangesetIdSql,
+        marker_id -> Binary,
+    }
+}
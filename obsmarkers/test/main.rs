@@ -0,0 +1,200 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the ObsMarkers store.
+
+#![deny(warnings)]
+
+extern crate async_unit;
+extern crate bytes;
+extern crate futures;
+
+extern crate mercurial_types;
+extern crate mercurial_types_mocks;
+extern crate obsmarkers;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::Future;
+
+use mercurial_types::HgObsMarker;
+use mercurial_types_mocks::nodehash::*;
+use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
+use obsmarkers::{MemWritesObsMarkers, MysqlObsMarkers, ObsMarkers, SqliteObsMarkers};
+
+fn amend_marker() -> HgObsMarker {
+    HgObsMarker {
+        predecessor: ONES_CSID,
+        successors: vec![TWOS_CSID],
+        flags: 0,
+        date: 1500000000.0,
+        tz_offset_secs: 0,
+        parents: None,
+        metadata: vec![(Bytes::from("operation"), Bytes::from("amend"))],
+    }
+}
+
+fn prune_marker() -> HgObsMarker {
+    HgObsMarker {
+        predecessor: THREES_CSID,
+        successors: vec![],
+        flags: 0,
+        date: 1500000001.0,
+        tz_offset_secs: 3600,
+        parents: Some(vec![FOURS_CSID]),
+        metadata: vec![],
+    }
+}
+
+fn sorted(mut markers: Vec<HgObsMarker>) -> Vec<HgObsMarker> {
+    markers.sort_by_key(|marker| marker.id().expect("marker id failed"));
+    markers
+}
+
+fn add_and_get<M: ObsMarkers>(store: M) {
+    store
+        .add(REPO_ZERO, vec![amend_marker(), prune_marker()])
+        .wait()
+        .expect("Adding markers failed");
+    // Adding the same marker again is not an error
+    store
+        .add(REPO_ZERO, vec![amend_marker()])
+        .wait()
+        .expect("Adding the same marker failed");
+
+    let all = store.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(all, sorted(vec![amend_marker(), prune_marker()]));
+
+    let other_repo = store.get_all(REPO_ONE).wait().expect("get_all failed");
+    assert_eq!(other_repo, vec![]);
+}
+
+fn get_relevant<M: ObsMarkers>(store: M) {
+    store
+        .add(REPO_ZERO, vec![amend_marker(), prune_marker()])
+        .wait()
+        .expect("Adding markers failed");
+
+    let get = |changesets| {
+        store
+            .get_relevant(REPO_ZERO, changesets)
+            .wait()
+            .expect("get_relevant failed")
+    };
+
+    // By predecessor
+    assert_eq!(get(vec![ONES_CSID]), vec![amend_marker()]);
+    // By successor
+    assert_eq!(get(vec![TWOS_CSID]), vec![amend_marker()]);
+    // By parent
+    assert_eq!(get(vec![FOURS_CSID]), vec![prune_marker()]);
+    // Markers are returned once even if several changesets match
+    assert_eq!(
+        get(vec![ONES_CSID, TWOS_CSID, THREES_CSID]),
+        sorted(vec![amend_marker(), prune_marker()])
+    );
+    assert_eq!(get(vec![FIVES_CSID]), vec![]);
+    assert_eq!(get(vec![]), vec![]);
+}
+
+fn mem_writes_overlay<M: ObsMarkers + 'static>(store: M) {
+    let inner: Arc<ObsMarkers> = Arc::new(store);
+    inner
+        .add(REPO_ZERO, vec![amend_marker()])
+        .wait()
+        .expect("Adding markers to inner failed");
+
+    let overlay = MemWritesObsMarkers::new(inner.clone());
+    overlay
+        .add(REPO_ZERO, vec![amend_marker(), prune_marker()])
+        .wait()
+        .expect("Adding markers to overlay failed");
+
+    // The overlay sees the markers of both, once...
+    let all = overlay.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(all, sorted(vec![amend_marker(), prune_marker()]));
+    let relevant = overlay
+        .get_relevant(REPO_ZERO, vec![FOURS_CSID])
+        .wait()
+        .expect("get_relevant failed");
+    assert_eq!(relevant, vec![prune_marker()]);
+    let other_repo = overlay.get_all(REPO_ONE).wait().expect("get_all failed");
+    assert_eq!(other_repo, vec![]);
+
+    // ...but the markers added to the overlay aren't written to the inner store.
+    let inner_all = inner.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(inner_all, vec![amend_marker()]);
+}
+
+macro_rules! obsmarkers_test_impl {
+    ($mod_name:ident => { new: $new_cb:expr, }) => {
+        mod $mod_name {
+            use super::*;
+
+            #[test]
+            fn test_add_and_get() {
+                async_unit::tokio_unit_test(|| {
+                    add_and_get($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_get_relevant() {
+                async_unit::tokio_unit_test(|| {
+                    get_relevant($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_mem_writes_overlay() {
+                async_unit::tokio_unit_test(|| {
+                    mem_writes_overlay($new_cb());
+                });
+            }
+        }
+    };
+}
+
+obsmarkers_test_impl! {
+    sqlite_test => {
+        new: new_sqlite,
+    }
+}
+
+obsmarkers_test_impl! {
+    sqlite_arced_test => {
+        new: new_sqlite_arced,
+    }
+}
+
+obsmarkers_test_impl! {
+    mysql_test => {
+        new: new_mysql,
+    }
+}
+
+obsmarkers_test_impl! {
+    mysql_arced_test => {
+        new: new_mysql_arced,
+    }
+}
+
+fn new_sqlite() -> SqliteObsMarkers {
+    SqliteObsMarkers::in_memory().expect("Creating an in-memory SQLite database failed")
+}
+
+fn new_sqlite_arced() -> Arc<ObsMarkers> {
+    Arc::new(new_sqlite())
+}
+
+fn new_mysql() -> MysqlObsMarkers {
+    MysqlObsMarkers::create_test_db("obsmarkers_test").expect("Failed to create test database")
+}
+
+fn new_mysql_arced() -> Arc<ObsMarkers> {
+    Arc::new(new_mysql())
+}
@@ -52,4 +52,8 @@ pub enum ErrorKind {
     ParentsFetchFailed(#[cause] BlobRepoErrorCause),
     #[fail(display = "checking existence failed")]
     CheckExistenceFailed(String, #[cause] BlobRepoErrorCause),
+    #[fail(display = "invalid serialized skiplist index: {}", _0)]
+    InvalidSerializedIndex(String),
+    #[fail(display = "{} nodes given, at most {} are supported", _0, _1)]
+    TooManyNodes(usize, usize),
 }
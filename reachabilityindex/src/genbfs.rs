@@ -8,7 +8,7 @@ use std::collections::HashSet;
 use std::sync::Arc;
 
 use failure::Error;
-use futures::future::{loop_fn, ok, Future, Loop};
+use futures::future::{join_all, loop_fn, ok, Future, Loop};
 use futures::stream::{iter_ok, Stream};
 use futures_ext::{BoxFuture, FutureExt};
 
@@ -20,6 +20,7 @@ use mononoke_types::Generation;
 use errors::*;
 use helpers::*;
 use index::ReachabilityIndex;
+use lca;
 
 pub struct GenerationNumberBFS {}
 
@@ -106,13 +107,79 @@ impl ReachabilityIndex for GenerationNumberBFS {
             .from_err()
             .boxify()
     }
+
+    fn lowest_common_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        nodes: Vec<HgNodeHash>,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        lca::lowest_common_ancestors(repo, nodes, |_, _| None)
+    }
+
+    fn filter_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        candidates: Vec<HgNodeHash>,
+        descendant: HgNodeHash,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        // A single bfs from the descendant, down to the lowest generation of the candidates,
+        // finds all of them that it can reach.
+        fetch_generation(repo.clone(), descendant)
+            .join(join_all(
+                candidates
+                    .iter()
+                    .map(|candidate| fetch_generation_and_join(repo.clone(), *candidate))
+                    .collect::<Vec<_>>(),
+            ))
+            .and_then(move |(descendant_gen, candidate_gens)| {
+                let wanted: HashSet<_> = candidate_gens
+                    .iter()
+                    .filter(|&&(_, gen)| gen <= descendant_gen)
+                    .map(|&(node, _)| node)
+                    .collect();
+                let min_gen = candidate_gens
+                    .iter()
+                    .map(|&(_, gen)| gen)
+                    .min()
+                    .unwrap_or(descendant_gen);
+                let start_bfs_layer: HashSet<_> = vec![descendant].into_iter().collect();
+                let start_seen: HashSet<_> = HashSet::new();
+                loop_fn(
+                    (start_bfs_layer, start_seen),
+                    move |(curr_layer, curr_seen)| {
+                        let all_found = wanted
+                            .iter()
+                            .all(|node| curr_seen.contains(node) || curr_layer.contains(node));
+                        if all_found || curr_layer.is_empty() {
+                            let mut seen = curr_seen;
+                            seen.extend(curr_layer);
+                            ok(Loop::Break(seen)).boxify()
+                        } else {
+                            process_bfs_layer(repo.clone(), curr_layer, curr_seen, min_gen)
+                                .map(move |(next_layer, next_seen)| {
+                                    Loop::Continue((next_layer, next_seen))
+                                })
+                                .boxify()
+                        }
+                    },
+                ).map(move |seen| {
+                    candidates
+                        .into_iter()
+                        .filter(|candidate| seen.contains(candidate))
+                        .collect()
+                })
+            })
+            .boxify()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use tests::test_branch_wide_reachability;
+    use tests::test_filter_ancestors;
     use tests::test_linear_reachability;
+    use tests::test_lowest_common_ancestors;
     use tests::test_merge_uneven_reachability;
 
     #[test]
@@ -132,4 +199,16 @@ mod test {
         let bfs_constructor = || GenerationNumberBFS::new();
         test_branch_wide_reachability(bfs_constructor);
     }
+
+    #[test]
+    fn lowest_common_ancestors() {
+        let bfs_constructor = || GenerationNumberBFS::new();
+        test_lowest_common_ancestors(bfs_constructor);
+    }
+
+    #[test]
+    fn filter_ancestors() {
+        let bfs_constructor = || GenerationNumberBFS::new();
+        test_filter_ancestors(bfs_constructor);
+    }
 }
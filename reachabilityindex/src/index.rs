@@ -21,4 +21,22 @@ pub trait ReachabilityIndex {
         src: HgNodeHash,
         dst: HgNodeHash,
     ) -> BoxFuture<bool, Error>;
+
+    /// Return a Future for the lowest common ancestors of the nodes: the common ancestors that
+    /// aren't ancestors of another common ancestor. There are several after criss-cross merges,
+    /// and none if the nodes have no common history.
+    fn lowest_common_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        nodes: Vec<HgNodeHash>,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error>;
+
+    /// Return a Future for the candidates that the descendant node can reach, in the order they
+    /// were given
+    fn filter_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        candidates: Vec<HgNodeHash>,
+        descendant: HgNodeHash,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error>;
 }
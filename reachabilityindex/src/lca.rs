@@ -0,0 +1,181 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Lowest common ancestors, found by walking the history of the nodes in decreasing generation
+//! order, like Mercurial's commonancestorsheads. Every node visited is marked with the input
+//! nodes it is an ancestor of. A node marked with all of them is a common ancestor, and the nodes
+//! below it are poisoned so that they aren't reported as lowest too.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+use failure::Error;
+use futures::future::{err, join_all, loop_fn, ok, Future, Loop};
+use futures_ext::{BoxFuture, FutureExt};
+
+use blobrepo::BlobRepo;
+use mercurial_types::HgNodeHash;
+use mononoke_types::Generation;
+
+use errors::*;
+use helpers::{changeset_to_nodehashes_with_generation_numbers, fetch_generation_and_join,
+              get_parents_from_nodehash};
+
+/// Nodes are marked with one bit per input node, and the last bit is the poison.
+const MAX_NODES: usize = 63;
+const POISON: u64 = 1 << 63;
+
+struct LcaWalk {
+    // The mark of a node that all the input nodes are ancestors of.
+    all: u64,
+    inputs: HashSet<HgNodeHash>,
+    marks: HashMap<HgNodeHash, u64>,
+    // The nodes left to visit, highest generation first.
+    pending: BinaryHeap<(Generation, HgNodeHash)>,
+    found: Vec<HgNodeHash>,
+}
+
+impl LcaWalk {
+    fn new(nodes: Vec<(HgNodeHash, Generation)>) -> Self {
+        let mut walk = LcaWalk {
+            all: (1 << nodes.len()) - 1,
+            inputs: nodes.iter().map(|&(node, _)| node).collect(),
+            marks: HashMap::new(),
+            pending: BinaryHeap::new(),
+            found: Vec::new(),
+        };
+        for (i, (node, gen)) in nodes.into_iter().enumerate() {
+            walk.mark(node, gen, 1 << i);
+        }
+        walk
+    }
+
+    fn mark(&mut self, node: HgNodeHash, gen: Generation, mark: u64) {
+        if let Some(existing) = self.marks.get_mut(&node) {
+            *existing |= mark;
+            return;
+        }
+        self.marks.insert(node, mark);
+        self.pending.push((gen, node));
+    }
+
+    /// A new common ancestor can only be found if the pending nodes that aren't poisoned are
+    /// still marked with all the input nodes between them.
+    fn can_find_more(&self) -> bool {
+        let marks = self.pending
+            .iter()
+            .map(|&(_, node)| self.marks[&node])
+            .filter(|mark| mark & POISON == 0)
+            .fold(0, |acc, mark| acc | mark);
+        marks == self.all
+    }
+
+    /// Remove the pending nodes of the highest generation. Nodes of the same generation can't
+    /// be ancestors of each other, so they can be visited together.
+    fn pop_generation(&mut self) -> Vec<(HgNodeHash, Generation)> {
+        let mut layer = Vec::new();
+        while let Some(&(gen, node)) = self.pending.peek() {
+            match layer.first() {
+                Some(&(_, layer_gen)) if layer_gen != gen => break,
+                _ => {}
+            }
+            self.pending.pop();
+            layer.push((node, gen));
+        }
+        layer
+    }
+}
+
+/// Find the lowest common ancestors of the nodes: their common ancestors that aren't ancestors
+/// of another common ancestor. There can be several of them after criss-cross merges, and none
+/// if the nodes have no common history. They are returned from the highest generation.
+///
+/// `jump(node, gen)` may return an ancestor of `node` with generation `gen` or more, with no
+/// merges on the way to it, for the walk to skip the history in between; e.g. along skip edges.
+/// It's only used when no other node is left to visit above `gen`, so that none of the skipped
+/// nodes can be reached from another branch.
+pub fn lowest_common_ancestors<F>(
+    repo: Arc<BlobRepo>,
+    nodes: Vec<HgNodeHash>,
+    jump: F,
+) -> BoxFuture<Vec<HgNodeHash>, Error>
+where
+    F: Fn(HgNodeHash, Generation) -> Option<(HgNodeHash, Generation)> + Send + 'static,
+{
+    let mut unique = HashSet::new();
+    let nodes: Vec<_> = nodes.into_iter().filter(|node| unique.insert(*node)).collect();
+    if nodes.len() > MAX_NODES {
+        return err(ErrorKind::TooManyNodes(nodes.len(), MAX_NODES).into()).boxify();
+    }
+
+    join_all(
+        nodes
+            .into_iter()
+            .map({
+                cloned!(repo);
+                move |node| fetch_generation_and_join(repo.clone(), node)
+            })
+            .collect::<Vec<_>>(),
+    ).and_then(move |nodes| {
+        if nodes.len() < 2 {
+            let nodes: Vec<_> = nodes.into_iter().map(|(node, _)| node).collect();
+            return ok(nodes).left_future();
+        }
+
+        loop_fn(LcaWalk::new(nodes), move |mut walk| {
+            if !walk.can_find_more() {
+                return ok(Loop::Break(walk.found)).boxify();
+            }
+
+            let mut expand = Vec::new();
+            for (node, gen) in walk.pop_generation() {
+                let mut mark = walk.marks[&node];
+                if mark == walk.all {
+                    if walk.inputs.contains(&node) {
+                        // All the other input nodes descend from this one.
+                        return ok(Loop::Break(vec![node])).boxify();
+                    }
+                    walk.found.push(node);
+                    mark |= POISON;
+                    walk.marks.insert(node, mark);
+                }
+                expand.push((node, gen, mark));
+            }
+
+            if expand.len() == 1 {
+                let (node, _, mark) = expand[0];
+                let next_gen = walk.pending.peek().map(|&(gen, _)| gen);
+                if let Some((ancestor, ancestor_gen)) = next_gen.and_then(|gen| jump(node, gen)) {
+                    if ancestor != node {
+                        walk.mark(ancestor, ancestor_gen, mark);
+                        return ok(Loop::Continue(walk)).boxify();
+                    }
+                }
+            }
+
+            join_all(expand.into_iter().map({
+                cloned!(repo);
+                move |(node, _, mark)| {
+                    cloned!(repo);
+                    get_parents_from_nodehash(repo.clone(), node)
+                        .and_then(move |parents| {
+                            changeset_to_nodehashes_with_generation_numbers(repo, parents)
+                        })
+                        .map(move |parents| (parents, mark))
+                }
+            })).map(move |expanded| {
+                for (parents, mark) in expanded {
+                    for (parent, gen) in parents {
+                        walk.mark(parent, gen, mark);
+                    }
+                }
+                Loop::Continue(walk)
+            })
+                .boxify()
+        }).right_future()
+    })
+        .boxify()
+}
@@ -5,6 +5,7 @@
 // GNU General Public License version 2 or any later version.
 
 #![deny(warnings)]
+extern crate byteorder;
 extern crate chashmap;
 #[macro_use]
 extern crate cloned;
@@ -14,10 +15,12 @@ extern crate futures;
 extern crate futures_ext;
 
 extern crate blobrepo;
+extern crate blobstore;
 extern crate mercurial_types;
 extern crate mononoke_types;
 
 mod helpers;
+mod lca;
 
 pub mod errors;
 pub use errors::ErrorKind;
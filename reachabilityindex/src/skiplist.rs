@@ -7,24 +7,37 @@
 use std::ops::Deref;
 use std::sync::Arc;
 
+use blobstore::Blobstore;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use chashmap::CHashMap;
-use failure::Error;
+use failure::{Error, Result};
 use futures::future::{join_all, ok, Future};
 use futures_ext::{BoxFuture, FutureExt};
 
 use blobrepo::BlobRepo;
 use mercurial_types::HgNodeHash;
-use mononoke_types::Generation;
+use mononoke_types::{BlobstoreBytes, Generation};
 
+use errors::ErrorKind;
 use helpers::{changeset_to_nodehashes_with_generation_numbers, fetch_generation_and_join,
               get_parents_from_nodehash};
 use index::ReachabilityIndex;
+use lca;
 
 const DEFAULT_EDGE_COUNT: u32 = 10;
 
+// Serialized indexes start with the version of their format, followed by the indexed nodes.
+// Each node is its hash, the type of its edges, the number of edges and then the edges, each
+// of which is a hash and a generation number. Integers are big-endian.
+const SERIALIZED_FORMAT_VERSION: u8 = 1;
+const SERIALIZED_SKIP_EDGES: u8 = 0;
+const SERIALIZED_PARENT_EDGES: u8 = 1;
+const NODE_HASH_LEN: usize = 20;
+
 // Each indexed node fits into one of two categories:
 // - It has skiplist edges
 // - It only has edges to its parents.
+#[derive(Clone)]
 enum SkiplistNodeType {
     // A list of skip edges which keep doubling
     // in distance from their root node.
@@ -52,6 +65,88 @@ impl SkiplistEdgeMapping {
             ..self
         }
     }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = vec![SERIALIZED_FORMAT_VERSION];
+        for (node, node_type) in self.mapping.clone() {
+            let (edge_type, edges) = match node_type {
+                SkiplistNodeType::SkipEdges(edges) => (SERIALIZED_SKIP_EDGES, edges),
+                SkiplistNodeType::ParentEdges(edges) => (SERIALIZED_PARENT_EDGES, edges),
+            };
+            data.extend_from_slice(node.as_bytes());
+            data.push(edge_type);
+            data.write_u32::<BigEndian>(edges.len() as u32)
+                .expect("writing to a Vec can't fail");
+            for (edge_node, edge_gen) in edges {
+                data.extend_from_slice(edge_node.as_bytes());
+                data.write_u64::<BigEndian>(edge_gen.value())
+                    .expect("writing to a Vec can't fail");
+            }
+        }
+        data
+    }
+
+    /// Add the nodes of a serialized index to this mapping, returning how many were added.
+    /// Nothing is added if the data is invalid.
+    pub fn deserialize_into(&self, data: &[u8]) -> Result<usize> {
+        let mut reader = SerializedReader { data };
+        let version = reader.uint(1)? as u8;
+        if version != SERIALIZED_FORMAT_VERSION {
+            let msg = format!("unknown format version {}", version);
+            return Err(ErrorKind::InvalidSerializedIndex(msg).into());
+        }
+
+        let mut nodes = Vec::new();
+        while !reader.data.is_empty() {
+            let node = reader.node()?;
+            let edge_type = reader.uint(1)? as u8;
+            let edge_count = reader.uint(4)?;
+            let mut edges = Vec::new();
+            for _ in 0..edge_count {
+                let edge_node = reader.node()?;
+                edges.push((edge_node, Generation::new(reader.uint(8)?)));
+            }
+            let node_type = match edge_type {
+                SERIALIZED_SKIP_EDGES => SkiplistNodeType::SkipEdges(edges),
+                SERIALIZED_PARENT_EDGES => SkiplistNodeType::ParentEdges(edges),
+                _ => {
+                    let msg = format!("unknown edge type {} of {}", edge_type, node);
+                    return Err(ErrorKind::InvalidSerializedIndex(msg).into());
+                }
+            };
+            nodes.push((node, node_type));
+        }
+
+        let count = nodes.len();
+        for (node, node_type) in nodes {
+            self.mapping.insert(node, node_type);
+        }
+        Ok(count)
+    }
+}
+
+struct SerializedReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SerializedReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            let msg = "unexpected end of data".to_string();
+            return Err(ErrorKind::InvalidSerializedIndex(msg).into());
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        Ok(BigEndian::read_uint(self.take(len)?, len))
+    }
+
+    fn node(&mut self) -> Result<HgNodeHash> {
+        HgNodeHash::from_bytes(self.take(NODE_HASH_LEN)?)
+    }
 }
 
 fn nth_node_or_last<T: Clone>(v: &Vec<T>, i: usize) -> Option<T> {
@@ -295,6 +390,24 @@ fn query_reachability(
         .boxify()
 }
 
+/// The furthest node that the skip edges of an indexed node reach without going below the
+/// generation, which is how far the lowest common ancestors walk can jump from it.
+fn furthest_skip_edge(
+    skip_list_edges: &SkiplistEdgeMapping,
+    node: HgNodeHash,
+    gen: Generation,
+) -> Option<(HgNodeHash, Generation)> {
+    let read_guard = skip_list_edges.mapping.get(&node)?;
+    match read_guard.deref() {
+        SkiplistNodeType::SkipEdges(edges) => edges
+            .iter()
+            .take_while(|edge_pair| edge_pair.1 >= gen)
+            .last()
+            .cloned(),
+        SkiplistNodeType::ParentEdges(_) => None,
+    }
+}
+
 impl SkiplistIndex {
     pub fn new() -> Self {
         SkiplistIndex {
@@ -345,6 +458,43 @@ impl SkiplistIndex {
     pub fn indexed_node_count(&self) -> usize {
         self.skip_list_edges.mapping.len()
     }
+
+    /// Serialize the index, so that it can be loaded with `deserialize_into` instead of being
+    /// built again.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.skip_list_edges.serialize()
+    }
+
+    /// Add the nodes of an index serialized with `serialize` to this index. Returns the number
+    /// of nodes added.
+    pub fn deserialize_into(&self, data: &[u8]) -> Result<usize> {
+        self.skip_list_edges.deserialize_into(data)
+    }
+
+    /// Store the serialized index as the blob `key` of the repo.
+    pub fn save_to_blobstore(&self, repo: &BlobRepo, key: String) -> BoxFuture<(), Error> {
+        repo.get_blobstore()
+            .put(key, BlobstoreBytes::from_bytes(self.serialize()))
+    }
+
+    /// Add the nodes of the index stored as the blob `key` of the repo by `save_to_blobstore`
+    /// to this index. Returns the number of nodes added, or None if there is no such blob.
+    pub fn load_from_blobstore(
+        &self,
+        repo: &BlobRepo,
+        key: String,
+    ) -> BoxFuture<Option<usize>, Error> {
+        let skip_list_edges = self.skip_list_edges.clone();
+        repo.get_blobstore()
+            .get(key)
+            .and_then(move |data| match data {
+                Some(data) => skip_list_edges
+                    .deserialize_into(data.as_bytes().as_ref())
+                    .map(Some),
+                None => Ok(None),
+            })
+            .boxify()
+    }
 }
 
 impl ReachabilityIndex for SkiplistIndex {
@@ -356,6 +506,51 @@ impl ReachabilityIndex for SkiplistIndex {
     ) -> BoxFuture<bool, Error> {
         query_reachability(repo, self.skip_list_edges.clone(), src, dst)
     }
+
+    fn lowest_common_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        nodes: Vec<HgNodeHash>,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        let skip_list_edges = self.skip_list_edges.clone();
+        lca::lowest_common_ancestors(repo, nodes, move |node, gen| {
+            furthest_skip_edge(&skip_list_edges, node, gen)
+        })
+    }
+
+    fn filter_ancestors(
+        &self,
+        repo: Arc<BlobRepo>,
+        candidates: Vec<HgNodeHash>,
+        descendant: HgNodeHash,
+    ) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        let skip_list_edges = self.skip_list_edges.clone();
+        fetch_generation_and_join(repo.clone(), descendant)
+            .join(join_all(
+                candidates
+                    .into_iter()
+                    .map(|candidate| fetch_generation_and_join(repo.clone(), candidate))
+                    .collect::<Vec<_>>(),
+            ))
+            .and_then(move |(descendant_hash_gen, candidate_hash_gens)| {
+                join_all(candidate_hash_gens.into_iter().map(move |candidate_hash_gen| {
+                    query_reachability_with_generation_hints(
+                        repo.clone(),
+                        skip_list_edges.clone(),
+                        descendant_hash_gen,
+                        candidate_hash_gen,
+                    ).map(move |reachable| (candidate_hash_gen.0, reachable))
+                }))
+            })
+            .map(|results| {
+                results
+                    .into_iter()
+                    .filter(|&(_, reachable)| reachable)
+                    .map(|(candidate, _)| candidate)
+                    .collect()
+            })
+            .boxify()
+    }
 }
 
 #[cfg(test)]
@@ -374,7 +569,9 @@ mod test {
     use fixtures::unshared_merge_even;
     use tests::string_to_nodehash;
     use tests::test_branch_wide_reachability;
+    use tests::test_filter_ancestors;
     use tests::test_linear_reachability;
+    use tests::test_lowest_common_ancestors;
     use tests::test_merge_uneven_reachability;
 
     #[test]
@@ -421,6 +618,52 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_serialize_roundtrip() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+            let sli = SkiplistIndex::new();
+            let merge_node = string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7");
+            sli.add_node(repo.clone(), merge_node, 100).wait().unwrap();
+
+            let key = "skiplist_index".to_string();
+            sli.save_to_blobstore(&repo, key.clone()).wait().unwrap();
+            let loaded = SkiplistIndex::new();
+            assert_eq!(
+                loaded.load_from_blobstore(&repo, key).wait().unwrap(),
+                Some(sli.indexed_node_count())
+            );
+            assert_eq!(loaded.indexed_node_count(), sli.indexed_node_count());
+            for (node, _) in sli.skip_list_edges.mapping.clone() {
+                assert!(loaded.is_node_indexed(node));
+                assert_eq!(loaded.get_skip_edges(node), sli.get_skip_edges(node));
+            }
+
+            let missing = "missing_skiplist_index".to_string();
+            assert_eq!(
+                loaded.load_from_blobstore(&repo, missing).wait().unwrap(),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+            let sli = SkiplistIndex::new();
+            let master_node = string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157");
+            sli.add_node(repo, master_node, 100).wait().unwrap();
+            let data = sli.serialize();
+
+            let loaded = SkiplistIndex::new();
+            assert!(loaded.deserialize_into(&data[..data.len() - 1]).is_err());
+            assert!(loaded.deserialize_into(&[SERIALIZED_FORMAT_VERSION + 1]).is_err());
+            assert_eq!(loaded.indexed_node_count(), 0);
+            assert_eq!(loaded.deserialize_into(&data).unwrap(), 8);
+        });
+    }
+
     #[test]
     fn test_skip_edges_reach_end_in_linear() {
         async_unit::tokio_unit_test(|| {
@@ -656,6 +899,46 @@ mod test {
         test_branch_wide_reachability(sli_constructor);
     }
 
+    #[test]
+    fn lowest_common_ancestors() {
+        let sli_constructor = || SkiplistIndex::new();
+        test_lowest_common_ancestors(sli_constructor);
+    }
+
+    #[test]
+    fn filter_ancestors() {
+        let sli_constructor = || SkiplistIndex::new();
+        test_filter_ancestors(sli_constructor);
+    }
+
+    #[test]
+    fn test_lowest_common_ancestors_along_skip_edges() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+            let sli = SkiplistIndex::new();
+            let merge_node = string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7");
+            sli.add_node(repo.clone(), merge_node, 100).wait().unwrap();
+
+            let root_node = string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c");
+            let branch_1_head = string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68");
+            let branch_2_head = string_to_nodehash("264f01429683b3dd8042cb3979e8bf37007118bc");
+            let branch_2_node = string_to_nodehash("4f7f3fd428bec1a48f9314414b063c706d9c1aed");
+
+            assert_eq!(
+                sli.lowest_common_ancestors(repo.clone(), vec![branch_1_head, branch_2_head])
+                    .wait()
+                    .unwrap(),
+                vec![root_node]
+            );
+            assert_eq!(
+                sli.lowest_common_ancestors(repo.clone(), vec![merge_node, branch_2_node])
+                    .wait()
+                    .unwrap(),
+                vec![branch_2_node]
+            );
+        });
+    }
+
     #[test]
     fn test_query_reachability_hint_on_self_is_true() {
         async_unit::tokio_unit_test(|| {
@@ -13,6 +13,7 @@ use mercurial_types::HgNodeHash;
 use fixtures::branch_wide;
 use fixtures::linear;
 use fixtures::merge_uneven;
+use fixtures::unshared_merge_uneven;
 
 pub fn string_to_nodehash(hash: &'static str) -> HgNodeHash {
     HgNodeHash::from_static_str(hash).expect("Can't turn string to HgNodeHash")
@@ -189,3 +190,105 @@ pub fn test_branch_wide_reachability<T: ReachabilityIndex + 'static>(index_creat
             .unwrap());
     });
 }
+
+pub fn test_lowest_common_ancestors<T: ReachabilityIndex + 'static>(index_creator: fn() -> T) {
+    async_unit::tokio_unit_test(move || {
+        let repo = Arc::new(merge_uneven::getrepo(None));
+        let index = index_creator();
+        let root_node = string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c");
+        let branch_1_head = string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68");
+        let branch_1_node = string_to_nodehash("1d8a907f7b4bf50c6a09c16361e2205047ecc5e5");
+        let branch_2_head = string_to_nodehash("264f01429683b3dd8042cb3979e8bf37007118bc");
+        let branch_2_node = string_to_nodehash("4f7f3fd428bec1a48f9314414b063c706d9c1aed");
+        let merge_node = string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7");
+
+        let lca = |nodes: Vec<HgNodeHash>| {
+            index
+                .lowest_common_ancestors(repo.clone(), nodes)
+                .wait()
+                .unwrap()
+        };
+
+        assert_eq!(lca(vec![]), Vec::<HgNodeHash>::new());
+        assert_eq!(lca(vec![branch_1_head]), vec![branch_1_head]);
+        assert_eq!(
+            lca(vec![branch_1_head, branch_1_head]),
+            vec![branch_1_head]
+        );
+        // branches of the merge only share the root
+        assert_eq!(lca(vec![branch_1_head, branch_2_head]), vec![root_node]);
+        assert_eq!(lca(vec![branch_1_node, branch_2_node]), vec![root_node]);
+        // a node is the lowest common ancestor of its descendants
+        assert_eq!(lca(vec![merge_node, branch_1_node]), vec![branch_1_node]);
+        assert_eq!(lca(vec![branch_2_node, merge_node]), vec![branch_2_node]);
+        assert_eq!(
+            lca(vec![merge_node, branch_1_head, branch_2_node]),
+            vec![root_node]
+        );
+
+        // histories that were merged without a common ancestor
+        let repo = Arc::new(unshared_merge_uneven::getrepo(None));
+        assert_eq!(
+            index
+                .lowest_common_ancestors(
+                    repo.clone(),
+                    vec![
+                        string_to_nodehash("64011f64aaf9c2ad2e674f57c033987da4016f51"),
+                        string_to_nodehash("1700524113b1a3b1806560341009684b4378660b"),
+                    ]
+                )
+                .wait()
+                .unwrap(),
+            Vec::<HgNodeHash>::new()
+        );
+    });
+}
+
+pub fn test_filter_ancestors<T: ReachabilityIndex + 'static>(index_creator: fn() -> T) {
+    async_unit::tokio_unit_test(move || {
+        let repo = Arc::new(linear::getrepo(None));
+        let index = index_creator();
+        // order is newest to oldest
+        let ordered_hashes = vec![
+            string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+            string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+            string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+            string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372"),
+            string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+            string_to_nodehash("607314ef579bd2407752361ba1b0c1729d08b281"),
+            string_to_nodehash("3e0e761030db6e479a7fb58b12881883f9f8c63f"),
+            string_to_nodehash("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536"),
+        ];
+
+        for (i, descendant) in ordered_hashes.iter().enumerate() {
+            let ancestors = index
+                .filter_ancestors(repo.clone(), ordered_hashes.clone(), *descendant)
+                .wait()
+                .unwrap();
+            assert_eq!(ancestors, ordered_hashes[i..].to_vec());
+        }
+
+        let repo = Arc::new(branch_wide::getrepo(None));
+        let root_node = string_to_nodehash("ecba698fee57eeeef88ac3dcc3b623ede4af47bd");
+        let b1 = string_to_nodehash("9e8521affb7f9d10e9551a99c526e69909042b20");
+        let b2 = string_to_nodehash("4685e9e62e4885d477ead6964a7600c750e39b03");
+        let b1_1 = string_to_nodehash("b6a8169454af58b4b72b3665f9aa0d25529755ff");
+        let b2_2 = string_to_nodehash("49f53ab171171b3180e125b918bd1cf0af7e5449");
+
+        // the candidates keep their order, and other branches are filtered out
+        assert_eq!(
+            index
+                .filter_ancestors(repo.clone(), vec![root_node, b2, b1_1, b1], b1_1)
+                .wait()
+                .unwrap(),
+            vec![root_node, b1_1, b1]
+        );
+        assert_eq!(
+            index
+                .filter_ancestors(repo.clone(), vec![b1_1, b2_2], b2)
+                .wait()
+                .unwrap(),
+            Vec::<HgNodeHash>::new()
+        );
+    });
+}
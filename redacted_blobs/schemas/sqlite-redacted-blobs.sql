@@ -0,0 +1,7 @@
+CREATE TABLE redacted_blobs (
+  repo_id INTEGER NOT NULL,
+  blobstore_key VARCHAR(255) NOT NULL,
+  reason VARCHAR(255) NOT NULL,
+  add_timestamp BIGINT NOT NULL,
+  PRIMARY KEY (repo_id, blobstore_key)
+);
@@ -0,0 +1,252 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! List of the blobs of a repo that must not be served anymore, e.g. because they contain
+//! secrets that were pushed by mistake.
+//!
+//! The blobs themselves stay in the blobstore, as other blobs refer to them, but reading one of
+//! them fails with `blobstore::ErrorKind::Censored`.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+extern crate db_conn;
+#[macro_use]
+extern crate diesel;
+extern crate failure_ext as failure;
+
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+extern crate mononoke_types;
+#[macro_use]
+extern crate stats;
+
+use std::result;
+use std::sync::{Arc, MutexGuard};
+
+use db_conn::{MysqlConnInner, SqliteConnInner};
+use diesel::{delete, replace_into, MysqlConnection, SqliteConnection};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+
+use futures_ext::{asynchronize, BoxFuture};
+use mercurial_types::RepositoryId;
+use mononoke_types::DateTime;
+use stats::Timeseries;
+
+mod models;
+mod schema;
+
+pub use failure::{Error, Result};
+use models::RedactedBlobRow;
+use schema::redacted_blobs;
+
+define_stats! {
+    prefix = "mononoke.redacted_blobs";
+    adds: timeseries(RATE, SUM),
+    removes: timeseries(RATE, SUM),
+    get_alls: timeseries(RATE, SUM),
+}
+
+/// A blob that must not be served.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RedactedBlob {
+    pub repo_id: RepositoryId,
+    /// Key of the blob, relative to the repo, i.e. without the repo prefix.
+    pub blobstore_key: String,
+    /// Why the blob was redacted. It is shown to the clients that try to read the blob.
+    pub reason: String,
+    /// When the blob was redacted.
+    pub timestamp: DateTime,
+}
+
+impl RedactedBlob {
+    pub fn new(
+        repo_id: RepositoryId,
+        blobstore_key: String,
+        reason: String,
+        timestamp: DateTime,
+    ) -> Self {
+        Self {
+            repo_id,
+            blobstore_key,
+            reason,
+            timestamp,
+        }
+    }
+}
+
+pub trait RedactedBlobs: Send + Sync {
+    /// Redact a blob. If the blob is already redacted, its reason and timestamp are replaced.
+    fn add(&self, entry: RedactedBlob) -> BoxFuture<(), Error>;
+
+    /// Make a blob readable again. Returns false if the blob wasn't redacted.
+    fn remove(&self, repo_id: RepositoryId, blobstore_key: String) -> BoxFuture<bool, Error>;
+
+    /// Returns all the redacted blobs of the repo, sorted by key.
+    fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<RedactedBlob>, Error>;
+}
+
+impl RedactedBlobs for Arc<RedactedBlobs> {
+    fn add(&self, entry: RedactedBlob) -> BoxFuture<(), Error> {
+        (**self).add(entry)
+    }
+
+    fn remove(&self, repo_id: RepositoryId, blobstore_key: String) -> BoxFuture<bool, Error> {
+        (**self).remove(repo_id, blobstore_key)
+    }
+
+    fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<RedactedBlob>, Error> {
+        (**self).get_all(repo_id)
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteRedactedBlobs {
+    inner: SqliteConnInner,
+}
+
+impl SqliteRedactedBlobs {
+    fn from(inner: SqliteConnInner) -> Self {
+        Self { inner }
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/sqlite-redacted-blobs.sql")
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::in_memory(
+            Self::get_up_query(),
+        )?))
+    }
+
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        Ok(Self::from(SqliteConnInner::open_or_create(
+            path,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        self.inner.get_master_conn()
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlRedactedBlobs {
+    inner: MysqlConnInner,
+}
+
+impl MysqlRedactedBlobs {
+    fn from(inner: MysqlConnInner) -> Self {
+        Self { inner }
+    }
+
+    pub fn open(db_address: &str) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::open(db_address)?))
+    }
+
+    fn get_up_query() -> &'static str {
+        include_str!("../schemas/mysql-redacted-blobs.sql")
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        Ok(Self::from(MysqlConnInner::create_test_db(
+            prefix,
+            Self::get_up_query(),
+        )?))
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_conn()
+    }
+
+    fn get_master_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.inner.get_master_conn()
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_redacted_blobs {
+    ($struct:ty) => {
+        impl RedactedBlobs for $struct {
+            fn add(&self, entry: RedactedBlob) -> BoxFuture<(), Error> {
+                STATS::adds.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let row = RedactedBlobRow {
+                        repo_id: entry.repo_id,
+                        blobstore_key: entry.blobstore_key,
+                        reason: entry.reason,
+                        add_timestamp: entry.timestamp.timestamp_secs(),
+                    };
+
+                    let connection = db.get_master_conn()?;
+                    replace_into(redacted_blobs::table)
+                        .values(&row)
+                        .execute(&*connection)?;
+                    Ok(())
+                })
+            }
+
+            fn remove(
+                &self,
+                repo_id: RepositoryId,
+                blobstore_key: String,
+            ) -> BoxFuture<bool, Error> {
+                STATS::removes.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    let removed = delete(
+                        redacted_blobs::table
+                            .filter(redacted_blobs::repo_id.eq(repo_id))
+                            .filter(redacted_blobs::blobstore_key.eq(blobstore_key)),
+                    ).execute(&*connection)?;
+                    Ok(removed > 0)
+                })
+            }
+
+            fn get_all(&self, repo_id: RepositoryId) -> BoxFuture<Vec<RedactedBlob>, Error> {
+                STATS::get_alls.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let rows = redacted_blobs::table
+                        .filter(redacted_blobs::repo_id.eq(repo_id))
+                        .order(redacted_blobs::blobstore_key.asc())
+                        .load::<RedactedBlobRow>(&*connection)?;
+                    rows.into_iter().map(row_to_entry).collect()
+                })
+            }
+        }
+    };
+}
+
+impl_redacted_blobs!(MysqlRedactedBlobs);
+impl_redacted_blobs!(SqliteRedactedBlobs);
+
+fn row_to_entry(row: RedactedBlobRow) -> Result<RedactedBlob> {
+    Ok(RedactedBlob {
+        repo_id: row.repo_id,
+        blobstore_key: row.blobstore_key,
+        reason: row.reason,
+        timestamp: DateTime::from_timestamp(row.add_timestamp, 0)?,
+    })
+}
@@ -0,0 +1,19 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use mercurial_types::RepositoryId;
+
+use schema::redacted_blobs;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Insertable, Queryable)]
+#[table_name = "redacted_blobs"]
+pub(crate) struct RedactedBlobRow {
+    pub repo_id: RepositoryId,
+    pub blobstore_key: String,
+    pub reason: String,
+    pub add_timestamp: i64,
+}
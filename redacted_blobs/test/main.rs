@@ -0,0 +1,155 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the list of redacted blobs.
+
+#![deny(warnings)]
+
+extern crate async_unit;
+extern crate futures;
+
+extern crate mercurial_types_mocks;
+extern crate mononoke_types;
+extern crate redacted_blobs;
+
+use std::sync::Arc;
+
+use futures::Future;
+
+use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
+use mononoke_types::DateTime;
+use redacted_blobs::{MysqlRedactedBlobs, RedactedBlob, RedactedBlobs, SqliteRedactedBlobs};
+
+fn entry(key: &str, reason: &str, timestamp: i64) -> RedactedBlob {
+    RedactedBlob::new(
+        REPO_ZERO,
+        key.to_string(),
+        reason.to_string(),
+        DateTime::from_timestamp(timestamp, 0).expect("invalid timestamp"),
+    )
+}
+
+fn add_and_get_all<R: RedactedBlobs>(redacted: R) {
+    redacted
+        .add(entry("key2", "leaked password", 100))
+        .wait()
+        .expect("Adding an entry failed");
+    redacted
+        .add(entry("key1", "leaked token", 200))
+        .wait()
+        .expect("Adding an entry failed");
+
+    let entries = redacted.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(
+        entries,
+        vec![
+            entry("key1", "leaked token", 200),
+            entry("key2", "leaked password", 100),
+        ]
+    );
+
+    // Redacting a blob again replaces its reason
+    redacted
+        .add(entry("key2", "leaked ssh key", 300))
+        .wait()
+        .expect("Adding an entry failed");
+    let entries = redacted.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(
+        entries,
+        vec![
+            entry("key1", "leaked token", 200),
+            entry("key2", "leaked ssh key", 300),
+        ]
+    );
+
+    let entries = redacted.get_all(REPO_ONE).wait().expect("get_all failed");
+    assert_eq!(entries, vec![]);
+}
+
+fn remove<R: RedactedBlobs>(redacted: R) {
+    redacted
+        .add(entry("key1", "leaked token", 100))
+        .wait()
+        .expect("Adding an entry failed");
+
+    let removed = redacted
+        .remove(REPO_ONE, "key1".to_string())
+        .wait()
+        .expect("remove failed");
+    assert!(!removed);
+
+    let removed = redacted
+        .remove(REPO_ZERO, "key1".to_string())
+        .wait()
+        .expect("remove failed");
+    assert!(removed);
+
+    let entries = redacted.get_all(REPO_ZERO).wait().expect("get_all failed");
+    assert_eq!(entries, vec![]);
+}
+
+macro_rules! redacted_blobs_test_impl {
+    ($mod_name:ident => { new: $new_cb:expr, }) => {
+        mod $mod_name {
+            use super::*;
+
+            #[test]
+            fn test_add_and_get_all() {
+                async_unit::tokio_unit_test(|| {
+                    add_and_get_all($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_remove() {
+                async_unit::tokio_unit_test(|| {
+                    remove($new_cb());
+                });
+            }
+        }
+    };
+}
+
+redacted_blobs_test_impl! {
+    sqlite_test => {
+        new: new_sqlite,
+    }
+}
+
+redacted_blobs_test_impl! {
+    sqlite_arced_test => {
+        new: new_sqlite_arced,
+    }
+}
+
+redacted_blobs_test_impl! {
+    mysql_test => {
+        new: new_mysql,
+    }
+}
+
+redacted_blobs_test_impl! {
+    mysql_arced_test => {
+        new: new_mysql_arced,
+    }
+}
+
+fn new_sqlite() -> SqliteRedactedBlobs {
+    SqliteRedactedBlobs::in_memory().expect("Creating an in-memory SQLite database failed")
+}
+
+fn new_sqlite_arced() -> Arc<RedactedBlobs> {
+    Arc::new(new_sqlite())
+}
+
+fn new_mysql() -> MysqlRedactedBlobs {
+    MysqlRedactedBlobs::create_test_db("redacted_blobs_test")
+        .expect("Failed to create test database")
+}
+
+fn new_mysql_arced() -> Arc<RedactedBlobs> {
+    Arc::new(new_mysql())
+}
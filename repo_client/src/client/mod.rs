@@ -16,22 +16,31 @@ use std::sync::Arc;
 use bytes::{BufMut, Bytes, BytesMut};
 use failure::err_msg;
 use futures::{future, stream, Async, Future, IntoFuture, Poll, Stream, stream::empty};
+use futures::sync::oneshot;
 use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 use futures_stats::{Timed, TimedStreamTrait};
 use itertools::Itertools;
 use slog::Logger;
 use stats::Histogram;
 use time_ext::DurationExt;
+use url::percent_encoding::percent_decode;
 
+use async_compression::CompressorType;
 use blobrepo::HgBlobChangeset;
+use blobstore::censored_reason;
+use bookmarks::Bookmark;
 use bundle2_resolver;
 use mercurial::{self, RevlogChangeset};
-use mercurial_bundles::{create_bundle_stream, parts, Bundle2EncodeBuilder, Bundle2Item};
-use mercurial_types::{percent_encode, Changeset, Entry, HgBlobNode, HgChangesetId, HgManifestId,
-                      HgNodeHash, MPath, RepoPath, Type, NULL_HASH};
+use mercurial_bundles::{create_bundle_stream, parts, Bundle2EncodeBuilder, Bundle2Item,
+                        Capabilities};
+use mercurial_bundles::changegroup::CgVersion;
+use mercurial_types::{b85encode, percent_encode, Changeset, Entry, HgBlobNode, HgChangesetId,
+                      HgManifestId, HgNodeHash, HgObsMarker, MPath, RepoPath, Type, NULL_HASH};
+use mercurial_types::obsmarker::OBSMARKERS_VERSION_1;
 use mercurial_types::manifest_utils::{and_pruner_combinator, changed_entry_stream,
                                       changed_entry_stream_with_pruner, file_pruner,
-                                      visited_pruner, ChangedEntry, EntryStatus};
+                                      narrow_pruner, visited_pruner, ChangedEntry, EntryStatus};
+use mercurial_types::narrow::{NarrowMatcher, NarrowPattern};
 use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
 use tracing::{TraceContext, Traced};
 
@@ -39,11 +48,15 @@ use blobrepo::BlobRepo;
 use hgproto::{self, GetbundleArgs, GettreepackArgs, HgCommandRes, HgCommands};
 use revset::DifferenceOfUnionsOfAncestorsNodeStream;
 
-use self::remotefilelog::create_remotefilelog_blob;
+use self::remotefilelog::{censored_tombstone, create_remotefilelog_blob};
 use errors::*;
 use mononoke_repo::MononokeRepo;
 
 const MAX_NODES_TO_LOG: usize = 5;
+// Same as the default level Mercurial uses for zstd.
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+// Same as Mercurial's limit for a single listkeys("obsolete") value.
+const OBSMARKERS_PUSHKEY_MAX_PAYLOAD: usize = 5300;
 
 define_stats! {
     prefix = "mononoke.repo_client";
@@ -60,6 +73,7 @@ mod ops {
     pub const UNBUNDLE: &str = "unbundle";
     pub const HEADS: &str = "heads";
     pub const LOOKUP: &str = "lookup";
+    pub const PUSHKEY: &str = "pushkey";
     pub const KNOWN: &str = "known";
     pub const BETWEEN: &str = "between";
     pub const GETBUNDLE: &str = "getbundle";
@@ -80,6 +94,16 @@ fn format_utf8_bytes_list(mut entries: Vec<Bytes>) -> String {
         .join(" ")
 }
 
+/// Parse the `old` or `new` value of a bookmarks pushkey. An empty value means that the bookmark
+/// doesn't exist (before a create or after a delete).
+fn parse_pushkey_changeset(value: &str) -> Result<Option<HgChangesetId>> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(HgChangesetId::from_str(value)?))
+    }
+}
+
 fn wireprotocaps() -> Vec<String> {
     vec![
         "lookup".to_string(),
@@ -89,6 +113,7 @@ fn wireprotocaps() -> Vec<String> {
         "gettreepack".to_string(),
         "remotefilelog".to_string(),
         "pushkey".to_string(),
+        "exp-narrow-1".to_string(),
     ]
 }
 
@@ -124,7 +149,11 @@ fn bundle2caps() -> String {
         // * To repro the race, run test-bookmark-race.t with the following line enabled.
 
         // ("listkeys", vec![]),
-        ("changegroup", vec!["02"]),
+        ("changegroup", vec!["02", "03"]),
+        // Bundle2 stream compression engines that can be used for getbundle responses. This is
+        // not a stock Mercurial capability, see `getbundle_compression` for how clients opt in.
+        ("compression", vec!["ZS", "UN"]),
+        ("obsmarkers", vec!["V1"]),
         ("b2x:infinitepush", vec![]),
         ("b2x:infinitepushscratchbookmarks", vec![]),
         ("pushkey", vec![]),
@@ -146,6 +175,78 @@ fn bundle2caps() -> String {
     percent_encode(&encodedcaps.join("\n"))
 }
 
+/// Extract the client's bundle2 capabilities from getbundle's `bundlecaps`. They are sent as a
+/// single url encoded `bundle2=<caps>` entry.
+fn client_bundle2caps(bundlecaps: &[Vec<u8>]) -> Result<Option<Capabilities>> {
+    let prefix = b"bundle2=";
+    match bundlecaps.iter().find(|cap| cap.starts_with(prefix)) {
+        Some(cap) => {
+            let caps: Vec<u8> = percent_decode(&cap[prefix.len()..]).collect();
+            Ok(Some(Capabilities::decode(&caps)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Encode markers for listkeys("obsolete") the same way Mercurial's `_pushkeyescape` does:
+/// markers are split into chunks of about `OBSMARKERS_PUSHKEY_MAX_PAYLOAD` bytes, and each chunk
+/// is prefixed with the format version and base85-encoded under a "dump<N>" key.
+fn obsmarkers_pushkey_escape(markers: &[HgObsMarker]) -> Result<HashMap<Vec<u8>, Vec<u8>>> {
+    let mut chunks: Vec<Vec<u8>> = vec![];
+    for marker in markers {
+        let data = marker.encode()?;
+        let start_new_chunk = match chunks.last() {
+            Some(chunk) => chunk.len() - 1 + data.len() > OBSMARKERS_PUSHKEY_MAX_PAYLOAD,
+            None => true,
+        };
+        if start_new_chunk {
+            chunks.push(vec![OBSMARKERS_VERSION_1]);
+        }
+        chunks
+            .last_mut()
+            .expect("chunk was just added")
+            .extend_from_slice(&data);
+    }
+
+    Ok(chunks
+        .into_iter()
+        .rev()
+        .enumerate()
+        .map(|(idx, chunk)| (format!("dump{}", idx).into_bytes(), b85encode(&chunk)))
+        .collect())
+}
+
+/// Pick the newest changegroup version that both the client and the server support.
+fn getbundle_cg_version(client_caps: Option<&Capabilities>) -> CgVersion {
+    match client_caps {
+        Some(caps) if caps.has_value("changegroup", CgVersion::Cg3Version.as_str()) => {
+            CgVersion::Cg3Version
+        }
+        _ => CgVersion::Cg2Version,
+    }
+}
+
+/// Compress getbundle responses only if the client explicitly asked for it.
+///
+/// Stock Mercurial clients have no way to ask for a compressed bundle2 stream over ssh: the only
+/// compression negotiation they do is the `comp=` list of the `X-HgProto-1` HTTP header, which
+/// picks a compression of the whole HTTP response rather than of the bundle2 stream, and their
+/// bundle2 caps (e.g. "HG20\nchangegroup=01,02\ndigests=md5,sha1,sha512\n...") never contain a
+/// "compression" entry. On top of that, they hang while trying to read compressed bundles over
+/// the wire: https://bz.mercurial-scm.org/show_bug.cgi?id=5646
+///
+/// So stock clients get uncompressed bundles, and only clients that were configured to add
+/// "compression=ZS" to the bundle2 caps they send (matching our "compression" bundle2
+/// capability) get zstd-compressed ones.
+fn getbundle_compression(client_caps: Option<&Capabilities>) -> Option<CompressorType> {
+    match client_caps {
+        Some(caps) if caps.has_value("compression", "ZS") => Some(CompressorType::Zstd {
+            level: ZSTD_COMPRESSION_LEVEL,
+        }),
+        _ => None,
+    }
+}
+
 #[derive(Clone)]
 pub struct RepoClient {
     repo: Arc<MononokeRepo>,
@@ -188,12 +289,33 @@ impl RepoClient {
     }
 
     fn create_bundle(&self, args: GetbundleArgs) -> hgproto::Result<HgCommandRes<Bytes>> {
+        let client_caps = client_bundle2caps(&args.bundlecaps)?;
+        if args.obsmarkers {
+            let supports_v1 = client_caps
+                .as_ref()
+                .map(|caps| caps.has_value("obsmarkers", "V1"))
+                .unwrap_or(false);
+            ensure_msg!(
+                supports_v1,
+                "client does not support a common obsmarkers format"
+            );
+        }
+        let cg_version = getbundle_cg_version(client_caps.as_ref());
+        let compression = getbundle_compression(client_caps.as_ref());
+        debug!(
+            self.logger,
+            "getbundle changegroup version {}, compression {:?}",
+            cg_version.as_str(),
+            compression
+        );
+
+        // Clients without narrow patterns fetch the files separately with getfiles, narrow
+        // clients get the revisions of the files of their narrow clone in the changegroup.
+        let matcher = NarrowMatcher::from_patterns(&args.includepats, &args.excludepats)?;
+
         let writer = Cursor::new(Vec::new());
         let mut bundle = Bundle2EncodeBuilder::new(writer);
-        // Mercurial currently hangs while trying to read compressed bundles over the wire:
-        // https://bz.mercurial-scm.org/show_bug.cgi?id=5646
-        // TODO: possibly enable compression support once this is fixed.
-        bundle.set_compressor_type(None);
+        bundle.set_compressor_type(compression);
 
         let blobrepo = self.repo.blobrepo();
 
@@ -219,10 +341,35 @@ impl RepoClient {
             DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(&blobrepo, heads, excludes)
                 .boxify();
 
+        // The filelogs and the obsmarkers part are encoded after the changelog entries, so they
+        // can reuse the list of nodes that the changegroup sends.
+        let mut nodes_senders = Vec::new();
+        let filelogs = match matcher {
+            Some(matcher) => {
+                let blobrepo = blobrepo.clone();
+                let matcher = Arc::new(matcher);
+                sent_nodes(&mut nodes_senders)
+                    .map(move |nodes| narrow_filelogs(blobrepo, nodes, matcher))
+                    .flatten_stream()
+                    .boxify()
+            }
+            None => stream::empty().boxify(),
+        };
+        let obsmarkers_nodes = if args.obsmarkers {
+            Some(sent_nodes(&mut nodes_senders))
+        } else {
+            None
+        };
+
         // TODO(stash): avoid collecting all the changelogs in the vector - T25767311
         let nodestosend = nodestosend
             .collect()
-            .map(|nodes| stream::iter_ok(nodes.into_iter().rev()))
+            .map(move |nodes| {
+                for nodes_sender in nodes_senders {
+                    let _ = nodes_sender.send(nodes.clone());
+                }
+                stream::iter_ok(nodes.into_iter().rev())
+            })
             .flatten_stream();
 
         let buffer_size = 100; // TODO(stash): make it configurable
@@ -255,7 +402,11 @@ impl RepoClient {
                 ))
             });
 
-        bundle.add_part(parts::changegroup_part(changelogentries)?);
+        bundle.add_part(parts::changegroup_part(
+            changelogentries,
+            filelogs,
+            cg_version,
+        )?);
 
         // XXX Note that listkeys is NOT returned as a bundle2 capability -- see comment in
         // bundle2caps() for why.
@@ -269,7 +420,17 @@ impl RepoClient {
             });
             bundle.add_part(parts::listkey_part("bookmarks", items)?);
         }
-        // TODO(stash): handle includepattern= and excludepattern=
+
+        if let Some(nodes) = obsmarkers_nodes {
+            let markers = nodes.and_then({
+                let blobrepo = blobrepo.clone();
+                move |nodes| {
+                    let changesets = nodes.into_iter().map(HgChangesetId::new).collect();
+                    blobrepo.get_relevant_obsmarkers(changesets)
+                }
+            });
+            bundle.add_part(parts::obsmarkers_part(markers)?);
+        }
 
         let encode_fut = bundle.build();
 
@@ -297,6 +458,13 @@ impl RepoClient {
             Some(try_boxstream!(MPath::new(params.rootdir)))
         };
 
+        // Without patterns the client wants all the trees.
+        let matcher = try_boxstream!(NarrowMatcher::from_patterns(
+            &params.includepats,
+            &params.excludepats
+        )).unwrap_or_else(|| NarrowMatcher::new(vec![NarrowPattern::Path(None)], vec![]));
+        let narrow_pruner = narrow_pruner(Arc::new(matcher));
+
         let changed_entries = if params.mfnodes.len() > 1 {
             let visited_pruner = visited_pruner();
             params
@@ -308,7 +476,10 @@ impl RepoClient {
                         &manifest_id,
                         &basemfnode,
                         rootpath.clone(),
-                        Some(and_pruner_combinator(&file_pruner, visited_pruner.clone())),
+                        Some(and_pruner_combinator(
+                            and_pruner_combinator(&file_pruner, narrow_pruner.clone()),
+                            visited_pruner.clone(),
+                        )),
                         self.trace.clone(),
                     );
                     cur_stream.select(new_stream).boxify()
@@ -320,7 +491,7 @@ impl RepoClient {
                     &mfnode,
                     &basemfnode,
                     rootpath.clone(),
-                    Some(&file_pruner),
+                    Some(and_pruner_combinator(&file_pruner, narrow_pruner)),
                     self.trace.clone(),
                 ),
                 None => empty().boxify(),
@@ -496,11 +667,9 @@ impl HgCommands for RepoClient {
         let mut scuba_logger = self.scuba_logger(ops::KNOWN, None);
         let trace = self.trace.clone();
 
-        future::join_all(
-            nodes
-                .into_iter()
-                .map(move |node| blobrepo.changeset_exists(&HgChangesetId::new(node))),
-        ).timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
+        blobrepo
+            .many_changesets_exist(nodes.into_iter().map(HgChangesetId::new).collect())
+            .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
 
@@ -556,6 +725,18 @@ impl HgCommands for RepoClient {
                     HashMap::from_iter(bookiter)
                 })
                 .boxify()
+        } else if namespace == "obsolete" {
+            self.repo
+                .blobrepo()
+                .get_all_obsmarkers()
+                .and_then(|markers| obsmarkers_pushkey_escape(&markers))
+                .boxify()
+        } else if namespace == "namespaces" {
+            // Mercurial checks this before pushing obsolescence markers.
+            let namespaces = vec!["bookmarks", "obsolete"]
+                .into_iter()
+                .map(|namespace| (Vec::from(namespace), vec![]));
+            future::ok(HashMap::from_iter(namespaces)).boxify()
         } else {
             info!(
                 self.get_logger(),
@@ -566,6 +747,65 @@ impl HgCommands for RepoClient {
         }
     }
 
+    // @wireprotocommand('pushkey', 'namespace key old new')
+    fn pushkey(
+        &self,
+        namespace: String,
+        key: String,
+        old: String,
+        new: String,
+    ) -> HgCommandRes<bool> {
+        info!(
+            self.logger,
+            "pushkey: {} {} {:?} -> {:?}", namespace, key, old, new
+        );
+
+        if namespace != "bookmarks" {
+            info!(
+                self.get_logger(),
+                "unsupported pushkey namespace: {}",
+                namespace
+            );
+            return future::ok(false).boxify();
+        }
+
+        let mut scuba_logger = self.scuba_logger(ops::PUSHKEY, None);
+        let trace = self.trace.clone();
+        let blobrepo = self.repo.blobrepo();
+
+        let txn = (|| -> Result<_> {
+            let bookmark = Bookmark::new(key)?;
+            let old = parse_pushkey_changeset(&old)?;
+            let new = parse_pushkey_changeset(&new)?;
+
+            let mut txn = blobrepo.update_bookmark_transaction();
+            // Same compare-and-swap semantics as a bookmark pushed in a bundle2 pushkey part
+            match (new, old) {
+                (Some(new), Some(old)) => txn.update(&bookmark, &new, &old)?,
+                (Some(new), None) => txn.create(&bookmark, &new)?,
+                (None, Some(old)) => txn.delete(&bookmark, &old)?,
+                (None, None) => {}
+            }
+            Ok((txn, new))
+        })();
+
+        txn.into_future()
+            .and_then(move |(txn, new)| match new {
+                // Don't let a bookmark point to a commit that the server doesn't have
+                Some(new) => blobrepo
+                    .changeset_exists(&new)
+                    .and_then(move |exists| {
+                        ensure_msg!(exists, "pushkey: changeset {} not found", new);
+                        Ok(txn)
+                    })
+                    .left_future(),
+                None => future::ok(txn).right_future(),
+            })
+            .and_then(|txn| txn.commit())
+            .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
+            .boxify()
+    }
+
     // @wireprotocommand('unbundle')
     fn unbundle(
         &self,
@@ -618,31 +858,237 @@ impl HgCommands for RepoClient {
         let this = self.clone();
         let getfiles_buffer_size = 100; // TODO(stash): make it configurable
         params
-            .map(move |(node, path)| {
-                let args = format!("node: {}, path: {}", node, path);
-                let mut scuba_logger = this.scuba_logger(ops::GETFILES, Some(args));
-
-                let repo = this.repo.clone();
-                create_remotefilelog_blob(repo.blobrepo(), node, path.clone(), trace.clone())
-                    .traced(
-                        &trace,
-                        "getfile",
-                        trace_args!("node" => format!("{}", node), "path" => format!("{}", path)),
-                    )
-                    .timed({
-                        let trace = trace.clone();
-                        move |stats, _| {
-                            STATS::getfiles_ms
-                                .add_value(stats.completion_time.as_millis_unchecked() as i64);
-                            scuba_logger.add_stats(&stats).log_with_trace(&trace)
-                        }
+            .ready_chunks(getfiles_buffer_size)
+            .map(move |params| {
+                // The filenodes of all the files that were asked for so far are fetched at once,
+                // which saves a database roundtrip per file.
+                let paths = params
+                    .iter()
+                    .map(|&(_, ref path)| RepoPath::FilePath(path.clone()))
+                    .collect();
+                let this = this.clone();
+                let trace = trace.clone();
+
+                this.repo
+                    .blobrepo()
+                    .get_all_filenodes_many(paths)
+                    .map(move |all_filenodes| {
+                        let files: Vec<_> = params
+                            .into_iter()
+                            .map(move |(node, path)| {
+                                let args = format!("node: {}, path: {}", node, path);
+                                let mut scuba_logger = this.scuba_logger(ops::GETFILES, Some(args));
+
+                                let filenodes = all_filenodes
+                                    .get(&RepoPath::FilePath(path.clone()))
+                                    .cloned()
+                                    .unwrap_or_default();
+                                let repo = this.repo.clone();
+                                create_remotefilelog_blob(
+                                    repo.blobrepo(),
+                                    node,
+                                    path.clone(),
+                                    filenodes,
+                                    trace.clone(),
+                                ).traced(
+                                    &trace,
+                                    "getfile",
+                                    trace_args!(
+                                        "node" => format!("{}", node),
+                                        "path" => format!("{}", path)
+                                    ),
+                                )
+                                    .timed({
+                                        let trace = trace.clone();
+                                        move |stats, _| {
+                                            STATS::getfiles_ms.add_value(
+                                                stats.completion_time.as_millis_unchecked() as i64,
+                                            );
+                                            scuba_logger.add_stats(&stats).log_with_trace(&trace)
+                                        }
+                                    })
+                            })
+                            .collect();
+                        stream::iter_ok(files)
                     })
+                    .flatten_stream()
             })
+            .flatten()
             .buffered(getfiles_buffer_size)
             .boxify()
     }
 }
 
+/// The list of nodes that the changegroup sends, once it's known. The senders are sent the list
+/// as soon as the changelog entries are generated.
+fn sent_nodes(
+    senders: &mut Vec<oneshot::Sender<Vec<HgNodeHash>>>,
+) -> impl Future<Item = Vec<HgNodeHash>, Error = Error> {
+    let (sender, receiver) = oneshot::channel();
+    senders.push(sender);
+    receiver.map_err(|_| err_msg("changegroup part did not produce the list of nodes"))
+}
+
+/// The revisions of the files of a narrow clone that the changesets `nodes` introduced, grouped
+/// by file. `nodes` are ordered from the highest generation, like the nodes of the changegroup
+/// before they're sent, so that the parent revisions of each file come first.
+fn narrow_filelogs(
+    repo: Arc<BlobRepo>,
+    nodes: Vec<HgNodeHash>,
+    matcher: Arc<NarrowMatcher>,
+) -> BoxStream<(MPath, Vec<parts::FilelogEntry>), Error> {
+    let buffer_size = 100;
+    stream::iter_ok(nodes.into_iter().rev())
+        .map(move |node| narrow_changed_files(repo.clone(), node, matcher.clone()))
+        .buffered(buffer_size)
+        .fold(
+            (Vec::new(), HashMap::new(), HashSet::new()),
+            |(mut filelogs, mut indexes, mut sent), files| {
+                for (path, entry) in files {
+                    // The same revision may be introduced again, e.g. by a backout.
+                    if !sent.insert((path.clone(), entry.node)) {
+                        continue;
+                    }
+                    let index = *indexes.entry(path.clone()).or_insert_with(|| {
+                        filelogs.push((path, Vec::new()));
+                        filelogs.len() - 1
+                    });
+                    filelogs[index].1.push(entry);
+                }
+                Ok::<_, Error>((filelogs, indexes, sent))
+            },
+        )
+        .map(|(filelogs, _, _)| stream::iter_ok(filelogs))
+        .flatten_stream()
+        .boxify()
+}
+
+/// The revisions of the files matched by `matcher` that the changeset `node` introduced, i.e.
+/// that differ from the revisions in each of its parents.
+fn narrow_changed_files(
+    repo: Arc<BlobRepo>,
+    node: HgNodeHash,
+    matcher: Arc<NarrowMatcher>,
+) -> BoxFuture<Vec<(MPath, parts::FilelogEntry)>, Error> {
+    let buffer_size = 100;
+    repo.get_changeset_by_changesetid(&HgChangesetId::new(node))
+        .and_then({
+            let repo = repo.clone();
+            move |cs| {
+                let mfid = cs.manifestid().clone().into_nodehash();
+                let p1_changed = parent_manifestid(&repo, cs.p1()).and_then({
+                    let repo = repo.clone();
+                    let matcher = matcher.clone();
+                    move |p1mfid| narrow_changed_entries(&repo, mfid, p1mfid, matcher)
+                });
+                // The files of a merge that are unchanged from its second parent were introduced
+                // by a changeset of that branch.
+                let p2_changed = match cs.p2() {
+                    Some(p2) => parent_manifestid(&repo, Some(p2))
+                        .and_then(move |p2mfid| {
+                            narrow_changed_entries(&repo, mfid, p2mfid, matcher)
+                        })
+                        .map(|entries| {
+                            let revisions = entries
+                                .into_iter()
+                                .map(|(path, entry)| (path, entry.get_hash().into_nodehash()))
+                                .collect::<HashSet<_>>();
+                            Some(revisions)
+                        })
+                        .left_future(),
+                    None => future::ok(None).right_future(),
+                };
+                p1_changed
+                    .join(p2_changed)
+                    .map(|(changed, p2_changed)| match p2_changed {
+                        Some(p2_changed) => changed
+                            .into_iter()
+                            .filter(|&(ref path, ref entry)| {
+                                let revision = (path.clone(), entry.get_hash().into_nodehash());
+                                p2_changed.contains(&revision)
+                            })
+                            .collect(),
+                        None => changed,
+                    })
+            }
+        })
+        .map(stream::iter_ok)
+        .flatten_stream()
+        .map(move |(path, entry)| {
+            let filenode = entry.get_hash().into_nodehash();
+            let linknode = repo.get_linknode(RepoPath::FilePath(path.clone()), &filenode)
+                .map(|linknode| linknode.into_nodehash());
+            let content = entry
+                .get_raw_content()
+                .and_then(|blob| blob.into_inner().ok_or(err_msg("bad blob content")))
+                .or_else(|err| {
+                    // Redacted files are sent as tombstones, like getfiles does.
+                    let tombstone = censored_reason(&err).map(censored_tombstone);
+                    tombstone.ok_or(err)
+                });
+            entry
+                .get_parents()
+                .join3(linknode, content)
+                .map(move |(parents, linknode, content)| {
+                    let (p1, p2) = parents.get_nodes();
+                    let entry = parts::FilelogEntry {
+                        node: filenode,
+                        p1: p1.cloned(),
+                        p2: p2.cloned(),
+                        linknode,
+                        content,
+                    };
+                    (path, entry)
+                })
+        })
+        .buffered(buffer_size)
+        .collect()
+        .boxify()
+}
+
+/// The manifest of the parent changeset `parent`, or the null manifest if there is none.
+fn parent_manifestid(
+    repo: &Arc<BlobRepo>,
+    parent: Option<&HgNodeHash>,
+) -> BoxFuture<HgNodeHash, Error> {
+    match parent {
+        Some(parent) => repo.get_changeset_by_changesetid(&HgChangesetId::new(*parent))
+            .map(|parent| parent.manifestid().clone().into_nodehash())
+            .boxify(),
+        None => future::ok(NULL_HASH).boxify(),
+    }
+}
+
+/// The files matched by `matcher` that were added or modified in the manifest `mfid` compared to
+/// the manifest `basemfid`.
+fn narrow_changed_entries(
+    repo: &Arc<BlobRepo>,
+    mfid: HgNodeHash,
+    basemfid: HgNodeHash,
+    matcher: Arc<NarrowMatcher>,
+) -> BoxFuture<Vec<(MPath, Box<Entry + Sync>)>, Error> {
+    repo.get_manifest_by_nodeid(&mfid)
+        .join(repo.get_manifest_by_nodeid(&basemfid))
+        .map(move |(mf, basemf)| {
+            changed_entry_stream_with_pruner(&mf, &basemf, None, narrow_pruner(matcher))
+        })
+        .flatten_stream()
+        .filter_map(|changed| match changed.status {
+            EntryStatus::Added(entry) | EntryStatus::Modified { to_entry: entry, .. } => {
+                if entry.get_type().is_tree() {
+                    None
+                } else {
+                    let path = MPath::join_element_opt(changed.dirname.as_ref(), entry.get_name())
+                        .expect("files always have a name");
+                    Some((path, entry))
+                }
+            }
+            EntryStatus::Deleted(_) => None,
+        })
+        .collect()
+        .boxify()
+}
+
 fn get_changed_entry_stream(
     repo: Arc<BlobRepo>,
     mfid: &HgNodeHash,
@@ -767,3 +1213,191 @@ fn fetch_treepack_part_input(
         })
         .boxify()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+    use std::time::Instant;
+
+    use async_unit;
+    use fixtures::{many_files_dirs, merge_uneven};
+    use uuid::Uuid;
+
+    // The second commit of many_files_dirs, a child of BASE, adds the files "2",
+    // "dir1/file_1_in_dir1", "dir1/file_2_in_dir1", "dir1/subdir1/file_1" and
+    // "dir2/file_1_in_dir2".
+    const BASE: &str = "5a28e25f924a5d209b82ce0713d8d83e68982bc8";
+    const MAIN: &str = "2f866e7e549760934e31bf0420a873f65100ad63";
+
+    fn node(hash: &str) -> HgNodeHash {
+        HgNodeHash::from_str(hash).unwrap()
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<Vec<u8>> {
+        patterns.iter().map(|p| p.as_bytes().to_vec()).collect()
+    }
+
+    fn filelog_paths(includes: &[&str], excludes: &[&str]) -> Vec<(String, Vec<HgNodeHash>)> {
+        let repo = Arc::new(many_files_dirs::getrepo(None));
+        let matcher = NarrowMatcher::from_patterns(&patterns(includes), &patterns(excludes))
+            .expect("invalid patterns")
+            .expect("no patterns");
+        narrow_filelogs(repo, vec![node(MAIN), node(BASE)], Arc::new(matcher))
+            .collect()
+            .wait()
+            .expect("narrow_filelogs failed")
+            .into_iter()
+            .map(|(path, entries)| {
+                let linknodes = entries.into_iter().map(|entry| entry.linknode).collect();
+                (format!("{}", path), linknodes)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn narrow_filelogs_include_dir() {
+        async_unit::tokio_unit_test(|| {
+            let mut paths = filelog_paths(&["path:dir1"], &["path:dir1/subdir1"]);
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec![
+                    ("dir1/file_1_in_dir1".to_string(), vec![node(MAIN)]),
+                    ("dir1/file_2_in_dir1".to_string(), vec![node(MAIN)]),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn narrow_filelogs_root_files() {
+        async_unit::tokio_unit_test(|| {
+            let mut paths = filelog_paths(&["rootfilesin:."], &[]);
+            paths.sort();
+            assert_eq!(
+                paths,
+                vec![
+                    ("1".to_string(), vec![node(BASE)]),
+                    ("2".to_string(), vec![node(MAIN)]),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn narrow_filelogs_merge() {
+        async_unit::tokio_unit_test(|| {
+            // The merge of merge_uneven, whose files mostly come unchanged from one of the
+            // branches.
+            let merge = node("b47ca72355a0af2c749d45a5689fd5bcce9898c7");
+            let repo = Arc::new(merge_uneven::getrepo(None));
+            let matcher = NarrowMatcher::from_patterns(&patterns(&["path:."]), &patterns(&[]))
+                .expect("invalid patterns")
+                .expect("no patterns");
+            let filelogs = narrow_filelogs(repo, vec![merge], Arc::new(matcher))
+                .collect()
+                .wait()
+                .expect("narrow_filelogs failed");
+            // Only the revisions that the merge introduced are linked to it.
+            for (path, entries) in filelogs {
+                for entry in entries {
+                    assert_eq!(entry.linknode, merge, "{} was not introduced by the merge", path);
+                }
+            }
+        });
+    }
+
+    fn getbundle(includepats: &[&str]) -> Result<Bytes> {
+        let repo = MononokeRepo::from_blobrepo(
+            "many_files_dirs",
+            Arc::new(many_files_dirs::getrepo(None)),
+        );
+        let logger = Logger::root(::slog::Discard, o!());
+        let client = RepoClient::new(
+            Arc::new(repo),
+            logger,
+            ScubaSampleBuilder::with_discard(),
+            TraceContext::new(Uuid::new_v4(), Instant::now()),
+        );
+        let args = GetbundleArgs {
+            heads: vec![node(MAIN)],
+            common: vec![],
+            bundlecaps: vec![],
+            listkeys: vec![],
+            obsmarkers: false,
+            includepats: patterns(includepats),
+            excludepats: vec![],
+        };
+        client.create_bundle(args)?.wait()
+    }
+
+    #[test]
+    fn narrow_getbundle() {
+        async_unit::tokio_unit_test(|| {
+            let full = getbundle(&[]).expect("getbundle failed");
+            let narrow = getbundle(&["path:dir2"]).expect("narrow getbundle failed");
+            // Only the narrow bundle has filelogs, with the file name in the section header.
+            assert!(narrow.len() > full.len());
+            let header = b"\0\0\0\x17dir2/file_1_in_dir2";
+            let has_filelog = |bundle: &Bytes| {
+                bundle
+                    .windows(header.len())
+                    .any(|window| window == &header[..])
+            };
+            assert!(!has_filelog(&full));
+            assert!(has_filelog(&narrow));
+        });
+    }
+
+    #[test]
+    fn narrow_getbundle_invalid_pattern() {
+        async_unit::tokio_unit_test(|| {
+            assert!(getbundle(&["glob:*.rs"]).is_err());
+        });
+    }
+
+    // The bundlecaps sent by a stock Mercurial 4.6 client on pull, with treemanifest enabled.
+    const CLIENT_BUNDLECAPS: &[&str] = &[
+        "HG20",
+        "bundle2=HG20%0Abookmarks%0Achangegroup%3D01%2C02%2C03%0Adigests%3Dmd5%2Csha1%2Csha512\
+         %0Aerror%3Dabort%2Cunsupportedcontent%2Cpushraced%2Cpushkey%0Ahgtagsfnodes%0Alistkeys\
+         %0Aphases%3Dheads%0Apushkey%0Aremote-changegroup%3Dhttp%2Chttps%0Arev-branch-cache",
+        "remotefilelog",
+    ];
+
+    fn client_caps(extra_caps: &str) -> Capabilities {
+        let bundlecaps: Vec<_> = CLIENT_BUNDLECAPS
+            .iter()
+            .map(|cap| {
+                let mut cap = cap.as_bytes().to_vec();
+                if cap.starts_with(b"bundle2=") {
+                    cap.extend_from_slice(extra_caps.as_bytes());
+                }
+                cap
+            })
+            .collect();
+        client_bundle2caps(&bundlecaps)
+            .expect("invalid bundlecaps")
+            .expect("no bundle2 caps")
+    }
+
+    #[test]
+    fn getbundle_stock_client_caps() {
+        let caps = client_caps("");
+        assert!(caps.has_value("digests", "sha512"));
+        assert_eq!(getbundle_cg_version(Some(&caps)), CgVersion::Cg3Version);
+        assert!(getbundle_compression(Some(&caps)).is_none());
+        assert!(getbundle_compression(None).is_none());
+    }
+
+    #[test]
+    fn getbundle_client_asks_for_compression() {
+        let caps = client_caps("%0Acompression%3DZS%2CUN");
+        match getbundle_compression(Some(&caps)) {
+            Some(CompressorType::Zstd { level }) => assert_eq!(level, ZSTD_COMPRESSION_LEVEL),
+            other => panic!("unexpected compression: {:?}", other),
+        }
+    }
+}
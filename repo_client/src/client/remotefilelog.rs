@@ -14,6 +14,7 @@ use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 use pylz4;
 
 use blobrepo::BlobRepo;
+use blobstore::censored_reason;
 use filenodes::FilenodeInfo;
 use mercurial_types::{HgChangesetId, HgNodeHash, HgParents, MPath, RepoPath, NULL_HASH};
 use tracing::{TraceContext, Traced};
@@ -23,33 +24,52 @@ use errors::*;
 const METAKEYFLAG: &str = "f";
 const METAKEYSIZE: &str = "s";
 
+/// The largest file that getfiles sends. Remotefilelog blobs are built whole in memory, so larger
+/// files are refused rather than fetched; they should be stored in LFS instead.
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// Content that Mercurial's censor support shows instead of the content of a censored file.
+pub fn censored_tombstone(reason: &str) -> Bytes {
+    Bytes::from(format!("\x01\ncensored: {}\n\x01\n", reason))
+}
+
 /// Remotefilelog blob consists of file content in `node` revision and all the history
-/// of the file up to `node`
+/// of the file up to `node`. `all_filenodes` are the filenodes of `path` fetched in bulk with
+/// `get_all_filenodes_many`.
 pub fn create_remotefilelog_blob(
     repo: Arc<BlobRepo>,
     node: HgNodeHash,
     path: MPath,
+    all_filenodes: Vec<FilenodeInfo>,
     trace: TraceContext,
 ) -> BoxFuture<Bytes, Error> {
     // raw_content includes copy information
-    let raw_content_bytes = repo.get_file_content(&node)
-        .and_then(move |raw_content| {
-            let raw_content = raw_content.into_bytes();
+    let raw_content_bytes = repo.get_file_content_bounded(&node, MAX_FILE_SIZE)
+        .map(|raw_content| raw_content.into_bytes())
+        .or_else(|err| {
+            // Redacted files are sent as tombstones, so that clients can still update to the
+            // revisions that contain them.
+            let tombstone = censored_reason(&err).map(censored_tombstone);
+            tombstone.ok_or(err)
+        })
+        .join(repo.get_file_flags(&node))
+        .and_then(move |(raw_content, flags)| {
             // requires digit counting to know for sure, use reasonable approximation
             let approximate_header_size = 12;
             let mut writer = Cursor::new(Vec::with_capacity(
                 approximate_header_size + raw_content.len(),
             ));
 
-            // Write header
-            // TODO(stash): support LFS files using METAKEYFLAG
+            // Write header. The flags are the ones the file node was pushed with: e.g. pointers
+            // of the `lfs` extension are flagged so that clients fetch the contents they refer
+            // to from the LFS store.
             let res = write!(
                 writer,
                 "v1\n{}{}\n{}{}\0",
                 METAKEYSIZE,
                 raw_content.len(),
                 METAKEYFLAG,
-                0,
+                flags,
             );
 
             res.and_then(|_| writer.write_all(&raw_content))
@@ -58,25 +78,16 @@ pub fn create_remotefilelog_blob(
         })
         .traced(&trace, "fetching remotefilelog content", trace_args!());
 
-    // Do bulk prefetch of the filenodes first. That saves lots of db roundtrips.
+    // The filenodes were prefetched in bulk, which saves lots of db roundtrips.
     // Prefetched filenodes are used as a cache. If filenode is not in the cache, then it will
     // be fetched again.
-    let prefetched_filenodes = repo.get_all_filenodes(RepoPath::FilePath(path.clone()))
-        .map(|filenodes| {
-            filenodes
-                .into_iter()
-                .map(|filenode| (filenode.filenode.into_nodehash(), filenode))
-                .collect()
-        });
-
-    let file_history_bytes = prefetched_filenodes
-        .and_then({
-            let node = node.clone();
-            let trace = trace.clone();
-            move |prefetched_filenodes| {
-                get_file_history(repo, node, path, prefetched_filenodes, trace).collect()
-            }
-        })
+    let prefetched_filenodes = all_filenodes
+        .into_iter()
+        .map(|filenode| (filenode.filenode.into_nodehash(), filenode))
+        .collect();
+
+    let file_history_bytes = get_file_history(repo, node, path, prefetched_filenodes, trace.clone())
+        .collect()
         .and_then(|history| {
             let approximate_history_entry_size = 81;
             let mut writer = Cursor::new(Vec::with_capacity(
@@ -28,8 +28,12 @@ extern crate stats;
 extern crate time_ext;
 #[macro_use]
 extern crate tracing;
+extern crate url;
 
+extern crate async_compression;
 extern crate blobrepo;
+extern crate blobstore;
+extern crate bookmarks;
 extern crate bundle2_resolver;
 extern crate filenodes;
 extern crate hgproto;
@@ -37,9 +41,17 @@ extern crate mercurial;
 extern crate mercurial_bundles;
 extern crate mercurial_types;
 extern crate metaconfig;
+extern crate reachabilityindex;
 extern crate revset;
 extern crate scuba_ext;
 
+#[cfg(test)]
+extern crate async_unit;
+#[cfg(test)]
+extern crate fixtures;
+#[cfg(test)]
+extern crate uuid;
+
 mod client;
 mod errors;
 mod mononoke_repo;
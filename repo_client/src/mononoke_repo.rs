@@ -9,16 +9,24 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
+use futures::{future, Stream};
+use futures_ext::{BoxFuture, FutureExt};
 use rand::Isaac64Rng;
 use rand::distributions::{Distribution, LogNormal};
 use slog::Logger;
 
-use blobrepo::BlobRepo;
+use blobrepo::{BlobRepo, CompressionArgs, DiskCacheArgs};
+use blobstore::Keyring;
 use mercurial_types::RepositoryId;
 use metaconfig::repoconfig::RepoType;
+use reachabilityindex::SkiplistIndex;
 
 use errors::*;
 
+// Indexing stops at the commits that are already indexed, so this only limits how much of the
+// history of a bookmark that was created away from the indexed commits gets indexed.
+const SKIPLIST_EXTEND_DEPTH: u64 = 1000;
+
 struct LogNormalGenerator {
     rng: Isaac64Rng,
     distribution: LogNormal,
@@ -27,16 +35,45 @@ struct LogNormalGenerator {
 pub struct MononokeRepo {
     path: String,
     blobrepo: Arc<BlobRepo>,
+    skiplist_index: Arc<SkiplistIndex>,
 }
 
 impl MononokeRepo {
-    pub fn new(logger: Logger, repo: &RepoType, repoid: RepositoryId) -> Result<Self> {
+    /// If `encryption_keyfile` is given, the blobs of local repos are encrypted with its keys,
+    /// and `encryption_allow_plaintext` lets the blobs written before that be read.
+    /// If `disk_cache` is given, the blobs of local repos are cached on local disk.
+    /// If `compression` is given, the large blobs of local repos are compressed.
+    pub fn new(
+        logger: Logger,
+        repo: &RepoType,
+        repoid: RepositoryId,
+        encryption_keyfile: Option<&Path>,
+        encryption_allow_plaintext: bool,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<Self> {
+        let keyring = match encryption_keyfile {
+            Some(keyfile) => {
+                Some(Keyring::open(keyfile)?.allow_plaintext(encryption_allow_plaintext))
+            }
+            None => None,
+        };
         Ok(MononokeRepo {
             path: format!("{}", repo.path().to_owned().display()),
-            blobrepo: Arc::new(repo.open(logger, repoid)?),
+            blobrepo: Arc::new(repo.open(logger, repoid, keyring, disk_cache, compression)?),
+            skiplist_index: Arc::new(SkiplistIndex::new()),
         })
     }
 
+    /// Wrap an already opened repo, for tools that don't get it from the repo configs.
+    pub fn from_blobrepo<P: Into<String>>(path: P, blobrepo: Arc<BlobRepo>) -> Self {
+        MononokeRepo {
+            path: path.into(),
+            blobrepo,
+            skiplist_index: Arc::new(SkiplistIndex::new()),
+        }
+    }
+
     pub fn path(&self) -> &String {
         &self.path
     }
@@ -44,6 +81,40 @@ impl MononokeRepo {
     pub fn blobrepo(&self) -> Arc<BlobRepo> {
         self.blobrepo.clone()
     }
+
+    /// The skiplist index of the repo, loaded on startup, built by the cache warmup and on
+    /// demand.
+    pub fn skiplist_index(&self) -> Arc<SkiplistIndex> {
+        self.skiplist_index.clone()
+    }
+
+    /// Index the commits that the bookmarks were moved to since they were last indexed, so that
+    /// the skiplist index keeps covering the history of the bookmarks. An empty index is left
+    /// alone, since it is only built on demand.
+    pub fn extend_skiplist_index(&self) -> BoxFuture<(), Error> {
+        if self.skiplist_index.indexed_node_count() == 0 {
+            return future::ok(()).boxify();
+        }
+
+        let blobrepo = self.blobrepo.clone();
+        let skiplist_index = self.skiplist_index.clone();
+        self.blobrepo
+            .get_bookmarks()
+            .map(|(_, cs_id)| *cs_id.as_nodehash())
+            .filter({
+                let skiplist_index = self.skiplist_index.clone();
+                move |node| !skiplist_index.is_node_indexed(*node)
+            })
+            .for_each(move |node| {
+                skiplist_index.add_node(blobrepo.clone(), node, SKIPLIST_EXTEND_DEPTH)
+            })
+            .boxify()
+    }
+
+    /// Store the skiplist index as the blob `key`, from where it is loaded on startup.
+    pub fn save_skiplist_index(&self, key: String) -> BoxFuture<(), Error> {
+        self.skiplist_index.save_to_blobstore(&self.blobrepo, key)
+    }
 }
 
 impl Debug for MononokeRepo {
@@ -53,18 +124,39 @@ impl Debug for MononokeRepo {
 }
 
 trait OpenableRepoType {
-    fn open(&self, logger: Logger, repoid: RepositoryId) -> Result<BlobRepo>;
+    fn open(
+        &self,
+        logger: Logger,
+        repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<BlobRepo>;
     fn path(&self) -> &Path;
 }
 
 impl OpenableRepoType for RepoType {
-    fn open(&self, logger: Logger, repoid: RepositoryId) -> Result<BlobRepo> {
+    fn open(
+        &self,
+        logger: Logger,
+        repoid: RepositoryId,
+        keyring: Option<Keyring>,
+        disk_cache: Option<&DiskCacheArgs>,
+        compression: Option<&CompressionArgs>,
+    ) -> Result<BlobRepo> {
         use hgproto::ErrorKind;
         use metaconfig::repoconfig::RepoType::*;
 
         let ret = match *self {
             Revlog(_) => Err(ErrorKind::CantServeRevlogRepo)?,
-            BlobRocks(ref path) => BlobRepo::new_rocksdb(logger, &path, repoid)?,
+            BlobRocks(ref path) => BlobRepo::new_rocksdb(
+                logger,
+                &path,
+                repoid,
+                keyring,
+                disk_cache,
+                compression,
+            )?,
             BlobManifold { ref args, .. } => BlobRepo::new_manifold(logger, args, repoid)?,
             TestBlobDelayRocks(ref path, mean, stddev) => {
                 // We take in an arithmetic mean and stddev, and deduce a log normal
@@ -106,6 +198,20 @@ impl OpenableRepoType for RepoType {
                     2, // assert_present
                 )?
             }
+            BlobMultiplexed {
+                ref path,
+                ref blobstores,
+                write_quorum,
+            } => BlobRepo::new_multiplexed(
+                logger,
+                &path,
+                blobstores,
+                write_quorum,
+                repoid,
+                keyring,
+                disk_cache,
+                compression,
+            )?,
         };
 
         Ok(ret)
@@ -118,6 +224,7 @@ impl OpenableRepoType for RepoType {
             Revlog(ref path) | BlobRocks(ref path) => path.as_ref(),
             BlobManifold { ref path, .. } => path.as_ref(),
             TestBlobDelayRocks(ref path, ..) => path.as_ref(),
+            BlobMultiplexed { ref path, .. } => path.as_ref(),
         }
     }
 }
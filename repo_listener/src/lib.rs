@@ -58,7 +58,7 @@ use metaconfig::repoconfig::RepoConfig;
 
 use connection_acceptor::connection_acceptor;
 use errors::*;
-use repo_handlers::repo_handlers;
+use repo_handlers::{repo_handlers, save_cache_snapshots};
 
 pub fn create_repo_listeners<I>(
     repos: I,
@@ -75,7 +75,20 @@ where
 
     (
         repo_handlers(repos, &root_log, &mut ready)
-            .and_then(move |handles| connection_acceptor(sockname, root_log, handles, tls_acceptor))
+            .and_then(move |handles| {
+                // The server shuts down when the acceptor stops, so that's when the caches are
+                // saved for the next start.
+                let repos: Vec<_> = handles
+                    .values()
+                    .map(|&(ref logger, _, ref repo, _)| (logger.clone(), repo.clone()))
+                    .collect();
+                connection_acceptor(sockname, root_log, handles, tls_acceptor).then(move |res| {
+                    for (logger, repo) in repos {
+                        save_cache_snapshots(&repo, &logger);
+                    }
+                    res
+                })
+            })
             .boxify(),
         ready.freeze(),
     )
@@ -5,12 +5,17 @@
 // GNU General Public License version 2 or any later version.
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use failure::prelude::*;
-use futures::{future, Future};
+use futures::{future, Future, Stream};
 use futures_ext::{BoxFuture, FutureExt};
 use slog::Logger;
+use tokio;
+use tokio::timer::Interval;
 
 use cache_warmup::cache_warmup;
 use mercurial_types::RepositoryId;
@@ -19,7 +24,21 @@ use ready_state::ReadyStateBuilder;
 use repo_client::MononokeRepo;
 use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
 
-pub type RepoHandler = (Logger, ScubaSampleBuilder, Arc<MononokeRepo>);
+// How often the skiplist index is extended with the commits that the bookmarks were moved to, and
+// stored again if that added anything.
+const SKIPLIST_INDEX_UPDATE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// How often the caches of repos with a snapshot directory are saved to it, so that a server that
+// doesn't shut down cleanly still restarts with warm caches.
+const CACHE_SNAPSHOT_SAVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Logger, scuba logger, repo and the directory where requests are recorded, if enabled.
+pub type RepoHandler = (
+    Logger,
+    ScubaSampleBuilder,
+    Arc<MononokeRepo>,
+    Option<PathBuf>,
+);
 
 pub fn repo_handlers<I>(
     repos: I,
@@ -46,6 +65,10 @@ where
                 root_log.new(o!("repo" => reponame.clone())),
                 &config.repotype,
                 RepositoryId::new(config.repoid),
+                config.encryption_keyfile.as_ref().map(PathBuf::as_path),
+                config.encryption_allow_plaintext,
+                config.disk_cache.as_ref(),
+                config.compression.as_ref(),
             ).expect(&format!("failed to initialize repo {}", reponame));
 
             let listen_log = root_log.new(o!("repo" => repo.path().clone()));
@@ -54,14 +77,47 @@ where
             scuba_logger.add_common_server_data();
 
             let repo = Arc::new(repo);
+            let wireproto_recording_dir = config.wireproto_recording_dir.clone();
+
+            // The skiplist index is loaded first, so that the warmup only has to index what
+            // happened since it was stored.
+            let skiplist_index_key = config.skiplist_index_blobstore_key.clone();
+            let skiplist_index_load = match skiplist_index_key.clone() {
+                Some(key) => load_skiplist_index(&repo, key, listen_log.clone()),
+                None => future::ok(()).boxify(),
+            };
 
-            let initial_warmup =
-                cache_warmup(repo.blobrepo(), config.cache_warmup, listen_log.clone())
-                    .context(format!("while warming up cache for repo: {}", reponame))
-                    .from_err();
+            let initial_warmup = skiplist_index_load
+                .and_then({
+                    let warmup = cache_warmup(
+                        repo.blobrepo(),
+                        repo.skiplist_index(),
+                        config.cache_warmup,
+                        ready,
+                        &reponame,
+                        listen_log.clone(),
+                    );
+                    move |()| warmup
+                })
+                .context(format!("while warming up cache for repo: {}", reponame))
+                .from_err();
             ready_handle
                 .wait_for(initial_warmup)
-                .map(move |()| (reponame, (listen_log, scuba_logger, repo)))
+                .map({
+                    cloned!(repo, listen_log);
+                    move |()| {
+                        if repo.blobrepo().has_cache_snapshots() {
+                            spawn_cache_snapshot_saver(repo.clone(), listen_log.clone());
+                        }
+                        tokio::spawn(update_skiplist_index(repo, skiplist_index_key, listen_log))
+                    }
+                })
+                .map(move |()| {
+                    (
+                        reponame,
+                        (listen_log, scuba_logger, repo, wireproto_recording_dir),
+                    )
+                })
         })
         .collect();
 
@@ -69,3 +125,70 @@ where
         .map(|repos| repos.into_iter().collect())
         .boxify()
 }
+
+fn load_skiplist_index(repo: &MononokeRepo, key: String, logger: Logger) -> BoxFuture<(), Error> {
+    info!(logger, "loading skiplist index from blob {}", key);
+    repo.skiplist_index()
+        .load_from_blobstore(&repo.blobrepo(), key.clone())
+        .map(move |loaded| match loaded {
+            Some(count) => info!(logger, "loaded skiplist index with {} nodes", count),
+            None => warn!(logger, "skiplist index blob {} not found", key),
+        })
+        .boxify()
+}
+
+/// Periodically index the commits that pushes moved the bookmarks to, off the push path, and
+/// store the index as the blob `key` if that added any nodes, so that a restarted server doesn't
+/// load a stale index. Failures are only logged, and the next update tries again.
+fn update_skiplist_index(
+    repo: Arc<MononokeRepo>,
+    key: Option<String>,
+    logger: Logger,
+) -> impl Future<Item = (), Error = ()> + Send + 'static {
+    let start = Instant::now() + SKIPLIST_INDEX_UPDATE_INTERVAL;
+    // Starting from 0 also stores what the cache warmup indexed, unless the index is empty.
+    Interval::new(start, SKIPLIST_INDEX_UPDATE_INTERVAL)
+        .map_err(|err| -> Error { err.into() })
+        .fold(0, move |saved_count, _| {
+            cloned!(key, logger, repo);
+            repo.extend_skiplist_index()
+                .and_then({
+                    cloned!(repo);
+                    move |()| {
+                        let count = repo.skiplist_index().indexed_node_count();
+                        match key {
+                            Some(ref key) if count != saved_count => repo
+                                .save_skiplist_index(key.clone())
+                                .map(move |()| count)
+                                .left_future(),
+                            _ => future::ok(saved_count).right_future(),
+                        }
+                    }
+                })
+                .then(move |res| match res {
+                    Ok(count) => Ok(count),
+                    Err(err) => {
+                        warn!(logger, "failed to update the skiplist index: {}", err);
+                        Ok(saved_count)
+                    }
+                })
+        })
+        .map(|_| ())
+        .map_err(|_| ())
+}
+
+/// Save the caches of the repo to its snapshot directory, if it has one.
+pub fn save_cache_snapshots(repo: &MononokeRepo, logger: &Logger) {
+    if let Err(err) = repo.blobrepo().save_cache_snapshots() {
+        warn!(logger, "failed to save cache snapshots: {}", err);
+    }
+}
+
+// Saving the snapshots writes whole caches to disk, so it's done on its own thread rather than on
+// the event loop.
+fn spawn_cache_snapshot_saver(repo: Arc<MononokeRepo>, logger: Logger) {
+    thread::spawn(move || loop {
+        thread::sleep(CACHE_SNAPSHOT_SAVE_INTERVAL);
+        save_cache_snapshots(&repo, &logger);
+    });
+}
@@ -23,7 +23,7 @@ use tokio::util::FutureExt as TokioFutureExt;
 use tracing::TraceContext;
 use uuid::Uuid;
 
-use hgproto::{sshproto, HgProtoHandler};
+use hgproto::{sshproto, HgProtoHandler, RequestRecorder};
 use repo_client::RepoClient;
 use scuba_ext::ScubaSampleBuilderExt;
 use sshrelay::{SenderBytesWrite, Stdio};
@@ -37,7 +37,7 @@ define_stats! {
 }
 
 pub fn request_handler(
-    (logger, mut scuba_logger, repo): RepoHandler,
+    (logger, mut scuba_logger, repo, wireproto_recording_dir): RepoHandler,
     stdio: Stdio,
     addr: SocketAddr,
 ) -> impl Future<Item = (), Error = ()> {
@@ -104,6 +104,22 @@ pub fn request_handler(
 
     scuba_logger.log_with_msg("Connection established", None);
 
+    let recorder = wireproto_recording_dir.and_then(|dir| {
+        let path = dir.join(format!("{}", session_uuid));
+        match RequestRecorder::new(&path, conn_log.clone()) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                warn!(
+                    conn_log,
+                    "failed to start recording requests to {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    });
+
     // Construct a hg protocol handler
     let proto_handler = HgProtoHandler::new(
         stdin,
@@ -112,6 +128,7 @@ pub fn request_handler(
         sshproto::HgSshCommandEncode,
         &conn_log,
         wireproto_calls.clone(),
+        recorder,
     );
 
     // send responses back
@@ -0,0 +1,275 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// Bisection keeps no state of its own: the caller passes all the changesets known to be good
+// and the last known bad one each time. The candidates are the ancestors of the bad changeset
+// that are not ancestors of a good one, i.e. `only(bad, goods)`, so that with merges the commits
+// of a branch stay candidates when a commit of another branch is found good. The next one to
+// test is the one whose generation number is closest to halfway between the generations of the
+// oldest candidate and the bad changeset: the branches of a merge can have very different
+// lengths, so that splits the history in two better than the middle of the candidates.
+
+use std::sync::Arc;
+
+use failure::{err_msg, Error};
+use futures::future::{self, Future};
+use futures::stream::Stream;
+use futures_ext::{BoxFuture, FutureExt};
+
+use blobrepo::BlobRepo;
+use mercurial_types::HgNodeHash;
+use mercurial_types::nodehash::HgChangesetId;
+use mononoke_types::Generation;
+
+use DifferenceOfUnionsOfAncestorsNodeStream;
+use errors::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BisectStep {
+    /// The changeset to test next, or `None` if the bad changeset is the first bad one.
+    pub next: Option<HgNodeHash>,
+    /// How many changesets may be the first bad one, including the bad changeset.
+    pub remaining: usize,
+}
+
+fn get_generation(repo: &Arc<BlobRepo>, node: HgNodeHash) -> BoxFuture<Generation, Error> {
+    repo.get_generation_number(&HgChangesetId::new(node))
+        .and_then(move |genopt| genopt.ok_or_else(|| err_msg(format!("{} not found", node))))
+        .map_err(|err| err.context(ErrorKind::GenerationFetchFailed).into())
+        .boxify()
+}
+
+/// Find the next changeset to test to find the first bad changeset, given all the changesets
+/// found good so far and the last one found bad. Fails with `InvalidBisect` if there are no
+/// good changesets, or if `bad` is an ancestor of one of them.
+pub fn bisect(
+    repo: &Arc<BlobRepo>,
+    goods: Vec<HgNodeHash>,
+    bad: HgNodeHash,
+) -> BoxFuture<BisectStep, Error> {
+    if goods.is_empty() {
+        return future::err(ErrorKind::InvalidBisect(bad).into()).boxify();
+    }
+
+    DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(repo, vec![bad], goods)
+        .and_then({
+            let repo = repo.clone();
+            move |node| get_generation(&repo, node).map(move |gen| (node, gen))
+        })
+        .collect()
+        .and_then(move |candidates| {
+            // The candidates are ordered from the highest generation, so the first one is `bad`,
+            // unless it is an ancestor of a good changeset.
+            let bad_gen = match candidates.first() {
+                Some(&(node, bad_gen)) if node == bad => bad_gen,
+                _ => return Err(ErrorKind::InvalidBisect(bad).into()),
+            };
+            // The oldest candidates are children of good changesets, or roots.
+            let oldest_gen = candidates[candidates.len() - 1].1;
+            let halfway = (oldest_gen.value() - 1 + bad_gen.value()) / 2;
+            let distance = |gen: Generation| {
+                if gen.value() > halfway {
+                    gen.value() - halfway
+                } else {
+                    halfway - gen.value()
+                }
+            };
+            let next = candidates[1..]
+                .iter()
+                .min_by_key(|&&(_, gen)| distance(gen))
+                .map(|&(node, _)| node);
+            Ok(BisectStep {
+                next,
+                remaining: candidates.len(),
+            })
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_unit;
+    use fixtures::linear;
+    use fixtures::merge_uneven;
+    use tests::string_to_nodehash;
+
+    fn nodes(hashes: &[&str]) -> Vec<HgNodeHash> {
+        hashes.iter().map(|hash| string_to_nodehash(hash)).collect()
+    }
+
+    fn bisect_step(repo: &Arc<BlobRepo>, goods: &[&str], bad: &str) -> BisectStep {
+        bisect(repo, nodes(goods), string_to_nodehash(bad))
+            .wait()
+            .expect("bisect failed")
+    }
+
+    #[test]
+    fn linear_bisect() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            assert_eq!(
+                bisect_step(
+                    &repo,
+                    &["d0a361e9022d226ae52f689667bd7d212a19cfe0"],
+                    "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157",
+                ),
+                BisectStep {
+                    next: Some(string_to_nodehash(
+                        "eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"
+                    )),
+                    remaining: 4,
+                }
+            );
+
+            assert_eq!(
+                bisect_step(
+                    &repo,
+                    &[
+                        "d0a361e9022d226ae52f689667bd7d212a19cfe0",
+                        "eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b",
+                    ],
+                    "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157",
+                ),
+                BisectStep {
+                    next: Some(string_to_nodehash(
+                        "0ed509bf086fadcb8a8a5384dc3b550729b0fc17"
+                    )),
+                    remaining: 2,
+                }
+            );
+
+            assert_eq!(
+                bisect_step(
+                    &repo,
+                    &["0ed509bf086fadcb8a8a5384dc3b550729b0fc17"],
+                    "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157",
+                ),
+                BisectStep {
+                    next: None,
+                    remaining: 1,
+                }
+            );
+        });
+    }
+
+    // The root of merge_uneven has generation 1 and the merge generation 10: its parents are the
+    // heads of a short branch of 3 changesets and a long branch of 8 changesets.
+    const ROOT: &str = "15c40d0abc36d47fb51c8eaec51ac7aad31f669c";
+    const MERGE: &str = "b47ca72355a0af2c749d45a5689fd5bcce9898c7";
+    const SHORT_BRANCH: &[&str] = &[
+        "3cda5c78aa35f0f5b09780d971197b51cad4613a",
+        "1d8a907f7b4bf50c6a09c16361e2205047ecc5e5",
+        "16839021e338500b3cf7c9b871c8a07351697d68",
+    ];
+
+    #[test]
+    fn merge_bisect() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            // The middle of the 12 candidates has generation 4, but halfway is generation 5, in
+            // the long branch.
+            assert_eq!(
+                bisect_step(&repo, &[ROOT], MERGE),
+                BisectStep {
+                    next: Some(string_to_nodehash(
+                        "795b8133cf375f6d68d27c6c23db24cd5d0cd00f"
+                    )),
+                    remaining: 12,
+                }
+            );
+
+            // The short branch stays in the candidates once a commit of the long one is good.
+            assert_eq!(
+                bisect_step(
+                    &repo,
+                    &[ROOT, "795b8133cf375f6d68d27c6c23db24cd5d0cd00f"],
+                    MERGE,
+                ),
+                BisectStep {
+                    next: Some(string_to_nodehash(
+                        "bc7b4d0f858c19e2474b03e442b8495fd7aeef33"
+                    )),
+                    remaining: 8,
+                }
+            );
+
+            // Only the head of the short branch and the merge are left.
+            assert_eq!(
+                bisect_step(
+                    &repo,
+                    &[
+                        "1d8a907f7b4bf50c6a09c16361e2205047ecc5e5",
+                        "264f01429683b3dd8042cb3979e8bf37007118bc",
+                    ],
+                    MERGE,
+                ),
+                BisectStep {
+                    next: Some(string_to_nodehash(SHORT_BRANCH[2])),
+                    remaining: 2,
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn merge_bisect_short_branch_culprit() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+            // The first bad changeset is in the middle of the short branch, so the changesets
+            // that are bad are its descendants.
+            let culprit = string_to_nodehash(SHORT_BRANCH[1]);
+            let mut is_bad = nodes(&SHORT_BRANCH[1..]);
+            is_bad.push(string_to_nodehash(MERGE));
+
+            let mut goods = nodes(&[ROOT]);
+            let mut bad = string_to_nodehash(MERGE);
+            loop {
+                let step = bisect(&repo, goods.clone(), bad)
+                    .wait()
+                    .expect("bisect failed");
+                match step.next {
+                    Some(next) if is_bad.contains(&next) => bad = next,
+                    Some(next) => goods.push(next),
+                    None => {
+                        assert_eq!(step.remaining, 1);
+                        break;
+                    }
+                }
+            }
+            assert_eq!(bad, culprit);
+        });
+    }
+
+    #[test]
+    fn bisect_unrelated() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            for &(goods, bad) in &[
+                (
+                    &["a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"][..],
+                    "d0a361e9022d226ae52f689667bd7d212a19cfe0",
+                ),
+                (
+                    &["a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"][..],
+                    "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157",
+                ),
+                (&[][..], "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+            ] {
+                let err = bisect(&repo, nodes(goods), string_to_nodehash(bad))
+                    .wait()
+                    .expect_err("bisect should fail");
+                match err.downcast::<ErrorKind>() {
+                    Ok(ErrorKind::InvalidBisect(..)) => {}
+                    other => panic!("unexpected result: {:?}", other),
+                }
+            }
+        });
+    }
+}
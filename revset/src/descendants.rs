@@ -0,0 +1,235 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// The descendants of the current node are itself, plus the union of all descendants of all
+// children. This is the ancestors stream run forwards: children always have a higher generation
+// number than their parents, so once all nodes of a generation have been output, all parents of
+// the nodes of the next generation up have been too.
+//
+// Unlike the other streams, the nodes are output from the lowest generation, so this stream can't
+// be combined with the set operations.
+
+use std::collections::{BTreeMap, HashSet};
+use std::collections::hash_set::IntoIter;
+use std::sync::Arc;
+
+use failure::{err_msg, Error};
+
+use futures::{Async, Poll};
+use futures::future::Future;
+use futures::stream::{iter_ok, Stream};
+
+use blobrepo::BlobRepo;
+use mercurial_types::HgNodeHash;
+use mercurial_types::nodehash::HgChangesetId;
+use mononoke_types::Generation;
+
+use NodeStream;
+use errors::*;
+
+pub struct DescendantsNodeStream {
+    repo: Arc<BlobRepo>,
+    next_generation: BTreeMap<Generation, HashSet<HgNodeHash>>,
+    pending_changesets: Box<Stream<Item = (HgNodeHash, Generation), Error = Error> + Send>,
+    drain: IntoIter<HgNodeHash>,
+}
+
+fn make_pending(
+    repo: Arc<BlobRepo>,
+    hashes: IntoIter<HgNodeHash>,
+) -> Box<Stream<Item = (HgNodeHash, Generation), Error = Error> + Send> {
+    let size = hashes.size_hint().0;
+    let new_repo_changesets = repo.clone();
+    let new_repo_gennums = repo.clone();
+
+    Box::new(
+        iter_ok::<_, Error>(hashes)
+            .map(move |hash| {
+                new_repo_changesets
+                    .get_changeset_children(&HgChangesetId::new(hash))
+                    .map_err(|err| err.context(ErrorKind::ChildrenFetchFailed).into())
+            })
+            .buffered(size)
+            .map(|children| iter_ok::<_, Error>(children.into_iter()))
+            .flatten()
+            .and_then(move |node_cs| {
+                new_repo_gennums
+                    .get_generation_number(&node_cs)
+                    .and_then(move |genopt| {
+                        genopt.ok_or_else(|| err_msg(format!("{} not found", node_cs)))
+                    })
+                    .map(move |gen_id| (*node_cs.as_nodehash(), gen_id))
+                    .map_err(|err| err.context(ErrorKind::GenerationFetchFailed).into())
+            }),
+    )
+}
+
+impl DescendantsNodeStream {
+    pub fn new(repo: &Arc<BlobRepo>, hash: HgNodeHash) -> Self {
+        let node_set: HashSet<HgNodeHash> = hashset!{hash};
+        DescendantsNodeStream {
+            repo: repo.clone(),
+            next_generation: BTreeMap::new(),
+            pending_changesets: make_pending(repo.clone(), node_set.clone().into_iter()),
+            drain: node_set.into_iter(),
+        }
+    }
+
+    pub fn boxed(self) -> Box<NodeStream> {
+        Box::new(self)
+    }
+}
+
+impl Stream for DescendantsNodeStream {
+    type Item = HgNodeHash;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Empty the drain if any - return all items for this generation
+        let next_in_drain = self.drain.next();
+        if next_in_drain.is_some() {
+            return Ok(Async::Ready(next_in_drain));
+        }
+
+        // Wait until we've drained pending_changesets - we can't continue until we know about all
+        // children of the just-output generation
+        loop {
+            match self.pending_changesets.poll()? {
+                Async::Ready(Some((hash, generation))) => {
+                    self.next_generation
+                        .entry(generation)
+                        .or_insert_with(HashSet::new)
+                        .insert(hash);
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(None) => break,
+            };
+        }
+
+        let lowest_generation = match self.next_generation.keys().next() {
+            Some(generation) => *generation,
+            // All children output - nothing more to send
+            None => return Ok(Async::Ready(None)),
+        };
+        let current_generation = self.next_generation
+            .remove(&lowest_generation)
+            .expect("Lowest generation doesn't exist");
+        self.pending_changesets =
+            make_pending(self.repo.clone(), current_generation.clone().into_iter());
+        self.drain = current_generation.into_iter();
+        Ok(Async::Ready(Some(self.drain.next().expect(
+            "Cannot create a generation without at least one node hash",
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_unit;
+    use fixtures::branch_wide;
+    use fixtures::linear;
+    use fixtures::merge_uneven;
+    use tests::string_to_nodehash;
+
+    fn collect_descendants(repo: &Arc<BlobRepo>, hash: &'static str) -> Vec<HgNodeHash> {
+        DescendantsNodeStream::new(repo, string_to_nodehash(hash))
+            .collect()
+            .wait()
+            .expect("failed to walk descendants")
+    }
+
+    #[test]
+    fn linear_descendants() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            assert_eq!(
+                collect_descendants(&repo, "607314ef579bd2407752361ba1b0c1729d08b281"),
+                vec![
+                    string_to_nodehash("607314ef579bd2407752361ba1b0c1729d08b281"),
+                    string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                    string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372"),
+                    string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+                    string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+                    string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn linear_descendants_of_head() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            assert_eq!(
+                collect_descendants(&repo, "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+                vec![string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157")]
+            );
+        });
+    }
+
+    #[test]
+    fn merge_descendants_one_branch() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            assert_eq!(
+                collect_descendants(&repo, "1d8a907f7b4bf50c6a09c16361e2205047ecc5e5"),
+                vec![
+                    string_to_nodehash("1d8a907f7b4bf50c6a09c16361e2205047ecc5e5"),
+                    string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                    string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7"),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn merge_descendants_from_root() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            let descendants =
+                collect_descendants(&repo, "15c40d0abc36d47fb51c8eaec51ac7aad31f669c");
+            // Every commit descends from the root, and the merge is output once, last.
+            assert_eq!(descendants.len(), 13);
+            assert_eq!(
+                descendants.iter().collect::<HashSet<_>>().len(),
+                descendants.len()
+            );
+            assert_eq!(
+                descendants.first(),
+                Some(&string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c"))
+            );
+            assert_eq!(
+                descendants.last(),
+                Some(&string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7"))
+            );
+        });
+    }
+
+    #[test]
+    fn branch_wide_descendants() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(branch_wide::getrepo(None));
+
+            let descendants =
+                collect_descendants(&repo, "9e8521affb7f9d10e9551a99c526e69909042b20");
+            assert_eq!(
+                descendants[0],
+                string_to_nodehash("9e8521affb7f9d10e9551a99c526e69909042b20")
+            );
+            assert_eq!(
+                descendants[1..].iter().cloned().collect::<HashSet<_>>(),
+                hashset!{
+                    string_to_nodehash("b6a8169454af58b4b72b3665f9aa0d25529755ff"),
+                    string_to_nodehash("c27ef5b7f15e9930e5b93b1f32cc2108a2aabe12"),
+                }
+            );
+        });
+    }
+}
@@ -13,4 +13,12 @@ pub enum ErrorKind {
     #[fail(display = "repo error checking for node: {}", _0)] RepoError(HgNodeHash),
     #[fail(display = "could not fetch node generation")] GenerationFetchFailed,
     #[fail(display = "failed to fetch parent nodes")] ParentsFetchFailed,
+    #[fail(display = "failed to fetch child nodes")] ChildrenFetchFailed,
+    #[fail(display = "invalid revset: {}", _0)] InvalidRevset(String),
+    #[fail(display = "unknown revision: {}", _0)] UnknownRevision(String),
+    #[fail(display = "revset needs more than {} changesets to be evaluated", _0)]
+    TooManyNodes(usize),
+    #[fail(display = "bisect needs good changesets, and bad changeset {} can't be an ancestor \
+                      of any of them", _0)]
+    InvalidBisect(HgNodeHash),
 }
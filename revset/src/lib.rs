@@ -6,6 +6,7 @@
 
 extern crate asyncmemo;
 extern crate blobrepo;
+extern crate bookmarks;
 #[macro_use]
 extern crate failure_ext as failure;
 #[macro_use]
@@ -44,12 +45,21 @@ pub use validation::ValidateNodeStream;
 mod ancestors;
 pub use ancestors::{common_ancestors, greatest_common_ancestor, AncestorsNodeStream};
 
+mod descendants;
+pub use descendants::DescendantsNodeStream;
+
 mod ancestorscombinators;
 pub use ancestorscombinators::DifferenceOfUnionsOfAncestorsNodeStream;
 
 mod range;
 pub use range::RangeNodeStream;
 
+mod revsetexpr;
+pub use revsetexpr::RevsetExpr;
+
+mod bisect;
+pub use bisect::{bisect, BisectStep};
+
 mod uniqueheap;
 use uniqueheap::UniqueHeap;
 
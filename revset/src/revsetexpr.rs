@@ -0,0 +1,883 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// A subset of Mercurial's revset language, compiled to the node streams of this crate. The
+// symbols of an expression are resolved first, so that a missing bookmark or changeset is an
+// error rather than an empty set. Every compiled stream outputs its nodes from the highest
+// generation, so that they can be combined with the set operations.
+
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+use std::sync::Arc;
+
+use failure::{err_msg, Error};
+use futures::future::{err, join_all, Future};
+use futures::stream::{self, Stream};
+use futures_ext::{BoxFuture, FutureExt};
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use mercurial_types::HgNodeHash;
+use mercurial_types::nodehash::HgChangesetId;
+use mononoke_types::Generation;
+
+use DifferenceOfUnionsOfAncestorsNodeStream;
+use IntersectNodeStream;
+use NodeStream;
+use SetDifferenceNodeStream;
+use SingleNodeHash;
+use UnionNodeStream;
+use errors::*;
+
+/// How many changesets `heads`, `roots` and `::` fetch the parents of at once.
+const PARENTS_FETCH_CONCURRENCY: usize = 100;
+
+/// How many changesets `heads`, `roots` and `::` can hold in memory at once. They need all the
+/// changesets of their input, which can be most of the repo, so larger inputs are an error.
+const MAX_COLLECTED_NODES: usize = 1_000_000;
+
+/// How deeply expressions can be nested. Parsing and evaluating recurse into the subexpressions,
+/// so deeper expressions, which can come from untrusted input, are rejected before they can
+/// overflow the stack.
+const MAX_DEPTH: usize = 100;
+
+/// A revset expression:
+///
+/// - a changeset hash, or a bookmark name; names with characters other than letters, digits and
+///   `._/@` must be quoted with `'` or `"`
+/// - `::x` or `ancestors(x)`: the ancestors of `x`, including `x`
+/// - `x::y`: the changesets that descend from `x` and are ancestors of `y`
+/// - `x % y` or `only(x, y)`: the ancestors of `x` that aren't ancestors of `y`
+/// - `heads(x)` and `roots(x)`: the changesets of `x` with no children or parents in `x`
+/// - `x & y`, `x | y` and `x - y`: intersection, union and difference
+///
+/// `|` binds the loosest, then `&`, `-` and `%`, which are left-associative, then `::`.
+/// Expressions can be nested at most 100 levels deep, counting both parentheses and operators.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RevsetExpr {
+    Symbol(String),
+    Ancestors(Box<RevsetExpr>),
+    DagRange(Box<RevsetExpr>, Box<RevsetExpr>),
+    Only(Box<RevsetExpr>, Box<RevsetExpr>),
+    Heads(Box<RevsetExpr>),
+    Roots(Box<RevsetExpr>),
+    Intersection(Box<RevsetExpr>, Box<RevsetExpr>),
+    Union(Box<RevsetExpr>, Box<RevsetExpr>),
+    Difference(Box<RevsetExpr>, Box<RevsetExpr>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Symbol(String),
+    // Quoted symbols can't be function names.
+    Quoted(String),
+    DagRange,
+    Only,
+    And,
+    Or,
+    Minus,
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn invalid<T>(message: String) -> Result<T> {
+    Err(ErrorKind::InvalidRevset(message).into())
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == '/' || c == '@' || !c.is_ascii()
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = input.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            ':' => match chars.next() {
+                Some((_, ':')) => Token::DagRange,
+                _ => return invalid(format!("expected '::' at position {}", pos)),
+            },
+            '%' => Token::Only,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '-' => Token::Minus,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '\'' | '"' => {
+                let mut symbol = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == c => break,
+                        Some((_, other)) => symbol.push(other),
+                        None => {
+                            return invalid(format!("unterminated string at position {}", pos))
+                        }
+                    }
+                }
+                Token::Quoted(symbol)
+            }
+            c if is_symbol_char(c) => {
+                let mut symbol = c.to_string();
+                while let Some(&(_, next)) = chars.peek() {
+                    if !is_symbol_char(next) {
+                        break;
+                    }
+                    symbol.push(next);
+                    chars.next();
+                }
+                Token::Symbol(symbol)
+            }
+            c => return invalid(format!("unexpected character '{}' at position {}", c, pos)),
+        };
+        tokens.push((pos, token));
+    }
+
+    Ok(tokens)
+}
+
+// The parser returns each expression with its depth, so that too deep expressions are rejected
+// before they are built.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    // How many parentheses and function calls are open.
+    nesting: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|&(_, ref token)| token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected<T>(&self) -> Result<T> {
+        match self.tokens.get(self.next) {
+            Some(&(pos, _)) => invalid(format!("unexpected token at position {}", pos)),
+            None => invalid("unexpected end of revset".into()),
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.unexpected()
+        }
+    }
+
+    fn check_depth(depth: usize) -> Result<usize> {
+        if depth > MAX_DEPTH {
+            invalid(format!("revset is nested more than {} levels deep", MAX_DEPTH))
+        } else {
+            Ok(depth)
+        }
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.nesting += 1;
+        Self::check_depth(self.nesting).map(|_| ())
+    }
+
+    fn parse_union(&mut self) -> Result<(RevsetExpr, usize)> {
+        let (mut expr, mut depth) = self.parse_intersection()?;
+        while self.eat(&Token::Or) {
+            let (rhs, rhs_depth) = self.parse_intersection()?;
+            depth = Self::check_depth(depth.max(rhs_depth) + 1)?;
+            expr = RevsetExpr::Union(Box::new(expr), Box::new(rhs));
+        }
+        Ok((expr, depth))
+    }
+
+    fn parse_intersection(&mut self) -> Result<(RevsetExpr, usize)> {
+        let (mut expr, mut depth) = self.parse_range()?;
+        loop {
+            let combine: fn(Box<RevsetExpr>, Box<RevsetExpr>) -> RevsetExpr = match self.peek() {
+                Some(&Token::And) => RevsetExpr::Intersection,
+                Some(&Token::Minus) => RevsetExpr::Difference,
+                Some(&Token::Only) => RevsetExpr::Only,
+                _ => return Ok((expr, depth)),
+            };
+            self.next += 1;
+            let (rhs, rhs_depth) = self.parse_range()?;
+            depth = Self::check_depth(depth.max(rhs_depth) + 1)?;
+            expr = combine(Box::new(expr), Box::new(rhs));
+        }
+    }
+
+    fn parse_range(&mut self) -> Result<(RevsetExpr, usize)> {
+        if self.eat(&Token::DagRange) {
+            let (expr, depth) = self.parse_primary()?;
+            let depth = Self::check_depth(depth + 1)?;
+            return Ok((RevsetExpr::Ancestors(Box::new(expr)), depth));
+        }
+
+        let (expr, depth) = self.parse_primary()?;
+        if self.eat(&Token::DagRange) {
+            let (end, end_depth) = self.parse_primary()?;
+            let depth = Self::check_depth(depth.max(end_depth) + 1)?;
+            Ok((RevsetExpr::DagRange(Box::new(expr), Box::new(end)), depth))
+        } else {
+            Ok((expr, depth))
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<(RevsetExpr, usize)> {
+        let pos = match self.tokens.get(self.next) {
+            Some(&(pos, _)) => pos,
+            None => return self.unexpected(),
+        };
+
+        match self.peek().cloned() {
+            Some(Token::LParen) => {
+                self.next += 1;
+                self.enter()?;
+                let parsed = self.parse_union()?;
+                self.expect(&Token::RParen)?;
+                self.nesting -= 1;
+                Ok(parsed)
+            }
+            Some(Token::Quoted(symbol)) => {
+                self.next += 1;
+                Ok((RevsetExpr::Symbol(symbol), 1))
+            }
+            Some(Token::Symbol(symbol)) => {
+                self.next += 1;
+                if self.eat(&Token::LParen) {
+                    self.enter()?;
+                    let args = self.parse_args()?;
+                    self.nesting -= 1;
+                    let depth = args.iter().map(|&(_, depth)| depth).max().unwrap_or(0);
+                    let depth = Self::check_depth(depth + 1)?;
+                    let args = args.into_iter().map(|(arg, _)| arg).collect();
+                    call(pos, &symbol, args).map(|expr| (expr, depth))
+                } else {
+                    Ok((RevsetExpr::Symbol(symbol), 1))
+                }
+            }
+            _ => self.unexpected(),
+        }
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<(RevsetExpr, usize)>> {
+        let mut args = Vec::new();
+        if self.eat(&Token::RParen) {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_union()?);
+            if self.eat(&Token::RParen) {
+                return Ok(args);
+            }
+            self.expect(&Token::Comma)?;
+        }
+    }
+}
+
+fn call(pos: usize, name: &str, args: Vec<RevsetExpr>) -> Result<RevsetExpr> {
+    let expected = match name {
+        "ancestors" | "heads" | "roots" => 1,
+        "only" => 2,
+        _ => return invalid(format!("unknown function {} at position {}", name, pos)),
+    };
+    if args.len() != expected {
+        return invalid(format!(
+            "{} takes {} arguments, {} given at position {}",
+            name,
+            expected,
+            args.len(),
+            pos
+        ));
+    }
+
+    let mut args = args.into_iter().map(Box::new);
+    let mut arg = || args.next().expect("number of arguments checked");
+    Ok(match name {
+        "ancestors" => RevsetExpr::Ancestors(arg()),
+        "heads" => RevsetExpr::Heads(arg()),
+        "roots" => RevsetExpr::Roots(arg()),
+        _ => {
+            let first = arg();
+            RevsetExpr::Only(first, arg())
+        }
+    })
+}
+
+impl RevsetExpr {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            next: 0,
+            nesting: 0,
+        };
+        let (expr, _) = parser.parse_union()?;
+        if parser.peek().is_some() {
+            return parser.unexpected();
+        }
+        Ok(expr)
+    }
+
+    /// The changesets of the expression, from the highest generation. Fails with
+    /// `UnknownRevision` if a symbol is neither a changeset nor a bookmark of the repo.
+    pub fn evaluate(&self, repo: &Arc<BlobRepo>) -> Box<NodeStream> {
+        let mut symbols = HashSet::new();
+        self.collect_symbols(&mut symbols);
+
+        let resolved: Vec<_> = symbols
+            .into_iter()
+            .map(|symbol| {
+                resolve_symbol(repo.clone(), symbol.clone()).map(move |node| (symbol, node))
+            })
+            .collect();
+
+        let expr = self.clone();
+        let repo = repo.clone();
+        Box::new(
+            join_all(resolved)
+                .map(move |resolved| expr.compile(&repo, &resolved.into_iter().collect()))
+                .flatten_stream(),
+        )
+    }
+
+    fn collect_symbols(&self, symbols: &mut HashSet<String>) {
+        use self::RevsetExpr::*;
+
+        match *self {
+            Symbol(ref symbol) => {
+                symbols.insert(symbol.clone());
+            }
+            Ancestors(ref x) | Heads(ref x) | Roots(ref x) => x.collect_symbols(symbols),
+            DagRange(ref x, ref y)
+            | Only(ref x, ref y)
+            | Intersection(ref x, ref y)
+            | Union(ref x, ref y)
+            | Difference(ref x, ref y) => {
+                x.collect_symbols(symbols);
+                y.collect_symbols(symbols);
+            }
+        }
+    }
+
+    fn compile(
+        &self,
+        repo: &Arc<BlobRepo>,
+        symbols: &HashMap<String, HgNodeHash>,
+    ) -> Box<NodeStream> {
+        use self::RevsetExpr::*;
+
+        match *self {
+            Symbol(ref symbol) => SingleNodeHash::new(symbols[symbol], repo).boxed(),
+            Ancestors(ref x) => Box::new(
+                x.compile(repo, symbols)
+                    .collect()
+                    .map({
+                        let repo = repo.clone();
+                        move |nodes| {
+                            DifferenceOfUnionsOfAncestorsNodeStream::new_union(&repo, nodes)
+                        }
+                    })
+                    .flatten_stream(),
+            ),
+            DagRange(ref x, ref y) => Box::new(
+                x.compile(repo, symbols)
+                    .collect()
+                    .join(y.compile(repo, symbols).collect())
+                    .map({
+                        let repo = repo.clone();
+                        move |(starts, ends)| dag_range(repo, starts, ends)
+                    })
+                    .flatten_stream(),
+            ),
+            Only(ref x, ref y) => Box::new(
+                x.compile(repo, symbols)
+                    .collect()
+                    .join(y.compile(repo, symbols).collect())
+                    .map({
+                        let repo = repo.clone();
+                        move |(nodes, excludes)| {
+                            DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+                                &repo,
+                                nodes,
+                                excludes,
+                            )
+                        }
+                    })
+                    .flatten_stream(),
+            ),
+            Heads(ref x) => Box::new(
+                collect_with_parents(repo, x.compile(repo, symbols), MAX_COLLECTED_NODES)
+                    .map(|nodes| stream::iter_ok(heads(nodes)))
+                    .flatten_stream(),
+            ),
+            Roots(ref x) => Box::new(
+                collect_with_parents(repo, x.compile(repo, symbols), MAX_COLLECTED_NODES)
+                    .map(|nodes| stream::iter_ok(roots(nodes)))
+                    .flatten_stream(),
+            ),
+            Intersection(ref x, ref y) => IntersectNodeStream::new(
+                repo,
+                vec![x.compile(repo, symbols), y.compile(repo, symbols)],
+            ).boxed(),
+            Union(ref x, ref y) => UnionNodeStream::new(
+                repo,
+                vec![x.compile(repo, symbols), y.compile(repo, symbols)],
+            ).boxed(),
+            Difference(ref x, ref y) => SetDifferenceNodeStream::new(
+                repo,
+                x.compile(repo, symbols),
+                y.compile(repo, symbols),
+            ).boxed(),
+        }
+    }
+}
+
+/// Resolve a symbol as a changeset hash, or else as a bookmark name.
+fn resolve_symbol(repo: Arc<BlobRepo>, symbol: String) -> BoxFuture<HgNodeHash, Error> {
+    match HgNodeHash::from_str(&symbol) {
+        Ok(node) => repo.changeset_exists(&HgChangesetId::new(node))
+            .and_then(move |exists| {
+                if exists {
+                    Ok(node)
+                } else {
+                    Err(ErrorKind::UnknownRevision(symbol).into())
+                }
+            })
+            .boxify(),
+        Err(_) => {
+            let bookmark = match Bookmark::new(&symbol) {
+                Ok(bookmark) => bookmark,
+                Err(_) => return err(ErrorKind::UnknownRevision(symbol).into()).boxify(),
+            };
+            repo.get_bookmark(&bookmark)
+                .and_then(move |node_cs| match node_cs {
+                    Some(node_cs) => Ok(*node_cs.as_nodehash()),
+                    None => Err(ErrorKind::UnknownRevision(symbol).into()),
+                })
+                .boxify()
+        }
+    }
+}
+
+/// `starts::ends`, that is `descendants(starts) & ::ends`. Instead of walking all the
+/// descendants of `starts`, the ancestors of `ends` are walked down to the lowest generation of
+/// `starts`, and the ones that descend from `starts` are kept.
+fn dag_range(
+    repo: Arc<BlobRepo>,
+    starts: Vec<HgNodeHash>,
+    ends: Vec<HgNodeHash>,
+) -> Box<NodeStream> {
+    let generations = starts.iter().map(|start| generation(&repo, *start));
+    let lowest_generation = join_all(generations).map(|generations| generations.into_iter().min());
+    let starts: HashSet<_> = starts.into_iter().collect();
+
+    Box::new(
+        lowest_generation
+            .map(move |lowest_generation| -> Box<NodeStream> {
+                let lowest_generation = match lowest_generation {
+                    Some(lowest_generation) => lowest_generation,
+                    None => return Box::new(stream::empty()),
+                };
+
+                let ancestors = DifferenceOfUnionsOfAncestorsNodeStream::new_union(&repo, ends)
+                    .map({
+                        let repo = repo.clone();
+                        move |node| generation(&repo, node).map(move |gen| (node, gen))
+                    })
+                    .buffered(PARENTS_FETCH_CONCURRENCY)
+                    .take_while(move |&(_, gen)| Ok(gen >= lowest_generation))
+                    .map(|(node, _)| node);
+
+                Box::new(
+                    collect_with_parents(&repo, Box::new(ancestors), MAX_COLLECTED_NODES)
+                        .map(move |nodes| {
+                            // Parents have a lower generation than their children, so going
+                            // from the lowest generation sees the parents first.
+                            let mut descendants: HashSet<_> = HashSet::new();
+                            for &(node, ref parents) in nodes.iter().rev() {
+                                if starts.contains(&node)
+                                    || parents.iter().any(|parent| descendants.contains(parent))
+                                {
+                                    descendants.insert(node);
+                                }
+                            }
+                            stream::iter_ok(
+                                nodes
+                                    .into_iter()
+                                    .map(|(node, _)| node)
+                                    .filter(move |node| descendants.contains(node)),
+                            )
+                        })
+                        .flatten_stream(),
+                )
+            })
+            .flatten_stream(),
+    )
+}
+
+fn generation(repo: &Arc<BlobRepo>, node: HgNodeHash) -> BoxFuture<Generation, Error> {
+    repo.get_generation_number(&HgChangesetId::new(node))
+        .and_then(move |gen| gen.ok_or_else(|| err_msg(format!("{} not found", node))))
+        .map_err(|err| err.context(ErrorKind::GenerationFetchFailed).into())
+        .boxify()
+}
+
+/// Collect the nodes of the stream with their parents, in the order of the stream. Fails with
+/// `TooManyNodes` if the stream has more than `max_nodes` nodes.
+fn collect_with_parents(
+    repo: &Arc<BlobRepo>,
+    nodes: Box<NodeStream>,
+    max_nodes: usize,
+) -> BoxFuture<Vec<(HgNodeHash, Vec<HgNodeHash>)>, Error> {
+    let repo = repo.clone();
+    let mut count = 0;
+    nodes
+        .and_then(move |node| {
+            count += 1;
+            if count > max_nodes {
+                Err(ErrorKind::TooManyNodes(max_nodes).into())
+            } else {
+                Ok(node)
+            }
+        })
+        .map(move |node| {
+            repo.get_changeset_parents(&HgChangesetId::new(node))
+                .map(move |parents| {
+                    let parents = parents.into_iter().map(|p| *p.as_nodehash()).collect();
+                    (node, parents)
+                })
+                .map_err(|err| err.context(ErrorKind::ParentsFetchFailed).into())
+        })
+        .buffered(PARENTS_FETCH_CONCURRENCY)
+        .collect()
+        .boxify()
+}
+
+/// The nodes that aren't a parent of another node of the set.
+fn heads(nodes: Vec<(HgNodeHash, Vec<HgNodeHash>)>) -> Vec<HgNodeHash> {
+    let parents: HashSet<_> = nodes
+        .iter()
+        .flat_map(|&(_, ref parents)| parents.iter().cloned())
+        .collect();
+    nodes
+        .into_iter()
+        .map(|(node, _)| node)
+        .filter(|node| !parents.contains(node))
+        .collect()
+}
+
+/// The nodes with no parent in the set.
+fn roots(nodes: Vec<(HgNodeHash, Vec<HgNodeHash>)>) -> Vec<HgNodeHash> {
+    let members: HashSet<_> = nodes.iter().map(|&(node, _)| node).collect();
+    nodes
+        .into_iter()
+        .filter(|&(_, ref parents)| !parents.iter().any(|parent| members.contains(parent)))
+        .map(|(node, _)| node)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_unit;
+    use fixtures::linear;
+    use fixtures::merge_uneven;
+    use tests::{assert_node_sequence, string_to_nodehash};
+
+    use self::RevsetExpr::*;
+
+    fn symbol(name: &str) -> Box<RevsetExpr> {
+        Box::new(Symbol(name.to_string()))
+    }
+
+    fn evaluate(repo: &Arc<BlobRepo>, revset: &str) -> Box<NodeStream> {
+        RevsetExpr::parse(revset)
+            .expect("failed to parse revset")
+            .evaluate(repo)
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert_eq!(RevsetExpr::parse("::a").unwrap(), Ancestors(symbol("a")));
+        assert_eq!(
+            RevsetExpr::parse("a::b").unwrap(),
+            DagRange(symbol("a"), symbol("b"))
+        );
+        assert_eq!(
+            RevsetExpr::parse("a % b").unwrap(),
+            Only(symbol("a"), symbol("b"))
+        );
+        assert_eq!(
+            RevsetExpr::parse("a - b - c").unwrap(),
+            Difference(Box::new(Difference(symbol("a"), symbol("b"))), symbol("c"))
+        );
+        assert_eq!(
+            RevsetExpr::parse("a | b & ::c").unwrap(),
+            Union(
+                symbol("a"),
+                Box::new(Intersection(symbol("b"), Box::new(Ancestors(symbol("c"))))),
+            )
+        );
+        assert_eq!(
+            RevsetExpr::parse("(a | b) & c").unwrap(),
+            Intersection(Box::new(Union(symbol("a"), symbol("b"))), symbol("c"))
+        );
+    }
+
+    #[test]
+    fn parse_functions_and_symbols() {
+        assert_eq!(
+            RevsetExpr::parse("heads(ancestors(master))").unwrap(),
+            Heads(Box::new(Ancestors(symbol("master"))))
+        );
+        assert_eq!(
+            RevsetExpr::parse("roots(only(a, b))").unwrap(),
+            Roots(Box::new(Only(symbol("a"), symbol("b"))))
+        );
+        assert_eq!(
+            RevsetExpr::parse("release/1.0@remote").unwrap(),
+            *symbol("release/1.0@remote")
+        );
+        assert_eq!(
+            RevsetExpr::parse("'feature-1' - \"heads\"").unwrap(),
+            Difference(symbol("feature-1"), symbol("heads"))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for revset in &[
+            "",
+            "a &",
+            "a b",
+            "(a",
+            "a)",
+            "a:b",
+            "'a",
+            "a $ b",
+            "unknown(a)",
+            "heads(a, b)",
+            "only(a)",
+        ] {
+            let err = RevsetExpr::parse(revset).expect_err(revset);
+            match err.downcast::<ErrorKind>() {
+                Ok(ErrorKind::InvalidRevset(_)) => {}
+                other => panic!("unexpected result for {:?}: {:?}", revset, other),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_depth_limit() {
+        let nested = |open: &str, close: &str, levels: usize| {
+            format!("{}a{}", open.repeat(levels), close.repeat(levels))
+        };
+
+        assert!(RevsetExpr::parse(&nested("(", ")", MAX_DEPTH)).is_ok());
+        assert!(RevsetExpr::parse(&nested("heads(", ")", MAX_DEPTH - 1)).is_ok());
+        assert!(RevsetExpr::parse(&format!("a{}", " - a".repeat(MAX_DEPTH - 1))).is_ok());
+
+        for revset in &[
+            nested("(", ")", 100_000),
+            nested("heads(", ")", MAX_DEPTH),
+            nested("::(", ")", MAX_DEPTH),
+            format!("a{}", " - a".repeat(100_000)),
+            format!("a{}", " | a".repeat(MAX_DEPTH)),
+        ] {
+            let err = RevsetExpr::parse(revset).expect_err("revset is too deep");
+            match err.downcast::<ErrorKind>() {
+                Ok(ErrorKind::InvalidRevset(_)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn linear_expressions() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+                    string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372"),
+                    string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                ],
+                evaluate(
+                    &repo,
+                    "d0a361e9022d226ae52f689667bd7d212a19cfe0::\
+                     eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b",
+                ),
+            );
+
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+                    string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+                ],
+                evaluate(
+                    &repo,
+                    "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157 % \
+                     eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b",
+                ),
+            );
+
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+                    string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+                    string_to_nodehash("607314ef579bd2407752361ba1b0c1729d08b281"),
+                ],
+                evaluate(
+                    &repo,
+                    "::0ed509bf086fadcb8a8a5384dc3b550729b0fc17 - \
+                     ::cb15ca4a43a59acff5388cea9648c162afde8372 | \
+                     607314ef579bd2407752361ba1b0c1729d08b281",
+                ),
+            );
+
+            assert_node_sequence(
+                &repo,
+                vec![string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372")],
+                evaluate(
+                    &repo,
+                    "heads(ancestors(cb15ca4a43a59acff5388cea9648c162afde8372) & \
+                     ::a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157)",
+                ),
+            );
+        });
+    }
+
+    #[test]
+    fn merge_dag_range() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            // Only the short branch descends from its root, the long one is left out.
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("b47ca72355a0af2c749d45a5689fd5bcce9898c7"),
+                    string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                    string_to_nodehash("1d8a907f7b4bf50c6a09c16361e2205047ecc5e5"),
+                    string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+                ],
+                evaluate(
+                    &repo,
+                    "3cda5c78aa35f0f5b09780d971197b51cad4613a::\
+                     b47ca72355a0af2c749d45a5689fd5bcce9898c7",
+                ),
+            );
+
+            // Several starts and ends, some of them unrelated.
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("264f01429683b3dd8042cb3979e8bf37007118bc"),
+                    string_to_nodehash("5d43888a3c972fe68c224f93d41b30e9f888df7c"),
+                    string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                ],
+                evaluate(
+                    &repo,
+                    "(16839021e338500b3cf7c9b871c8a07351697d68 | \
+                     5d43888a3c972fe68c224f93d41b30e9f888df7c)::\
+                     (264f01429683b3dd8042cb3979e8bf37007118bc | \
+                     16839021e338500b3cf7c9b871c8a07351697d68)",
+                ),
+            );
+        });
+    }
+
+    #[test]
+    fn merge_heads_and_roots() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("264f01429683b3dd8042cb3979e8bf37007118bc"),
+                    string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                ],
+                evaluate(
+                    &repo,
+                    "heads(::b47ca72355a0af2c749d45a5689fd5bcce9898c7 - \
+                     b47ca72355a0af2c749d45a5689fd5bcce9898c7)",
+                ),
+            );
+
+            assert_node_sequence(
+                &repo,
+                vec![
+                    string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+                    string_to_nodehash("d7542c9db7f4c77dab4b315edd328edf1514952f"),
+                ],
+                evaluate(
+                    &repo,
+                    "roots(only(b47ca72355a0af2c749d45a5689fd5bcce9898c7, \
+                     15c40d0abc36d47fb51c8eaec51ac7aad31f669c))",
+                ),
+            );
+        });
+    }
+
+    #[test]
+    fn collect_with_parents_limit() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+            let all = "::a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157";
+
+            // The linear repo has 8 changesets.
+            let nodes = collect_with_parents(&repo, evaluate(&repo, all), 8)
+                .wait()
+                .expect("collect_with_parents failed");
+            assert_eq!(nodes.len(), 8);
+
+            let err = collect_with_parents(&repo, evaluate(&repo, all), 7)
+                .wait()
+                .expect_err("collect_with_parents should fail");
+            match err.downcast::<ErrorKind>() {
+                Ok(ErrorKind::TooManyNodes(7)) => {}
+                other => panic!("unexpected result: {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn unknown_revision() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            for revset in &[
+                "::1111111111111111111111111111111111111111",
+                "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157 - nosuchbookmark",
+            ] {
+                let err = evaluate(&repo, revset)
+                    .collect()
+                    .wait()
+                    .expect_err(revset);
+                match err.downcast::<ErrorKind>() {
+                    Ok(ErrorKind::UnknownRevision(_)) => {}
+                    other => panic!("unexpected result for {:?}: {:?}", revset, other),
+                }
+            }
+        });
+    }
+}
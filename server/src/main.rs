@@ -118,6 +118,9 @@ fn get_config<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<RepoConfi
         logger.new(o!["repo" => "Config repo"]),
         &crpath,
         RepositoryId::new(0),
+        None,
+        None,
+        None,
     )?;
 
     let changesetid = match matches.value_of("crbook") {